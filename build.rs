@@ -7,11 +7,22 @@
 //! Cargo re-run the build script whenever `memory.x` is changed,
 //! updating `memory.x` ensures a rebuild of the application with the
 //! new memory settings.
+//!
+//! It also bakes the wavetable into the firmware: the WAV file named by the
+//! `WAVETABLE` environment variable (single cycle frames of 2048 samples,
+//! first channel only) or, without it, a generated default table. Both end
+//! up as 16 bit PCM in `wavetable.bin`, see `src/baked.rs`.
 
 use std::env;
-use std::fs::File;
+use std::f64::consts::PI;
+use std::fs::{self, File};
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+/// Samples per frame, must match `wavetable::FRAME_LENGTH`
+const FRAME_LENGTH: usize = 2048;
+/// Frames that fit into the internal flash next to the firmware, 4 KB each
+const MAX_BAKED_FRAMES: usize = 8;
 
 fn main() {
     // Put `memory.x` in our output directory and ensure it's
@@ -28,4 +39,111 @@ fn main() {
     // here, we ensure the build script is only re-run when
     // `memory.x` is changed.
     println!("cargo:rerun-if-changed=memory.x");
+
+    // Bake the wavetable
+    println!("cargo:rerun-if-env-changed=WAVETABLE");
+    let samples = match env::var_os("WAVETABLE") {
+        Some(path) => {
+            let path = Path::new(&path);
+            println!("cargo:rerun-if-changed={}", path.display());
+            let data = fs::read(path)
+                .unwrap_or_else(|error| panic!("Can't read {}: {error}", path.display()));
+            read_wav(&data).unwrap_or_else(|error| panic!("{}: {error}", path.display()))
+        }
+        None => default_wavetable(),
+    };
+    assert!(
+        !samples.is_empty() && samples.len().is_multiple_of(FRAME_LENGTH),
+        "The wavetable must consist of frames of {FRAME_LENGTH} samples"
+    );
+    let frames = samples.len() / FRAME_LENGTH;
+    assert!(
+        frames <= MAX_BAKED_FRAMES,
+        "The wavetable has {frames} frames, but only {MAX_BAKED_FRAMES} ({} KB) fit into the \
+         128 KB of internal flash next to the firmware. Larger tables have to be loaded at \
+         runtime with `Wavetable::load_pcm16`.",
+        MAX_BAKED_FRAMES * FRAME_LENGTH * 2 / 1024
+    );
+    let bytes: Vec<u8> = samples
+        .iter()
+        .flat_map(|sample| ((sample.clamp(-1.0, 1.0) * 32767.0).round() as i16).to_le_bytes())
+        .collect();
+    File::create(out.join("wavetable.bin"))
+        .unwrap()
+        .write_all(&bytes)
+        .unwrap();
+}
+
+/// Morphs from sine over triangle and saw to square
+fn default_wavetable() -> Vec<f32> {
+    let shapes: [fn(f64) -> f64; 4] = [
+        |phase| (2.0 * PI * phase).sin(),
+        |phase| 1.0 - 4.0 * ((phase + 0.25).fract() - 0.5).abs(),
+        |phase| {
+            if phase < 0.5 {
+                2.0 * phase
+            } else {
+                2.0 * phase - 2.0
+            }
+        },
+        |phase| if phase < 0.5 { 1.0 } else { -1.0 },
+    ];
+    shapes
+        .iter()
+        .flat_map(|shape| (0..FRAME_LENGTH).map(|i| shape(i as f64 / FRAME_LENGTH as f64) as f32))
+        .collect()
+}
+
+/// Reads the first channel of a PCM (16, 24 or 32 bit) or float WAV file
+fn read_wav(data: &[u8]) -> Result<Vec<f32>, String> {
+    if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+        return Err("Not a WAV file".into());
+    }
+
+    let mut format = None;
+    let mut position = 12;
+    while position + 8 <= data.len() {
+        let id = &data[position..position + 4];
+        let size =
+            u32::from_le_bytes(data[position + 4..position + 8].try_into().unwrap()) as usize;
+        let body = data
+            .get(position + 8..position + 8 + size)
+            .ok_or("Truncated chunk")?;
+        match id {
+            b"fmt " => {
+                if body.len() < 16 {
+                    return Err("Invalid format chunk".into());
+                }
+                let mut tag = u16::from_le_bytes([body[0], body[1]]);
+                let channels = u16::from_le_bytes([body[2], body[3]]) as usize;
+                let bits = u16::from_le_bytes([body[14], body[15]]);
+                // WAVE_FORMAT_EXTENSIBLE keeps the actual format in the sub format GUID
+                if tag == 0xfffe && body.len() >= 26 {
+                    tag = u16::from_le_bytes([body[24], body[25]]);
+                }
+                format = Some((tag, channels, bits));
+            }
+            b"data" => {
+                let (tag, channels, bits) = format.ok_or("Data before format chunk")?;
+                let width = bits as usize / 8;
+                let decode: fn(&[u8]) -> f32 = match (tag, bits) {
+                    (1, 16) => |b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0,
+                    (1, 24) => |b| i32::from_le_bytes([0, b[0], b[1], b[2]]) as f32 / 2147483648.0,
+                    (1, 32) => {
+                        |b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2147483648.0
+                    }
+                    (3, 32) => |b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+                    _ => return Err(format!("Unsupported format {tag} with {bits} bits")),
+                };
+                return Ok(body
+                    .chunks_exact(width * channels.max(1))
+                    .map(|frame| decode(&frame[..width]))
+                    .collect());
+            }
+            _ => {}
+        }
+        // Chunks are padded to an even size
+        position += 8 + size + size % 2;
+    }
+    Err("No data chunk".into())
 }
@@ -0,0 +1,286 @@
+// Allocator
+//
+// Assigns notes to a fixed number of voices. The allocator only deals with
+// note numbers and voice indices and tells the caller what to do with the
+// voices through `VoiceEvent`s, so it doesn't depend on what a voice is.
+// The caller reports back which voices are still sounding (e.g. in their
+// release) and how loud they are, which the stealing strategies rely on.
+//
+// Polyphonic modes prefer idle voices, then released voices and only steal
+// a voice whose note is still held as a last resort. Mono and unison keep a
+// stack of the held notes, so releasing a note returns to the one that the
+// note priority picks from the remaining notes.
+use heapless::Vec;
+
+/// Maximum number of held notes remembered in mono and unison mode
+pub const NOTE_STACK_SIZE: usize = 16;
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum NotePriority {
+    /// The most recent note wins
+    #[default]
+    Last,
+    /// The lowest held note wins
+    Low,
+    /// The highest held note wins
+    High,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum AllocationMode {
+    /// Polyphonic, steals the voice that started first
+    #[default]
+    Oldest,
+    /// Polyphonic, steals the voice with the lowest level
+    Quietest,
+    /// Polyphonic, cycles through the voices
+    RoundRobin,
+    /// One note on all voices, legato changes notes without retriggering
+    Unison {
+        priority: NotePriority,
+        legato: bool,
+    },
+    /// One note on the first voice, legato changes notes without
+    /// retriggering
+    Mono {
+        priority: NotePriority,
+        legato: bool,
+    },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum VoiceEvent {
+    /// Play `note` on `voice`, replacing what it played before. Without
+    /// `retrigger` only the pitch changes.
+    NoteOn {
+        voice: usize,
+        note: u8,
+        velocity: u8,
+        retrigger: bool,
+    },
+    /// Release `voice`
+    NoteOff { voice: usize },
+}
+
+#[derive(Default, Copy, Clone)]
+struct Slot {
+    /// Note whose key is still held
+    note: Option<u8>,
+    /// Last note played, the voice may still sound in its release
+    last_note: u8,
+    active: bool,
+    level: f32,
+    /// Allocation counter at the time the note started
+    age: u32,
+}
+
+pub struct VoiceAllocator<const N: usize> {
+    mode: AllocationMode,
+    slots: [Slot; N],
+    counter: u32,
+    next: usize,
+    /// Held notes with their velocity, oldest first (mono and unison)
+    stack: Vec<(u8, u8), NOTE_STACK_SIZE>,
+    /// Note that the voices play in mono and unison mode
+    playing: Option<u8>,
+}
+
+impl<const N: usize> VoiceAllocator<N> {
+    pub fn new(mode: AllocationMode) -> Self {
+        Self {
+            mode,
+            slots: [Slot::default(); N],
+            counter: 0,
+            next: 0,
+            stack: Vec::new(),
+            playing: None,
+        }
+    }
+
+    pub fn mode(&self) -> AllocationMode {
+        self.mode
+    }
+
+    /// Switching the mode releases all voices
+    pub fn set_mode(&mut self, mode: AllocationMode) -> Vec<VoiceEvent, N> {
+        let events = self.all_notes_off();
+        self.mode = mode;
+        events
+    }
+
+    /// Reports whether a voice is still sounding and how loud it is, call
+    /// this after rendering the voice
+    pub fn update_voice(&mut self, voice: usize, active: bool, level: f32) {
+        let slot = &mut self.slots[voice];
+        slot.active = active || slot.note.is_some();
+        slot.level = level;
+    }
+
+    /// Note held on the voice, if any
+    pub fn voice_note(&self, voice: usize) -> Option<u8> {
+        self.slots[voice].note
+    }
+
+    pub fn note_on(&mut self, note: u8, velocity: u8) -> Vec<VoiceEvent, N> {
+        match self.mode {
+            AllocationMode::Unison { priority, legato }
+            | AllocationMode::Mono { priority, legato } => {
+                self.stack.retain(|(held, _)| *held != note);
+                if self.stack.is_full() {
+                    self.stack.remove(0);
+                }
+                let _ = self.stack.push((note, velocity));
+                self.play_stack(priority, legato)
+            }
+            _ => self.poly_note_on(note, velocity),
+        }
+    }
+
+    pub fn note_off(&mut self, note: u8) -> Vec<VoiceEvent, N> {
+        match self.mode {
+            AllocationMode::Unison { priority, legato }
+            | AllocationMode::Mono { priority, legato } => {
+                self.stack.retain(|(held, _)| *held != note);
+                self.play_stack(priority, legato)
+            }
+            _ => {
+                let mut events = Vec::new();
+                for (voice, slot) in self.slots.iter_mut().enumerate() {
+                    if slot.note == Some(note) {
+                        slot.note = None;
+                        let _ = events.push(VoiceEvent::NoteOff { voice });
+                    }
+                }
+                events
+            }
+        }
+    }
+
+    pub fn all_notes_off(&mut self) -> Vec<VoiceEvent, N> {
+        self.stack.clear();
+        self.playing = None;
+        let mut events = Vec::new();
+        for (voice, slot) in self.slots.iter_mut().enumerate() {
+            if slot.note.take().is_some() {
+                let _ = events.push(VoiceEvent::NoteOff { voice });
+            }
+        }
+        events
+    }
+
+    fn poly_note_on(&mut self, note: u8, velocity: u8) -> Vec<VoiceEvent, N> {
+        let mut events = Vec::new();
+        if N == 0 {
+            return events;
+        }
+
+        // Repeating a note reuses its voice, even in its release, anything
+        // else would stack up voices on the same pitch
+        let voice = self
+            .slots
+            .iter()
+            .position(|slot| slot.active && slot.last_note == note)
+            .unwrap_or_else(|| self.pick_voice());
+
+        self.counter = self.counter.wrapping_add(1);
+        let slot = &mut self.slots[voice];
+        *slot = Slot {
+            note: Some(note),
+            last_note: note,
+            active: true,
+            level: slot.level,
+            age: self.counter,
+        };
+        self.next = (voice + 1) % N;
+        let _ = events.push(VoiceEvent::NoteOn {
+            voice,
+            note,
+            velocity,
+            retrigger: true,
+        });
+        events
+    }
+
+    /// Idle voices first, then released voices, then held ones
+    fn pick_voice(&self) -> usize {
+        let counter = self.counter;
+        let candidates = |filter: fn(&Slot) -> bool| {
+            (0..N)
+                .map(|i| (self.next + i) % N)
+                .filter(move |&voice| filter(&self.slots[voice]))
+        };
+        let oldest = |voices: &mut dyn Iterator<Item = usize>| {
+            voices.max_by_key(|&voice| counter.wrapping_sub(self.slots[voice].age))
+        };
+        let quietest = |voices: &mut dyn Iterator<Item = usize>| {
+            voices.min_by(|&a, &b| self.slots[a].level.total_cmp(&self.slots[b].level))
+        };
+
+        let idle = |slot: &Slot| !slot.active;
+        let released = |slot: &Slot| slot.active && slot.note.is_none();
+        let held = |slot: &Slot| slot.note.is_some();
+
+        match self.mode {
+            AllocationMode::RoundRobin => candidates(idle)
+                .chain(candidates(released))
+                .chain(candidates(held))
+                .next(),
+            AllocationMode::Quietest => candidates(idle)
+                .next()
+                .or_else(|| quietest(&mut candidates(released)))
+                .or_else(|| quietest(&mut candidates(held))),
+            _ => candidates(idle)
+                .next()
+                .or_else(|| oldest(&mut candidates(released)))
+                .or_else(|| oldest(&mut candidates(held))),
+        }
+        .unwrap_or(0)
+    }
+
+    /// Makes the voices follow the note picked from the stack
+    fn play_stack(&mut self, priority: NotePriority, legato: bool) -> Vec<VoiceEvent, N> {
+        let mut events = Vec::new();
+        let voices = match self.mode {
+            AllocationMode::Mono { .. } => N.min(1),
+            _ => N,
+        };
+
+        let picked = match priority {
+            NotePriority::Last => self.stack.last(),
+            NotePriority::Low => self.stack.iter().min_by_key(|(note, _)| *note),
+            NotePriority::High => self.stack.iter().max_by_key(|(note, _)| *note),
+        }
+        .copied();
+
+        match picked {
+            None => {
+                self.playing = None;
+                for (voice, slot) in self.slots[..voices].iter_mut().enumerate() {
+                    if slot.note.take().is_some() {
+                        let _ = events.push(VoiceEvent::NoteOff { voice });
+                    }
+                }
+            }
+            Some((note, velocity)) if self.playing != Some(note) => {
+                let retrigger = !(legato && self.playing.is_some());
+                self.playing = Some(note);
+                self.counter = self.counter.wrapping_add(1);
+                for (voice, slot) in self.slots[..voices].iter_mut().enumerate() {
+                    slot.note = Some(note);
+                    slot.last_note = note;
+                    slot.active = true;
+                    slot.age = self.counter;
+                    let _ = events.push(VoiceEvent::NoteOn {
+                        voice,
+                        note,
+                        velocity,
+                        retrigger,
+                    });
+                }
+            }
+            // Still the same note
+            Some(_) => {}
+        }
+        events
+    }
+}
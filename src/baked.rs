@@ -0,0 +1,8 @@
+// Baked
+//
+// Data baked into the firmware by the build script. Kept apart from the
+// modules that use it, which also build on the host without it.
+
+/// Wavetable, 16 bit little endian frames of `wavetable::FRAME_LENGTH`
+/// samples, see `build.rs`
+pub static WAVETABLE: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/wavetable.bin"));
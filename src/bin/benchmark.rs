@@ -1,8 +1,26 @@
 #![no_main]
 #![no_std]
 
+use core::mem::MaybeUninit;
+
 use daisy::audio::BLOCK_LENGTH;
-use daisy_kickstart::{US, bench_time, processor::Processor};
+use daisy_kickstart::{
+    US, baked, bench_cycles, bench_time,
+    convolution::Convolver,
+    drums::{Clap, HiHat, Kick, Snare},
+    octaver::Octaver,
+    oscillator::{Oscillator, OscillatorParams, Waveform},
+    physical::{KarplusStrong, ModalParams, ModalResonator},
+    pitch_shift::{PitchShiftParams, PitchShifter},
+    processor::Processor,
+    synth::Synth,
+    vocoder::{Vocoder, VocoderParams},
+    wavetable::{FRAME_LENGTH, FRAME_STORAGE, Wavetable, WavetableOscillator, WavetableParams},
+};
+
+/// Memory for two frames of the baked wavetable in the AXI SRAM, see `memory.x`
+#[unsafe(link_section = ".sram")]
+static mut WAVETABLE_MEMORY: MaybeUninit<[f32; 2 * FRAME_STORAGE]> = MaybeUninit::uninit();
 
 #[cortex_m_rt::entry]
 fn main() -> ! {
@@ -43,6 +61,153 @@ fn main() -> ! {
     let process_time = BLOCK_LENGTH as f32 / daisy::audio::FS.to_Hz() as f32;
 
     defmt::println!("Time: {} us", execution_time * US as f32);
+
+    // Benchmark the pitch shifter, one per channel like in the processor
+    let mut pitch_shifter_left = PitchShifter::new();
+    let mut pitch_shifter_right = PitchShifter::new();
+    let pitch_shift_params = PitchShiftParams {
+        semitones: 7.0,
+        ..Default::default()
+    };
+    pitch_shifter_left.set_params(pitch_shift_params).unwrap();
+    pitch_shifter_right.set_params(pitch_shift_params).unwrap();
+    let execution_time = bench_time!(cortex_peripherals, system_clock_frequency_hz, {
+        for (left, right) in audio_buffer.iter_mut() {
+            *left = pitch_shifter_left.tick(*left);
+            *right = pitch_shifter_right.tick(*right);
+        }
+    });
+    defmt::println!("Pitch shifter time: {} us", execution_time * US as f32);
+
+    // Benchmark the octaver
+    let mut octaver_left = Octaver::new();
+    let mut octaver_right = Octaver::new();
+    let execution_time = bench_time!(cortex_peripherals, system_clock_frequency_hz, {
+        for (left, right) in audio_buffer.iter_mut() {
+            *left = octaver_left.tick(*left);
+            *right = octaver_right.tick(*right);
+        }
+    });
+    defmt::println!("Octaver time: {} us", execution_time * US as f32);
+
+    // Benchmark a 2048 tap convolution of one channel
+    let mut convolver = Convolver::<{ 2 * BLOCK_LENGTH }, { 2048 / BLOCK_LENGTH }>::new().unwrap();
+    convolver.set_impulse_response(&[0.01; 2048]).unwrap();
+    let mut channel = [1.0; BLOCK_LENGTH];
+    let execution_time = bench_time!(cortex_peripherals, system_clock_frequency_hz, {
+        convolver.process(&mut channel);
+    });
+    defmt::println!("Convolution time: {} us", execution_time * US as f32);
+
+    // Benchmark the vocoder with the maximum number of bands
+    let mut vocoder = Vocoder::new();
+    vocoder
+        .set_params(VocoderParams {
+            bands: daisy_kickstart::vocoder::MAX_BANDS,
+            ..Default::default()
+        })
+        .unwrap();
+    let execution_time = bench_time!(cortex_peripherals, system_clock_frequency_hz, {
+        vocoder.process(&mut audio_buffer);
+    });
+    defmt::println!("Vocoder time: {} us", execution_time * US as f32);
+
+    // Benchmark a band-limited square, the most expensive waveform
+    let mut oscillator = Oscillator::new();
+    oscillator
+        .set_params(OscillatorParams {
+            waveform: Waveform::Square,
+            frequency: 1000.0,
+            pulse_width: 0.3,
+            ..Default::default()
+        })
+        .unwrap();
+    let execution_time = bench_time!(cortex_peripherals, system_clock_frequency_hz, {
+        oscillator.process(&mut audio_buffer);
+    });
+    defmt::println!("Oscillator time: {} us", execution_time * US as f32);
+
+    // Benchmark the wavetable oscillator while morphing between two frames
+    let wavetable_memory = unsafe {
+        let memory = core::ptr::addr_of_mut!(WAVETABLE_MEMORY) as *mut f32;
+        memory.write_bytes(0, 2 * FRAME_STORAGE);
+        core::slice::from_raw_parts_mut(memory, 2 * FRAME_STORAGE)
+    };
+    let mut wavetable = Wavetable::new(wavetable_memory).unwrap();
+    wavetable
+        .load_pcm16(&baked::WAVETABLE[..4 * FRAME_LENGTH])
+        .unwrap();
+    let mut wavetable_oscillator = WavetableOscillator::new();
+    wavetable_oscillator
+        .set_params(WavetableParams {
+            frequency: 1000.0,
+            morph: 0.5,
+        })
+        .unwrap();
+    let execution_time = bench_time!(cortex_peripherals, system_clock_frequency_hz, {
+        wavetable_oscillator.process(&wavetable, &mut audio_buffer);
+    });
+    defmt::println!("Wavetable time: {} us", execution_time * US as f32);
+
+    // Benchmark the synth with all eight voices playing
+    let mut synth = Synth::<8>::new(daisy::audio::FS.to_Hz() as f32);
+    for note in [48, 52, 55, 60, 64, 67, 72, 76] {
+        synth.note_on(note, 100);
+    }
+    let execution_time = bench_time!(cortex_peripherals, system_clock_frequency_hz, {
+        synth.process(&mut audio_buffer);
+    });
+    defmt::println!("Synth time: {} us", execution_time * US as f32);
+
+    // Benchmark a plucked string
+    let mut string = KarplusStrong::new();
+    string.pluck(1.0);
+    let execution_time = bench_time!(cortex_peripherals, system_clock_frequency_hz, {
+        string.process(&mut audio_buffer);
+    });
+    defmt::println!("String time: {} us", execution_time * US as f32);
+
+    // Benchmark the modal resonator with the eight modes of the bell
+    let mut resonator = ModalResonator::new();
+    resonator
+        .set_params(ModalParams::bell(220.0, 4000.0))
+        .unwrap();
+    resonator.strike(1.0);
+    let execution_time = bench_time!(cortex_peripherals, system_clock_frequency_hz, {
+        resonator.process(&mut audio_buffer);
+    });
+    defmt::println!("Modal time: {} us", execution_time * US as f32);
+
+    // Benchmark every drum voice right after its trigger
+    let sample_rate = daisy::audio::FS.to_Hz() as f32;
+    let mut kick = Kick::new(sample_rate);
+    kick.trigger(true);
+    let cycles = bench_cycles!(cortex_peripherals, {
+        kick.process(&mut audio_buffer);
+    });
+    defmt::println!("Kick cycles: {}", cycles);
+
+    let mut snare = Snare::new(sample_rate);
+    snare.trigger(true);
+    let cycles = bench_cycles!(cortex_peripherals, {
+        snare.process(&mut audio_buffer);
+    });
+    defmt::println!("Snare cycles: {}", cycles);
+
+    let mut hihat = HiHat::new(sample_rate);
+    hihat.trigger(true);
+    let cycles = bench_cycles!(cortex_peripherals, {
+        hihat.process(&mut audio_buffer);
+    });
+    defmt::println!("Hi-hat cycles: {}", cycles);
+
+    let mut clap = Clap::new(sample_rate);
+    clap.trigger(true);
+    let cycles = bench_cycles!(cortex_peripherals, {
+        clap.process(&mut audio_buffer);
+    });
+    defmt::println!("Clap cycles: {}", cycles);
+
     defmt::println!("Time available: {} us", process_time * US as f32);
 
     // Loop infinite
@@ -79,7 +79,9 @@ fn DMA1_STR1() {
             PROCESSOR.borrow(cs).borrow_mut().as_mut(),
             PARAMS.borrow(cs).borrow(),
         ) {
-            processor.update(params.clone());
+            // The pots stay within the range of the filter, an invalid
+            // setting would leave the last valid one playing
+            let _ = processor.update(params.clone());
             audio_interface
                 .handle_interrupt_dma1_str1(|audio_buffer| {
                     processor.process(audio_buffer);
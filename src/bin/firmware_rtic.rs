@@ -3,22 +3,84 @@
 #![no_main]
 #![no_std]
 
+use core::mem::MaybeUninit;
+
 use {defmt_rtt as _, panic_probe as _};
 
+/// Frames per looper buffer, about 65 s at 48 kHz
+const LOOPER_FRAMES: usize = 3 * 1024 * 1024;
+
+// The looper memory lives in the external SDRAM, see `memory.x`. The runtime
+// does not initialize it, so it must not be touched before the SDRAM
+// controller is set up.
+#[unsafe(link_section = ".sdram_bss")]
+static mut LOOPER_MEMORY: MaybeUninit<[(f32, f32); 2 * LOOPER_FRAMES]> = MaybeUninit::uninit();
+
+/// Splits the SDRAM looper memory into the loop and the undo buffer
+///
+/// # Safety
+/// Must be called only once and only after the SDRAM has been initialized.
+unsafe fn looper_memory() -> (&'static mut [(f32, f32)], &'static mut [(f32, f32)]) {
+    unsafe {
+        let memory = core::ptr::addr_of_mut!(LOOPER_MEMORY) as *mut (f32, f32);
+        // All zero bytes are valid silent frames
+        memory.write_bytes(0, 2 * LOOPER_FRAMES);
+        core::slice::from_raw_parts_mut(memory, 2 * LOOPER_FRAMES).split_at_mut(LOOPER_FRAMES)
+    }
+}
+
 #[rtic::app(device = stm32h7xx_hal::pac, peripherals = true, dispatchers = [EXTI0, EXTI1])]
 mod app {
-    use daisy_kickstart::{filter::FilterParams, processor::Processor};
-    use heapless::spsc::{Consumer, Producer, Queue};
+    use daisy::audio::BLOCK_LENGTH;
+    use daisy_kickstart::{
+        filter::FilterParams,
+        flash::SECTOR_SIZE,
+        lfo::{Lfo, LfoParams, LfoWaveform},
+        looper::{Looper, LooperState},
+        midi::{KnobCc, MidiEncoder, MidiMessage, MidiParser},
+        midi_clock::MidiClock,
+        midi_map::{Controller, MAX_MAPPINGS, Mapping, MidiMap, SoftTakeover},
+        modulation::{Destination, EnvelopeFollower, ModulationMatrix, Range, Route, Source},
+        morph::MorphControl,
+        octaver::OctaverParams,
+        preset::{MAX_PRESETS, Preset, slot_key},
+        processor::Processor,
+        qspi_flash::{FLASH_SIZE, QspiFlash},
+        sequencer::{
+            ClockSource, PlayMode, Sequencer, SequencerEvent, SequencerEvents, SequencerParams,
+        },
+        settings::{MAX_VALUE, SettingsStore},
+        synth::Synth,
+        sysex::{Slot, SysExError, SysExMessage},
+        tuner::Tuner,
+    };
+    #[cfg(feature = "usb-midi")]
+    use daisy_kickstart::{
+        usb_midi::UsbMidiParser,
+        usb_midi_class::{MAX_PACKET_SIZE, UsbMidiClass},
+    };
+    use heapless::{
+        Deque, Vec,
+        spsc::{Consumer, Producer, Queue},
+    };
 
     use stm32h7xx_hal::prelude::*;
     use stm32h7xx_hal::{
         adc::AdcSampleTime,
         delay::DelayFromCountDownTimer,
-        gpio::{Analog, Pin},
+        gpio::{Analog, Input, Pin},
+        serial::{Rx, Tx},
+    };
+    #[cfg(feature = "usb-midi")]
+    use stm32h7xx_hal::{
+        rcc::rec::UsbClkSel,
+        usb_hs::{USB2, UsbBus},
     };
     use systick_monotonic::Systick;
+    #[cfg(feature = "usb-midi")]
+    use usb_device::{bus::UsbBusAllocator, prelude::*};
     use {
-        daisy::pac::ADC1,
+        daisy::pac::{ADC1, USART1},
         defmt_rtt as _, panic_probe as _,
         stm32h7xx_hal::adc::{self, Adc, Enabled},
     };
@@ -26,24 +88,104 @@ mod app {
     use cortex_m::Peripherals as CorePeripherals;
     use daisy::{audio::Interface, pac::Peripherals as DevicePeripherals};
 
+    /// Voices of the synth played over MIDI
+    const SYNTH_VOICES: usize = 4;
+
     #[monotonic(binds = SysTick, default = true)]
     type Mono = Systick<1000>; // 1 kHz / 1 ms granularity
 
+    /// Tempo of the MIDI clock sent out, until `CLOCK_TEMPO` changes it.
+    /// The Daisy is the clock master, received clock and transport messages
+    /// are ignored.
+    const TEMPO: f32 = 120.0;
+    /// First channel mode message, CC 120-127 (all sound off, all notes off
+    /// and the like) are no controllers and go to `dsp` with the notes
+    const CHANNEL_MODE: u8 = 120;
+    /// Controllers sent by the knobs: brightness and resonance
+    const KNOB_CCS: [u8; 2] = [74, 71];
+
+    // Controllers of the looper on any channel, from the undefined ones.
+    // The buttons act on values of 64 and above, as sent when pressed.
+    /// Records, closes the loop, overdubs and plays in turn
+    const LOOPER_RECORD: u8 = 102;
+    const LOOPER_STOP: u8 = 103;
+    const LOOPER_UNDO: u8 = 104;
+    const LOOPER_CLEAR: u8 = 105;
+    /// Switches, on at 64 and above
+    const LOOPER_REVERSE: u8 = 106;
+    const LOOPER_HALF_SPEED: u8 = 107;
+    /// Amount of the loop kept when overdubbing
+    const LOOPER_FEEDBACK: u8 = 108;
+    /// Tempo of the MIDI clock, from `MIN_TEMPO` up in steps of 1 BPM
+    const CLOCK_TEMPO: u8 = 109;
+    const MIN_TEMPO: f32 = 60.0;
+    /// Switch of the arpeggiator, on at 64 and above
+    const ARPEGGIATOR: u8 = 110;
+    /// Starts the MIDI clock at 64 and above and stops it below
+    const CLOCK_TRANSPORT: u8 = 111;
+    /// Switches of the spectral effects, on at 64 and above: holds the
+    /// spectrum in freeze mode, learns the noise profile in denoise mode
+    const SPECTRAL_FREEZE: u8 = 117;
+    const DENOISE_LEARN: u8 = 118;
+    /// Transposition in semitones from 64 up and down, up to an octave
+    const PITCH_SHIFT: u8 = 85;
+    /// Levels of the octave below and above
+    const OCTAVE_DOWN: u8 = 86;
+    const OCTAVE_UP: u8 = 87;
+
+    /// The settings take the last 64 KB of the flash
+    const SETTINGS_SECTORS: usize = 16;
+    const SETTINGS_ADDRESS: u32 = FLASH_SIZE - SETTINGS_SECTORS as u32 * SECTOR_SIZE;
+    /// Settings key of the MIDI mappings, the presets start at `PRESET_KEY`
+    const MAPPINGS_KEY: u16 = 0x0200;
+
     #[shared]
-    struct Shared {}
+    struct Shared {
+        midi_in: MidiIn,
+        midi_out: MidiOut,
+    }
 
     #[local]
     struct Local {
         audio_interface: Interface,
         processor: Processor,
+        looper: Looper<'static>,
         inputs: Inputs,
-        params_producer: Producer<'static, FilterParams, 8>,
-        params_consumer: Consumer<'static, FilterParams, 8>,
+        controls: Controls,
+        modulation: Modulation,
+        follower: EnvelopeFollower,
+        level_producer: Producer<'static, f32, 4>,
+        level_consumer: Consumer<'static, f32, 4>,
+        params_producer: Producer<'static, Preset, 8>,
+        params_consumer: Consumer<'static, Preset, 8>,
+        tuner: Tuner,
+        tuner_producer: Producer<'static, [f32; BLOCK_LENGTH], 64>,
+        tuner_consumer: Consumer<'static, [f32; BLOCK_LENGTH], 64>,
+        synth: Synth<SYNTH_VOICES>,
+        sequencer: Sequencer,
+        midi_rx: Rx<USART1>,
+        midi_parser: MidiParser,
+        midi_consumer: Consumer<'static, MidiMessage, 64>,
+        control_consumer: Consumer<'static, MidiMessage, 32>,
+        #[cfg(feature = "usb-midi")]
+        usb_midi: UsbMidi,
+        midi_clock: MidiClock,
+        knob_ccs: [KnobCc; 2],
+        settings: SettingsStore<QspiFlash>,
+        recall_producer: Producer<'static, (usize, Preset, Preset), 4>,
+        recall_consumer: Consumer<'static, (usize, Preset, Preset), 4>,
+        sysex_consumer: Consumer<'static, SysExMessage, 4>,
     }
 
     #[init(
         local = [
-            param_queue: Queue<FilterParams, 8> = Queue::new(),
+            param_queue: Queue<Preset, 8> = Queue::new(),
+            recall_queue: Queue<(usize, Preset, Preset), 4> = Queue::new(),
+            tuner_queue: Queue<[f32; BLOCK_LENGTH], 64> = Queue::new(),
+            midi_queue: Queue<MidiMessage, 64> = Queue::new(),
+            control_queue: Queue<MidiMessage, 32> = Queue::new(),
+            sysex_queue: Queue<SysExMessage, 4> = Queue::new(),
+            level_queue: Queue<f32, 4> = Queue::new(),
         ]
     )]
     fn init(cx: init::Context) -> (Shared, Local, init::Monotonics) {
@@ -51,20 +193,94 @@ mod app {
         let audio_interface = system.audio_interface;
         let mono = system.mono;
         let inputs = system.inputs;
+        let looper = system.looper;
+        let midi_rx = system.midi_rx;
+        let mut settings =
+            SettingsStore::mount(system.flash, SETTINGS_ADDRESS, SETTINGS_SECTORS).unwrap();
+        let midi_out = MidiOut::new(system.midi_tx);
+        #[cfg(feature = "usb-midi")]
+        let usb_midi = system.usb_midi;
 
         let (params_producer, params_consumer) = cx.local.param_queue.split();
+        let (tuner_producer, tuner_consumer) = cx.local.tuner_queue.split();
+        let (midi_producer, midi_consumer) = cx.local.midi_queue.split();
+        let (control_producer, control_consumer) = cx.local.control_queue.split();
+        let (recall_producer, recall_consumer) = cx.local.recall_queue.split();
+        let (sysex_producer, sysex_consumer) = cx.local.sysex_queue.split();
+        let (level_producer, level_consumer) = cx.local.level_queue.split();
         let processor = Processor::new();
+        let tuner = Tuner::new(daisy::audio::FS.to_Hz() as f32).unwrap();
+        let synth = Synth::new(daisy::audio::FS.to_Hz() as f32);
+        // Arpeggiates the held notes in sixteenths of the MIDI clock sent out
+        let mut sequencer = Sequencer::new(daisy::audio::FS.to_Hz() as f32);
+        sequencer
+            .set_params(SequencerParams {
+                clock: ClockSource::External,
+                pulses_per_step: 6,
+                play: PlayMode::Arpeggiator,
+                ..SequencerParams::default()
+            })
+            .unwrap();
+        let mut midi_clock = MidiClock::new(daisy::audio::FS.to_Hz() as f32);
+        midi_clock.set_tempo(TEMPO).unwrap();
+
+        // Start with the first preset, if there is one, and the mappings
+        let mut controls = Controls::new();
+        if let Some((preset, target)) = load_morph(&mut settings, 0) {
+            controls.recall(0, preset, target);
+        }
+        let mappings = load_mappings(&mut settings);
+        if mappings.is_empty() {
+            controls.midi_map.set_mapping(MORPH_MAPPING).unwrap();
+        } else if let Err(error) = controls.midi_map.restore(&mappings) {
+            defmt::println!("MIDI mappings: {}", defmt::Debug2Format(&error));
+        }
+
+        // Modulation goes on top of the parameters set by the knobs and MIDI
+        let modulation = Modulation::new();
+        let follower = EnvelopeFollower::new(daisy::audio::FS.to_Hz() as f32);
 
         input::spawn().unwrap();
+        tuner::spawn().unwrap();
 
         (
-            Shared {},
+            Shared {
+                midi_in: MidiIn {
+                    midi_producer,
+                    control_producer,
+                    sysex_producer,
+                },
+                midi_out,
+            },
             Local {
                 audio_interface,
                 processor,
+                looper,
                 inputs,
+                controls,
+                modulation,
+                follower,
+                level_producer,
+                level_consumer,
                 params_producer,
                 params_consumer,
+                tuner,
+                tuner_producer,
+                tuner_consumer,
+                synth,
+                sequencer,
+                midi_rx,
+                midi_parser: MidiParser::new(),
+                midi_consumer,
+                control_consumer,
+                #[cfg(feature = "usb-midi")]
+                usb_midi,
+                midi_clock,
+                knob_ccs: KNOB_CCS.map(|controller| KnobCc::new(0, controller)),
+                settings,
+                recall_producer,
+                recall_consumer,
+                sysex_consumer,
             },
             init::Monotonics(mono),
         )
@@ -73,11 +289,165 @@ mod app {
     // Audio is tranfered from the input and to the input periodically thorugh DMA.
     // Every time Daisy is done transferring data, it will ask for more by triggering
     // the DMA 1 Stream 1 interrupt.
-    #[task(binds = DMA1_STR1, local = [audio_interface, processor, params_consumer])]
-    fn dsp(cx: dsp::Context) {
+    #[task(
+        binds = DMA1_STR1,
+        local = [
+            audio_interface,
+            processor,
+            looper,
+            params_consumer,
+            tuner_producer,
+            follower,
+            level_producer,
+            synth,
+            sequencer,
+            midi_consumer,
+            midi_clock,
+        ],
+        shared = [midi_out],
+        priority = 3,
+    )]
+    fn dsp(mut cx: dsp::Context) {
         let audio_interface = cx.local.audio_interface;
         let processor = cx.local.processor;
+        let looper = cx.local.looper;
         let params_consumer = cx.local.params_consumer;
+        let tuner_producer = cx.local.tuner_producer;
+        let follower = cx.local.follower;
+        let level_producer = cx.local.level_producer;
+        let synth = cx.local.synth;
+        let sequencer = cx.local.sequencer;
+        let midi_consumer = cx.local.midi_consumer;
+        let midi_clock = cx.local.midi_clock;
+
+        // play the notes received since the last block unless the
+        // arpeggiator runs, it keeps track of the held notes either way so
+        // that it starts with them
+        while let Some(message) = midi_consumer.dequeue() {
+            match message {
+                MidiMessage::NoteOn { note, velocity, .. } => {
+                    sequencer.note_on(note, velocity);
+                    if !sequencer.is_running() {
+                        synth.note_on(note, velocity);
+                    }
+                }
+                MidiMessage::NoteOff { note, .. } => {
+                    sequencer.note_off(note);
+                    if !sequencer.is_running() {
+                        synth.note_off(note);
+                    }
+                }
+                MidiMessage::ControlChange {
+                    controller: ARPEGGIATOR,
+                    value,
+                    ..
+                } => {
+                    if value < 64 {
+                        play_sequence(synth, sequencer.stop());
+                    } else if !sequencer.is_running() {
+                        // The arpeggiator takes over the held notes
+                        synth.all_notes_off();
+                        sequencer.start();
+                    }
+                }
+                MidiMessage::ControlChange {
+                    controller: controller @ LOOPER_RECORD..=LOOPER_FEEDBACK,
+                    value,
+                    ..
+                } => control_looper(looper, controller, value),
+                MidiMessage::ControlChange {
+                    controller: CLOCK_TRANSPORT,
+                    value,
+                    ..
+                } => {
+                    if value < 64 {
+                        midi_clock.stop();
+                    } else if !midi_clock.is_running() {
+                        midi_clock.start();
+                    }
+                }
+                MidiMessage::ControlChange {
+                    controller: CLOCK_TEMPO,
+                    value,
+                    ..
+                } => midi_clock
+                    .set_tempo(MIN_TEMPO + f32::from(value))
+                    .expect("The tempo is in range"),
+                MidiMessage::ControlChange {
+                    controller: SPECTRAL_FREEZE,
+                    value,
+                    ..
+                } => processor.set_freeze(value >= 64),
+                MidiMessage::ControlChange {
+                    controller: DENOISE_LEARN,
+                    value,
+                    ..
+                } => processor.set_denoise_learning(value >= 64),
+                MidiMessage::ControlChange {
+                    controller: PITCH_SHIFT,
+                    value,
+                    ..
+                } => {
+                    let semitones = (i16::from(value) - 64).clamp(-12, 12);
+                    processor
+                        .set_pitch_shift(f32::from(semitones))
+                        .expect("The transposition is in range");
+                }
+                MidiMessage::ControlChange {
+                    controller: controller @ (OCTAVE_DOWN | OCTAVE_UP),
+                    value,
+                    ..
+                } => {
+                    let level = f32::from(value) / 127.0;
+                    let params = processor.octaver_params();
+                    processor.set_octaver(if controller == OCTAVE_DOWN {
+                        OctaverParams {
+                            down: level,
+                            ..params
+                        }
+                    } else {
+                        OctaverParams {
+                            up: level,
+                            ..params
+                        }
+                    });
+                }
+                // All sound off and all notes off
+                MidiMessage::ControlChange {
+                    controller: 120 | 123,
+                    ..
+                }
+                | MidiMessage::Reset => synth.all_notes_off(),
+                _ => {}
+            }
+        }
+
+        // the clock pulses of this block, counting blocks keeps them locked
+        // to the audio. The arpeggiator follows them and starts over with
+        // the clock.
+        let clock = midi_clock.process(BLOCK_LENGTH);
+        for (_, message) in clock.iter() {
+            match message {
+                MidiMessage::Start => sequencer.rewind(),
+                MidiMessage::Clock => sequencer.clock(),
+                _ => {}
+            }
+        }
+
+        // the notes of the arpeggio start at the beginning of the block, like
+        // the clock pulses below
+        play_sequence(synth, sequencer.process(BLOCK_LENGTH));
+
+        // send the clock pulses. They all go out now rather than at their
+        // offset within the block, at most a block (0.7 ms) early, which is
+        // less than the 1 ms it takes to send a three byte message anyway.
+        if !clock.is_empty() {
+            cx.shared.midi_out.lock(|midi_out| {
+                for (_, message) in clock {
+                    midi_out.send(message);
+                }
+            });
+        }
 
         // get the last item in the queue
         let mut params = None;
@@ -85,72 +455,824 @@ mod app {
             params = Some(p);
         }
 
-        // update the processor if there was something in the queue
+        // update the processor if there was something in the queue, the
+        // spectral modes delay the output
         if let Some(params) = params {
-            processor.update(params);
+            let latency = processor.latency();
+            if let Err(error) = processor.load(params) {
+                defmt::println!("Filter: {}", defmt::Debug2Format(&error));
+            }
+            if processor.latency() != latency {
+                defmt::println!("Latency: {} samples", processor.latency());
+            }
         }
 
         // process audio
         audio_interface
             .handle_interrupt_dma1_str1(|audio_buffer| {
+                // hand the dry input over to the tuner, drop it if the tuner lags behind
+                let mut block = [0.0; BLOCK_LENGTH];
+                for (sample, (left, _)) in block.iter_mut().zip(audio_buffer.iter()) {
+                    *sample = *left;
+                }
+                let _ = tuner_producer.enqueue(block);
+                // and its level to the modulation
+                let _ = level_producer.enqueue(follower.process(&block));
+
+                synth.process(audio_buffer);
                 processor.process(audio_buffer);
+                looper.process(audio_buffer);
             })
             .unwrap();
     }
 
+    fn play_sequence(synth: &mut Synth<SYNTH_VOICES>, events: SequencerEvents) {
+        for (_, event) in events {
+            match event {
+                SequencerEvent::NoteOn { note, velocity, .. } => synth.note_on(note, velocity),
+                SequencerEvent::NoteOff { note } => synth.note_off(note),
+            }
+        }
+    }
+
+    fn control_looper(looper: &mut Looper, controller: u8, value: u8) {
+        let pressed = value >= 64;
+        let result = match controller {
+            LOOPER_RECORD if pressed => match looper.state() {
+                LooperState::Empty => looper.record(),
+                LooperState::Playing => looper.overdub(),
+                LooperState::Recording | LooperState::Overdubbing | LooperState::Stopped => {
+                    looper.play()
+                }
+            },
+            LOOPER_STOP if pressed => {
+                looper.stop();
+                Ok(())
+            }
+            LOOPER_UNDO if pressed => looper.undo(),
+            LOOPER_CLEAR if pressed => {
+                looper.clear();
+                Ok(())
+            }
+            LOOPER_REVERSE => {
+                looper.set_reverse(pressed);
+                Ok(())
+            }
+            LOOPER_HALF_SPEED => {
+                looper.set_half_speed(pressed);
+                Ok(())
+            }
+            LOOPER_FEEDBACK => looper.set_feedback(f32::from(value) / 127.0),
+            _ => Ok(()),
+        };
+        if let Err(error) = result {
+            defmt::println!("Looper: {}", defmt::Debug2Format(&error));
+        }
+    }
+
+    // Every byte from the MIDI input raises the USART1 interrupt, and so does
+    // the output whenever it is ready for the next byte. It runs above `dsp`,
+    // so that no byte gets lost while a block is processed, and hands the
+    // complete messages over to `dsp`, or to `input` for the controllers and
+    // SysEx.
+    #[task(
+        binds = USART1,
+        local = [midi_rx, midi_parser],
+        shared = [midi_in, midi_out],
+        priority = 4,
+    )]
+    fn midi(mut cx: midi::Context) {
+        let midi_rx = cx.local.midi_rx;
+        let midi_parser = cx.local.midi_parser;
+
+        // Reading clears the interrupt, an overrun ends the loop and clears
+        // its flag as well
+        while let Ok(byte) = midi_rx.read() {
+            match midi_parser.parse(byte) {
+                Ok(Some(MidiMessage::SysEx { .. })) => cx
+                    .shared
+                    .midi_in
+                    .lock(|midi_in| midi_in.push_sysex(midi_parser.sysex())),
+                Ok(Some(message)) => cx.shared.midi_in.lock(|midi_in| midi_in.push(message)),
+                Ok(None) => {}
+                Err(error) => defmt::println!("MIDI: {}", defmt::Debug2Format(&error)),
+            }
+        }
+
+        cx.shared.midi_out.lock(|midi_out| midi_out.flush());
+    }
+
+    // With the `usb-midi` feature the Daisy is a USB MIDI device as well.
+    // The USB interrupt runs at the priority of the serial input and its
+    // messages take the same way.
+    #[cfg(feature = "usb-midi")]
+    #[task(binds = OTG_FS, local = [usb_midi], shared = [midi_in], priority = 4)]
+    fn usb(mut cx: usb::Context) {
+        let usb_midi = cx.local.usb_midi;
+        cx.shared.midi_in.lock(|midi_in| usb_midi.poll(midi_in));
+    }
+
+    /// The receiving end of all MIDI inputs
+    struct MidiIn {
+        midi_producer: Producer<'static, MidiMessage, 64>,
+        control_producer: Producer<'static, MidiMessage, 32>,
+        sysex_producer: Producer<'static, SysExMessage, 4>,
+    }
+
+    impl MidiIn {
+        /// Hands the message over to `dsp`, or to `input` for the
+        /// controllers below `CHANNEL_MODE` and program changes. Drops it if
+        /// the tasks lag behind.
+        fn push(&mut self, message: MidiMessage) {
+            match message {
+                // The looper, the clock, the arpeggiator and the effects of
+                // the processor run in `dsp`
+                MidiMessage::ControlChange {
+                    controller:
+                        LOOPER_RECORD..=CLOCK_TRANSPORT
+                        | SPECTRAL_FREEZE
+                        | DENOISE_LEARN
+                        | PITCH_SHIFT..=OCTAVE_UP,
+                    ..
+                } => {
+                    let _ = self.midi_producer.enqueue(message);
+                }
+                // The Daisy sends the clock, it doesn't follow one
+                MidiMessage::Clock
+                | MidiMessage::Start
+                | MidiMessage::Continue
+                | MidiMessage::Stop => {}
+                MidiMessage::ControlChange {
+                    controller: ..CHANNEL_MODE,
+                    ..
+                }
+                | MidiMessage::ProgramChange { .. } => {
+                    let _ = self.control_producer.enqueue(message);
+                }
+                _ => {
+                    let _ = self.midi_producer.enqueue(message);
+                }
+            }
+        }
+
+        /// Hands preset requests and dumps over to `input`, other SysEx
+        /// messages are ignored
+        fn push_sysex(&mut self, data: &[u8]) {
+            match SysExMessage::parse(data) {
+                Ok(message) => {
+                    let _ = self.sysex_producer.enqueue(message);
+                }
+                Err(SysExError::NotForUs) => {}
+                Err(error) => defmt::println!("SysEx: {}", defmt::Debug2Format(&error)),
+            }
+        }
+    }
+
+    #[cfg(feature = "usb-midi")]
+    struct UsbMidi {
+        device: UsbDevice<'static, UsbBus<USB2>>,
+        class: UsbMidiClass<'static, UsbBus<USB2>>,
+        parser: UsbMidiParser,
+    }
+
+    #[cfg(feature = "usb-midi")]
+    impl UsbMidi {
+        fn new(usb: USB2) -> Self {
+            let endpoint_memory = cortex_m::singleton!(: [u32; 1024] = [0; 1024]).unwrap();
+            let bus = cortex_m::singleton!(
+                : UsbBusAllocator<UsbBus<USB2>> = UsbBus::new(usb, endpoint_memory)
+            )
+            .unwrap();
+            let class = UsbMidiClass::new(bus);
+            // The test PID of pid.codes, for development only
+            let device = UsbDeviceBuilder::new(bus, UsbVidPid(0x1209, 0x0001))
+                .strings(&[StringDescriptors::default()
+                    .manufacturer("Daisy Kickstart")
+                    .product("Daisy Kickstart MIDI")])
+                .unwrap()
+                .build();
+            Self {
+                device,
+                class,
+                parser: UsbMidiParser::new(0),
+            }
+        }
+
+        /// Handles the USB events and hands the received messages to
+        /// `midi_in`
+        fn poll(&mut self, midi_in: &mut MidiIn) {
+            if !self.device.poll(&mut [&mut self.class]) {
+                return;
+            }
+            let mut data = [0; MAX_PACKET_SIZE as usize];
+            while let Ok(length) = self.class.read(&mut data) {
+                // A SysEx message ends with its packet, its data is only
+                // there until the parser goes on
+                for packet in data[..length].chunks_exact(4) {
+                    let mut sysex = false;
+                    self.parser
+                        .parse(
+                            [packet[0], packet[1], packet[2], packet[3]],
+                            |result| match result {
+                                Ok(MidiMessage::SysEx { .. }) => sysex = true,
+                                Ok(message) => midi_in.push(message),
+                                Err(error) => {
+                                    defmt::println!("USB MIDI: {}", defmt::Debug2Format(&error))
+                                }
+                            },
+                        );
+                    if sysex {
+                        midi_in.push_sysex(self.parser.sysex());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Size of the MIDI output queue, about 80 ms worth of bytes
+    const MIDI_OUT_BYTES: usize = 256;
+
+    /// Queues the bytes for the MIDI output, so that sending never blocks.
+    /// The output interrupt takes the next byte whenever the last one is out.
+    struct MidiOut {
+        tx: Tx<USART1>,
+        encoder: MidiEncoder,
+        queue: Deque<u8, MIDI_OUT_BYTES>,
+    }
+
+    impl MidiOut {
+        fn new(tx: Tx<USART1>) -> Self {
+            Self {
+                tx,
+                encoder: MidiEncoder::new(true),
+                queue: Deque::new(),
+            }
+        }
+
+        /// Drops the message if the queue is full
+        fn send(&mut self, message: MidiMessage) {
+            if message.is_realtime() {
+                // Realtime bytes may go between the bytes of other messages,
+                // so the clock jumps the queue and keeps its timing
+                if let Some(&byte) = self.encoder.encode(message).first() {
+                    let _ = self.queue.push_front(byte);
+                }
+            } else if self.queue.capacity() - self.queue.len() >= 3 {
+                for byte in self.encoder.encode(message) {
+                    let _ = self.queue.push_back(byte);
+                }
+            }
+            self.flush();
+        }
+
+        /// Sends a whole system exclusive message, from 0xF0 to 0xF7.
+        /// Drops it if it doesn't fit into the queue.
+        fn send_sysex(&mut self, bytes: &[u8]) {
+            if self.queue.capacity() - self.queue.len() >= bytes.len() {
+                for byte in bytes {
+                    let _ = self.queue.push_back(*byte);
+                }
+                // SysEx ends the running status
+                self.encoder.reset();
+            }
+            self.flush();
+        }
+
+        /// Hands bytes to the UART while it takes them
+        fn flush(&mut self) {
+            while let Some(&byte) = self.queue.front() {
+                if self.tx.write(byte).is_err() {
+                    break;
+                }
+                self.queue.pop_front();
+            }
+            if self.queue.is_empty() {
+                self.tx.unlisten();
+            } else {
+                self.tx.listen();
+            }
+        }
+    }
+
+    // Pitch detection is too expensive for the audio interrupt, so it runs
+    // at the lowest priority and gets preempted by `dsp` and `input`.
+    #[task(local = [tuner, tuner_consumer], priority = 1)]
+    fn tuner(cx: tuner::Context) {
+        tuner::spawn_after(systick_monotonic::ExtU64::millis(20))
+            .ok()
+            .unwrap();
+
+        let tuner = cx.local.tuner;
+        let tuner_consumer = cx.local.tuner_consumer;
+
+        while let Some(block) = tuner_consumer.dequeue() {
+            tuner.push_samples(&block);
+        }
+
+        if let Some(pitch) = tuner.detect() {
+            defmt::println!(
+                "{}{} {} Hz {} cents (confidence {})",
+                pitch.note_name(),
+                pitch.octave(),
+                pitch.frequency,
+                pitch.cents,
+                pitch.confidence
+            );
+        }
+    }
+
+    /// Calls of the `input` task per second
+    const INPUT_RATE: f32 = 1000.0;
+
     #[task(
         local = [
             inputs,
+            controls,
+            modulation,
+            level_consumer,
             params_producer,
+            control_consumer,
+            recall_consumer,
+            sysex_consumer,
+            knob_ccs,
         ],
+        shared = [midi_out],
         priority = 2,
     )]
-    fn input(cx: input::Context) {
+    fn input(mut cx: input::Context) {
         input::spawn_after(systick_monotonic::ExtU64::millis(1))
             .ok()
             .unwrap();
 
         let inputs = cx.local.inputs;
+        let controls = cx.local.controls;
+        let modulation = cx.local.modulation;
+        let level_consumer = cx.local.level_consumer;
         let params_producer = cx.local.params_producer;
+        let control_consumer = cx.local.control_consumer;
+        let recall_consumer = cx.local.recall_consumer;
+        let sysex_consumer = cx.local.sysex_consumer;
+        let knob_ccs = cx.local.knob_ccs;
+
+        let knobs = inputs.knobs();
+        if controls.update_button(knobs, inputs.learn_pressed()) {
+            let _ = storage::spawn(StorageCommand::SavePreset(controls.slot, controls.preset));
+        }
+        controls.update_knobs(knobs);
+        for (knob_cc, knob) in knob_ccs.iter_mut().zip(knobs) {
+            if let Some(message) = knob_cc.update(knob) {
+                cx.shared.midi_out.lock(|midi_out| midi_out.send(message));
+            }
+        }
+        while let Some(message) = control_consumer.dequeue() {
+            match message {
+                MidiMessage::ProgramChange { program, .. } => {
+                    let _ = storage::spawn(StorageCommand::RecallPreset(usize::from(program)));
+                }
+                _ if modulation.handle_midi(message) => {}
+                _ => {
+                    if controls.handle_midi(message) {
+                        let mappings = Vec::from_slice(controls.midi_map.mappings()).unwrap();
+                        let _ = storage::spawn(StorageCommand::SaveMappings(mappings));
+                    }
+                }
+            }
+        }
+        while let Some(message) = sysex_consumer.dequeue() {
+            match message {
+                SysExMessage::Request(Slot::Current) => {
+                    let dump = SysExMessage::Dump(Slot::Current, controls.preset);
+                    cx.shared
+                        .midi_out
+                        .lock(|midi_out| send_sysex(midi_out, dump));
+                }
+                SysExMessage::Request(Slot::Stored(slot)) => {
+                    let _ = storage::spawn(StorageCommand::DumpPreset(slot));
+                }
+                SysExMessage::Dump(Slot::Current, preset) => {
+                    controls.recall(controls.slot, preset, controls.morph_target);
+                }
+                SysExMessage::Dump(Slot::Stored(slot), preset) => {
+                    let _ = storage::spawn(StorageCommand::SavePreset(slot, preset));
+                }
+            }
+        }
+        while let Some((slot, preset, target)) = recall_consumer.dequeue() {
+            controls.recall(slot, preset, target);
+        }
+        // the input level of the last block
+        let mut level = None;
+        while let Some(l) = level_consumer.dequeue() {
+            level = Some(l);
+        }
+        modulation.process(level);
+
+        let preset = controls
+            .morph
+            .apply(&controls.preset, &controls.morph_target);
+        let _ = params_producer.enqueue(Preset {
+            filter: modulation.matrix.modulate_filter(preset.filter),
+            ..preset
+        });
+    }
+
+    enum StorageCommand {
+        SavePreset(usize, Preset),
+        RecallPreset(usize),
+        /// Sends the preset as SysEx
+        DumpPreset(usize),
+        SaveMappings(Vec<Mapping, MAX_MAPPINGS>),
+    }
+
+    // Erasing a sector of the flash takes up to 300 ms, so settings are saved
+    // and read at the lowest priority. Recalled presets go back to `input`.
+    #[task(
+        local = [settings, recall_producer],
+        shared = [midi_out],
+        capacity = 4,
+        priority = 1,
+    )]
+    fn storage(mut cx: storage::Context, command: StorageCommand) {
+        let settings = cx.local.settings;
+        let recall_producer = cx.local.recall_producer;
+
+        match command {
+            StorageCommand::SavePreset(slot, preset) => {
+                if save_preset(settings, slot, &preset).is_some() {
+                    defmt::println!("Preset {}: saved", slot);
+                }
+            }
+            StorageCommand::RecallPreset(slot) => {
+                if let Some((preset, target)) = load_morph(settings, slot) {
+                    let _ = recall_producer.enqueue((slot, preset, target));
+                    defmt::println!("Preset {}: recalled", slot);
+                }
+            }
+            StorageCommand::DumpPreset(slot) => {
+                if let Some(preset) = load_preset(settings, slot) {
+                    let dump = SysExMessage::Dump(Slot::Stored(slot), preset);
+                    cx.shared
+                        .midi_out
+                        .lock(|midi_out| send_sysex(midi_out, dump));
+                }
+            }
+            StorageCommand::SaveMappings(mappings) => save_mappings(settings, &mappings),
+        }
+    }
 
-        let _ = params_producer.enqueue(inputs.filter_params());
+    fn send_sysex(midi_out: &mut MidiOut, message: SysExMessage) {
+        match message.encode() {
+            Ok(bytes) => midi_out.send_sysex(&bytes),
+            Err(error) => defmt::println!("SysEx: {}", defmt::Debug2Format(&error)),
+        }
+    }
+
+    /// Reads a preset, `None` if the slot is empty or broken
+    fn load_preset(settings: &mut SettingsStore<QspiFlash>, slot: usize) -> Option<Preset> {
+        let key = slot_key(slot)
+            .inspect_err(|error| preset_error(slot, error))
+            .ok()?;
+        let mut bytes = [0; MAX_VALUE];
+        let length = settings
+            .get(key, &mut bytes)
+            .inspect_err(|error| preset_error(slot, error))
+            .ok()??;
+        Preset::from_bytes(&bytes[..length])
+            .inspect_err(|error| preset_error(slot, error))
+            .ok()
+    }
+
+    /// Reads a preset and the one in the next slot to morph into, the same
+    /// one again if that is empty
+    fn load_morph(
+        settings: &mut SettingsStore<QspiFlash>,
+        slot: usize,
+    ) -> Option<(Preset, Preset)> {
+        let preset = load_preset(settings, slot)?;
+        let target = load_preset(settings, (slot + 1) % MAX_PRESETS).unwrap_or(preset);
+        Some((preset, target))
+    }
+
+    fn save_preset(
+        settings: &mut SettingsStore<QspiFlash>,
+        slot: usize,
+        preset: &Preset,
+    ) -> Option<()> {
+        let key = slot_key(slot)
+            .inspect_err(|error| preset_error(slot, error))
+            .ok()?;
+        settings
+            .set(key, &preset.to_bytes())
+            .inspect_err(|error| preset_error(slot, error))
+            .ok()
+    }
+
+    fn preset_error(slot: usize, error: &impl core::fmt::Debug) {
+        defmt::println!("Preset {}: {}", slot, defmt::Debug2Format(error));
+    }
+
+    /// Drops mappings that don't make sense to this firmware
+    fn load_mappings(settings: &mut SettingsStore<QspiFlash>) -> Vec<Mapping, MAX_MAPPINGS> {
+        let mut bytes = [0; MAX_VALUE];
+        let length = match settings.get(MAPPINGS_KEY, &mut bytes) {
+            Ok(length) => length.unwrap_or(0),
+            Err(error) => {
+                defmt::println!("MIDI mappings: {}", defmt::Debug2Format(&error));
+                0
+            }
+        };
+        bytes[..length]
+            .chunks_exact(Mapping::SIZE)
+            .filter_map(|chunk| Mapping::from_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]).ok())
+            .take(MAX_MAPPINGS)
+            .collect()
+    }
+
+    fn save_mappings(settings: &mut SettingsStore<QspiFlash>, mappings: &[Mapping]) {
+        let mut bytes = [0; MAX_MAPPINGS * Mapping::SIZE];
+        for (chunk, mapping) in bytes.chunks_exact_mut(Mapping::SIZE).zip(mappings) {
+            chunk.copy_from_slice(&mapping.to_bytes());
+        }
+        match settings.set(MAPPINGS_KEY, &bytes[..mappings.len() * Mapping::SIZE]) {
+            Ok(()) => defmt::println!("MIDI mappings: saved"),
+            Err(error) => defmt::println!("MIDI mappings: {}", defmt::Debug2Format(&error)),
+        }
+    }
+
+    /// Parameters of the knobs
+    const KNOB_PARAMETERS: [Destination; 2] =
+        [Destination::FilterFrequency, Destination::FilterQuality];
+    /// Morph from the recalled preset to the one in the next slot, on the
+    /// mod wheel until other mappings are saved
+    const MORPH: Destination = Destination::Custom(0);
+    const MORPH_MAPPING: Mapping = Mapping {
+        channel: 0,
+        controller: Controller::Cc(1),
+        destination: MORPH,
+    };
+    /// Distance a knob has to move to pick its parameter for MIDI learn
+    const LEARN_DISTANCE: f32 = 0.1;
+    /// Seconds to hold the learn button without moving a knob to save the
+    /// preset
+    const SAVE_HOLD: f32 = 2.0;
+
+    // Controllers of the modulation routes on any channel, with the depth
+    // centered at 64, and of the LFO rates
+    const MODULATION_ROUTES: [(u8, Source, Destination); 3] = [
+        (112, Source::Lfo(0), Destination::FilterFrequency),
+        (113, Source::Lfo(1), Destination::FilterQuality),
+        (114, Source::Follower(0), Destination::FilterFrequency),
+    ];
+    const LFO_RATES: [u8; 2] = [115, 116];
+    const LFO_RATE: Range = Range::exponential(0.05, 20.0);
+
+    /// LFOs and the input level modulating the filter, all routes start at
+    /// zero depth
+    struct Modulation {
+        matrix: ModulationMatrix,
+        lfos: [Lfo; 2],
+    }
+
+    impl Modulation {
+        fn new() -> Self {
+            let mut matrix = ModulationMatrix::new(INPUT_RATE);
+            for (_, source, destination) in MODULATION_ROUTES {
+                matrix
+                    .set_route(Route {
+                        source,
+                        destination,
+                        depth: 0.0,
+                    })
+                    .unwrap();
+            }
+            let mut lfos = [
+                Lfo::new(INPUT_RATE, 0x1F0_0001),
+                Lfo::new(INPUT_RATE, 0x1F0_0002),
+            ];
+            for (lfo, waveform) in lfos
+                .iter_mut()
+                .zip([LfoWaveform::Sine, LfoWaveform::SmoothRandom])
+            {
+                lfo.set_params(LfoParams {
+                    waveform,
+                    ..LfoParams::default()
+                })
+                .unwrap();
+            }
+            Self { matrix, lfos }
+        }
+
+        /// Sets a depth or a rate, returns false for other messages
+        fn handle_midi(&mut self, message: MidiMessage) -> bool {
+            let MidiMessage::ControlChange {
+                controller, value, ..
+            } = message
+            else {
+                return false;
+            };
+            let normalized = f32::from(value) / 127.0;
+            if let Some((_, source, destination)) = MODULATION_ROUTES
+                .into_iter()
+                .find(|(cc, ..)| *cc == controller)
+            {
+                let depth = ((f32::from(value) - 64.0) / 63.0).clamp(-1.0, 1.0);
+                self.matrix
+                    .set_route(Route {
+                        source,
+                        destination,
+                        depth,
+                    })
+                    .unwrap();
+            } else if let Some(index) = LFO_RATES.iter().position(|cc| *cc == controller) {
+                let lfo = &mut self.lfos[index];
+                let params = LfoParams {
+                    frequency: LFO_RATE.denormalize(normalized),
+                    ..*lfo.params()
+                };
+                lfo.set_params(params).unwrap();
+            } else {
+                return false;
+            }
+            true
+        }
+
+        /// Once per call of `input`, with the latest input level if any
+        fn process(&mut self, level: Option<f32>) {
+            for (index, lfo) in self.lfos.iter_mut().enumerate() {
+                let value = lfo.advance(1);
+                self.matrix
+                    .set_source(Source::Lfo(index as u8), value)
+                    .unwrap();
+            }
+            if let Some(level) = level {
+                self.matrix.set_source(Source::Follower(0), level).unwrap();
+            }
+            self.matrix.process();
+        }
+    }
+
+    /// Parameters set by the knobs, MIDI controllers and presets
+    struct Controls {
+        preset: Preset,
+        /// Slot of the last recalled preset, saving goes there
+        slot: usize,
+        /// The preset in the next slot, at the other end of the morph
+        morph_target: Preset,
+        morph: MorphControl,
+        midi_map: MidiMap,
+        takeovers: [SoftTakeover; 2],
+        /// Knob positions when the learn button was pressed
+        learn_knobs: Option<[f32; 2]>,
+        learn_pressed: bool,
+        /// Calls of `update_button` since the button was pressed
+        held: u32,
+    }
+
+    impl Controls {
+        fn new() -> Self {
+            Self {
+                preset: Preset::default(),
+                slot: 0,
+                morph_target: Preset::default(),
+                morph: MorphControl::new(),
+                midi_map: MidiMap::new(),
+                takeovers: Default::default(),
+                learn_knobs: None,
+                learn_pressed: false,
+                held: 0,
+            }
+        }
+
+        /// For MIDI learn press the button, then move the knob of the
+        /// parameter and then the controller. Holding the button without
+        /// moving a knob saves the preset instead, returns true then.
+        fn update_button(&mut self, knobs: [f32; 2], learn_pressed: bool) -> bool {
+            if learn_pressed && !self.learn_pressed {
+                self.learn_knobs = Some(knobs);
+                self.held = 0;
+                self.midi_map.cancel_learn();
+                defmt::println!("MIDI learn: move a knob");
+            }
+            self.learn_pressed = learn_pressed;
+
+            if learn_pressed && self.learn_knobs.is_some() {
+                self.held += 1;
+                if self.held as f32 >= SAVE_HOLD * INPUT_RATE {
+                    self.learn_knobs = None;
+                    return true;
+                }
+            }
+            false
+        }
+
+        fn update_knobs(&mut self, knobs: [f32; 2]) {
+            if let Some(learn_knobs) = self.learn_knobs {
+                // The knobs don't change anything while picking a parameter
+                let moved = (0..knobs.len())
+                    .find(|&knob| (knobs[knob] - learn_knobs[knob]).abs() > LEARN_DISTANCE);
+                if let Some(knob) = moved {
+                    self.learn_knobs = None;
+                    self.midi_map.learn(KNOB_PARAMETERS[knob]).unwrap();
+                    // Soft takeover brings it back to the value
+                    self.takeovers[knob].set_value(self.takeovers[knob].value());
+                    defmt::println!("MIDI learn: move a controller");
+                }
+                return;
+            }
+
+            for ((takeover, knob), destination) in
+                self.takeovers.iter_mut().zip(knobs).zip(KNOB_PARAMETERS)
+            {
+                if let Some(value) = takeover.update(knob) {
+                    set_parameter(&mut self.preset.filter, destination, value);
+                }
+            }
+        }
+
+        /// Returns true when MIDI learn added a mapping
+        fn handle_midi(&mut self, message: MidiMessage) -> bool {
+            let learning = self.midi_map.learning().is_some();
+            let Some((destination, value)) = self.midi_map.handle(message) else {
+                return false;
+            };
+            let learned = learning && self.midi_map.learning().is_none();
+            if learned {
+                defmt::println!("MIDI learn: done");
+            }
+            if destination == MORPH {
+                self.morph.set_amount(value);
+            } else {
+                set_parameter(&mut self.preset.filter, destination, value);
+            }
+            if let Some(knob) = KNOB_PARAMETERS.iter().position(|knob| *knob == destination) {
+                self.takeovers[knob].set_value(value);
+            }
+            learned
+        }
+
+        /// The knobs take over again once they reach the recalled values
+        fn recall(&mut self, slot: usize, preset: Preset, morph_target: Preset) {
+            self.slot = slot;
+            self.preset = preset;
+            self.morph_target = morph_target;
+            for (takeover, destination) in self.takeovers.iter_mut().zip(KNOB_PARAMETERS) {
+                takeover.set_value(parameter(&preset.filter, destination));
+            }
+        }
+    }
+
+    /// Normalized value of a parameter
+    fn parameter(params: &FilterParams, destination: Destination) -> f32 {
+        match destination {
+            Destination::FilterFrequency => Range::FILTER_FREQUENCY.normalize(params.frequency),
+            Destination::FilterQuality => Range::FILTER_QUALITY.normalize(params.quality),
+            Destination::FilterGain => Range::FILTER_GAIN.normalize(params.gain),
+            Destination::Custom(_) => 0.0,
+        }
+    }
+
+    fn set_parameter(params: &mut FilterParams, destination: Destination, value: f32) {
+        match destination {
+            Destination::FilterFrequency => {
+                params.frequency = Range::FILTER_FREQUENCY.denormalize(value)
+            }
+            Destination::FilterQuality => params.quality = Range::FILTER_QUALITY.denormalize(value),
+            Destination::FilterGain => params.gain = Range::FILTER_GAIN.denormalize(value),
+            Destination::Custom(_) => {}
+        }
     }
 
     struct Inputs {
         pub adc1: Adc<ADC1, Enabled>,
         pub pot1_pin: Pin<'C', 4, Analog>,
         pub pot2_pin: Pin<'C', 0, Analog>,
+        pub learn_pin: Pin<'B', 12, Input>,
     }
 
     impl Inputs {
-        fn filter_params(&mut self) -> FilterParams {
+        fn knobs(&mut self) -> [f32; 2] {
             let knob1_raw: u32 = self.adc1.read(&mut self.pot1_pin).unwrap();
-            // Normalize 16-bit ADC (0..65535) to 0.0..1.0
-            let knob1_norm = knob1_raw as f32 / 65_535.0;
-
-            const MIN_FREQ: f32 = 20.0;
-            const MAX_FREQ: f32 = 20_000.0;
-            let frequency = MIN_FREQ * libm::powf(MAX_FREQ / MIN_FREQ, knob1_norm);
-
             let knob2_raw: u32 = self.adc1.read(&mut self.pot2_pin).unwrap();
             // Normalize 16-bit ADC (0..65535) to 0.0..1.0
-            let knob2_norm = knob2_raw as f32 / 65_535.0;
-            const MIN_Q: f32 = 0.1;
-            const MAX_Q: f32 = 6.0;
-            let quality = MIN_Q + (MAX_Q - MIN_Q) * knob2_norm;
+            [knob1_raw as f32 / 65_535.0, knob2_raw as f32 / 65_535.0]
+        }
 
-            FilterParams {
-                frequency,
-                quality,
-                gain: 0.0,
-            }
+        /// The button pulls the pin to ground
+        fn learn_pressed(&self) -> bool {
+            self.learn_pin.is_low()
         }
     }
 
     struct System {
         pub mono: Systick<1000>,
         pub inputs: Inputs,
+        pub looper: Looper<'static>,
         pub audio_interface: Interface,
+        pub midi_rx: Rx<USART1>,
+        pub midi_tx: Tx<USART1>,
+        pub flash: QspiFlash,
+        #[cfg(feature = "usb-midi")]
+        pub usb_midi: UsbMidi,
     }
 
     impl System {
@@ -162,9 +1284,17 @@ mod app {
             cp.SCB.enable_dcache(&mut cp.CPUID);
 
             let board = daisy::Board::take().unwrap();
-            let ccdr = daisy::board_freeze_clocks!(board, dp);
+            #[allow(unused_mut)]
+            let mut ccdr = daisy::board_freeze_clocks!(board, dp);
+            // USB runs from the 48 MHz of the HSI48 oscillator
+            #[cfg(feature = "usb-midi")]
+            ccdr.peripheral.kernel_usb_clk_mux(UsbClkSel::Hsi48);
             let pins = daisy::board_split_gpios!(board, ccdr, dp);
-            // let sdram = daisy::board_split_sdram!(cp, dp, ccdr, pins);
+            let _sdram = daisy::board_split_sdram!(cp, dp, ccdr, pins);
+            // SAFETY: The SDRAM is initialized and this is the only call
+            let (loop_buffer, undo_buffer) = unsafe { crate::looper_memory() };
+            let looper =
+                Looper::new(loop_buffer, undo_buffer, daisy::audio::FS.to_Hz() as f32).unwrap();
             let audio_interface = daisy::board_split_audio!(ccdr, pins);
             // Start audio processing and put its abstraction into a global.
             let audio_interface = audio_interface.spawn().unwrap();
@@ -194,12 +1324,64 @@ mod app {
                 adc1,
                 pot1_pin: adc1_channel,
                 pot2_pin: adc2_channel,
+                learn_pin: pins.GPIO.PIN_0.into_pull_up_input(),
             };
 
+            // MIDI on USART1, in on D14 (PB7) through the usual optocoupler
+            // circuit, out on D13 (PB6)
+            let midi_tx = pins.GPIO.PIN_13.into_alternate::<7>();
+            let midi_rx = pins.GPIO.PIN_14.into_alternate::<7>();
+            let (midi_tx, mut midi_rx) = dp
+                .USART1
+                .serial(
+                    (midi_tx, midi_rx),
+                    31_250.bps(),
+                    ccdr.peripheral.USART1,
+                    &ccdr.clocks,
+                )
+                .unwrap()
+                .split();
+            midi_rx.listen();
+
+            // The presets live in the QSPI flash
+            let _flash_cs = pins.FLASH.CS.into_alternate::<10>();
+            let qspi = dp.QUADSPI.bank1(
+                (
+                    pins.FLASH.SCK.into_alternate(),
+                    pins.FLASH.IO0.into_alternate(),
+                    pins.FLASH.IO1.into_alternate(),
+                    pins.FLASH.IO2.into_alternate(),
+                    pins.FLASH.IO3.into_alternate(),
+                ),
+                25.MHz(),
+                &ccdr.clocks,
+                ccdr.peripheral.QSPI,
+            );
+            let flash = QspiFlash::new(qspi).unwrap();
+
+            // USB MIDI on the micro USB connector, the OTG2 peripheral with
+            // its internal full speed PHY on PA11 and PA12
+            #[cfg(feature = "usb-midi")]
+            let usb_midi = UsbMidi::new(USB2::new(
+                dp.OTG2_HS_GLOBAL,
+                dp.OTG2_HS_DEVICE,
+                dp.OTG2_HS_PWRCLK,
+                pins.USB2.DN.into_alternate(),
+                pins.USB2.DP.into_alternate(),
+                ccdr.peripheral.USB2OTG,
+                &ccdr.clocks,
+            ));
+
             Self {
                 mono,
                 inputs,
+                looper,
                 audio_interface,
+                midi_rx,
+                midi_tx,
+                flash,
+                #[cfg(feature = "usb-midi")]
+                usb_midi,
             }
         }
     }
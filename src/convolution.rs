@@ -0,0 +1,164 @@
+// Convolution
+//
+// Uniformly partitioned convolution (overlap-save) for cabinet impulse
+// responses and short reverbs. The impulse response is split into partitions
+// of one block each, whose spectra are multiplied with a frequency domain
+// delay line of past input spectra. Every call to `process` produces the
+// output of the block that was passed in, so there is no latency on top of
+// the block based audio processing itself.
+//
+// Impulse responses up to `DIRECT_FORM_MAX_TAPS` are convolved directly in
+// the time domain, which is cheaper than two FFTs for such short filters.
+use crate::fft::RealFft;
+
+/// Longest impulse response that is convolved in the time domain
+pub const DIRECT_FORM_MAX_TAPS: usize = 16;
+
+#[derive(Debug)]
+pub enum ConvolutionError {
+    SizeNotPowerOfTwo,
+    ImpulseResponseEmpty,
+    ImpulseResponseTooLong,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Mode {
+    Silent,
+    Direct,
+    Partitioned,
+}
+
+/// Convolution engine with an FFT size of `N` (processing blocks of `N / 2`
+/// samples) for impulse responses of up to `P` blocks.
+///
+/// Instances are large, `2 * N * P` floats, so better keep them in a static.
+pub struct Convolver<const N: usize, const P: usize> {
+    fft: RealFft<N>,
+    mode: Mode,
+    taps: usize,
+    partitions: usize,
+    spectra: [[f32; N]; P],
+    history: [[f32; N]; P],
+    head: usize,
+    frame: [f32; N],
+    accumulator: [f32; N],
+}
+
+impl<const N: usize, const P: usize> Convolver<N, P> {
+    pub const BLOCK_LENGTH: usize = N / 2;
+
+    pub fn new() -> Result<Self, ConvolutionError> {
+        let fft = RealFft::new().map_err(|_| ConvolutionError::SizeNotPowerOfTwo)?;
+        Ok(Self {
+            fft,
+            mode: Mode::Silent,
+            taps: 0,
+            partitions: 0,
+            spectra: [[0.0; N]; P],
+            history: [[0.0; N]; P],
+            head: 0,
+            frame: [0.0; N],
+            accumulator: [0.0; N],
+        })
+    }
+
+    /// Longest supported impulse response in samples
+    pub const fn max_length() -> usize {
+        P * N / 2
+    }
+
+    /// Loads a new impulse response and clears the signal history.
+    ///
+    /// This transforms every partition, so don't call it from the audio
+    /// interrupt for long impulse responses.
+    pub fn set_impulse_response(
+        &mut self,
+        impulse_response: &[f32],
+    ) -> Result<(), ConvolutionError> {
+        if impulse_response.is_empty() {
+            return Err(ConvolutionError::ImpulseResponseEmpty);
+        }
+        if impulse_response.len() > Self::max_length() {
+            return Err(ConvolutionError::ImpulseResponseTooLong);
+        }
+
+        let block_length = Self::BLOCK_LENGTH;
+        self.taps = impulse_response.len();
+        if self.taps <= DIRECT_FORM_MAX_TAPS.min(block_length) {
+            // The first spectrum slot doubles as tap storage
+            self.spectra[0][..self.taps].copy_from_slice(impulse_response);
+            self.mode = Mode::Direct;
+        } else {
+            self.partitions = self.taps.div_ceil(block_length);
+            for (spectrum, partition) in self
+                .spectra
+                .iter_mut()
+                .zip(impulse_response.chunks(block_length))
+            {
+                spectrum.fill(0.0);
+                spectrum[..partition.len()].copy_from_slice(partition);
+                self.fft.forward(spectrum);
+            }
+            self.mode = Mode::Partitioned;
+        }
+
+        self.reset();
+        Ok(())
+    }
+
+    /// Convolves one block of `N / 2` samples in place
+    ///
+    /// # Panics
+    /// Panics if the block doesn't have `N / 2` samples
+    pub fn process(&mut self, block: &mut [f32]) {
+        let block_length = Self::BLOCK_LENGTH;
+        assert_eq!(block.len(), block_length);
+
+        // Slide the input frame by one block
+        self.frame.copy_within(block_length.., 0);
+        self.frame[block_length..].copy_from_slice(block);
+
+        match self.mode {
+            Mode::Silent => block.fill(0.0),
+            Mode::Direct => {
+                let taps = &self.spectra[0][..self.taps];
+                for (i, output) in block.iter_mut().enumerate() {
+                    let newest = block_length + i;
+                    *output = taps
+                        .iter()
+                        .enumerate()
+                        .map(|(j, tap)| tap * self.frame[newest - j])
+                        .sum();
+                }
+            }
+            Mode::Partitioned => {
+                self.head = (self.head + self.partitions - 1) % self.partitions;
+                let newest = &mut self.history[self.head];
+                newest.copy_from_slice(&self.frame);
+                self.fft.forward(newest);
+
+                self.accumulator.fill(0.0);
+                for partition in 0..self.partitions {
+                    let input = &self.history[(self.head + partition) % self.partitions];
+                    RealFft::multiply_accumulate(
+                        &mut self.accumulator,
+                        input,
+                        &self.spectra[partition],
+                    );
+                }
+                self.fft.inverse(&mut self.accumulator);
+
+                // The first half is circularly aliased, the second is valid
+                block.copy_from_slice(&self.accumulator[block_length..]);
+            }
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.frame.fill(0.0);
+        for spectrum in self.history.iter_mut() {
+            spectrum.fill(0.0);
+        }
+        self.head = 0;
+    }
+}
@@ -0,0 +1,22 @@
+// CRC
+//
+// CRC-32 as used by zip and Ethernet (reflected, polynomial 0xEDB88320), to
+// tell data read back from flash from garbage. Computed bit by bit, the
+// amounts of data are small and a table would cost 1 KB.
+
+/// CRC-32 of `bytes`
+pub fn crc32(bytes: &[u8]) -> u32 {
+    !crc32_update(!0, bytes)
+}
+
+/// Continues a CRC over more bytes, starting from `!0` and inverted at the
+/// end, for data that isn't in one piece
+pub fn crc32_update(mut crc: u32, bytes: &[u8]) -> u32 {
+    for byte in bytes {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    crc
+}
@@ -0,0 +1,636 @@
+// Drums
+//
+// Analog style drum voices after the classic drum machines. The kick is a
+// sine whose pitch falls quickly from a multiple of its frequency, the snare
+// mixes a sine with noise through a bandpass filter, the hi-hat sums six
+// square waves at inharmonic frequencies into a metallic cluster and the clap
+// fires a few short noise bursts before its tail. All of them are one shot:
+// a trigger starts the envelopes, optionally with an accent that makes the
+// hit louder, and the voice goes idle once it has decayed.
+use crate::envelope::{Envelope, EnvelopeError, EnvelopeParams, Segment};
+use crate::filter::{Filter, FilterError, FilterParams, FilterType};
+use crate::oscillator::{Oscillator, OscillatorError, OscillatorParams, Waveform};
+use crate::random::Random;
+
+/// Most bursts of the clap before the tail
+pub const MAX_BURSTS: usize = 3;
+
+/// Highest frequency relative to the sample rate, keeps clear of Nyquist
+const MAX_FREQUENCY: f32 = 0.45;
+/// Shape of the decays, close to an exponential that reaches zero
+const DECAY_CURVE: f32 = 0.8;
+/// Level the clap bursts decay to before the next one
+const BURST_FLOOR: f32 = 0.1;
+/// Frequencies of the hi-hat oscillators in Hz, as in the TR-808
+const HIHAT_FREQUENCIES: [f32; 6] = [205.3, 304.4, 369.6, 522.7, 540.0, 800.0];
+
+#[derive(Debug)]
+pub enum DrumError {
+    FrequencyNegative,
+    QualityOutOfRange,
+    DecayNegative,
+    DriveOutOfRange,
+    MixOutOfRange,
+    BurstsOutOfRange,
+    Envelope(EnvelopeError),
+    Filter(FilterError),
+    Oscillator(OscillatorError),
+}
+
+impl From<EnvelopeError> for DrumError {
+    fn from(error: EnvelopeError) -> Self {
+        DrumError::Envelope(error)
+    }
+}
+
+impl From<FilterError> for DrumError {
+    fn from(error: FilterError) -> Self {
+        DrumError::Filter(error)
+    }
+}
+
+impl From<OscillatorError> for DrumError {
+    fn from(error: OscillatorError) -> Self {
+        DrumError::Oscillator(error)
+    }
+}
+
+/// Instant attack followed by a decay to zero
+fn decay(decay_ms: f32) -> Result<EnvelopeParams, DrumError> {
+    if decay_ms.is_sign_negative() {
+        return Err(DrumError::DecayNegative);
+    }
+    Ok(EnvelopeParams::new(
+        &[
+            Segment::new(1.0, 0.0, 0.0),
+            Segment::new(0.0, decay_ms, DECAY_CURVE),
+        ],
+        None,
+        None,
+    )?)
+}
+
+fn check_frequency(frequency: f32) -> Result<(), DrumError> {
+    if frequency.is_sign_negative() {
+        return Err(DrumError::FrequencyNegative);
+    }
+    Ok(())
+}
+
+/// Clamps a frequency below Nyquist, e.g. for low sample rates
+fn limit(frequency: f32, sample_rate: f32) -> f32 {
+    frequency.min(MAX_FREQUENCY * sample_rate)
+}
+
+fn accent_gain(level: f32, accent: f32, accented: bool) -> f32 {
+    if accented {
+        level * (1.0 + accent)
+    } else {
+        level
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub struct KickParams {
+    /// Frequency at the end of the pitch sweep
+    pub frequency: f32,
+    /// Start of the pitch sweep in octaves above `frequency`
+    pub pitch_depth: f32,
+    pub pitch_decay_ms: f32,
+    pub decay_ms: f32,
+    /// Saturation (0.0..=1.0)
+    pub drive: f32,
+    pub level: f32,
+    /// Extra level of accented hits, 1.0 doubles it
+    pub accent: f32,
+}
+
+impl Default for KickParams {
+    fn default() -> Self {
+        Self {
+            frequency: 50.0,
+            pitch_depth: 2.0,
+            pitch_decay_ms: 40.0,
+            decay_ms: 500.0,
+            drive: 0.2,
+            level: 0.5,
+            accent: 0.5,
+        }
+    }
+}
+
+pub struct Kick {
+    params: KickParams,
+    sample_rate: f32,
+    oscillator: Oscillator,
+    pitch_envelope: Envelope,
+    amp_envelope: Envelope,
+    gain: f32,
+}
+
+impl Kick {
+    pub fn new(sample_rate: f32) -> Self {
+        let mut kick = Self {
+            params: KickParams::default(),
+            sample_rate,
+            oscillator: Oscillator::new(),
+            pitch_envelope: Envelope::new(sample_rate),
+            amp_envelope: Envelope::new(sample_rate),
+            gain: 0.0,
+        };
+        kick.update().expect("Those settings always work");
+        kick
+    }
+
+    pub fn set_params(&mut self, params: KickParams) -> Result<(), DrumError> {
+        if self.params != params {
+            let previous = self.params;
+            self.params = params;
+            if let Err(error) = self.update() {
+                self.params = previous;
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn params(&self) -> &KickParams {
+        &self.params
+    }
+
+    pub fn trigger(&mut self, accent: bool) {
+        self.gain = accent_gain(self.params.level, self.params.accent, accent);
+        self.oscillator.reset();
+        self.pitch_envelope.gate_on(false);
+        self.amp_envelope.gate_on(false);
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.amp_envelope.is_active()
+    }
+
+    #[inline]
+    pub fn tick(&mut self) -> f32 {
+        // The pitch envelope sweeps the frequency through the FM input
+        let sample = self.oscillator.tick_fm(self.pitch_envelope.tick()) * self.amp_envelope.tick();
+        let drive = 4.0 * self.params.drive;
+        sample * (1.0 + drive) / (1.0 + drive * sample.abs()) * self.gain
+    }
+
+    /// Adds the kick to both channels of the buffer
+    pub fn process(&mut self, audio_buffer: &mut [(f32, f32)]) {
+        if !self.is_active() {
+            return;
+        }
+        for (left, right) in audio_buffer.iter_mut() {
+            let sample = self.tick();
+            *left += sample;
+            *right += sample;
+        }
+    }
+
+    fn update(&mut self) -> Result<(), DrumError> {
+        let params = self.params;
+        check_frequency(params.frequency)?;
+        if !(0.0..=1.0).contains(&params.drive) {
+            return Err(DrumError::DriveOutOfRange);
+        }
+        // The sweep must stay below Nyquist as well
+        let ratio = libm::exp2f(params.pitch_depth.max(0.0));
+        let frequency = limit(params.frequency * ratio, self.sample_rate) / ratio;
+        self.pitch_envelope
+            .set_params(decay(params.pitch_decay_ms)?)?;
+        self.amp_envelope.set_params(decay(params.decay_ms)?)?;
+        self.oscillator.set_sample_rate(self.sample_rate)?;
+        self.oscillator.set_params(OscillatorParams {
+            waveform: Waveform::Sine,
+            frequency,
+            fm_index: ratio - 1.0,
+            ..Default::default()
+        })?;
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub struct SnareParams {
+    /// Frequency of the drum head
+    pub frequency: f32,
+    pub tone_decay_ms: f32,
+    /// Center of the noise band, the snares
+    pub noise_frequency: f32,
+    pub noise_quality: f32,
+    pub noise_decay_ms: f32,
+    /// Balance between the head and the snares (0.0..=1.0)
+    pub snappy: f32,
+    pub level: f32,
+    /// Extra level of accented hits, 1.0 doubles it
+    pub accent: f32,
+}
+
+impl Default for SnareParams {
+    fn default() -> Self {
+        Self {
+            frequency: 180.0,
+            tone_decay_ms: 120.0,
+            noise_frequency: 3_000.0,
+            noise_quality: 0.8,
+            noise_decay_ms: 200.0,
+            snappy: 0.6,
+            level: 0.4,
+            accent: 0.5,
+        }
+    }
+}
+
+pub struct Snare {
+    params: SnareParams,
+    sample_rate: f32,
+    oscillator: Oscillator,
+    random: Random,
+    filter: Filter,
+    tone_envelope: Envelope,
+    noise_envelope: Envelope,
+    gain: f32,
+}
+
+impl Snare {
+    pub fn new(sample_rate: f32) -> Self {
+        let mut snare = Self {
+            params: SnareParams::default(),
+            sample_rate,
+            oscillator: Oscillator::new(),
+            random: Random::new(0xD8_0001),
+            filter: Filter::new(FilterType::Bandpass),
+            tone_envelope: Envelope::new(sample_rate),
+            noise_envelope: Envelope::new(sample_rate),
+            gain: 0.0,
+        };
+        snare.update().expect("Those settings always work");
+        snare
+    }
+
+    pub fn set_params(&mut self, params: SnareParams) -> Result<(), DrumError> {
+        if self.params != params {
+            let previous = self.params;
+            self.params = params;
+            if let Err(error) = self.update() {
+                self.params = previous;
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn params(&self) -> &SnareParams {
+        &self.params
+    }
+
+    pub fn trigger(&mut self, accent: bool) {
+        self.gain = accent_gain(self.params.level, self.params.accent, accent);
+        self.oscillator.reset();
+        self.tone_envelope.gate_on(false);
+        self.noise_envelope.gate_on(false);
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.tone_envelope.is_active() || self.noise_envelope.is_active()
+    }
+
+    #[inline]
+    pub fn tick(&mut self) -> f32 {
+        let tone = self.oscillator.tick() * self.tone_envelope.tick();
+        let noise = self.filter.tick(self.random.bipolar()) * self.noise_envelope.tick();
+        let snappy = self.params.snappy;
+        (tone * (1.0 - snappy) + noise * snappy) * self.gain
+    }
+
+    /// Adds the snare to both channels of the buffer
+    pub fn process(&mut self, audio_buffer: &mut [(f32, f32)]) {
+        if !self.is_active() {
+            return;
+        }
+        for (left, right) in audio_buffer.iter_mut() {
+            let sample = self.tick();
+            *left += sample;
+            *right += sample;
+        }
+    }
+
+    fn update(&mut self) -> Result<(), DrumError> {
+        let params = self.params;
+        check_frequency(params.frequency)?;
+        check_frequency(params.noise_frequency)?;
+        if params.noise_quality <= 0.0 {
+            return Err(DrumError::QualityOutOfRange);
+        }
+        if !(0.0..=1.0).contains(&params.snappy) {
+            return Err(DrumError::MixOutOfRange);
+        }
+        self.tone_envelope
+            .set_params(decay(params.tone_decay_ms)?)?;
+        self.noise_envelope
+            .set_params(decay(params.noise_decay_ms)?)?;
+        self.oscillator.set_sample_rate(self.sample_rate)?;
+        self.oscillator.set_params(OscillatorParams {
+            waveform: Waveform::Sine,
+            frequency: limit(params.frequency, self.sample_rate),
+            ..Default::default()
+        })?;
+        self.filter.set_sample_rate(self.sample_rate)?;
+        self.filter.set_params(FilterParams {
+            frequency: limit(params.noise_frequency, self.sample_rate),
+            quality: params.noise_quality,
+            gain: 0.0,
+        })?;
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub struct HiHatParams {
+    /// Scales the frequencies of the oscillators, 1.0 is the original tuning
+    pub tone: f32,
+    /// Center of the band that is kept of the cluster
+    pub frequency: f32,
+    /// Short for a closed hi-hat, long for an open one
+    pub decay_ms: f32,
+    pub level: f32,
+    /// Extra level of accented hits, 1.0 doubles it
+    pub accent: f32,
+}
+
+impl Default for HiHatParams {
+    fn default() -> Self {
+        Self {
+            tone: 1.0,
+            frequency: 9_000.0,
+            decay_ms: 60.0,
+            level: 0.8,
+            accent: 0.5,
+        }
+    }
+}
+
+pub struct HiHat {
+    params: HiHatParams,
+    sample_rate: f32,
+    oscillators: [Oscillator; 6],
+    filter: Filter,
+    envelope: Envelope,
+    gain: f32,
+}
+
+impl HiHat {
+    pub fn new(sample_rate: f32) -> Self {
+        let mut hihat = Self {
+            params: HiHatParams::default(),
+            sample_rate,
+            oscillators: core::array::from_fn(|_| Oscillator::new()),
+            filter: Filter::new(FilterType::Bandpass),
+            envelope: Envelope::new(sample_rate),
+            gain: 0.0,
+        };
+        hihat.update().expect("Those settings always work");
+        hihat
+    }
+
+    pub fn set_params(&mut self, params: HiHatParams) -> Result<(), DrumError> {
+        if self.params != params {
+            let previous = self.params;
+            self.params = params;
+            if let Err(error) = self.update() {
+                self.params = previous;
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn params(&self) -> &HiHatParams {
+        &self.params
+    }
+
+    /// The oscillators run freely, like in the original circuit
+    pub fn trigger(&mut self, accent: bool) {
+        self.gain = accent_gain(self.params.level, self.params.accent, accent);
+        self.envelope.gate_on(false);
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.envelope.is_active()
+    }
+
+    #[inline]
+    pub fn tick(&mut self) -> f32 {
+        let cluster: f32 = self.oscillators.iter_mut().map(|o| o.tick()).sum();
+        self.filter.tick(cluster / 6.0) * self.envelope.tick() * self.gain
+    }
+
+    /// Adds the hi-hat to both channels of the buffer
+    pub fn process(&mut self, audio_buffer: &mut [(f32, f32)]) {
+        if !self.is_active() {
+            return;
+        }
+        for (left, right) in audio_buffer.iter_mut() {
+            let sample = self.tick();
+            *left += sample;
+            *right += sample;
+        }
+    }
+
+    fn update(&mut self) -> Result<(), DrumError> {
+        let params = self.params;
+        check_frequency(params.tone)?;
+        check_frequency(params.frequency)?;
+        self.envelope.set_params(decay(params.decay_ms)?)?;
+        for (oscillator, frequency) in self.oscillators.iter_mut().zip(HIHAT_FREQUENCIES) {
+            oscillator.set_sample_rate(self.sample_rate)?;
+            oscillator.set_params(OscillatorParams {
+                waveform: Waveform::Square,
+                frequency: limit(frequency * params.tone, self.sample_rate),
+                ..Default::default()
+            })?;
+        }
+        self.filter.set_sample_rate(self.sample_rate)?;
+        self.filter.set_params(FilterParams {
+            frequency: limit(params.frequency, self.sample_rate),
+            quality: 1.0,
+            gain: 0.0,
+        })?;
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub struct ClapParams {
+    /// Center of the noise band
+    pub frequency: f32,
+    pub quality: f32,
+    /// Short bursts before the tail (1..=MAX_BURSTS)
+    pub bursts: usize,
+    /// Time between the bursts
+    pub spacing_ms: f32,
+    pub decay_ms: f32,
+    pub level: f32,
+    /// Extra level of accented hits, 1.0 doubles it
+    pub accent: f32,
+}
+
+impl Default for ClapParams {
+    fn default() -> Self {
+        Self {
+            frequency: 1_200.0,
+            quality: 1.5,
+            bursts: 3,
+            spacing_ms: 10.0,
+            decay_ms: 250.0,
+            level: 0.4,
+            accent: 0.5,
+        }
+    }
+}
+
+pub struct Clap {
+    params: ClapParams,
+    sample_rate: f32,
+    random: Random,
+    filter: Filter,
+    envelope: Envelope,
+    gain: f32,
+}
+
+impl Clap {
+    pub fn new(sample_rate: f32) -> Self {
+        let mut clap = Self {
+            params: ClapParams::default(),
+            sample_rate,
+            random: Random::new(0xD8_0002),
+            filter: Filter::new(FilterType::Bandpass),
+            envelope: Envelope::new(sample_rate),
+            gain: 0.0,
+        };
+        clap.update().expect("Those settings always work");
+        clap
+    }
+
+    pub fn set_params(&mut self, params: ClapParams) -> Result<(), DrumError> {
+        if self.params != params {
+            let previous = self.params;
+            self.params = params;
+            if let Err(error) = self.update() {
+                self.params = previous;
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn params(&self) -> &ClapParams {
+        &self.params
+    }
+
+    pub fn trigger(&mut self, accent: bool) {
+        self.gain = accent_gain(self.params.level, self.params.accent, accent);
+        self.envelope.gate_on(false);
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.envelope.is_active()
+    }
+
+    #[inline]
+    pub fn tick(&mut self) -> f32 {
+        self.filter.tick(self.random.bipolar()) * self.envelope.tick() * self.gain
+    }
+
+    /// Adds the clap to both channels of the buffer
+    pub fn process(&mut self, audio_buffer: &mut [(f32, f32)]) {
+        if !self.is_active() {
+            return;
+        }
+        for (left, right) in audio_buffer.iter_mut() {
+            let sample = self.tick();
+            *left += sample;
+            *right += sample;
+        }
+    }
+
+    fn update(&mut self) -> Result<(), DrumError> {
+        let params = self.params;
+        check_frequency(params.frequency)?;
+        if params.quality <= 0.0 {
+            return Err(DrumError::QualityOutOfRange);
+        }
+        if !(1..=MAX_BURSTS).contains(&params.bursts) {
+            return Err(DrumError::BurstsOutOfRange);
+        }
+        if params.spacing_ms.is_sign_negative() || params.decay_ms.is_sign_negative() {
+            return Err(DrumError::DecayNegative);
+        }
+
+        // Every burst and the tail jump up and decay, one segment each
+        let mut segments = [Segment::default(); 2 * (MAX_BURSTS + 1)];
+        for burst in segments[..2 * params.bursts].chunks_mut(2) {
+            burst[0] = Segment::new(1.0, 0.0, 0.0);
+            burst[1] = Segment::new(BURST_FLOOR, params.spacing_ms, DECAY_CURVE);
+        }
+        let tail = 2 * params.bursts;
+        segments[tail] = Segment::new(1.0, 0.0, 0.0);
+        segments[tail + 1] = Segment::new(0.0, params.decay_ms, DECAY_CURVE);
+        self.envelope
+            .set_params(EnvelopeParams::new(&segments[..tail + 2], None, None)?)?;
+
+        self.filter.set_sample_rate(self.sample_rate)?;
+        self.filter.set_params(FilterParams {
+            frequency: limit(params.frequency, self.sample_rate),
+            quality: params.quality,
+            gain: 0.0,
+        })?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Drum {
+    Kick,
+    Snare,
+    HiHat,
+    Clap,
+}
+
+/// All four voices, triggered by name and mixed together
+pub struct DrumKit {
+    pub kick: Kick,
+    pub snare: Snare,
+    pub hihat: HiHat,
+    pub clap: Clap,
+}
+
+impl DrumKit {
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            kick: Kick::new(sample_rate),
+            snare: Snare::new(sample_rate),
+            hihat: HiHat::new(sample_rate),
+            clap: Clap::new(sample_rate),
+        }
+    }
+
+    pub fn trigger(&mut self, drum: Drum, accent: bool) {
+        match drum {
+            Drum::Kick => self.kick.trigger(accent),
+            Drum::Snare => self.snare.trigger(accent),
+            Drum::HiHat => self.hihat.trigger(accent),
+            Drum::Clap => self.clap.trigger(accent),
+        }
+    }
+
+    /// Adds all voices to both channels of the buffer
+    pub fn process(&mut self, audio_buffer: &mut [(f32, f32)]) {
+        self.kick.process(audio_buffer);
+        self.snare.process(audio_buffer);
+        self.hihat.process(audio_buffer);
+        self.clap.process(audio_buffer);
+    }
+}
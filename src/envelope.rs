@@ -0,0 +1,373 @@
+// Envelope
+//
+// Multi-segment envelope generator. Every segment moves from the current
+// level to its target level in a fixed number of samples, linearly or along
+// an exponential curve. The curve is computed recursively by scaling the
+// distance to a virtual target beyond the real one, chosen so that the real
+// target is hit exactly at the end of the segment. Scaling the distance
+// rather than the level keeps the rounding errors from piling up on curves
+// that move away from the virtual target.
+//
+// While the gate is on, the envelope holds at the end of the sustain
+// segment, or jumps back to the loop start from there. Releasing the gate
+// continues with the segment after the sustain segment, from wherever the
+// envelope currently is. ADSR, AR and AHDSR are just presets of segments.
+
+/// Maximum number of segments of an envelope
+pub const MAX_SEGMENTS: usize = 8;
+/// Curve that resembles the decay of an analog (RC) envelope
+pub const ANALOG_CURVE: f32 = 0.5;
+/// Curvature of the segment shape at a `curve` of ±1.0
+const MAX_CURVATURE: f32 = 10.0;
+
+#[derive(Debug, PartialEq)]
+pub enum EnvelopeError {
+    NoSegments,
+    TooManySegments,
+    TimeNegative,
+    CurveOutOfRange,
+    SustainOutOfRange,
+    LoopOutOfRange,
+}
+
+#[derive(Default, Copy, Clone, PartialEq)]
+pub struct Segment {
+    /// Level at the end of the segment
+    pub level: f32,
+    pub time_ms: f32,
+    /// Shape of the segment (-1.0..=1.0), 0.0 is linear, positive values
+    /// move fast first and slow down towards the end like an analog
+    /// envelope, negative values start slowly
+    pub curve: f32,
+}
+
+impl Segment {
+    pub const fn new(level: f32, time_ms: f32, curve: f32) -> Self {
+        Self {
+            level,
+            time_ms,
+            curve,
+        }
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum Retrigger {
+    /// Restart from the current level, which avoids clicks
+    #[default]
+    FromCurrent,
+    /// Restart from zero, for percussive sounds
+    FromZero,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub struct EnvelopeParams {
+    pub segments: [Segment; MAX_SEGMENTS],
+    /// Number of used segments
+    pub count: usize,
+    /// Segment at whose end the envelope holds while the gate is on, without
+    /// a sustain segment the envelope runs through (one shot)
+    pub sustain: Option<usize>,
+    /// Segment to jump back to from the end of the sustain segment while the
+    /// gate is on
+    pub loop_start: Option<usize>,
+    pub retrigger: Retrigger,
+}
+
+impl EnvelopeParams {
+    pub fn new(
+        segments: &[Segment],
+        sustain: Option<usize>,
+        loop_start: Option<usize>,
+    ) -> Result<Self, EnvelopeError> {
+        if segments.len() > MAX_SEGMENTS {
+            return Err(EnvelopeError::TooManySegments);
+        }
+        let mut params = Self {
+            segments: [Segment::default(); MAX_SEGMENTS],
+            count: segments.len(),
+            sustain,
+            loop_start,
+            retrigger: Retrigger::FromCurrent,
+        };
+        params.segments[..segments.len()].copy_from_slice(segments);
+        params.validate()?;
+        Ok(params)
+    }
+
+    pub fn adsr(
+        attack_ms: f32,
+        decay_ms: f32,
+        sustain: f32,
+        release_ms: f32,
+    ) -> Result<Self, EnvelopeError> {
+        Self::new(
+            &[
+                Segment::new(1.0, attack_ms, 0.0),
+                Segment::new(sustain, decay_ms, ANALOG_CURVE),
+                Segment::new(0.0, release_ms, ANALOG_CURVE),
+            ],
+            Some(1),
+            None,
+        )
+    }
+
+    pub fn ar(attack_ms: f32, release_ms: f32) -> Result<Self, EnvelopeError> {
+        Self::new(
+            &[
+                Segment::new(1.0, attack_ms, 0.0),
+                Segment::new(0.0, release_ms, ANALOG_CURVE),
+            ],
+            Some(0),
+            None,
+        )
+    }
+
+    /// ADSR that holds the peak for `hold_ms` before the decay
+    pub fn ahdsr(
+        attack_ms: f32,
+        hold_ms: f32,
+        decay_ms: f32,
+        sustain: f32,
+        release_ms: f32,
+    ) -> Result<Self, EnvelopeError> {
+        Self::new(
+            &[
+                Segment::new(1.0, attack_ms, 0.0),
+                Segment::new(1.0, hold_ms, 0.0),
+                Segment::new(sustain, decay_ms, ANALOG_CURVE),
+                Segment::new(0.0, release_ms, ANALOG_CURVE),
+            ],
+            Some(2),
+            None,
+        )
+    }
+
+    /// Checks the parameters as `Envelope::set_params` does
+    pub fn validate(&self) -> Result<(), EnvelopeError> {
+        if self.count == 0 {
+            return Err(EnvelopeError::NoSegments);
+        }
+        if self.count > MAX_SEGMENTS {
+            return Err(EnvelopeError::TooManySegments);
+        }
+        for segment in &self.segments[..self.count] {
+            if segment.time_ms.is_sign_negative() {
+                return Err(EnvelopeError::TimeNegative);
+            }
+            if !(-1.0..=1.0).contains(&segment.curve) {
+                return Err(EnvelopeError::CurveOutOfRange);
+            }
+        }
+        if self.sustain.is_some_and(|sustain| sustain >= self.count) {
+            return Err(EnvelopeError::SustainOutOfRange);
+        }
+        if let Some(loop_start) = self.loop_start {
+            match self.sustain {
+                Some(sustain) if loop_start <= sustain => {}
+                _ => return Err(EnvelopeError::LoopOutOfRange),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for EnvelopeParams {
+    fn default() -> Self {
+        Self::adsr(5.0, 100.0, 0.7, 200.0).expect("Those settings always work")
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum EnvelopeStage {
+    /// Finished or never triggered, holds the last level
+    #[default]
+    Idle,
+    Segment(usize),
+    /// Holding the level of the sustain segment while the gate is on
+    Sustain,
+}
+
+pub struct Envelope {
+    params: EnvelopeParams,
+    sample_rate: f32,
+    stage: EnvelopeStage,
+    gate: bool,
+    value: f32,
+    remaining: u32,
+    target: f32,
+    distance: f32,
+    coefficient: f32,
+    step: f32,
+}
+
+impl Envelope {
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            params: EnvelopeParams::default(),
+            sample_rate,
+            stage: EnvelopeStage::Idle,
+            gate: false,
+            value: 0.0,
+            remaining: 0,
+            target: 0.0,
+            distance: 0.0,
+            coefficient: 1.0,
+            step: 0.0,
+        }
+    }
+
+    /// Takes effect with the next segment
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+    }
+
+    /// Takes effect with the next segment
+    pub fn set_params(&mut self, params: EnvelopeParams) -> Result<(), EnvelopeError> {
+        params.validate()?;
+        self.params = params;
+        // The stage must still exist
+        match self.stage {
+            EnvelopeStage::Segment(index) if index >= params.count => {
+                self.stage = EnvelopeStage::Idle;
+            }
+            EnvelopeStage::Sustain if params.sustain.is_none() => {
+                self.stage = EnvelopeStage::Idle;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Starts the envelope from the first segment. With `legato` an envelope
+    /// whose gate is still on carries on instead, e.g. for overlapping notes.
+    pub fn gate_on(&mut self, legato: bool) {
+        if legato && self.gate {
+            return;
+        }
+        self.gate = true;
+        if self.params.retrigger == Retrigger::FromZero {
+            self.value = 0.0;
+        }
+        self.start_segment(0);
+    }
+
+    /// Continues with the release, the segment after the sustain segment
+    pub fn gate_off(&mut self) {
+        if !self.gate {
+            return;
+        }
+        self.gate = false;
+        let Some(sustain) = self.params.sustain else {
+            return;
+        };
+        let releasing = match self.stage {
+            EnvelopeStage::Segment(index) => index > sustain,
+            EnvelopeStage::Sustain => false,
+            EnvelopeStage::Idle => true,
+        };
+        if !releasing {
+            if sustain + 1 < self.params.count {
+                self.start_segment(sustain + 1);
+            } else {
+                self.stage = EnvelopeStage::Idle;
+            }
+        }
+    }
+
+    pub fn gate(&self) -> bool {
+        self.gate
+    }
+
+    pub fn stage(&self) -> EnvelopeStage {
+        self.stage
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.stage != EnvelopeStage::Idle
+    }
+
+    pub fn value(&self) -> f32 {
+        self.value
+    }
+
+    /// Jumps to the idle stage at level zero
+    pub fn reset(&mut self) {
+        self.stage = EnvelopeStage::Idle;
+        self.gate = false;
+        self.value = 0.0;
+    }
+
+    #[inline]
+    pub fn tick(&mut self) -> f32 {
+        match self.stage {
+            EnvelopeStage::Idle => {}
+            EnvelopeStage::Sustain => {
+                if let Some(sustain) = self.params.sustain {
+                    self.value = self.params.segments[sustain].level;
+                }
+            }
+            EnvelopeStage::Segment(index) => {
+                self.distance = self.distance * self.coefficient - self.step;
+                self.value = self.target + self.distance;
+                self.remaining -= 1;
+                if self.remaining == 0 {
+                    self.value = self.params.segments[index].level;
+                    self.next_segment(index);
+                }
+            }
+        }
+        self.value
+    }
+
+    /// Writes one value per sample
+    pub fn process(&mut self, output: &mut [f32]) {
+        for value in output.iter_mut() {
+            *value = self.tick();
+        }
+    }
+
+    /// Advances by `samples` and returns the level reached, for control
+    /// rate modulation once per block
+    pub fn advance(&mut self, samples: usize) -> f32 {
+        for _ in 0..samples {
+            self.tick();
+        }
+        self.value
+    }
+
+    fn next_segment(&mut self, index: usize) {
+        if self.gate && self.params.sustain == Some(index) {
+            match self.params.loop_start {
+                Some(loop_start) => self.start_segment(loop_start),
+                None => self.stage = EnvelopeStage::Sustain,
+            }
+        } else if index + 1 < self.params.count {
+            self.start_segment(index + 1);
+        } else {
+            self.stage = EnvelopeStage::Idle;
+        }
+    }
+
+    fn start_segment(&mut self, index: usize) {
+        let segment = self.params.segments[index];
+        let samples = libm::roundf(segment.time_ms * 0.001 * self.sample_rate).max(1.0);
+        self.stage = EnvelopeStage::Segment(index);
+        self.remaining = samples as u32;
+
+        let curvature = segment.curve * MAX_CURVATURE;
+        if curvature == 0.0 {
+            self.target = segment.level;
+            self.coefficient = 1.0;
+            self.step = (self.value - segment.level) / samples;
+        } else {
+            // The distance shrinks (or grows) by `c` per sample and by
+            // `c^samples` over the segment, which determines the target
+            let decay = libm::expf(-curvature);
+            self.target = (segment.level - self.value * decay) / (1.0 - decay);
+            self.coefficient = libm::expf(-curvature / samples);
+            self.step = 0.0;
+        }
+        self.distance = self.value - self.target;
+    }
+}
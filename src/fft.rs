@@ -0,0 +1,269 @@
+// FFT
+//
+// In-place radix-2/4 FFT and a real FFT built on top of it, which computes a
+// spectrum of `N` real samples with a complex FFT of size `N / 2`.
+//
+// Real spectra use the packed layout known from CMSIS-DSP: `[0]` holds the DC
+// bin, `[1]` the Nyquist bin (both purely real) and `[2k]`, `[2k + 1]` the
+// real and imaginary part of bin `k` for `k` in `1..N / 2`.
+
+#[derive(Debug)]
+pub enum FftError {
+    SizeNotPowerOfTwo,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct Complex {
+    pub re: f32,
+    pub im: f32,
+}
+
+impl Complex {
+    pub const ZERO: Self = Self { re: 0.0, im: 0.0 };
+
+    pub const fn new(re: f32, im: f32) -> Self {
+        Self { re, im }
+    }
+
+    #[inline]
+    pub fn conj(self) -> Self {
+        Self::new(self.re, -self.im)
+    }
+
+    #[inline]
+    pub fn norm_sqr(self) -> f32 {
+        self.re * self.re + self.im * self.im
+    }
+}
+
+impl core::ops::Add for Complex {
+    type Output = Self;
+
+    #[inline]
+    fn add(self, other: Self) -> Self {
+        Self::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl core::ops::Sub for Complex {
+    type Output = Self;
+
+    #[inline]
+    fn sub(self, other: Self) -> Self {
+        Self::new(self.re - other.re, self.im - other.im)
+    }
+}
+
+impl core::ops::Mul for Complex {
+    type Output = Self;
+
+    #[inline]
+    fn mul(self, other: Self) -> Self {
+        Self::new(
+            self.re * other.re - self.im * other.im,
+            self.re * other.im + self.im * other.re,
+        )
+    }
+}
+
+impl core::ops::Mul<f32> for Complex {
+    type Output = Self;
+
+    #[inline]
+    fn mul(self, scale: f32) -> Self {
+        Self::new(self.re * scale, self.im * scale)
+    }
+}
+
+/// Complex FFT of size `N`
+pub struct Fft<const N: usize> {
+    /// `e^(-2πik/N)` for `k` in `0..N`
+    twiddles: [Complex; N],
+}
+
+impl<const N: usize> Fft<N> {
+    pub fn new() -> Result<Self, FftError> {
+        if !N.is_power_of_two() || N < 2 {
+            return Err(FftError::SizeNotPowerOfTwo);
+        }
+        Ok(Self {
+            twiddles: twiddles::<N>(),
+        })
+    }
+
+    pub fn forward(&self, data: &mut [Complex; N]) {
+        self.transform(data, 1);
+    }
+
+    /// Inverse transform, scaled by `1 / N` so it undoes `forward`
+    pub fn inverse(&self, data: &mut [Complex; N]) {
+        self.transform(data, 1);
+        // Conjugating the twiddles is the same as reversing bins 1..N
+        data[1..].reverse();
+        let scale = 1.0 / N as f32;
+        for value in data.iter_mut() {
+            *value = *value * scale;
+        }
+    }
+
+    /// Transforms the first `N / stride` values using every `stride`th twiddle
+    fn transform(&self, data: &mut [Complex], stride: usize) {
+        let n = data.len();
+        bit_reverse(data);
+
+        // With an odd number of radix-2 stages, start with a single one
+        let mut half = 1;
+        if n.trailing_zeros() % 2 == 1 {
+            for pair in data.chunks_exact_mut(2) {
+                let (a, b) = (pair[0], pair[1]);
+                pair[0] = a + b;
+                pair[1] = a - b;
+            }
+            half = 2;
+        }
+
+        // Radix-4 passes, each one merges two radix-2 stages and saves one
+        // of the four twiddle multiplications
+        while half < n {
+            let step = n / (4 * half) * stride;
+            for start in (0..n).step_by(4 * half) {
+                for k in 0..half {
+                    let i0 = start + k;
+                    let (i1, i2, i3) = (i0 + half, i0 + 2 * half, i0 + 3 * half);
+                    let a0 = data[i0];
+                    let t1 = data[i1] * self.twiddles[2 * k * step];
+                    let t2 = data[i2] * self.twiddles[k * step];
+                    let t3 = data[i3] * self.twiddles[3 * k * step];
+
+                    let b0 = a0 + t1;
+                    let b1 = a0 - t1;
+                    let b2 = t2 + t3;
+                    // (t2 - t3) * -i
+                    let b3 = Complex::new(t2.im - t3.im, t3.re - t2.re);
+
+                    data[i0] = b0 + b2;
+                    data[i1] = b1 + b3;
+                    data[i2] = b0 - b2;
+                    data[i3] = b1 - b3;
+                }
+            }
+            half *= 4;
+        }
+    }
+}
+
+/// FFT of `N` real samples
+pub struct RealFft<const N: usize> {
+    fft: Fft<N>,
+}
+
+impl<const N: usize> RealFft<N> {
+    pub fn new() -> Result<Self, FftError> {
+        if N < 4 {
+            return Err(FftError::SizeNotPowerOfTwo);
+        }
+        Ok(Self { fft: Fft::new()? })
+    }
+
+    /// Replaces the samples with their packed spectrum
+    pub fn forward(&self, data: &mut [f32; N]) {
+        let half = N / 2;
+        let packed = as_complex(data);
+        self.fft.transform(packed, 2);
+
+        // Split the spectrum of the even/odd interleaved signal
+        let z0 = packed[0];
+        packed[0] = Complex::new(z0.re + z0.im, z0.re - z0.im);
+        for k in 1..=half / 2 {
+            let z_k = packed[k];
+            let z_m = packed[half - k].conj();
+            let even = (z_k + z_m) * 0.5;
+            let odd = (z_k - z_m) * Complex::new(0.0, -0.5);
+            let rotated = self.fft.twiddles[k] * odd;
+            packed[k] = even + rotated;
+            packed[half - k] = (even - rotated).conj();
+        }
+    }
+
+    /// Replaces a packed spectrum with its samples, scaled to undo `forward`
+    pub fn inverse(&self, data: &mut [f32; N]) {
+        let half = N / 2;
+        let packed = as_complex(data);
+
+        let x0 = packed[0];
+        packed[0] = Complex::new(x0.re + x0.im, x0.re - x0.im) * 0.5;
+        for k in 1..=half / 2 {
+            let x_k = packed[k];
+            let x_m = packed[half - k].conj();
+            let even = (x_k + x_m) * 0.5;
+            let odd = self.fft.twiddles[k].conj() * ((x_k - x_m) * 0.5);
+            // z = even + i * odd
+            packed[k] = even + odd * Complex::new(0.0, 1.0);
+            packed[half - k] = (even - odd * Complex::new(0.0, 1.0)).conj();
+        }
+
+        self.fft.transform(packed, 2);
+        packed[1..].reverse();
+        let scale = 1.0 / half as f32;
+        for value in packed.iter_mut() {
+            *value = *value * scale;
+        }
+    }
+
+    /// Bin `k` (`0..=N / 2`) of a packed spectrum
+    #[inline]
+    pub fn bin(spectrum: &[f32; N], k: usize) -> Complex {
+        match k {
+            0 => Complex::new(spectrum[0], 0.0),
+            k if k == N / 2 => Complex::new(spectrum[1], 0.0),
+            k => Complex::new(spectrum[2 * k], spectrum[2 * k + 1]),
+        }
+    }
+
+    /// Multiplies two packed spectra and adds the result to `accumulator`
+    #[inline]
+    pub fn multiply_accumulate(accumulator: &mut [f32; N], a: &[f32; N], b: &[f32; N]) {
+        accumulator[0] += a[0] * b[0];
+        accumulator[1] += a[1] * b[1];
+        for k in 1..N / 2 {
+            let (re, im) = (2 * k, 2 * k + 1);
+            accumulator[re] += a[re] * b[re] - a[im] * b[im];
+            accumulator[im] += a[re] * b[im] + a[im] * b[re];
+        }
+    }
+}
+
+/// Reinterprets interleaved real/imaginary pairs as complex numbers
+fn as_complex(data: &mut [f32]) -> &mut [Complex] {
+    // SAFETY: `Complex` is `repr(C)` with two `f32` fields, so it has the
+    // size and alignment of `[f32; 2]`
+    unsafe { core::slice::from_raw_parts_mut(data.as_mut_ptr() as *mut Complex, data.len() / 2) }
+}
+
+fn bit_reverse(data: &mut [Complex]) {
+    let n = data.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            data.swap(i, j);
+        }
+    }
+}
+
+/// Computes `e^(-2πik/N)` in double precision, the approximations of
+/// micromath are not accurate enough for twiddle factors
+fn twiddles<const N: usize>() -> [Complex; N] {
+    let mut twiddles = [Complex::ZERO; N];
+    for (k, twiddle) in twiddles.iter_mut().enumerate() {
+        let (sin, cos) = libm::sincos(-2.0 * core::f64::consts::PI * k as f64 / N as f64);
+        *twiddle = Complex::new(cos as f32, sin as f32);
+    }
+    twiddles
+}
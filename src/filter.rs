@@ -10,14 +10,15 @@ pub enum FilterError {
 }
 
 #[allow(unused)]
-#[derive(Default, Copy, Clone, PartialEq)]
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
 pub enum FilterType {
     #[default]
     Lowpass,
     Bell,
+    Bandpass,
 }
 
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct FilterParams {
     pub frequency: f32,
     pub quality: f32,
@@ -49,6 +50,25 @@ impl Coefficients {
         filter_type: FilterType,
         sample_rate: f32,
         params: FilterParams,
+    ) -> Result<Self, FilterError> {
+        Self::with_tan(filter_type, sample_rate, params, |x| x.tan())
+    }
+
+    /// As `new`, but with libm's `tan`. micromath's is about 1% low, which
+    /// detunes narrow bands such as the modes of a resonator.
+    pub fn precise(
+        filter_type: FilterType,
+        sample_rate: f32,
+        params: FilterParams,
+    ) -> Result<Self, FilterError> {
+        Self::with_tan(filter_type, sample_rate, params, libm::tanf)
+    }
+
+    fn with_tan(
+        filter_type: FilterType,
+        sample_rate: f32,
+        params: FilterParams,
+        tan: fn(f32) -> f32,
     ) -> Result<Self, FilterError> {
         if params.frequency > sample_rate / 2.0 {
             return Err(FilterError::FrequencyOverNyqist);
@@ -63,7 +83,7 @@ impl Coefficients {
         let mut coeffs = Coefficients::default();
         match filter_type {
             FilterType::Lowpass => {
-                let g = (core::f32::consts::PI * params.frequency / sample_rate).tan();
+                let g = tan(core::f32::consts::PI * params.frequency / sample_rate);
                 let k = 1.0 / params.quality;
                 coeffs.a1 = 1.0 / (1.0 + g * (g + k));
                 coeffs.a2 = g * coeffs.a1;
@@ -74,7 +94,7 @@ impl Coefficients {
             }
             FilterType::Bell => {
                 let a = 10_f32.powf(params.gain / 40.0);
-                let g = (core::f32::consts::PI * params.frequency / sample_rate).tan();
+                let g = tan(core::f32::consts::PI * params.frequency / sample_rate);
                 let k = 1.0 / (params.quality * a);
                 coeffs.a1 = 1.0 / (1.0 + g * (g + k));
                 coeffs.a2 = g * coeffs.a1;
@@ -83,6 +103,16 @@ impl Coefficients {
                 coeffs.m1 = k * (a * a - 1.0);
                 coeffs.m2 = 0.0;
             }
+            FilterType::Bandpass => {
+                let g = tan(core::f32::consts::PI * params.frequency / sample_rate);
+                let k = 1.0 / params.quality;
+                coeffs.a1 = 1.0 / (1.0 + g * (g + k));
+                coeffs.a2 = g * coeffs.a1;
+                coeffs.a3 = g * coeffs.a2;
+                coeffs.m0 = 0.0;
+                coeffs.m1 = k;
+                coeffs.m2 = 0.0;
+            }
         }
         Ok(coeffs)
     }
@@ -94,6 +124,8 @@ pub struct Filter {
     filter_type: FilterType,
     sample_rate: f32,
     coeffs: Coefficients,
+    /// Computes the coefficients with `Coefficients::precise`
+    precise: bool,
     ic1eq: f32,
     ic2eq: f32,
 }
@@ -106,6 +138,7 @@ impl Filter {
             params: FilterParams::default(),
             coeffs: Coefficients::new(filter_type, 48000.0, FilterParams::default())
                 .expect("Those settings always work"),
+            precise: false,
             ic1eq: 0.0,
             ic2eq: 0.0,
         }
@@ -120,6 +153,15 @@ impl Filter {
         Ok(())
     }
 
+    /// Tunes the filter exactly at the cost of a slower update
+    pub fn set_precise(&mut self, precise: bool) -> Result<(), FilterError> {
+        if self.precise != precise {
+            self.precise = precise;
+            self.update_coefficients()?;
+        }
+        Ok(())
+    }
+
     pub fn set_sample_rate(&mut self, sample_rate: f32) -> Result<(), FilterError> {
         if self.sample_rate != sample_rate {
             self.sample_rate = sample_rate;
@@ -154,7 +196,17 @@ impl Filter {
     }
 
     fn update_coefficients(&mut self) -> Result<(), FilterError> {
-        self.coeffs = Coefficients::new(self.filter_type, self.sample_rate, self.params)?;
+        self.coeffs = if self.precise {
+            Coefficients::precise(self.filter_type, self.sample_rate, self.params)?
+        } else {
+            Coefficients::new(self.filter_type, self.sample_rate, self.params)?
+        };
         Ok(())
     }
 }
+
+/// Coefficient of a one pole smoother reaching ~63% after `time_ms`
+pub fn one_pole_coefficient(time_ms: f32, sample_rate: f32) -> f32 {
+    let samples = time_ms * 0.001 * sample_rate;
+    1.0 / (samples + 1.0)
+}
@@ -0,0 +1,143 @@
+// Flash
+//
+// Interface of NOR flash for storing settings: erasing sets a whole sector
+// to 0xFF, programming only clears bits. `RamFlash` behaves the same way in
+// RAM for the host tests and can lose power after any number of steps,
+// leaving the write it was in the middle of half done.
+
+/// Smallest erasable unit
+pub const SECTOR_SIZE: u32 = 4096;
+
+#[derive(Debug, PartialEq)]
+pub enum FlashError {
+    OutOfRange,
+    /// Erasing has to start at a sector
+    Unaligned,
+    Bus,
+    /// Only from `RamFlash`
+    PowerLoss,
+}
+
+pub trait Flash {
+    /// Size in bytes
+    fn capacity(&self) -> u32;
+    fn read(&mut self, address: u32, buffer: &mut [u8]) -> Result<(), FlashError>;
+    /// Sets the sector at `address` to 0xFF
+    fn erase_sector(&mut self, address: u32) -> Result<(), FlashError>;
+    /// Clears the bits that are 0 in `data`
+    fn program(&mut self, address: u32, data: &[u8]) -> Result<(), FlashError>;
+
+    /// Fails unless the range fits into the flash
+    fn check_range(&self, address: u32, length: usize) -> Result<(), FlashError> {
+        match address.checked_add(length as u32) {
+            Some(end) if end <= self.capacity() => Ok(()),
+            _ => Err(FlashError::OutOfRange),
+        }
+    }
+}
+
+impl<T: Flash + ?Sized> Flash for &mut T {
+    fn capacity(&self) -> u32 {
+        (**self).capacity()
+    }
+
+    fn read(&mut self, address: u32, buffer: &mut [u8]) -> Result<(), FlashError> {
+        (**self).read(address, buffer)
+    }
+
+    fn erase_sector(&mut self, address: u32) -> Result<(), FlashError> {
+        (**self).erase_sector(address)
+    }
+
+    fn program(&mut self, address: u32, data: &[u8]) -> Result<(), FlashError> {
+        (**self).program(address, data)
+    }
+}
+
+#[derive(Clone)]
+pub struct RamFlash<const SIZE: usize> {
+    memory: [u8; SIZE],
+    /// Steps left until the power fails
+    power: Option<usize>,
+}
+
+impl<const SIZE: usize> RamFlash<SIZE> {
+    pub fn new() -> Self {
+        Self {
+            memory: [0xFF; SIZE],
+            power: None,
+        }
+    }
+
+    /// Loses power after `steps` more programmed bytes or erased sectors,
+    /// until it is called again. `None` never loses power.
+    pub fn set_power(&mut self, steps: Option<usize>) {
+        self.power = steps;
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// Takes a step, false if there is no power left for it
+    fn step(&mut self) -> bool {
+        match &mut self.power {
+            Some(0) => false,
+            Some(steps) => {
+                *steps -= 1;
+                true
+            }
+            None => true,
+        }
+    }
+}
+
+impl<const SIZE: usize> Default for RamFlash<SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const SIZE: usize> Flash for RamFlash<SIZE> {
+    fn capacity(&self) -> u32 {
+        SIZE as u32
+    }
+
+    fn read(&mut self, address: u32, buffer: &mut [u8]) -> Result<(), FlashError> {
+        self.check_range(address, buffer.len())?;
+        let start = address as usize;
+        buffer.copy_from_slice(&self.memory[start..start + buffer.len()]);
+        Ok(())
+    }
+
+    fn erase_sector(&mut self, address: u32) -> Result<(), FlashError> {
+        self.check_range(address, SECTOR_SIZE as usize)?;
+        if !address.is_multiple_of(SECTOR_SIZE) {
+            return Err(FlashError::Unaligned);
+        }
+        let powered = self.step();
+        let sector = &mut self.memory[address as usize..(address + SECTOR_SIZE) as usize];
+        if !powered {
+            // Interrupted halfway
+            sector[..SECTOR_SIZE as usize / 2].fill(0xFF);
+            return Err(FlashError::PowerLoss);
+        }
+        sector.fill(0xFF);
+        Ok(())
+    }
+
+    fn program(&mut self, address: u32, data: &[u8]) -> Result<(), FlashError> {
+        self.check_range(address, data.len())?;
+        for (index, byte) in data.iter().enumerate() {
+            let powered = self.step();
+            let cell = &mut self.memory[address as usize + index];
+            if !powered {
+                // Only some of the bits of the last byte make it
+                *cell &= byte | 0x0F;
+                return Err(FlashError::PowerLoss);
+            }
+            *cell &= byte;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,266 @@
+// Granular
+//
+// Captures the input into a ring buffer and plays it back as overlapping,
+// windowed grains. All grains live in a fixed size pool, spawning a grain
+// when the pool is full simply drops it, so nothing is ever allocated and
+// the engine is safe to run in the audio interrupt.
+use micromath::F32Ext;
+
+use crate::random::Random;
+
+/// Maximum number of simultaneously playing grains
+pub const MAX_GRAINS: usize = 16;
+const MAX_SEMITONES: f32 = 24.0;
+
+#[derive(Debug)]
+pub enum GranularError {
+    BufferTooShort,
+    SizeOutOfRange,
+    DensityOutOfRange,
+    PitchOutOfRange,
+    PositionOutOfRange,
+    PanOutOfRange,
+    MixOutOfRange,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub struct GranularParams {
+    /// Distance of the grain start behind the write head, as fraction of the buffer (0.0..=1.0)
+    pub position: f32,
+    /// Random deviation of the position, as fraction of the buffer (0.0..=1.0)
+    pub position_spread: f32,
+    /// Grain length in milliseconds
+    pub size_ms: f32,
+    /// Random deviation of the grain length (0.0..=1.0, relative)
+    pub size_spread: f32,
+    /// Transposition in semitones
+    pub pitch: f32,
+    /// Random deviation of the transposition in semitones
+    pub pitch_spread: f32,
+    /// Random stereo spread (0.0 = center, 1.0 = full left/right)
+    pub pan_spread: f32,
+    /// Grains started per second
+    pub density: f32,
+    /// Dry/wet mix (0.0 = dry, 1.0 = wet)
+    pub mix: f32,
+}
+
+impl Default for GranularParams {
+    fn default() -> Self {
+        Self {
+            position: 0.1,
+            position_spread: 0.05,
+            size_ms: 80.0,
+            size_spread: 0.2,
+            pitch: 0.0,
+            pitch_spread: 0.0,
+            pan_spread: 0.5,
+            density: 30.0,
+            mix: 0.5,
+        }
+    }
+}
+
+#[derive(Default, Copy, Clone)]
+struct Grain {
+    active: bool,
+    position: f32,
+    increment: f32,
+    age: usize,
+    length: usize,
+    gain_left: f32,
+    gain_right: f32,
+}
+
+pub struct Granular<'a> {
+    params: GranularParams,
+    sample_rate: f32,
+    buffer: &'a mut [f32],
+    write_pos: usize,
+    frozen: bool,
+    grains: [Grain; MAX_GRAINS],
+    countdown: usize,
+    normalization: f32,
+    random: Random,
+}
+
+impl<'a> Granular<'a> {
+    pub fn new(buffer: &'a mut [f32], sample_rate: f32) -> Result<Self, GranularError> {
+        let mut granular = Self {
+            params: GranularParams::default(),
+            sample_rate,
+            buffer,
+            write_pos: 0,
+            frozen: false,
+            grains: [Grain::default(); MAX_GRAINS],
+            countdown: 0,
+            normalization: 1.0,
+            random: Random::new(0x2545_f491),
+        };
+        granular.buffer.fill(0.0);
+        // The defaults are valid, so this only fails if the buffer can't hold a grain
+        granular
+            .set_params(GranularParams::default())
+            .map_err(|_| GranularError::BufferTooShort)?;
+        Ok(granular)
+    }
+
+    pub fn set_params(&mut self, params: GranularParams) -> Result<(), GranularError> {
+        if !(0.0..=1.0).contains(&params.position) || !(0.0..=1.0).contains(&params.position_spread)
+        {
+            return Err(GranularError::PositionOutOfRange);
+        }
+        if params.pitch.abs() + params.pitch_spread.abs() > MAX_SEMITONES {
+            return Err(GranularError::PitchOutOfRange);
+        }
+        if !(0.0..=1.0).contains(&params.pan_spread) {
+            return Err(GranularError::PanOutOfRange);
+        }
+        if params.density <= 0.0 {
+            return Err(GranularError::DensityOutOfRange);
+        }
+        if !(0.0..=1.0).contains(&params.mix) {
+            return Err(GranularError::MixOutOfRange);
+        }
+        // The same bound as in `spawn`: a grain pitched up needs its span
+        // and two samples on both ends, one pitched down also the distance
+        // it falls behind the write head
+        let max_ratio = 2_f32.powf(MAX_SEMITONES / 12.0);
+        let longest =
+            libm::floorf(params.size_ms * (1.0 + params.size_spread) * 0.001 * self.sample_rate)
+                .max(1.0);
+        let reach = longest * max_ratio.max(2.0 - 1.0 / max_ratio) + 4.0;
+        if params.size_ms <= 0.0
+            || !(0.0..=1.0).contains(&params.size_spread)
+            || reach > self.buffer.len() as f32
+        {
+            return Err(GranularError::SizeOutOfRange);
+        }
+
+        // Overlapping grains add up, keep the level roughly constant
+        let overlap = params.density * params.size_ms * 0.001;
+        self.normalization = 1.0 / overlap.max(1.0).sqrt();
+        self.params = params;
+        Ok(())
+    }
+
+    /// Stops recording, the grains keep playing from the captured audio
+    pub fn set_freeze(&mut self, frozen: bool) {
+        self.frozen = frozen;
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.frozen
+    }
+
+    pub fn process(&mut self, audio_buffer: &mut [(f32, f32)]) {
+        for frame in audio_buffer.iter_mut() {
+            *frame = self.tick(*frame);
+        }
+    }
+
+    #[inline]
+    pub fn tick(&mut self, input: (f32, f32)) -> (f32, f32) {
+        if !self.frozen {
+            self.buffer[self.write_pos] = 0.5 * (input.0 + input.1);
+            self.write_pos = (self.write_pos + 1) % self.buffer.len();
+        }
+
+        if self.countdown == 0 {
+            self.spawn();
+        } else {
+            self.countdown -= 1;
+        }
+
+        let length = self.buffer.len();
+        let mut left = 0.0;
+        let mut right = 0.0;
+        for grain in self.grains.iter_mut().filter(|grain| grain.active) {
+            let whole = grain.position as usize;
+            let fraction = grain.position - whole as f32;
+            let a = self.buffer[whole % length];
+            let b = self.buffer[(whole + 1) % length];
+            let sample = a + fraction * (b - a);
+
+            // Parabolic window, cheaper than Hann and just as smooth at the ends
+            let phase = grain.age as f32 / grain.length as f32;
+            let window = 4.0 * phase * (1.0 - phase);
+
+            left += sample * window * grain.gain_left;
+            right += sample * window * grain.gain_right;
+
+            grain.position += grain.increment;
+            if grain.position >= length as f32 {
+                grain.position -= length as f32;
+            }
+            grain.age += 1;
+            if grain.age >= grain.length {
+                grain.active = false;
+            }
+        }
+
+        let mix = self.params.mix;
+        let wet_left = left * self.normalization;
+        let wet_right = right * self.normalization;
+        (
+            input.0 + mix * (wet_left - input.0),
+            input.1 + mix * (wet_right - input.1),
+        )
+    }
+
+    pub fn reset(&mut self) {
+        self.buffer.fill(0.0);
+        self.write_pos = 0;
+        self.countdown = 0;
+        for grain in self.grains.iter_mut() {
+            grain.active = false;
+        }
+    }
+
+    fn spawn(&mut self) {
+        let params = self.params;
+
+        // Jitter the interval between grains to avoid a comb filter effect
+        let interval = self.sample_rate / params.density;
+        self.countdown = (interval * (1.0 + 0.25 * self.random.bipolar())) as usize;
+
+        let Some(grain) = self.grains.iter_mut().find(|grain| !grain.active) else {
+            return;
+        };
+
+        let size = params.size_ms * (1.0 + params.size_spread * self.random.bipolar());
+        let length = ((size * 0.001 * self.sample_rate) as usize).max(1);
+        let semitones = params.pitch + params.pitch_spread * self.random.bipolar();
+        let increment = 2_f32.powf(semitones / 12.0);
+
+        // The grain must neither overtake the write head nor, falling behind
+        // it when pitched down, read what the write head already overwrote
+        let buffer_length = self.buffer.len() as f32;
+        let span = length as f32 * increment.max(1.0) + 2.0;
+        let drift = length as f32 * (1.0 - increment).max(0.0);
+        let position = params.position + params.position_spread * self.random.bipolar();
+        // `set_params` leaves room for both, but the approximated `powf`
+        // may overshoot a little at the limit, then the read side wins
+        let delay = (position * buffer_length)
+            .max(span)
+            .min(buffer_length - 2.0 - drift);
+        let mut start = self.write_pos as f32 - delay;
+        if start < 0.0 {
+            start += buffer_length;
+        }
+
+        // Equal power panning
+        let pan = 0.5 + 0.5 * params.pan_spread * self.random.bipolar();
+        let angle = pan * core::f32::consts::FRAC_PI_2;
+
+        *grain = Grain {
+            active: true,
+            position: start,
+            increment,
+            age: 0,
+            length,
+            gain_left: angle.cos(),
+            gain_right: angle.sin(),
+        };
+    }
+}
@@ -0,0 +1,170 @@
+// LFO
+//
+// Low frequency oscillator for modulation. Aliasing doesn't matter at these
+// rates, so the waveforms are naive. It runs per sample with `tick` or once
+// per block with `advance`, the output is bipolar (-1.0..=1.0).
+use crate::random::Random;
+
+#[derive(Debug)]
+pub enum LfoError {
+    FrequencyNegative,
+    FrequencyOverNyquist,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum LfoWaveform {
+    #[default]
+    Sine,
+    Triangle,
+    /// Rising ramp
+    Saw,
+    Square,
+    /// New random value every cycle
+    SampleAndHold,
+    /// Random values connected by ramps
+    SmoothRandom,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub struct LfoParams {
+    pub waveform: LfoWaveform,
+    pub frequency: f32,
+    /// Start of the cycle on `reset` (0.0..1.0)
+    pub phase: f32,
+}
+
+impl Default for LfoParams {
+    fn default() -> Self {
+        Self {
+            waveform: LfoWaveform::Sine,
+            frequency: 1.0,
+            phase: 0.0,
+        }
+    }
+}
+
+pub struct Lfo {
+    params: LfoParams,
+    sample_rate: f32,
+    phase: f32,
+    increment: f32,
+    random: Random,
+    current: f32,
+    previous: f32,
+    value: f32,
+}
+
+impl Lfo {
+    pub fn new(sample_rate: f32, seed: u32) -> Self {
+        let mut lfo = Self {
+            params: LfoParams::default(),
+            sample_rate,
+            phase: 0.0,
+            increment: 0.0,
+            random: Random::new(seed),
+            current: 0.0,
+            previous: 0.0,
+            value: 0.0,
+        };
+        lfo.update().expect("Those settings always work");
+        lfo
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) -> Result<(), LfoError> {
+        if self.sample_rate != sample_rate {
+            self.sample_rate = sample_rate;
+            self.update()?;
+        }
+        Ok(())
+    }
+
+    pub fn set_params(&mut self, params: LfoParams) -> Result<(), LfoError> {
+        if self.params != params {
+            let previous = self.params;
+            self.params = params;
+            if let Err(error) = self.update() {
+                self.params = previous;
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn params(&self) -> &LfoParams {
+        &self.params
+    }
+
+    /// Restarts the cycle, e.g. on a new note
+    pub fn reset(&mut self) {
+        self.phase = self.params.phase - libm::floorf(self.params.phase);
+        self.value = self.evaluate();
+    }
+
+    /// Picks the next random value now and restarts the cycle, e.g. on a
+    /// clock or gate input
+    pub fn trigger(&mut self) {
+        self.phase = 0.0;
+        self.next_random();
+        self.value = self.evaluate();
+    }
+
+    /// Last output
+    pub fn value(&self) -> f32 {
+        self.value
+    }
+
+    #[inline]
+    pub fn tick(&mut self) -> f32 {
+        self.advance(1)
+    }
+
+    /// Moves on by `samples` and returns the output there, for control rate
+    /// modulation once per block
+    pub fn advance(&mut self, samples: usize) -> f32 {
+        self.phase += self.increment * samples as f32;
+        if self.phase >= 1.0 {
+            self.phase -= libm::floorf(self.phase);
+            self.next_random();
+        }
+        self.value = self.evaluate();
+        self.value
+    }
+
+    fn next_random(&mut self) {
+        self.previous = self.current;
+        self.current = self.random.bipolar();
+    }
+
+    fn evaluate(&self) -> f32 {
+        let phase = self.phase;
+        match self.params.waveform {
+            LfoWaveform::Sine => libm::sinf(core::f32::consts::TAU * phase),
+            LfoWaveform::Triangle => {
+                // Starts at zero and rises like the sine
+                1.0 - 4.0 * ((phase + 0.25) % 1.0 - 0.5).abs()
+            }
+            LfoWaveform::Saw => 2.0 * phase - 1.0,
+            LfoWaveform::Square => {
+                if phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            LfoWaveform::SampleAndHold => self.current,
+            LfoWaveform::SmoothRandom => self.previous + phase * (self.current - self.previous),
+        }
+    }
+
+    fn update(&mut self) -> Result<(), LfoError> {
+        let frequency = self.params.frequency;
+        if frequency.is_sign_negative() {
+            return Err(LfoError::FrequencyNegative);
+        }
+        if frequency > self.sample_rate / 2.0 {
+            return Err(LfoError::FrequencyOverNyquist);
+        }
+        self.increment = frequency / self.sample_rate;
+        Ok(())
+    }
+}
@@ -5,8 +5,44 @@ use daisy::hal as _;
 use defmt_rtt as _;
 use panic_probe as _;
 
+pub mod allocator;
+pub mod baked;
+pub mod convolution;
+pub mod crc;
+pub mod drums;
+pub mod envelope;
+pub mod fft;
 pub mod filter;
+pub mod flash;
+pub mod granular;
+pub mod lfo;
+pub mod looper;
+pub mod midi;
+pub mod midi_clock;
+pub mod midi_map;
+pub mod modulation;
+pub mod morph;
+pub mod noise;
+pub mod octaver;
+pub mod oscillator;
+pub mod physical;
+pub mod pitch_shift;
+pub mod preset;
 pub mod processor;
+pub mod qspi_flash;
+pub mod random;
+pub mod sequencer;
+pub mod settings;
+pub mod spectral;
+pub mod spectrum;
+pub mod synth;
+pub mod sysex;
+pub mod tuner;
+pub mod usb_midi;
+#[cfg(feature = "usb-midi")]
+pub mod usb_midi_class;
+pub mod vocoder;
+pub mod wavetable;
 
 pub const MS: u32 = 1_000;
 pub const US: u32 = 1_000_000;
@@ -0,0 +1,426 @@
+// Looper
+//
+// Stereo looper working on externally provided memory, on the Daisy this is
+// the SDRAM (`.sdram_bss` section), on the host any slice will do.
+//
+// Clicks are avoided in three places:
+// - the loop boundary: after the first recording is closed, the input keeps
+//   being recorded for a short time and crossfaded into the start of the loop
+// - starting and stopping playback ramps the loop level
+// - punching in and out of overdubs ramps the recording level
+//
+// The undo buffer holds the previous content of every frame touched by the
+// last overdub layer. Because the playhead never jumps, the touched frames
+// always form one contiguous arc of the loop, which is all we need to track.
+
+/// Number of frames restored per call to `process` after an undo, eight
+/// blocks of 32 frames on the Daisy. That keeps the copy in the audio
+/// interrupt at 2 KB each way and still outruns the playhead eight times.
+const RESTORE_FRAMES_PER_BLOCK: usize = 256;
+const FADE_MS: f32 = 10.0;
+
+#[derive(Debug, PartialEq)]
+pub enum LooperError {
+    BufferTooShort,
+    BufferSizeMismatch,
+    FeedbackOutOfRange,
+    NothingRecorded,
+    AlreadyRecorded,
+    UndoUnavailable,
+    UndoInProgress,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum LooperState {
+    #[default]
+    Empty,
+    Recording,
+    Playing,
+    Overdubbing,
+    Stopped,
+}
+
+pub struct Looper<'a> {
+    buffer: &'a mut [(f32, f32)],
+    undo_buffer: &'a mut [(f32, f32)],
+    state: LooperState,
+    length: usize,
+    index: usize,
+    fraction: f32,
+    reverse: bool,
+    half_speed: bool,
+    feedback: f32,
+    fade_length: usize,
+    fade_step: f32,
+    playback_gain: f32,
+    record_gain: f32,
+    tail_index: usize,
+    last_written: Option<usize>,
+    layer: Option<Layer>,
+    restore: Option<Restore>,
+}
+
+/// Arc of frames touched by the current overdub layer, relative to `start`
+#[derive(Copy, Clone)]
+struct Layer {
+    start: usize,
+    offset: isize,
+    min_offset: isize,
+    max_offset: isize,
+}
+
+impl Layer {
+    fn touched(&self) -> usize {
+        (self.max_offset - self.min_offset + 1).max(0) as usize
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Restore {
+    next: usize,
+    remaining: usize,
+}
+
+impl<'a> Looper<'a> {
+    pub fn new(
+        buffer: &'a mut [(f32, f32)],
+        undo_buffer: &'a mut [(f32, f32)],
+        sample_rate: f32,
+    ) -> Result<Self, LooperError> {
+        if buffer.len() != undo_buffer.len() {
+            return Err(LooperError::BufferSizeMismatch);
+        }
+        let fade_length = ((FADE_MS * 0.001 * sample_rate) as usize).max(1);
+        if buffer.len() < 4 * fade_length {
+            return Err(LooperError::BufferTooShort);
+        }
+        Ok(Self {
+            buffer,
+            undo_buffer,
+            state: LooperState::Empty,
+            length: 0,
+            index: 0,
+            fraction: 0.0,
+            reverse: false,
+            half_speed: false,
+            feedback: 1.0,
+            fade_length,
+            fade_step: 1.0 / fade_length as f32,
+            playback_gain: 0.0,
+            record_gain: 0.0,
+            tail_index: fade_length,
+            last_written: None,
+            layer: None,
+            restore: None,
+        })
+    }
+
+    pub fn state(&self) -> LooperState {
+        self.state
+    }
+
+    /// Length of the loop in frames, 0 while nothing has been recorded
+    pub fn length(&self) -> usize {
+        self.length
+    }
+
+    pub fn position(&self) -> usize {
+        self.index
+    }
+
+    /// Maximum loop length in frames
+    pub fn capacity(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.layer.is_some()
+    }
+
+    /// Starts the first recording
+    pub fn record(&mut self) -> Result<(), LooperError> {
+        if self.state != LooperState::Empty {
+            return Err(LooperError::AlreadyRecorded);
+        }
+        self.state = LooperState::Recording;
+        self.index = 0;
+        self.fraction = 0.0;
+        Ok(())
+    }
+
+    /// Closes the loop while recording, otherwise (re)starts playback or
+    /// punches out of an overdub
+    pub fn play(&mut self) -> Result<(), LooperError> {
+        match self.state {
+            LooperState::Empty => return Err(LooperError::NothingRecorded),
+            LooperState::Recording => self.close_loop()?,
+            LooperState::Stopped => self.rewind(),
+            LooperState::Playing | LooperState::Overdubbing => {}
+        }
+        self.state = LooperState::Playing;
+        Ok(())
+    }
+
+    /// Starts recording a new layer on top of the loop
+    pub fn overdub(&mut self) -> Result<(), LooperError> {
+        if self.restore.is_some() {
+            return Err(LooperError::UndoInProgress);
+        }
+        match self.state {
+            LooperState::Empty => return Err(LooperError::NothingRecorded),
+            LooperState::Recording => self.close_loop()?,
+            LooperState::Overdubbing => return Ok(()),
+            LooperState::Stopped => self.rewind(),
+            LooperState::Playing => {}
+        }
+        self.layer = Some(Layer {
+            start: self.index,
+            offset: 0,
+            min_offset: 1,
+            max_offset: -1,
+        });
+        self.last_written = None;
+        self.state = LooperState::Overdubbing;
+        Ok(())
+    }
+
+    pub fn stop(&mut self) {
+        match self.state {
+            LooperState::Empty | LooperState::Stopped => {}
+            LooperState::Recording => {
+                if self.close_loop().is_ok() {
+                    self.playback_gain = 0.0;
+                    self.state = LooperState::Stopped;
+                }
+            }
+            LooperState::Playing | LooperState::Overdubbing => {
+                self.state = LooperState::Stopped;
+            }
+        }
+    }
+
+    /// Removes the last overdub layer.
+    ///
+    /// The previous content is restored over the next few calls to `process`.
+    pub fn undo(&mut self) -> Result<(), LooperError> {
+        if self.restore.is_some() {
+            return Err(LooperError::UndoInProgress);
+        }
+        let layer = self.layer.take().ok_or(LooperError::UndoUnavailable)?;
+        if self.state == LooperState::Overdubbing {
+            self.state = LooperState::Playing;
+        }
+        self.record_gain = 0.0;
+        let touched = layer.touched();
+        self.restore = Some(Restore {
+            next: self.wrap(layer.start as isize + layer.min_offset),
+            remaining: touched.min(self.length),
+        });
+        Ok(())
+    }
+
+    /// Forgets the loop, the memory can be recorded to again
+    pub fn clear(&mut self) {
+        self.state = LooperState::Empty;
+        self.length = 0;
+        self.index = 0;
+        self.fraction = 0.0;
+        self.playback_gain = 0.0;
+        self.record_gain = 0.0;
+        self.tail_index = self.fade_length;
+        self.layer = None;
+        self.restore = None;
+    }
+
+    pub fn set_reverse(&mut self, reverse: bool) {
+        self.reverse = reverse;
+    }
+
+    pub fn set_half_speed(&mut self, half_speed: bool) {
+        self.half_speed = half_speed;
+    }
+
+    /// Amount of the existing loop kept when overdubbing (0.0..=1.0)
+    pub fn set_feedback(&mut self, feedback: f32) -> Result<(), LooperError> {
+        if !(0.0..=1.0).contains(&feedback) {
+            return Err(LooperError::FeedbackOutOfRange);
+        }
+        self.feedback = feedback;
+        Ok(())
+    }
+
+    /// Mixes the loop into the audio buffer and records the input
+    pub fn process(&mut self, audio_buffer: &mut [(f32, f32)]) {
+        self.restore_chunk();
+        for frame in audio_buffer.iter_mut() {
+            *frame = self.tick(*frame);
+        }
+    }
+
+    #[inline]
+    pub fn tick(&mut self, input: (f32, f32)) -> (f32, f32) {
+        if self.state == LooperState::Recording {
+            self.buffer[self.index] = input;
+            self.index += 1;
+            if self.index == self.buffer.len() {
+                self.close_loop().expect("The buffer is not empty");
+                self.state = LooperState::Playing;
+            }
+            return input;
+        }
+
+        let playback_target = match self.state {
+            LooperState::Playing | LooperState::Overdubbing => 1.0,
+            _ => 0.0,
+        };
+        let record_target = if self.state == LooperState::Overdubbing {
+            1.0
+        } else {
+            0.0
+        };
+        self.playback_gain = ramp(self.playback_gain, playback_target, self.fade_step);
+        self.record_gain = ramp(self.record_gain, record_target, self.fade_step);
+
+        if self.length == 0 || (self.playback_gain <= 0.0 && self.record_gain <= 0.0) {
+            return input;
+        }
+
+        let output = self.read();
+
+        if self.tail_index < self.fade_length {
+            // Crossfade what was played after the loop end into its start
+            let weight = self.tail_index as f32 / self.fade_length as f32;
+            let start = &mut self.buffer[self.tail_index];
+            start.0 = input.0 + weight * (start.0 - input.0);
+            start.1 = input.1 + weight * (start.1 - input.1);
+            self.tail_index += 1;
+        }
+
+        if self.record_gain > 0.0 && self.last_written != Some(self.index) {
+            self.write_overdub(input);
+        }
+
+        self.advance();
+
+        (
+            input.0 + self.playback_gain * output.0,
+            input.1 + self.playback_gain * output.1,
+        )
+    }
+
+    fn close_loop(&mut self) -> Result<(), LooperError> {
+        if self.index == 0 {
+            self.clear();
+            return Err(LooperError::NothingRecorded);
+        }
+        self.length = self.index;
+        self.index = 0;
+        self.fraction = 0.0;
+        self.tail_index = if self.length >= 2 * self.fade_length {
+            0
+        } else {
+            self.fade_length
+        };
+        self.playback_gain = 1.0;
+        self.layer = None;
+        Ok(())
+    }
+
+    /// Moves the playhead back to the loop start, unless the loop is still
+    /// fading out and would click
+    fn rewind(&mut self) {
+        if self.playback_gain <= 0.0 {
+            self.index = if self.reverse { self.length - 1 } else { 0 };
+            self.fraction = 0.0;
+        }
+    }
+
+    #[inline]
+    fn read(&self) -> (f32, f32) {
+        let a = self.buffer[self.index];
+        if self.fraction == 0.0 {
+            return a;
+        }
+        let b = self.buffer[self.wrap(self.index as isize + 1)];
+        (
+            a.0 + self.fraction * (b.0 - a.0),
+            a.1 + self.fraction * (b.1 - a.1),
+        )
+    }
+
+    fn write_overdub(&mut self, input: (f32, f32)) {
+        // Frames outside of the arc touched so far still hold the previous
+        // layer, keep a copy for undo
+        if let Some(layer) = self.layer.as_mut() {
+            let is_new = layer.offset > layer.max_offset || layer.offset < layer.min_offset;
+            if is_new && layer.touched() < self.length {
+                self.undo_buffer[self.index] = self.buffer[self.index];
+                layer.min_offset = layer.min_offset.min(layer.offset);
+                layer.max_offset = layer.max_offset.max(layer.offset);
+            }
+        }
+
+        let keep = 1.0 - self.record_gain * (1.0 - self.feedback);
+        let frame = &mut self.buffer[self.index];
+        frame.0 = keep * frame.0 + self.record_gain * input.0;
+        frame.1 = keep * frame.1 + self.record_gain * input.1;
+        self.last_written = Some(self.index);
+    }
+
+    #[inline]
+    fn advance(&mut self) {
+        let speed = if self.half_speed { 0.5 } else { 1.0 };
+        let mut step: isize = 0;
+        if self.reverse {
+            self.fraction -= speed;
+            while self.fraction < 0.0 {
+                self.fraction += 1.0;
+                step -= 1;
+            }
+        } else {
+            self.fraction += speed;
+            while self.fraction >= 1.0 {
+                self.fraction -= 1.0;
+                step += 1;
+            }
+        }
+        if step != 0 {
+            self.index = self.wrap(self.index as isize + step);
+            if let Some(layer) = self.layer.as_mut() {
+                layer.offset += step;
+            }
+        }
+    }
+
+    fn restore_chunk(&mut self) {
+        let Some(restore) = self.restore.as_mut() else {
+            return;
+        };
+        let count = restore.remaining.min(RESTORE_FRAMES_PER_BLOCK);
+        for _ in 0..count {
+            self.buffer[restore.next] = self.undo_buffer[restore.next];
+            restore.next += 1;
+            if restore.next == self.length {
+                restore.next = 0;
+            }
+        }
+        restore.remaining -= count;
+        if restore.remaining == 0 {
+            self.restore = None;
+        }
+    }
+
+    #[inline]
+    fn wrap(&self, index: isize) -> usize {
+        index.rem_euclid(self.length as isize) as usize
+    }
+}
+
+#[inline]
+fn ramp(value: f32, target: f32, step: f32) -> f32 {
+    if value < target {
+        (value + step).min(target)
+    } else {
+        (value - step).max(target)
+    }
+}
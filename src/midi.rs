@@ -0,0 +1,448 @@
+// MIDI
+//
+// Parser for the MIDI 1.0 byte stream of a serial input. Bytes go in one at
+// a time as they arrive and complete messages come out. Channel messages
+// may use running status, i.e. leave out the status byte when it repeats,
+// and realtime bytes (clock, start, stop...) may show up anywhere, even in
+// the middle of another message, without disturbing it.
+//
+// System exclusive messages are collected into a buffer and reported once
+// complete, their data is then available from `MidiParser::sysex`.
+// Malformed input is reported as an error and skipped, the parser picks up
+// again with the next status byte.
+//
+// The encoder turns messages back into bytes for an output, with running
+// status to save bandwidth.
+use heapless::Vec;
+
+/// Longest system exclusive message kept, without the 0xF0 and 0xF7 bytes
+pub const MAX_SYSEX: usize = 256;
+
+#[derive(Debug, PartialEq)]
+pub enum MidiError {
+    /// Data byte without a status byte to belong to, or 0xF7 outside of a
+    /// system exclusive message
+    UnexpectedByte(u8),
+    /// Status byte in the middle of a channel or system common message
+    IncompleteMessage,
+    /// Status byte that the specification leaves undefined
+    UndefinedStatus(u8),
+    /// System exclusive message that ended without 0xF7
+    UnterminatedSysEx,
+    /// System exclusive message longer than `MAX_SYSEX`, it is dropped
+    SysExOverflow,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MidiMessage {
+    /// Also for note on messages with velocity 0
+    NoteOff {
+        channel: u8,
+        note: u8,
+        velocity: u8,
+    },
+    NoteOn {
+        channel: u8,
+        note: u8,
+        velocity: u8,
+    },
+    PolyPressure {
+        channel: u8,
+        note: u8,
+        pressure: u8,
+    },
+    ControlChange {
+        channel: u8,
+        controller: u8,
+        value: u8,
+    },
+    ProgramChange {
+        channel: u8,
+        program: u8,
+    },
+    ChannelPressure {
+        channel: u8,
+        pressure: u8,
+    },
+    /// Bend around the center (-8192..=8191)
+    PitchBend {
+        channel: u8,
+        value: i16,
+    },
+    /// Complete system exclusive message of `length` bytes, see
+    /// `MidiParser::sysex`
+    SysEx {
+        length: usize,
+    },
+    TimeCode(u8),
+    /// Sixteenth notes since the start of the song
+    SongPosition(u16),
+    SongSelect(u8),
+    TuneRequest,
+    Clock,
+    Start,
+    Continue,
+    Stop,
+    ActiveSensing,
+    Reset,
+}
+
+impl MidiMessage {
+    /// Channel (0..=15) of channel messages
+    pub fn channel(&self) -> Option<u8> {
+        match *self {
+            MidiMessage::NoteOff { channel, .. }
+            | MidiMessage::NoteOn { channel, .. }
+            | MidiMessage::PolyPressure { channel, .. }
+            | MidiMessage::ControlChange { channel, .. }
+            | MidiMessage::ProgramChange { channel, .. }
+            | MidiMessage::ChannelPressure { channel, .. }
+            | MidiMessage::PitchBend { channel, .. } => Some(channel),
+            _ => None,
+        }
+    }
+
+    pub fn is_realtime(&self) -> bool {
+        matches!(
+            self,
+            MidiMessage::Clock
+                | MidiMessage::Start
+                | MidiMessage::Continue
+                | MidiMessage::Stop
+                | MidiMessage::ActiveSensing
+                | MidiMessage::Reset
+        )
+    }
+}
+
+/// Number of data bytes following a status byte, `None` for statuses
+/// without a fixed length
+fn data_length(status: u8) -> Option<usize> {
+    match status {
+        0x80..=0xBF | 0xE0..=0xEF => Some(2),
+        0xC0..=0xDF => Some(1),
+        0xF1 | 0xF3 => Some(1),
+        0xF2 => Some(2),
+        0xF6 => Some(0),
+        _ => None,
+    }
+}
+
+pub struct MidiParser {
+    /// Status of the message in progress, kept for running status
+    status: Option<u8>,
+    data: [u8; 2],
+    received: usize,
+    in_sysex: bool,
+    sysex_overflow: bool,
+    sysex: Vec<u8, MAX_SYSEX>,
+}
+
+impl MidiParser {
+    pub fn new() -> Self {
+        Self {
+            status: None,
+            data: [0; 2],
+            received: 0,
+            in_sysex: false,
+            sysex_overflow: false,
+            sysex: Vec::new(),
+        }
+    }
+
+    /// Data of the last complete system exclusive message
+    pub fn sysex(&self) -> &[u8] {
+        if self.in_sysex { &[] } else { &self.sysex }
+    }
+
+    /// Forgets the message in progress and the running status
+    pub fn reset(&mut self) {
+        self.status = None;
+        self.received = 0;
+        self.in_sysex = false;
+        self.sysex.clear();
+    }
+
+    /// Parses the next byte, returns a message once it is complete
+    pub fn parse(&mut self, byte: u8) -> Result<Option<MidiMessage>, MidiError> {
+        match byte {
+            0xF8..=0xFF => self.parse_realtime(byte),
+            0x80..=0xF7 => self.parse_status(byte),
+            _ => self.parse_data(byte),
+        }
+    }
+
+    /// Parses all `bytes`, handing the messages and errors to `handle`
+    pub fn parse_slice(
+        &mut self,
+        bytes: &[u8],
+        mut handle: impl FnMut(Result<MidiMessage, MidiError>),
+    ) {
+        for byte in bytes {
+            match self.parse(*byte) {
+                Ok(Some(message)) => handle(Ok(message)),
+                Ok(None) => {}
+                Err(error) => handle(Err(error)),
+            }
+        }
+    }
+
+    fn parse_realtime(&mut self, byte: u8) -> Result<Option<MidiMessage>, MidiError> {
+        // Realtime bytes leave the message in progress alone
+        let message = match byte {
+            0xF8 => MidiMessage::Clock,
+            0xFA => MidiMessage::Start,
+            0xFB => MidiMessage::Continue,
+            0xFC => MidiMessage::Stop,
+            0xFE => MidiMessage::ActiveSensing,
+            0xFF => MidiMessage::Reset,
+            _ => return Err(MidiError::UndefinedStatus(byte)),
+        };
+        if message == MidiMessage::Reset {
+            self.reset();
+        }
+        Ok(Some(message))
+    }
+
+    fn parse_status(&mut self, byte: u8) -> Result<Option<MidiMessage>, MidiError> {
+        if byte == 0xF7 {
+            if !self.in_sysex {
+                return Err(MidiError::UnexpectedByte(byte));
+            }
+            self.in_sysex = false;
+            if self.sysex_overflow {
+                self.sysex.clear();
+                return Err(MidiError::SysExOverflow);
+            }
+            return Ok(Some(MidiMessage::SysEx {
+                length: self.sysex.len(),
+            }));
+        }
+
+        // Any other status ends the message in progress
+        let error = if self.in_sysex {
+            self.in_sysex = false;
+            self.sysex.clear();
+            Some(MidiError::UnterminatedSysEx)
+        } else if self.received > 0 {
+            Some(MidiError::IncompleteMessage)
+        } else {
+            None
+        };
+        self.received = 0;
+        self.status = None;
+
+        let result = match byte {
+            0xF0 => {
+                self.in_sysex = true;
+                self.sysex_overflow = false;
+                self.sysex.clear();
+                Ok(None)
+            }
+            0xF6 => Ok(Some(MidiMessage::TuneRequest)),
+            _ => match data_length(byte) {
+                Some(_) => {
+                    self.status = Some(byte);
+                    Ok(None)
+                }
+                None => Err(MidiError::UndefinedStatus(byte)),
+            },
+        };
+        match error {
+            Some(error) => Err(error),
+            None => result,
+        }
+    }
+
+    fn parse_data(&mut self, byte: u8) -> Result<Option<MidiMessage>, MidiError> {
+        if self.in_sysex {
+            if self.sysex.push(byte).is_err() {
+                self.sysex_overflow = true;
+            }
+            return Ok(None);
+        }
+        let Some(status) = self.status else {
+            return Err(MidiError::UnexpectedByte(byte));
+        };
+        self.data[self.received] = byte;
+        self.received += 1;
+        if Some(self.received) != data_length(status) {
+            return Ok(None);
+        }
+        self.received = 0;
+
+        // Only channel messages keep their status for the next one
+        if status >= 0xF0 {
+            self.status = None;
+        }
+        let channel = status & 0x0F;
+        let [first, second] = self.data;
+        let message = match status & 0xF0 {
+            0x80 => MidiMessage::NoteOff {
+                channel,
+                note: first,
+                velocity: second,
+            },
+            0x90 if second == 0 => MidiMessage::NoteOff {
+                channel,
+                note: first,
+                velocity: 0,
+            },
+            0x90 => MidiMessage::NoteOn {
+                channel,
+                note: first,
+                velocity: second,
+            },
+            0xA0 => MidiMessage::PolyPressure {
+                channel,
+                note: first,
+                pressure: second,
+            },
+            0xB0 => MidiMessage::ControlChange {
+                channel,
+                controller: first,
+                value: second,
+            },
+            0xC0 => MidiMessage::ProgramChange {
+                channel,
+                program: first,
+            },
+            0xD0 => MidiMessage::ChannelPressure {
+                channel,
+                pressure: first,
+            },
+            0xE0 => MidiMessage::PitchBend {
+                channel,
+                value: (((second as i16) << 7) | first as i16) - 8192,
+            },
+            _ => match status {
+                0xF1 => MidiMessage::TimeCode(first),
+                0xF2 => MidiMessage::SongPosition(((second as u16) << 7) | first as u16),
+                _ => MidiMessage::SongSelect(first),
+            },
+        };
+        Ok(Some(message))
+    }
+}
+
+impl Default for MidiParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Bytes of one encoded message
+pub type MidiBytes = Vec<u8, 3>;
+
+pub struct MidiEncoder {
+    running_status: bool,
+    status: Option<u8>,
+}
+
+impl MidiEncoder {
+    /// With `running_status` channel messages leave out the status byte
+    /// when it repeats
+    pub fn new(running_status: bool) -> Self {
+        Self {
+            running_status,
+            status: None,
+        }
+    }
+
+    /// Sends the next status byte in any case, e.g. after bytes were lost
+    pub fn reset(&mut self) {
+        self.status = None;
+    }
+
+    /// Bytes of the message. Data is cut to 7 bits, system exclusive
+    /// messages have no bytes here, their data isn't part of the message.
+    pub fn encode(&mut self, message: MidiMessage) -> MidiBytes {
+        let (status, data): (u8, &[u8]) = match message {
+            MidiMessage::NoteOff { note, velocity, .. } => (0x80, &[note, velocity]),
+            MidiMessage::NoteOn { note, velocity, .. } => (0x90, &[note, velocity]),
+            MidiMessage::PolyPressure { note, pressure, .. } => (0xA0, &[note, pressure]),
+            MidiMessage::ControlChange {
+                controller, value, ..
+            } => (0xB0, &[controller, value]),
+            MidiMessage::ProgramChange { program, .. } => (0xC0, &[program]),
+            MidiMessage::ChannelPressure { pressure, .. } => (0xD0, &[pressure]),
+            MidiMessage::PitchBend { value, .. } => {
+                let value = (value.clamp(-8192, 8191) + 8192) as u16;
+                (0xE0, &[value as u8, (value >> 7) as u8])
+            }
+            MidiMessage::SysEx { .. } => return Vec::new(),
+            MidiMessage::TimeCode(value) => (0xF1, &[value]),
+            MidiMessage::SongPosition(position) => (0xF2, &[position as u8, (position >> 7) as u8]),
+            MidiMessage::SongSelect(song) => (0xF3, &[song]),
+            MidiMessage::TuneRequest => (0xF6, &[]),
+            MidiMessage::Clock => (0xF8, &[]),
+            MidiMessage::Start => (0xFA, &[]),
+            MidiMessage::Continue => (0xFB, &[]),
+            MidiMessage::Stop => (0xFC, &[]),
+            MidiMessage::ActiveSensing => (0xFE, &[]),
+            MidiMessage::Reset => (0xFF, &[]),
+        };
+        let status = match message.channel() {
+            Some(channel) => status | channel & 0x0F,
+            None => status,
+        };
+
+        let mut bytes = Vec::new();
+        if !(self.running_status && self.status == Some(status)) {
+            let _ = bytes.push(status);
+        }
+        match status {
+            // Realtime messages leave the running status alone
+            0xF8.. => {}
+            0xF0.. => self.status = None,
+            _ => self.status = Some(status),
+        }
+        for byte in data {
+            let _ = bytes.push(byte & 0x7F);
+        }
+        bytes
+    }
+}
+
+/// Sends a control change when a knob moves, with hysteresis against a
+/// value flickering between two steps
+pub struct KnobCc {
+    channel: u8,
+    controller: u8,
+    value: Option<u8>,
+}
+
+impl KnobCc {
+    /// Knobs have to move this many steps beyond the last value sent
+    const HYSTERESIS: f32 = 0.75;
+
+    pub fn new(channel: u8, controller: u8) -> Self {
+        Self {
+            channel: channel & 0x0F,
+            controller: controller & 0x7F,
+            value: None,
+        }
+    }
+
+    /// Takes the knob position (0.0..=1.0), returns a message if the knob
+    /// moved far enough
+    pub fn update(&mut self, knob: f32) -> Option<MidiMessage> {
+        let scaled = knob.clamp(0.0, 1.0) * 127.0;
+        if self
+            .value
+            .is_some_and(|value| (scaled - value as f32).abs() < Self::HYSTERESIS)
+        {
+            return None;
+        }
+        let value = libm::roundf(scaled) as u8;
+        if self.value == Some(value) {
+            return None;
+        }
+        self.value = Some(value);
+        Some(MidiMessage::ControlChange {
+            channel: self.channel,
+            controller: self.controller,
+            value,
+        })
+    }
+}
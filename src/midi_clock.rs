@@ -0,0 +1,126 @@
+// MIDI Clock
+//
+// Generates MIDI clock at 24 pulses per quarter note from an internal tempo,
+// plus start, continue and stop. The pulses are scheduled on the count of
+// audio samples rather than on a timer, so they stay locked to the audio and
+// don't drift: the time of every pulse is computed exactly and rounded to a
+// sample, the rounding never accumulates. Called once per audio block, it
+// returns the messages due within the block with their offset.
+use heapless::Vec;
+
+use crate::midi::MidiMessage;
+
+/// Clock pulses per quarter note
+pub const PPQN: f64 = 24.0;
+/// Most messages of one block, enough for blocks of 2048 samples at 48 kHz
+/// and the fastest tempo
+pub const MAX_CLOCK_MESSAGES: usize = 8;
+
+#[derive(Debug, PartialEq)]
+pub enum MidiClockError {
+    TempoOutOfRange,
+}
+
+/// Messages with their offset in samples within the block
+pub type ClockMessages = Vec<(usize, MidiMessage), MAX_CLOCK_MESSAGES>;
+
+pub struct MidiClock {
+    sample_rate: f32,
+    tempo: f32,
+    samples_per_pulse: f64,
+    running: bool,
+    /// Samples since the clock was created
+    now: u64,
+    /// Time of the next pulse in samples
+    next_pulse: f64,
+    /// Start, continue or stop waiting for the next block
+    transport: Option<MidiMessage>,
+}
+
+impl MidiClock {
+    pub fn new(sample_rate: f32) -> Self {
+        let mut clock = Self {
+            sample_rate,
+            tempo: 120.0,
+            samples_per_pulse: 1.0,
+            running: false,
+            now: 0,
+            next_pulse: 0.0,
+            transport: None,
+        };
+        clock.set_tempo(120.0).expect("Those settings always work");
+        clock
+    }
+
+    /// Beats per minute (20.0..=300.0), takes effect with the next pulse
+    pub fn set_tempo(&mut self, tempo: f32) -> Result<(), MidiClockError> {
+        if !(20.0..=300.0).contains(&tempo) {
+            return Err(MidiClockError::TempoOutOfRange);
+        }
+        let previous = self.samples_per_pulse;
+        self.tempo = tempo;
+        self.samples_per_pulse = self.sample_rate as f64 * 60.0 / (tempo as f64 * PPQN);
+        if self.running {
+            // The pulse already scheduled keeps its distance to the last one
+            // at the new tempo
+            let last_pulse = self.next_pulse - previous;
+            self.next_pulse = (last_pulse + self.samples_per_pulse).max(self.now as f64);
+        }
+        Ok(())
+    }
+
+    pub fn tempo(&self) -> f32 {
+        self.tempo
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// Sends start at the beginning of the next block, followed by the first
+    /// pulse
+    pub fn start(&mut self) {
+        self.transport = Some(MidiMessage::Start);
+        self.restart();
+    }
+
+    /// Like `start`, but the receivers carry on from where they stopped
+    pub fn resume(&mut self) {
+        self.transport = Some(MidiMessage::Continue);
+        self.restart();
+    }
+
+    pub fn stop(&mut self) {
+        self.transport = Some(MidiMessage::Stop);
+        self.running = false;
+    }
+
+    /// Returns the messages due within the next block of `samples`
+    pub fn process(&mut self, samples: usize) -> ClockMessages {
+        let mut messages = Vec::new();
+        if let Some(transport) = self.transport.take() {
+            let _ = messages.push((0, transport));
+        }
+        let end = self.now + samples as u64;
+        if self.running {
+            loop {
+                let pulse = libm::round(self.next_pulse) as u64;
+                if pulse >= end {
+                    break;
+                }
+                let offset = pulse.saturating_sub(self.now) as usize;
+                if messages.push((offset, MidiMessage::Clock)).is_err() {
+                    break;
+                }
+                self.next_pulse += self.samples_per_pulse;
+            }
+        }
+        self.now = end;
+        messages
+    }
+
+    fn restart(&mut self) {
+        self.running = true;
+        self.next_pulse = self.now as f64;
+    }
+}
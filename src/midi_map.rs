@@ -0,0 +1,360 @@
+// MIDI Map
+//
+// Maps MIDI controllers to parameters, identified by their modulation
+// `Destination`. Plain control changes have 7 bits, NRPNs (non-registered
+// parameter numbers, selected with CC 99 and 98 and set with the data entry
+// CCs 6 and 38) have 14 bits. Values come out normalized (0.0..=1.0), to be
+// mapped onto the parameter with its `Range`.
+//
+// In learn mode the next controller that moves gets mapped to the parameter
+// waiting for it. Soft takeover keeps the knobs from making a parameter
+// jump after it has been set over MIDI.
+use heapless::Vec;
+
+use crate::midi::MidiMessage;
+use crate::modulation::Destination;
+
+pub const MAX_MAPPINGS: usize = 16;
+/// Knobs this close to the value take over right away
+const TAKEOVER_DISTANCE: f32 = 0.02;
+
+const CC_DATA_ENTRY: u8 = 6;
+const CC_DATA_ENTRY_LSB: u8 = 38;
+const CC_NRPN_LSB: u8 = 98;
+const CC_NRPN_MSB: u8 = 99;
+const CC_RPN_LSB: u8 = 100;
+const CC_RPN_MSB: u8 = 101;
+
+#[derive(Debug, PartialEq)]
+pub enum MidiMapError {
+    TooManyMappings,
+    ChannelOutOfRange,
+    ControllerOutOfRange,
+    DestinationOutOfRange,
+    InvalidBytes,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Controller {
+    /// 7 bit control change, except the ones for (N)RPNs
+    Cc(u8),
+    /// 14 bit NRPN (0..=16383)
+    Nrpn(u16),
+}
+
+impl Controller {
+    fn is_valid(self) -> bool {
+        match self {
+            Controller::Cc(number) => {
+                number < 120
+                    && !matches!(
+                        number,
+                        CC_DATA_ENTRY
+                            | CC_DATA_ENTRY_LSB
+                            | CC_NRPN_LSB
+                            | CC_NRPN_MSB
+                            | CC_RPN_LSB
+                            | CC_RPN_MSB
+                    )
+            }
+            Controller::Nrpn(number) => number < 1 << 14,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Mapping {
+    /// MIDI channel (0..=15)
+    pub channel: u8,
+    pub controller: Controller,
+    pub destination: Destination,
+}
+
+impl Mapping {
+    /// Bytes of a stored mapping
+    pub const SIZE: usize = 4;
+
+    fn validate(&self) -> Result<(), MidiMapError> {
+        if self.channel > 15 {
+            return Err(MidiMapError::ChannelOutOfRange);
+        }
+        if !self.controller.is_valid() {
+            return Err(MidiMapError::ControllerOutOfRange);
+        }
+        if self.destination.id().is_none() {
+            return Err(MidiMapError::DestinationOutOfRange);
+        }
+        Ok(())
+    }
+
+    /// Channel and kind of controller, the controller number and the
+    /// parameter
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let (kind, number) = match self.controller {
+            Controller::Cc(number) => (0, u16::from(number)),
+            Controller::Nrpn(number) => (1, number),
+        };
+        let [high, low] = number.to_be_bytes();
+        [
+            kind << 4 | self.channel,
+            high,
+            low,
+            self.destination.id().unwrap_or(u8::MAX),
+        ]
+    }
+
+    pub fn from_bytes(bytes: [u8; Self::SIZE]) -> Result<Self, MidiMapError> {
+        let number = u16::from_be_bytes([bytes[1], bytes[2]]);
+        let controller = match bytes[0] >> 4 {
+            0 => Controller::Cc(u8::try_from(number).map_err(|_| MidiMapError::InvalidBytes)?),
+            1 => Controller::Nrpn(number),
+            _ => return Err(MidiMapError::InvalidBytes),
+        };
+        let mapping = Self {
+            channel: bytes[0] & 0x0F,
+            controller,
+            destination: Destination::from_id(bytes[3]).ok_or(MidiMapError::InvalidBytes)?,
+        };
+        mapping.validate()?;
+        Ok(mapping)
+    }
+}
+
+/// NRPN selection and data entry of one channel
+#[derive(Default, Copy, Clone)]
+struct NrpnState {
+    msb: Option<u8>,
+    lsb: Option<u8>,
+    /// An RPN is selected, data entry isn't for us
+    rpn: bool,
+    value_msb: u8,
+}
+
+impl NrpnState {
+    fn parameter(&self) -> Option<u16> {
+        match (self.rpn, self.msb, self.lsb) {
+            (false, Some(msb), Some(lsb)) => Some(u16::from(msb) << 7 | u16::from(lsb)),
+            _ => None,
+        }
+    }
+}
+
+pub struct MidiMap {
+    mappings: Vec<Mapping, MAX_MAPPINGS>,
+    nrpn: [NrpnState; 16],
+    learning: Option<Destination>,
+}
+
+impl MidiMap {
+    pub fn new() -> Self {
+        Self {
+            mappings: Vec::new(),
+            nrpn: [NrpnState::default(); 16],
+            learning: None,
+        }
+    }
+
+    pub fn mappings(&self) -> &[Mapping] {
+        &self.mappings
+    }
+
+    /// Adds a mapping, replacing the one of the same controller
+    pub fn set_mapping(&mut self, mapping: Mapping) -> Result<(), MidiMapError> {
+        mapping.validate()?;
+        match self.mappings.iter_mut().find(|existing| {
+            existing.channel == mapping.channel && existing.controller == mapping.controller
+        }) {
+            Some(existing) => *existing = mapping,
+            None => self
+                .mappings
+                .push(mapping)
+                .map_err(|_| MidiMapError::TooManyMappings)?,
+        }
+        Ok(())
+    }
+
+    /// Removes all mappings to the parameter
+    pub fn remove(&mut self, destination: Destination) {
+        self.mappings
+            .retain(|mapping| mapping.destination != destination);
+    }
+
+    pub fn clear(&mut self) {
+        self.mappings.clear();
+    }
+
+    /// Replaces all mappings, e.g. with stored ones. Leaves the mappings
+    /// alone if any of them is invalid.
+    pub fn restore(&mut self, mappings: &[Mapping]) -> Result<(), MidiMapError> {
+        if mappings.len() > MAX_MAPPINGS {
+            return Err(MidiMapError::TooManyMappings);
+        }
+        mappings.iter().try_for_each(Mapping::validate)?;
+        self.mappings.clear();
+        mappings
+            .iter()
+            .try_for_each(|mapping| self.set_mapping(*mapping))
+    }
+
+    /// Maps the next controller that moves to the parameter, in place of
+    /// the controllers mapped to it so far
+    pub fn learn(&mut self, destination: Destination) -> Result<(), MidiMapError> {
+        if destination.id().is_none() {
+            return Err(MidiMapError::DestinationOutOfRange);
+        }
+        self.learning = Some(destination);
+        Ok(())
+    }
+
+    pub fn cancel_learn(&mut self) {
+        self.learning = None;
+    }
+
+    /// Parameter waiting for a controller in learn mode
+    pub fn learning(&self) -> Option<Destination> {
+        self.learning
+    }
+
+    /// Returns the mapped parameter and its new normalized value if the
+    /// message moves a controller
+    pub fn handle(&mut self, message: MidiMessage) -> Option<(Destination, f32)> {
+        let MidiMessage::ControlChange {
+            channel,
+            controller,
+            value,
+        } = message
+        else {
+            return None;
+        };
+        let (controller, value) = self.controller_value(channel, controller, value)?;
+
+        if let Some(destination) = self.learning {
+            self.remove(destination);
+            let mapping = Mapping {
+                channel,
+                controller,
+                destination,
+            };
+            // Stays in learn mode if there is no room for the mapping
+            if self.set_mapping(mapping).is_ok() {
+                self.learning = None;
+            }
+        }
+
+        self.mappings
+            .iter()
+            .find(|mapping| mapping.channel == channel && mapping.controller == controller)
+            .map(|mapping| (mapping.destination, value))
+    }
+
+    /// Tracks the NRPN selection and returns the controller that moved
+    /// with its normalized value
+    fn controller_value(
+        &mut self,
+        channel: u8,
+        number: u8,
+        value: u8,
+    ) -> Option<(Controller, f32)> {
+        let nrpn = &mut self.nrpn[usize::from(channel & 0x0F)];
+        match number {
+            CC_NRPN_MSB => {
+                nrpn.msb = Some(value);
+                nrpn.rpn = false;
+                None
+            }
+            CC_NRPN_LSB => {
+                nrpn.lsb = Some(value);
+                nrpn.rpn = false;
+                None
+            }
+            CC_RPN_MSB | CC_RPN_LSB => {
+                nrpn.rpn = true;
+                None
+            }
+            // The coarse value right away, the fine one usually follows
+            CC_DATA_ENTRY => {
+                nrpn.value_msb = value;
+                let parameter = nrpn.parameter()?;
+                Some((
+                    Controller::Nrpn(parameter),
+                    f32::from(u16::from(value) << 7) / 16_383.0,
+                ))
+            }
+            CC_DATA_ENTRY_LSB => {
+                let parameter = nrpn.parameter()?;
+                let value = u16::from(nrpn.value_msb) << 7 | u16::from(value);
+                Some((Controller::Nrpn(parameter), f32::from(value) / 16_383.0))
+            }
+            // Channel mode messages
+            120.. => None,
+            _ => Some((Controller::Cc(number), f32::from(value) / 127.0)),
+        }
+    }
+}
+
+impl Default for MidiMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Holds off a knob after its parameter was changed otherwise, until the
+/// knob reaches the new value. The parameter doesn't jump that way. Starts
+/// out with the knob in control.
+pub struct SoftTakeover {
+    value: f32,
+    knob: Option<f32>,
+    engaged: bool,
+}
+
+impl SoftTakeover {
+    pub fn new() -> Self {
+        Self {
+            value: 0.0,
+            knob: None,
+            engaged: true,
+        }
+    }
+
+    /// Sets the parameter from somewhere else, the knob lets go of it
+    pub fn set_value(&mut self, value: f32) {
+        self.value = value;
+        self.engaged = self
+            .knob
+            .is_some_and(|knob| (knob - value).abs() < TAKEOVER_DISTANCE);
+    }
+
+    pub fn value(&self) -> f32 {
+        self.value
+    }
+
+    /// Whether the knob controls the parameter
+    pub fn is_engaged(&self) -> bool {
+        self.engaged
+    }
+
+    /// Feeds in the knob position, returns the new value once the knob
+    /// controls the parameter
+    pub fn update(&mut self, knob: f32) -> Option<f32> {
+        if !self.engaged {
+            // The knob has to come close or pass the value
+            let crossed = self
+                .knob
+                .is_some_and(|previous| (previous - self.value) * (knob - self.value) <= 0.0);
+            self.engaged = crossed || (knob - self.value).abs() < TAKEOVER_DISTANCE;
+        }
+        self.knob = Some(knob);
+        if self.engaged {
+            self.value = knob;
+            Some(knob)
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for SoftTakeover {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,324 @@
+// Modulation
+//
+// Modulation matrix: routes sources (LFOs, envelopes, knobs, MIDI CCs,
+// envelope followers) with a depth to destination parameters. Everything is
+// summed in normalized space, where every parameter spans 0.0..=1.0 of its
+// `Range`, so a depth of 1.0 sweeps the whole range no matter whether the
+// parameter is a frequency in Hz or a gain in dB. The sums are smoothed and
+// updated once per block, the caller feeds in the sources before `process`.
+use heapless::Vec;
+
+use crate::filter::{FilterParams, one_pole_coefficient};
+
+pub const MAX_ROUTES: usize = 16;
+pub const MAX_LFOS: usize = 4;
+pub const MAX_ENVELOPES: usize = 4;
+pub const MAX_KNOBS: usize = 8;
+pub const MAX_FOLLOWERS: usize = 2;
+/// General purpose destinations for anything without its own variant
+pub const MAX_CUSTOM: usize = 8;
+
+const CC_COUNT: usize = 128;
+const SOURCE_COUNT: usize = MAX_LFOS + MAX_ENVELOPES + MAX_KNOBS + CC_COUNT + MAX_FOLLOWERS;
+const DESTINATION_COUNT: usize = 3 + MAX_CUSTOM;
+
+#[derive(Debug, PartialEq)]
+pub enum ModulationError {
+    SourceOutOfRange,
+    DestinationOutOfRange,
+    DepthOutOfRange,
+    TooManyRoutes,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Source {
+    /// Bipolar (-1.0..=1.0)
+    Lfo(u8),
+    Envelope(u8),
+    Knob(u8),
+    /// MIDI control change by controller number
+    Cc(u8),
+    Follower(u8),
+}
+
+impl Source {
+    fn index(self) -> Option<usize> {
+        let (offset, index, count) = match self {
+            Source::Lfo(index) => (0, index, MAX_LFOS),
+            Source::Envelope(index) => (MAX_LFOS, index, MAX_ENVELOPES),
+            Source::Knob(index) => (MAX_LFOS + MAX_ENVELOPES, index, MAX_KNOBS),
+            Source::Cc(index) => (MAX_LFOS + MAX_ENVELOPES + MAX_KNOBS, index, CC_COUNT),
+            Source::Follower(index) => (SOURCE_COUNT - MAX_FOLLOWERS, index, MAX_FOLLOWERS),
+        };
+        (usize::from(index) < count).then_some(offset + usize::from(index))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Destination {
+    FilterFrequency,
+    FilterQuality,
+    FilterGain,
+    Custom(u8),
+}
+
+impl Destination {
+    fn index(self) -> Option<usize> {
+        match self {
+            Destination::FilterFrequency => Some(0),
+            Destination::FilterQuality => Some(1),
+            Destination::FilterGain => Some(2),
+            Destination::Custom(index) => {
+                (usize::from(index) < MAX_CUSTOM).then_some(3 + usize::from(index))
+            }
+        }
+    }
+
+    /// Stable number of the parameter, for storing references to it
+    pub fn id(self) -> Option<u8> {
+        self.index().map(|index| index as u8)
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Destination::FilterFrequency),
+            1 => Some(Destination::FilterQuality),
+            2 => Some(Destination::FilterGain),
+            _ => Destination::Custom(id - 3)
+                .id()
+                .map(|_| Destination::Custom(id - 3)),
+        }
+    }
+}
+
+/// Maps a parameter to and from normalized space
+#[derive(Copy, Clone, PartialEq)]
+pub struct Range {
+    pub min: f32,
+    pub max: f32,
+    /// Equal steps in normalized space are equal ratios, for frequencies.
+    /// Both ends must be positive.
+    pub exponential: bool,
+}
+
+impl Range {
+    pub const FILTER_FREQUENCY: Range = Range::exponential(20.0, 20_000.0);
+    pub const FILTER_QUALITY: Range = Range::linear(0.1, 6.0);
+    pub const FILTER_GAIN: Range = Range::linear(-24.0, 24.0);
+
+    pub const fn linear(min: f32, max: f32) -> Self {
+        Self {
+            min,
+            max,
+            exponential: false,
+        }
+    }
+
+    pub const fn exponential(min: f32, max: f32) -> Self {
+        Self {
+            min,
+            max,
+            exponential: true,
+        }
+    }
+
+    /// Whether `value` lies within the range, never for NaN
+    pub fn contains(&self, value: f32) -> bool {
+        (self.min..=self.max).contains(&value)
+    }
+
+    pub fn normalize(&self, value: f32) -> f32 {
+        let normalized = if self.exponential {
+            libm::logf(value / self.min) / libm::logf(self.max / self.min)
+        } else {
+            (value - self.min) / (self.max - self.min)
+        };
+        normalized.clamp(0.0, 1.0)
+    }
+
+    pub fn denormalize(&self, normalized: f32) -> f32 {
+        let normalized = normalized.clamp(0.0, 1.0);
+        if self.exponential {
+            self.min * libm::powf(self.max / self.min, normalized)
+        } else {
+            self.min + normalized * (self.max - self.min)
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Route {
+    pub source: Source,
+    pub destination: Destination,
+    /// Share of the destination range covered by the full source (-1.0..=1.0)
+    pub depth: f32,
+}
+
+pub struct ModulationMatrix {
+    routes: Vec<Route, MAX_ROUTES>,
+    sources: [f32; SOURCE_COUNT],
+    targets: [f32; DESTINATION_COUNT],
+    smoothed: [f32; DESTINATION_COUNT],
+    block_rate: f32,
+    smoothing: f32,
+}
+
+impl ModulationMatrix {
+    /// `block_rate` is the number of calls to `process` per second
+    pub fn new(block_rate: f32) -> Self {
+        let mut matrix = Self {
+            routes: Vec::new(),
+            sources: [0.0; SOURCE_COUNT],
+            targets: [0.0; DESTINATION_COUNT],
+            smoothed: [0.0; DESTINATION_COUNT],
+            block_rate,
+            smoothing: 1.0,
+        };
+        matrix.set_smoothing(5.0);
+        matrix
+    }
+
+    /// Time constant of the smoothing of every destination
+    pub fn set_smoothing(&mut self, time_ms: f32) {
+        self.smoothing = one_pole_coefficient(time_ms.max(0.0), self.block_rate);
+    }
+
+    /// Adds a route, or updates the depth if the source is already routed to
+    /// the destination
+    pub fn set_route(&mut self, route: Route) -> Result<(), ModulationError> {
+        if route.source.index().is_none() {
+            return Err(ModulationError::SourceOutOfRange);
+        }
+        if route.destination.index().is_none() {
+            return Err(ModulationError::DestinationOutOfRange);
+        }
+        if !(-1.0..=1.0).contains(&route.depth) {
+            return Err(ModulationError::DepthOutOfRange);
+        }
+        match self
+            .routes
+            .iter_mut()
+            .find(|r| r.source == route.source && r.destination == route.destination)
+        {
+            Some(existing) => existing.depth = route.depth,
+            None => self
+                .routes
+                .push(route)
+                .map_err(|_| ModulationError::TooManyRoutes)?,
+        }
+        Ok(())
+    }
+
+    pub fn remove_route(&mut self, source: Source, destination: Destination) {
+        self.routes
+            .retain(|route| route.source != source || route.destination != destination);
+    }
+
+    pub fn clear_routes(&mut self) {
+        self.routes.clear();
+    }
+
+    pub fn routes(&self) -> &[Route] {
+        &self.routes
+    }
+
+    /// Sets the current value of a source, unipolar sources span 0.0..=1.0
+    pub fn set_source(&mut self, source: Source, value: f32) -> Result<(), ModulationError> {
+        let index = source.index().ok_or(ModulationError::SourceOutOfRange)?;
+        self.sources[index] = value;
+        Ok(())
+    }
+
+    /// Sums the routes and smoothes the result, once per block
+    pub fn process(&mut self) {
+        self.targets.fill(0.0);
+        for route in &self.routes {
+            if let (Some(source), Some(destination)) =
+                (route.source.index(), route.destination.index())
+            {
+                self.targets[destination] += self.sources[source] * route.depth;
+            }
+        }
+        for (smoothed, target) in self.smoothed.iter_mut().zip(&self.targets) {
+            *smoothed += self.smoothing * (target - *smoothed);
+        }
+    }
+
+    /// Smoothed sum of all routes to the destination in normalized space
+    pub fn offset(&self, destination: Destination) -> f32 {
+        destination
+            .index()
+            .map_or(0.0, |index| self.smoothed[index])
+    }
+
+    /// Applies the modulation to a parameter with its base value
+    pub fn modulate(&self, destination: Destination, base: f32, range: Range) -> f32 {
+        range.denormalize(range.normalize(base) + self.offset(destination))
+    }
+
+    pub fn modulate_filter(&self, base: FilterParams) -> FilterParams {
+        FilterParams {
+            frequency: self.modulate(
+                Destination::FilterFrequency,
+                base.frequency,
+                Range::FILTER_FREQUENCY,
+            ),
+            quality: self.modulate(
+                Destination::FilterQuality,
+                base.quality,
+                Range::FILTER_QUALITY,
+            ),
+            gain: self.modulate(Destination::FilterGain, base.gain, Range::FILTER_GAIN),
+        }
+    }
+}
+
+/// Follows the level of a signal, as unipolar modulation source
+pub struct EnvelopeFollower {
+    sample_rate: f32,
+    attack: f32,
+    release: f32,
+    value: f32,
+}
+
+impl EnvelopeFollower {
+    pub fn new(sample_rate: f32) -> Self {
+        let mut follower = Self {
+            sample_rate,
+            attack: 1.0,
+            release: 1.0,
+            value: 0.0,
+        };
+        follower.set_times(5.0, 100.0);
+        follower
+    }
+
+    pub fn set_times(&mut self, attack_ms: f32, release_ms: f32) {
+        self.attack = one_pole_coefficient(attack_ms.max(0.0), self.sample_rate);
+        self.release = one_pole_coefficient(release_ms.max(0.0), self.sample_rate);
+    }
+
+    #[inline]
+    pub fn tick(&mut self, input: f32) -> f32 {
+        let level = input.abs();
+        let coefficient = if level > self.value {
+            self.attack
+        } else {
+            self.release
+        };
+        self.value += coefficient * (level - self.value);
+        self.value
+    }
+
+    /// Follows a block of samples and returns the level at its end
+    pub fn process(&mut self, block: &[f32]) -> f32 {
+        for sample in block {
+            self.tick(*sample);
+        }
+        self.value
+    }
+
+    pub fn value(&self) -> f32 {
+        self.value
+    }
+}
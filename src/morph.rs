@@ -0,0 +1,147 @@
+// Morph
+//
+// Interpolates between two parameter sets, A at an amount of 0.0 and B at
+// 1.0, so that one knob fades from one sound into another. Continuous
+// parameters move along the curve of their `Range`: a frequency halfway
+// between 100 Hz and 400 Hz is 200 Hz, not 250 Hz. Stepped parameters like
+// the filter type can't be in between, they switch from A to B once the
+// amount crosses a threshold. `MorphControl` adds some hysteresis to that,
+// so that a noisy knob resting on the threshold doesn't flip them back and
+// forth.
+use crate::filter::{FilterParams, FilterType};
+use crate::modulation::Range;
+use crate::preset::Preset;
+use crate::spectral::SpectralMode;
+
+/// Stepped parameters switch halfway by default
+pub const DEFAULT_THRESHOLD: f32 = 0.5;
+/// How far the amount has to go back past the threshold to switch back
+const HYSTERESIS: f32 = 0.02;
+
+#[derive(Debug, PartialEq)]
+pub enum MorphError {
+    ThresholdOutOfRange,
+}
+
+/// A set of parameters that can be interpolated
+pub trait Morph {
+    /// `self` at an `amount` of 0.0 and `other` at 1.0. Stepped parameters
+    /// are the ones of `other` from `threshold` on.
+    fn morph(&self, other: &Self, amount: f32, threshold: f32) -> Self;
+}
+
+/// Interpolates a continuous parameter along the curve of its range. Values
+/// outside of the range are not clamped, but an exponential range needs
+/// both to be positive.
+pub fn interpolate(range: &Range, a: f32, b: f32, amount: f32) -> f32 {
+    let amount = amount.clamp(0.0, 1.0);
+    if range.exponential {
+        a * libm::powf(b / a, amount)
+    } else {
+        a + (b - a) * amount
+    }
+}
+
+/// Picks a stepped parameter
+pub fn step<T: Copy>(a: T, b: T, amount: f32, threshold: f32) -> T {
+    if amount >= threshold { b } else { a }
+}
+
+impl Morph for FilterParams {
+    fn morph(&self, other: &Self, amount: f32, _threshold: f32) -> Self {
+        Self {
+            frequency: interpolate(
+                &Range::FILTER_FREQUENCY,
+                self.frequency,
+                other.frequency,
+                amount,
+            ),
+            quality: interpolate(&Range::FILTER_QUALITY, self.quality, other.quality, amount),
+            gain: interpolate(&Range::FILTER_GAIN, self.gain, other.gain, amount),
+        }
+    }
+}
+
+impl Morph for FilterType {
+    fn morph(&self, other: &Self, amount: f32, threshold: f32) -> Self {
+        step(*self, *other, amount, threshold)
+    }
+}
+
+impl Morph for SpectralMode {
+    fn morph(&self, other: &Self, amount: f32, threshold: f32) -> Self {
+        step(*self, *other, amount, threshold)
+    }
+}
+
+impl Morph for Preset {
+    fn morph(&self, other: &Self, amount: f32, threshold: f32) -> Self {
+        Self {
+            filter_type: self
+                .filter_type
+                .morph(&other.filter_type, amount, threshold),
+            filter: self.filter.morph(&other.filter, amount, threshold),
+            spectral_mode: self
+                .spectral_mode
+                .morph(&other.spectral_mode, amount, threshold),
+        }
+    }
+}
+
+/// Morph amount set by a knob or controller
+pub struct MorphControl {
+    amount: f32,
+    threshold: f32,
+    /// Stepped parameters are the ones of B
+    switched: bool,
+}
+
+impl MorphControl {
+    pub fn new() -> Self {
+        Self {
+            amount: 0.0,
+            threshold: DEFAULT_THRESHOLD,
+            switched: false,
+        }
+    }
+
+    pub fn set_threshold(&mut self, threshold: f32) -> Result<(), MorphError> {
+        if !(0.0..=1.0).contains(&threshold) {
+            return Err(MorphError::ThresholdOutOfRange);
+        }
+        self.threshold = threshold;
+        self.switched = self.amount >= threshold;
+        Ok(())
+    }
+
+    pub fn set_amount(&mut self, amount: f32) {
+        self.amount = amount.clamp(0.0, 1.0);
+        if self.switched {
+            self.switched = self.amount >= self.threshold - HYSTERESIS;
+        } else {
+            self.switched = self.amount >= (self.threshold + HYSTERESIS).min(1.0);
+        }
+    }
+
+    pub fn amount(&self) -> f32 {
+        self.amount
+    }
+
+    /// Stepped parameters are the ones of B
+    pub fn is_switched(&self) -> bool {
+        self.switched
+    }
+
+    /// The parameters at the current amount
+    pub fn apply<T: Morph>(&self, a: &T, b: &T) -> T {
+        // The hysteresis already picked the side of the stepped parameters
+        let threshold = if self.switched { 0.0 } else { f32::INFINITY };
+        a.morph(b, self.amount, threshold)
+    }
+}
+
+impl Default for MorphControl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,323 @@
+// Noise
+//
+// Noise generators on top of the xorshift generator of `random`. White noise
+// has the same power at all frequencies, pink noise falls by 3 dB and brown
+// noise by 6 dB per octave. Pink noise uses the Voss-McCartney algorithm: a
+// number of random rows, row `k` renewed every 2^(k+1) samples, add up to an
+// approximately pink spectrum without any filtering. Velvet noise is a
+// sparse series of impulses of random sign at random positions, which
+// sounds smooth at a fraction of the cost for decorrelation and reverbs.
+//
+// The sample-and-hold source picks random values at a rate or on external
+// triggers and glides between them, for modulation. It reuses the random
+// waveform of the LFO.
+use crate::filter::one_pole_coefficient;
+use crate::lfo::{Lfo, LfoError, LfoParams, LfoWaveform};
+use crate::random::Random;
+
+/// Rows of the pink noise generator, pink down to fs / 2^16 (~0.7 Hz)
+const PINK_ROWS: usize = 16;
+/// Brings the sum of the pink rows to an RMS of about 0.2, peaks beyond
+/// full scale are very rare
+const PINK_GAIN: f32 = 1.0 / 12.0;
+/// Leak of the brown noise integrator, keeps it from drifting away below
+/// about fs / 3000
+const BROWN_LEAK: f32 = 0.998;
+
+#[derive(Debug)]
+pub enum NoiseError {
+    DensityOutOfRange,
+    RateNegative,
+    RateOverNyquist,
+}
+
+impl From<LfoError> for NoiseError {
+    fn from(error: LfoError) -> Self {
+        match error {
+            LfoError::FrequencyNegative => NoiseError::RateNegative,
+            LfoError::FrequencyOverNyquist => NoiseError::RateOverNyquist,
+        }
+    }
+}
+
+/// Uniformly distributed white noise
+pub struct WhiteNoise {
+    random: Random,
+}
+
+impl WhiteNoise {
+    pub fn new(seed: u32) -> Self {
+        Self {
+            random: Random::new(seed),
+        }
+    }
+
+    #[inline]
+    pub fn tick(&mut self) -> f32 {
+        self.random.bipolar()
+    }
+
+    /// Triangular distribution (-1.0..1.0) as the sum of two uniform values,
+    /// scaled to one LSB this is the usual dither before truncation
+    #[inline]
+    pub fn triangular(&mut self) -> f32 {
+        0.5 * (self.random.bipolar() + self.random.bipolar())
+    }
+
+    /// Writes the noise to both channels of the buffer
+    pub fn process(&mut self, audio_buffer: &mut [(f32, f32)]) {
+        for frame in audio_buffer.iter_mut() {
+            let sample = self.tick();
+            *frame = (sample, sample);
+        }
+    }
+}
+
+pub struct PinkNoise {
+    random: Random,
+    rows: [f32; PINK_ROWS],
+    sum: f32,
+    counter: u32,
+}
+
+impl PinkNoise {
+    pub fn new(seed: u32) -> Self {
+        let mut random = Random::new(seed);
+        let rows: [f32; PINK_ROWS] = core::array::from_fn(|_| random.bipolar());
+        Self {
+            random,
+            rows,
+            sum: rows.iter().sum(),
+            counter: 0,
+        }
+    }
+
+    #[inline]
+    pub fn tick(&mut self) -> f32 {
+        // The number of trailing zeros of the counter picks the row, row k
+        // comes up every 2^(k+1) samples
+        self.counter = self.counter.wrapping_add(1);
+        let row = (self.counter.trailing_zeros() as usize).min(PINK_ROWS - 1);
+        let value = self.random.bipolar();
+        self.sum += value - self.rows[row];
+        self.rows[row] = value;
+        // One more white value fills in the top octave
+        (self.sum + self.random.bipolar()) * PINK_GAIN
+    }
+
+    /// Writes the noise to both channels of the buffer
+    pub fn process(&mut self, audio_buffer: &mut [(f32, f32)]) {
+        for frame in audio_buffer.iter_mut() {
+            let sample = self.tick();
+            *frame = (sample, sample);
+        }
+    }
+}
+
+/// Integrated white noise, also known as red noise
+pub struct BrownNoise {
+    random: Random,
+    value: f32,
+}
+
+impl BrownNoise {
+    pub fn new(seed: u32) -> Self {
+        Self {
+            random: Random::new(seed),
+            value: 0.0,
+        }
+    }
+
+    #[inline]
+    pub fn tick(&mut self) -> f32 {
+        self.value = BROWN_LEAK * self.value + 0.1 * self.random.bipolar();
+        // The leaky integrator settles at an RMS of about 0.9, scaled to
+        // about 0.23 the peaks stay in range nearly all of the time
+        (self.value * 0.25).clamp(-1.0, 1.0)
+    }
+
+    /// Writes the noise to both channels of the buffer
+    pub fn process(&mut self, audio_buffer: &mut [(f32, f32)]) {
+        for frame in audio_buffer.iter_mut() {
+            let sample = self.tick();
+            *frame = (sample, sample);
+        }
+    }
+}
+
+pub struct VelvetNoise {
+    random: Random,
+    sample_rate: f32,
+    density: f32,
+    /// Distance between the impulses on average
+    period: f32,
+    /// Fractional part of the periods so far, so that the density is exact
+    /// on average
+    fraction: f32,
+    length: u32,
+    elapsed: u32,
+    /// Sample of the current period that holds the impulse
+    impulse: u32,
+    sign: f32,
+}
+
+impl VelvetNoise {
+    /// Impulses per second by default, sounds smooth like white noise
+    pub const DEFAULT_DENSITY: f32 = 2000.0;
+
+    pub fn new(sample_rate: f32, seed: u32) -> Self {
+        let mut noise = Self {
+            random: Random::new(seed),
+            sample_rate,
+            density: Self::DEFAULT_DENSITY,
+            period: 1.0,
+            fraction: 0.0,
+            length: 0,
+            elapsed: 0,
+            impulse: 0,
+            sign: 1.0,
+        };
+        noise
+            .set_density(Self::DEFAULT_DENSITY)
+            .expect("Those settings always work");
+        noise
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) -> Result<(), NoiseError> {
+        let previous = self.sample_rate;
+        self.sample_rate = sample_rate;
+        if let Err(error) = self.set_density(self.density) {
+            self.sample_rate = previous;
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    /// Impulses per second, at most one per sample
+    pub fn set_density(&mut self, density: f32) -> Result<(), NoiseError> {
+        if density <= 0.0 || density > self.sample_rate {
+            return Err(NoiseError::DensityOutOfRange);
+        }
+        self.density = density;
+        self.period = self.sample_rate / density;
+        Ok(())
+    }
+
+    #[inline]
+    pub fn tick(&mut self) -> f32 {
+        if self.elapsed >= self.length {
+            self.start_period();
+        }
+        let output = if self.elapsed == self.impulse {
+            self.sign
+        } else {
+            0.0
+        };
+        self.elapsed += 1;
+        output
+    }
+
+    /// Writes the noise to both channels of the buffer
+    pub fn process(&mut self, audio_buffer: &mut [(f32, f32)]) {
+        for frame in audio_buffer.iter_mut() {
+            let sample = self.tick();
+            *frame = (sample, sample);
+        }
+    }
+
+    /// Places one impulse at a random position of the next period
+    fn start_period(&mut self) {
+        self.fraction += self.period;
+        let length = libm::floorf(self.fraction);
+        self.fraction -= length;
+        self.length = (length as u32).max(1);
+        self.elapsed = 0;
+        self.impulse = (self.random.unipolar() * self.length as f32) as u32;
+        self.sign = if self.random.next_u32() & 1 == 0 {
+            1.0
+        } else {
+            -1.0
+        };
+    }
+}
+
+/// Random values held for a while, stepping or gliding from one to the next.
+/// The values and the rate come from an `Lfo` with the sample-and-hold
+/// waveform, this adds the glide.
+pub struct SampleAndHold {
+    lfo: Lfo,
+    sample_rate: f32,
+    value: f32,
+    slew_ms: f32,
+    slew: f32,
+}
+
+impl SampleAndHold {
+    pub fn new(sample_rate: f32, seed: u32) -> Self {
+        let mut lfo = Lfo::new(sample_rate, seed);
+        lfo.set_params(LfoParams {
+            waveform: LfoWaveform::SampleAndHold,
+            frequency: 0.0,
+            phase: 0.0,
+        })
+        .expect("Those settings always work");
+        lfo.trigger();
+        Self {
+            value: lfo.value(),
+            lfo,
+            sample_rate,
+            slew_ms: 0.0,
+            slew: 1.0,
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) -> Result<(), NoiseError> {
+        self.lfo.set_sample_rate(sample_rate)?;
+        self.sample_rate = sample_rate;
+        self.set_slew(self.slew_ms);
+        Ok(())
+    }
+
+    /// New values per second, 0.0 only steps on `trigger`
+    pub fn set_rate(&mut self, rate: f32) -> Result<(), NoiseError> {
+        self.lfo.set_params(LfoParams {
+            frequency: rate,
+            ..*self.lfo.params()
+        })?;
+        Ok(())
+    }
+
+    /// Time constant of the glide to a new value, 0.0 steps
+    pub fn set_slew(&mut self, time_ms: f32) {
+        self.slew_ms = time_ms.max(0.0);
+        self.slew = one_pole_coefficient(self.slew_ms, self.sample_rate);
+    }
+
+    /// Picks a new value now, e.g. on a clock or gate input
+    pub fn trigger(&mut self) {
+        self.lfo.trigger();
+    }
+
+    /// Last output (-1.0..=1.0)
+    pub fn value(&self) -> f32 {
+        self.value
+    }
+
+    #[inline]
+    pub fn tick(&mut self) -> f32 {
+        self.advance(1)
+    }
+
+    /// Moves on by `samples` and returns the output there, for control rate
+    /// modulation once per block
+    pub fn advance(&mut self, samples: usize) -> f32 {
+        let target = self.lfo.advance(samples);
+        // The glide runs per block as well, compensated for its length
+        let coefficient = match samples {
+            1 => self.slew,
+            _ => 1.0 - libm::powf(1.0 - self.slew, samples as f32),
+        };
+        self.value += coefficient * (target - self.value);
+        self.value
+    }
+}
@@ -0,0 +1,140 @@
+// Octaver
+//
+// Analog style octave effect. The octave up is generated by full wave
+// rectification, the octave down by a flip-flop that toggles on every
+// positive zero crossing and is shaped by the envelope of the input, just
+// like the classic divider pedals. Both only track monophonic input well.
+use crate::filter::{Filter, FilterError, FilterParams, FilterType, one_pole_coefficient};
+
+const INPUT_CUTOFF: f32 = 800.0;
+const SUB_CUTOFF: f32 = 600.0;
+const UP_CUTOFF: f32 = 4_000.0;
+const DC_CUTOFF: f32 = 20.0;
+const ENVELOPE_ATTACK_MS: f32 = 1.0;
+const ENVELOPE_RELEASE_MS: f32 = 50.0;
+const HYSTERESIS: f32 = 1.0e-3;
+
+#[derive(Copy, Clone, PartialEq)]
+pub struct OctaverParams {
+    /// Level of the unprocessed input
+    pub dry: f32,
+    /// Level of the octave below
+    pub down: f32,
+    /// Level of the octave above
+    pub up: f32,
+}
+
+impl Default for OctaverParams {
+    fn default() -> Self {
+        Self {
+            dry: 1.0,
+            down: 0.5,
+            up: 0.0,
+        }
+    }
+}
+
+pub struct Octaver {
+    params: OctaverParams,
+    input_filter: Filter,
+    sub_filter: Filter,
+    up_filter: Filter,
+    dc_filter: Filter,
+    envelope: f32,
+    attack: f32,
+    release: f32,
+    previous: f32,
+    flip_flop: f32,
+}
+
+impl Octaver {
+    pub fn new() -> Self {
+        let mut octaver = Self {
+            params: OctaverParams::default(),
+            input_filter: Filter::new(FilterType::Lowpass),
+            sub_filter: Filter::new(FilterType::Lowpass),
+            up_filter: Filter::new(FilterType::Lowpass),
+            dc_filter: Filter::new(FilterType::Lowpass),
+            envelope: 0.0,
+            attack: 0.0,
+            release: 0.0,
+            previous: 0.0,
+            flip_flop: 1.0,
+        };
+        octaver
+            .set_sample_rate(48000.0)
+            .expect("Those settings always work");
+        octaver
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) -> Result<(), FilterError> {
+        for (filter, frequency) in [
+            (&mut self.input_filter, INPUT_CUTOFF),
+            (&mut self.sub_filter, SUB_CUTOFF),
+            (&mut self.up_filter, UP_CUTOFF),
+            (&mut self.dc_filter, DC_CUTOFF),
+        ] {
+            filter.set_sample_rate(sample_rate)?;
+            filter.set_params(FilterParams {
+                frequency,
+                quality: 0.71,
+                gain: 0.0,
+            })?;
+        }
+        self.attack = one_pole_coefficient(ENVELOPE_ATTACK_MS, sample_rate);
+        self.release = one_pole_coefficient(ENVELOPE_RELEASE_MS, sample_rate);
+        Ok(())
+    }
+
+    pub fn set_params(&mut self, params: OctaverParams) {
+        self.params = params;
+    }
+
+    #[inline]
+    pub fn tick(&mut self, input: f32) -> f32 {
+        // Band limit the input so harmonics don't trigger extra zero crossings
+        let conditioned = self.input_filter.tick(input);
+
+        let rectified = conditioned.abs();
+        let coefficient = if rectified > self.envelope {
+            self.attack
+        } else {
+            self.release
+        };
+        self.envelope += coefficient * (rectified - self.envelope);
+
+        if self.previous < -HYSTERESIS && conditioned >= HYSTERESIS {
+            self.flip_flop = -self.flip_flop;
+        }
+        if conditioned.abs() >= HYSTERESIS {
+            self.previous = conditioned;
+        }
+
+        let down = self.sub_filter.tick(self.flip_flop * self.envelope);
+
+        // The rectified signal carries a large DC offset, remove it with a
+        // highpass built from the lowpass core (x - lowpass(x))
+        let full_wave = input.abs();
+        let up = self
+            .up_filter
+            .tick(full_wave - self.dc_filter.tick(full_wave));
+
+        self.params.dry * input + self.params.down * down + self.params.up * up
+    }
+
+    pub fn reset(&mut self) {
+        self.input_filter.reset();
+        self.sub_filter.reset();
+        self.up_filter.reset();
+        self.dc_filter.reset();
+        self.envelope = 0.0;
+        self.previous = 0.0;
+        self.flip_flop = 1.0;
+    }
+}
+
+impl Default for Octaver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,364 @@
+// Oscillator
+//
+// Band-limited oscillators for subtractive synthesis. The naive waveforms
+// are corrected around their discontinuities with polynomial band-limited
+// steps (PolyBLEP) and around the corners of the triangle with integrated
+// steps (PolyBLAMP), which removes most of the aliasing for a handful of
+// operations per sample. The corrections reach one sample ahead, so the
+// output is delayed by one sample.
+//
+// The phase may run backwards, which allows through-zero FM, and another
+// oscillator can reset it in between two samples for hard sync.
+use micromath::F32Ext;
+
+/// Fastest phase increment per sample, anything faster folds over
+const MAX_INCREMENT: f32 = 0.5;
+/// Narrowest pulse, keeps the edges of the square apart
+const MIN_PULSE_WIDTH: f32 = 0.02;
+
+#[derive(Debug)]
+pub enum OscillatorError {
+    FrequencyOverNyquist,
+    FrequencyNegative,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum Waveform {
+    #[default]
+    Sine,
+    Saw,
+    Square,
+    Triangle,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub struct OscillatorParams {
+    pub waveform: Waveform,
+    pub frequency: f32,
+    /// Duty cycle of the square (0.0..=1.0, clamped to keep a pulse)
+    pub pulse_width: f32,
+    /// Depth of the linear FM, a modulator of 1.0 at an index of 1.0
+    /// doubles the frequency and -1.0 stops the phase
+    pub fm_index: f32,
+}
+
+impl Default for OscillatorParams {
+    fn default() -> Self {
+        Self {
+            waveform: Waveform::Sine,
+            frequency: 440.0,
+            pulse_width: 0.5,
+            fm_index: 0.0,
+        }
+    }
+}
+
+/// A point of the waveform where the value jumps by `step` or the slope
+/// (per cycle) changes by `bend`
+#[derive(Copy, Clone)]
+struct Breakpoint {
+    phase: f32,
+    step: f32,
+    bend: f32,
+}
+
+pub struct Oscillator {
+    params: OscillatorParams,
+    sample_rate: f32,
+    pulse_width: f32,
+    increment: f32,
+    phase: f32,
+    next_sample: f32,
+    sync: Option<f32>,
+    wrap: Option<f32>,
+}
+
+impl Oscillator {
+    /// Delay of the output in samples
+    pub const LATENCY: usize = 1;
+
+    pub fn new() -> Self {
+        let mut oscillator = Self {
+            params: OscillatorParams::default(),
+            sample_rate: 48000.0,
+            pulse_width: 0.5,
+            increment: 0.0,
+            phase: 0.0,
+            next_sample: 0.0,
+            sync: None,
+            wrap: None,
+        };
+        oscillator.update().expect("Those settings always work");
+        oscillator
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) -> Result<(), OscillatorError> {
+        if self.sample_rate != sample_rate {
+            self.sample_rate = sample_rate;
+            self.update()?;
+        }
+        Ok(())
+    }
+
+    pub fn set_params(&mut self, params: OscillatorParams) -> Result<(), OscillatorError> {
+        if self.params != params {
+            let previous = self.params;
+            self.params = params;
+            if let Err(error) = self.update() {
+                self.params = previous;
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
+    /// Shortcut for the parameter that changes with every note
+    pub fn set_frequency(&mut self, frequency: f32) -> Result<(), OscillatorError> {
+        self.set_params(OscillatorParams {
+            frequency,
+            ..self.params
+        })
+    }
+
+    pub fn params(&self) -> OscillatorParams {
+        self.params
+    }
+
+    /// Resets the phase in the next `tick`, `elapsed` samples (0.0..1.0)
+    /// before that output sample, see `wrap`
+    pub fn sync(&mut self, elapsed: Option<f32>) {
+        self.sync = elapsed;
+    }
+
+    /// If the phase wrapped around during the last `tick`, the time since
+    /// then in samples. Passing it to `sync` of another oscillator ticked
+    /// right afterwards hard syncs that one to this one.
+    pub fn wrap(&self) -> Option<f32> {
+        self.wrap
+    }
+
+    pub fn reset(&mut self) {
+        self.phase = 0.0;
+        self.next_sample = 0.0;
+        self.sync = None;
+        self.wrap = None;
+    }
+
+    #[inline]
+    pub fn tick(&mut self) -> f32 {
+        self.tick_fm(0.0)
+    }
+
+    /// Runs at the frequency scaled by `1.0 + fm_index * modulator`, which
+    /// may turn negative to run the phase backwards (through-zero FM)
+    #[inline]
+    pub fn tick_fm(&mut self, modulator: f32) -> f32 {
+        let increment = (self.increment * (1.0 + self.params.fm_index * modulator))
+            .clamp(-MAX_INCREMENT, MAX_INCREMENT);
+
+        let mut this_sample = self.next_sample;
+        self.next_sample = 0.0;
+        self.wrap = None;
+
+        let from = self.phase;
+        let to = match self.sync.take() {
+            Some(elapsed) => {
+                // Run up to the reset, jump to the start of the cycle and run
+                // the remaining part of the sample from there
+                let elapsed = elapsed.clamp(0.0, 1.0);
+                let before = from + increment * (1.0 - elapsed);
+                self.correct(from, before, increment, elapsed, &mut this_sample);
+                let step = self.naive(0.0) - self.naive(before - before.floor());
+                this_sample += step * this_blep(elapsed);
+                self.next_sample += step * next_blep(elapsed);
+
+                let to = increment * elapsed;
+                self.correct(0.0, to, increment, 0.0, &mut this_sample);
+                to
+            }
+            None => {
+                let to = from + increment;
+                self.correct(from, to, increment, 0.0, &mut this_sample);
+                to
+            }
+        };
+
+        self.phase = if to >= 1.0 {
+            self.wrap = Some((to - 1.0) / increment);
+            to - 1.0
+        } else if to < 0.0 {
+            self.wrap = Some(to / increment);
+            to + 1.0
+        } else {
+            to
+        };
+
+        self.next_sample += self.naive(self.phase);
+        this_sample
+    }
+
+    /// Writes the oscillator to both channels of the buffer
+    pub fn process(&mut self, audio_buffer: &mut [(f32, f32)]) {
+        for frame in audio_buffer.iter_mut() {
+            let sample = self.tick();
+            *frame = (sample, sample);
+        }
+    }
+
+    fn update(&mut self) -> Result<(), OscillatorError> {
+        let frequency = self.params.frequency;
+        if frequency.is_sign_negative() {
+            return Err(OscillatorError::FrequencyNegative);
+        }
+        if frequency > self.sample_rate / 2.0 {
+            return Err(OscillatorError::FrequencyOverNyquist);
+        }
+        self.increment = frequency / self.sample_rate;
+        self.pulse_width = self
+            .params
+            .pulse_width
+            .clamp(MIN_PULSE_WIDTH, 1.0 - MIN_PULSE_WIDTH);
+        Ok(())
+    }
+
+    #[inline]
+    fn naive(&self, phase: f32) -> f32 {
+        match self.params.waveform {
+            Waveform::Sine => libm::sinf(core::f32::consts::TAU * phase),
+            Waveform::Saw => 2.0 * phase - 1.0,
+            Waveform::Square => {
+                if phase < self.pulse_width {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::Triangle => {
+                if phase < 0.5 {
+                    4.0 * phase - 1.0
+                } else {
+                    3.0 - 4.0 * phase
+                }
+            }
+        }
+    }
+
+    #[inline]
+    fn breakpoints(&self) -> ([Breakpoint; 2], usize) {
+        let none = Breakpoint {
+            phase: 0.0,
+            step: 0.0,
+            bend: 0.0,
+        };
+        match self.params.waveform {
+            Waveform::Sine => ([none; 2], 0),
+            Waveform::Saw => (
+                [
+                    Breakpoint {
+                        phase: 0.0,
+                        step: -2.0,
+                        bend: 0.0,
+                    },
+                    none,
+                ],
+                1,
+            ),
+            Waveform::Square => (
+                [
+                    Breakpoint {
+                        phase: 0.0,
+                        step: 2.0,
+                        bend: 0.0,
+                    },
+                    Breakpoint {
+                        phase: self.pulse_width,
+                        step: -2.0,
+                        bend: 0.0,
+                    },
+                ],
+                2,
+            ),
+            Waveform::Triangle => (
+                [
+                    Breakpoint {
+                        phase: 0.0,
+                        step: 0.0,
+                        bend: 8.0,
+                    },
+                    Breakpoint {
+                        phase: 0.5,
+                        step: 0.0,
+                        bend: -8.0,
+                    },
+                ],
+                2,
+            ),
+        }
+    }
+
+    /// Corrects the samples around every breakpoint the phase crossed on its
+    /// way from `from` to `to` (not wrapped), which it reached `remaining`
+    /// samples before the next output sample
+    #[inline]
+    fn correct(
+        &mut self,
+        from: f32,
+        to: f32,
+        increment: f32,
+        remaining: f32,
+        this_sample: &mut f32,
+    ) {
+        let (breakpoints, count) = self.breakpoints();
+        // Running backwards turns the steps around, the bends stay
+        let direction = increment.signum();
+        let speed = increment.abs();
+        for breakpoint in &breakpoints[..count] {
+            for position in [breakpoint.phase, breakpoint.phase + 1.0] {
+                let crossed = if increment > 0.0 {
+                    from < position && position <= to
+                } else {
+                    to < position && position <= from
+                };
+                if crossed {
+                    let elapsed = (to - position) / increment + remaining;
+                    let step = direction * breakpoint.step;
+                    let bend = speed * breakpoint.bend;
+                    *this_sample += step * this_blep(elapsed) + bend * this_blamp(elapsed);
+                    self.next_sample += step * next_blep(elapsed) + bend * next_blamp(elapsed);
+                }
+            }
+        }
+    }
+}
+
+impl Default for Oscillator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Residuals of a unit step (BLEP) and a unit change of slope (BLAMP) that
+// happened `t` samples before the next sample, for the sample before the
+// discontinuity and the one after it
+
+#[inline]
+fn this_blep(t: f32) -> f32 {
+    0.5 * t * t
+}
+
+#[inline]
+fn next_blep(t: f32) -> f32 {
+    let t = 1.0 - t;
+    -0.5 * t * t
+}
+
+#[inline]
+fn this_blamp(t: f32) -> f32 {
+    t * t * t / 6.0
+}
+
+#[inline]
+fn next_blamp(t: f32) -> f32 {
+    let t = 1.0 - t;
+    t * t * t / 6.0
+}
@@ -0,0 +1,601 @@
+// Physical
+//
+// Physical modeling voices. The plucked string is a Karplus-Strong delay
+// loop: a noise burst circulates through a delay line as long as one period,
+// a lowpass filter in the loop damps the higher harmonics faster than the
+// fundamental and a first order allpass adds the fractional part of the
+// period, which keeps high notes in tune. A DC blocker keeps the offset of
+// the burst from circulating. The phase delay of the filters at the
+// fundamental is taken off the delay line.
+//
+// The modal resonator is a bank of bandpass filters, one per mode of the
+// vibrating body, each tuned to a ratio of the fundamental with its own
+// decay time. Both are excited by audio input, by noise bursts or by both.
+use crate::filter::{Filter, FilterError, FilterParams, FilterType};
+use crate::random::Random;
+
+/// Longest delay line of the string, sets the lowest note (~12 Hz at 48 kHz)
+pub const MAX_DELAY: usize = 4096;
+pub const MAX_MODES: usize = 16;
+
+/// Highest filter frequency relative to the sample rate, keeps clear of
+/// Nyquist
+const MAX_FREQUENCY: f32 = 0.45;
+/// Strongest feedback of the string, keeps the loop stable
+const MAX_LOSS: f32 = 0.9999;
+/// Cutoff of the DC blocker in the loop, below the lowest note
+const DC_CUTOFF: f32 = 5.0;
+/// Quality of the damping filter, higher values would amplify in the loop
+const DAMPING_QUALITY: f32 = 0.5;
+/// Range of the damping filter cutoff in octaves above the fundamental
+const DAMPING_OCTAVES: (f32, f32) = (3.0, 9.0);
+/// Length of the noise burst that strikes the modal resonator
+const STRIKE_MS: f32 = 2.0;
+/// T60 is the time to decay by 60 dB, ln(1000)
+const LN_1000: f32 = 6.907_755;
+
+#[derive(Debug)]
+pub enum PhysicalError {
+    FrequencyTooLow,
+    FrequencyTooHigh,
+    DecayNegative,
+    DampingOutOfRange,
+    NoModes,
+    TooManyModes,
+    Filter(FilterError),
+}
+
+impl From<FilterError> for PhysicalError {
+    fn from(error: FilterError) -> Self {
+        PhysicalError::Filter(error)
+    }
+}
+
+/// Noise burst through a lowpass filter that fades out linearly, softer
+/// strikes sound darker
+struct Burst {
+    random: Random,
+    filter: Filter,
+    sample_rate: f32,
+    amplitude: f32,
+    length: u32,
+    remaining: u32,
+}
+
+impl Burst {
+    fn new(seed: u32) -> Self {
+        Self {
+            random: Random::new(seed),
+            filter: Filter::new(FilterType::Lowpass),
+            sample_rate: 48000.0,
+            amplitude: 0.0,
+            length: 1,
+            remaining: 0,
+        }
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f32) -> Result<(), FilterError> {
+        self.sample_rate = sample_rate;
+        self.filter.set_sample_rate(sample_rate)
+    }
+
+    /// `velocity` (0.0..=1.0) sets the level and the brightness
+    fn trigger(&mut self, samples: u32, velocity: f32) {
+        let velocity = velocity.clamp(0.0, 1.0);
+        let cutoff = 500.0 * libm::exp2f(5.0 * velocity);
+        self.filter
+            .set_params(FilterParams {
+                frequency: cutoff.min(MAX_FREQUENCY * self.sample_rate),
+                quality: 0.71,
+                gain: 0.0,
+            })
+            .expect("Cutoff is clamped below Nyquist");
+        self.filter.reset();
+        self.amplitude = velocity;
+        self.length = samples.max(1);
+        self.remaining = self.length;
+    }
+
+    #[inline]
+    fn tick(&mut self) -> f32 {
+        if self.remaining == 0 {
+            return 0.0;
+        }
+        let fade = self.remaining as f32 / self.length as f32;
+        self.remaining -= 1;
+        self.filter.tick(self.random.bipolar()) * self.amplitude * fade
+    }
+
+    fn reset(&mut self) {
+        self.remaining = 0;
+        self.filter.reset();
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub struct StringParams {
+    pub frequency: f32,
+    /// Time for the fundamental to decay by 60 dB
+    pub decay_ms: f32,
+    /// Loss of the higher harmonics (0.0..=1.0), 0.0 is bright like steel
+    /// and 1.0 dull like nylon
+    pub damping: f32,
+}
+
+impl Default for StringParams {
+    fn default() -> Self {
+        Self {
+            frequency: 110.0,
+            decay_ms: 3000.0,
+            damping: 0.5,
+        }
+    }
+}
+
+pub struct KarplusStrong {
+    params: StringParams,
+    sample_rate: f32,
+    buffer: [f32; MAX_DELAY],
+    write: usize,
+    delay: usize,
+    damping: Filter,
+    loss: f32,
+    allpass: f32,
+    allpass_input: f32,
+    allpass_output: f32,
+    dc_blocker: f32,
+    dc_input: f32,
+    dc_output: f32,
+    burst: Burst,
+}
+
+impl KarplusStrong {
+    pub fn new() -> Self {
+        let mut string = Self {
+            params: StringParams::default(),
+            sample_rate: 48000.0,
+            buffer: [0.0; MAX_DELAY],
+            write: 0,
+            delay: 1,
+            damping: Filter::new(FilterType::Lowpass),
+            loss: 0.0,
+            allpass: 0.0,
+            allpass_input: 0.0,
+            allpass_output: 0.0,
+            dc_blocker: 0.0,
+            dc_input: 0.0,
+            dc_output: 0.0,
+            burst: Burst::new(0x5EED_0001),
+        };
+        string.update().expect("Those settings always work");
+        string
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) -> Result<(), PhysicalError> {
+        if self.sample_rate != sample_rate {
+            let previous = self.sample_rate;
+            self.sample_rate = sample_rate;
+            if let Err(error) = self.update() {
+                self.sample_rate = previous;
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn set_params(&mut self, params: StringParams) -> Result<(), PhysicalError> {
+        if self.params != params {
+            let previous = self.params;
+            self.params = params;
+            if let Err(error) = self.update() {
+                self.params = previous;
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn params(&self) -> &StringParams {
+        &self.params
+    }
+
+    /// Plucks the string with a noise burst one period long, on top of
+    /// whatever still rings
+    pub fn pluck(&mut self, velocity: f32) {
+        let period = libm::roundf(self.sample_rate / self.params.frequency);
+        self.burst.trigger(period as u32, velocity);
+    }
+
+    /// Excites the string with the input on top of the plucks and returns
+    /// the output of the string
+    #[inline]
+    pub fn tick(&mut self, input: f32) -> f32 {
+        let output = self.buffer[(self.write + MAX_DELAY - self.delay) % MAX_DELAY];
+        let damped = self.damping.tick(output) * self.loss;
+        let blocked = damped - self.dc_input + self.dc_blocker * self.dc_output;
+        self.dc_input = damped;
+        self.dc_output = blocked;
+        let tuned = self.allpass * (blocked - self.allpass_output) + self.allpass_input;
+        self.allpass_input = blocked;
+        self.allpass_output = tuned;
+        self.buffer[self.write] = tuned + input + self.burst.tick();
+        self.write = (self.write + 1) % MAX_DELAY;
+        output
+    }
+
+    /// Takes the excitation from the left channel, the string goes to both
+    /// outputs
+    pub fn process(&mut self, audio_buffer: &mut [(f32, f32)]) {
+        for (left, right) in audio_buffer.iter_mut() {
+            let output = self.tick(*left);
+            *left = output;
+            *right = output;
+        }
+    }
+
+    /// Silences the string
+    pub fn reset(&mut self) {
+        self.buffer = [0.0; MAX_DELAY];
+        self.damping.reset();
+        self.allpass_input = 0.0;
+        self.allpass_output = 0.0;
+        self.dc_input = 0.0;
+        self.dc_output = 0.0;
+        self.burst.reset();
+    }
+
+    fn update(&mut self) -> Result<(), PhysicalError> {
+        let params = self.params;
+        if !(0.0..=1.0).contains(&params.damping) {
+            return Err(PhysicalError::DampingOutOfRange);
+        }
+        if params.decay_ms.is_sign_negative() {
+            return Err(PhysicalError::DecayNegative);
+        }
+        if params.frequency <= 0.0 {
+            return Err(PhysicalError::FrequencyTooLow);
+        }
+        if params.frequency > MAX_FREQUENCY * self.sample_rate {
+            return Err(PhysicalError::FrequencyTooHigh);
+        }
+
+        let (low, high) = DAMPING_OCTAVES;
+        let octaves = high + params.damping * (low - high);
+        let cutoff =
+            (params.frequency * libm::exp2f(octaves)).min(MAX_FREQUENCY * self.sample_rate);
+        let dc_blocker = 1.0 - core::f32::consts::TAU * DC_CUTOFF / self.sample_rate;
+        let (magnitude, filter_delay) =
+            loop_response(params.frequency, cutoff, dc_blocker, self.sample_rate);
+
+        // The allpass handles between 0.5 and 1.5 samples, where its delay
+        // is flattest, the delay line the rest
+        let period = self.sample_rate / params.frequency;
+        let length = period - filter_delay;
+        let delay = libm::floorf(length - 0.5);
+        if delay < 1.0 {
+            return Err(PhysicalError::FrequencyTooHigh);
+        }
+        if delay >= MAX_DELAY as f32 {
+            return Err(PhysicalError::FrequencyTooLow);
+        }
+        let fraction = length - delay;
+
+        // `loop_response` assumes the exact tuning
+        self.damping.set_precise(true)?;
+        self.damping.set_sample_rate(self.sample_rate)?;
+        self.damping.set_params(FilterParams {
+            frequency: cutoff,
+            quality: DAMPING_QUALITY,
+            gain: 0.0,
+        })?;
+        self.burst.set_sample_rate(self.sample_rate)?;
+        self.delay = delay as usize;
+        self.dc_blocker = dc_blocker;
+
+        // Coefficient for exactly `fraction` samples of phase delay at the
+        // fundamental rather than only at DC
+        let omega = core::f32::consts::TAU * params.frequency / self.sample_rate;
+        self.allpass =
+            libm::sinf(0.5 * omega * (1.0 - fraction)) / libm::sinf(0.5 * omega * (1.0 + fraction));
+
+        // Loss per round trip for the decay of the fundamental, the damping
+        // filter already takes away part of it
+        let samples = params.decay_ms * 0.001 * self.sample_rate;
+        let decay = libm::expf(-LN_1000 * period / samples.max(1.0));
+        self.loss = (decay / magnitude).min(MAX_LOSS);
+        Ok(())
+    }
+}
+
+impl Default for KarplusStrong {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Magnitude and phase delay in samples of the damping filter and the DC
+/// blocker of the string at `frequency`
+fn loop_response(frequency: f32, cutoff: f32, dc_blocker: f32, sample_rate: f32) -> (f32, f32) {
+    let pi = core::f32::consts::PI;
+    let omega = 2.0 * pi * frequency / sample_rate;
+
+    // The SVF is the bilinear transform of the analog prototype, warped to
+    // match at the cutoff
+    let x = libm::tanf(0.5 * omega) / libm::tanf(pi * cutoff / sample_rate);
+    let real = 1.0 - x * x;
+    let imaginary = x / DAMPING_QUALITY;
+    let lowpass_magnitude = 1.0 / libm::sqrtf(real * real + imaginary * imaginary);
+    let lowpass_phase = -libm::atan2f(imaginary, real);
+
+    // (1 - z^-1) / (1 - r z^-1), the numerator leads by (pi - omega) / 2
+    let real = 1.0 - dc_blocker * libm::cosf(omega);
+    let imaginary = dc_blocker * libm::sinf(omega);
+    let blocker_magnitude =
+        2.0 * libm::sinf(0.5 * omega) / libm::sqrtf(real * real + imaginary * imaginary);
+    let blocker_phase = 0.5 * (pi - omega) - libm::atan2f(imaginary, real);
+
+    (
+        lowpass_magnitude * blocker_magnitude,
+        -(lowpass_phase + blocker_phase) / omega,
+    )
+}
+
+#[derive(Default, Copy, Clone, PartialEq)]
+pub struct Mode {
+    /// Frequency relative to the fundamental
+    pub ratio: f32,
+    pub amplitude: f32,
+    /// Decay time relative to `ModalParams::decay_ms`
+    pub decay: f32,
+}
+
+impl Mode {
+    pub const fn new(ratio: f32, amplitude: f32, decay: f32) -> Self {
+        Self {
+            ratio,
+            amplitude,
+            decay,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub struct ModalParams {
+    pub frequency: f32,
+    /// Time for a mode with a `decay` of 1.0 to decay by 60 dB
+    pub decay_ms: f32,
+    pub modes: [Mode; MAX_MODES],
+    /// Number of used modes
+    pub count: usize,
+}
+
+impl ModalParams {
+    pub fn new(frequency: f32, decay_ms: f32, modes: &[Mode]) -> Result<Self, PhysicalError> {
+        if modes.is_empty() {
+            return Err(PhysicalError::NoModes);
+        }
+        if modes.len() > MAX_MODES {
+            return Err(PhysicalError::TooManyModes);
+        }
+        Ok(Self::preset(frequency, decay_ms, modes))
+    }
+
+    /// Struck bar like a marimba or a glockenspiel
+    pub fn bar(frequency: f32, decay_ms: f32) -> Self {
+        Self::preset(
+            frequency,
+            decay_ms,
+            &[
+                Mode::new(1.0, 1.0, 1.0),
+                Mode::new(2.756, 0.5, 0.6),
+                Mode::new(5.404, 0.25, 0.4),
+                Mode::new(8.933, 0.12, 0.25),
+            ],
+        )
+    }
+
+    /// Circular drum head
+    pub fn membrane(frequency: f32, decay_ms: f32) -> Self {
+        Self::preset(
+            frequency,
+            decay_ms,
+            &[
+                Mode::new(1.0, 1.0, 1.0),
+                Mode::new(1.594, 0.8, 0.8),
+                Mode::new(2.136, 0.6, 0.7),
+                Mode::new(2.296, 0.5, 0.6),
+                Mode::new(2.653, 0.4, 0.5),
+                Mode::new(2.918, 0.3, 0.4),
+                Mode::new(3.156, 0.25, 0.35),
+                Mode::new(3.501, 0.2, 0.3),
+            ],
+        )
+    }
+
+    /// Church bell, the hum tone an octave below the fundamental rings
+    /// longest
+    pub fn bell(frequency: f32, decay_ms: f32) -> Self {
+        Self::preset(
+            frequency,
+            decay_ms,
+            &[
+                Mode::new(0.5, 0.6, 1.0),
+                Mode::new(1.0, 1.0, 0.8),
+                Mode::new(1.183, 0.7, 0.6),
+                Mode::new(1.506, 0.5, 0.5),
+                Mode::new(2.0, 0.6, 0.45),
+                Mode::new(2.514, 0.3, 0.35),
+                Mode::new(2.662, 0.3, 0.3),
+                Mode::new(3.011, 0.2, 0.25),
+            ],
+        )
+    }
+
+    fn preset(frequency: f32, decay_ms: f32, modes: &[Mode]) -> Self {
+        let mut params = Self {
+            frequency,
+            decay_ms,
+            modes: [Mode::default(); MAX_MODES],
+            count: modes.len(),
+        };
+        params.modes[..modes.len()].copy_from_slice(modes);
+        params
+    }
+}
+
+impl Default for ModalParams {
+    fn default() -> Self {
+        Self::bar(440.0, 1000.0)
+    }
+}
+
+pub struct ModalResonator {
+    params: ModalParams,
+    sample_rate: f32,
+    filters: [Filter; MAX_MODES],
+    amplitudes: [f32; MAX_MODES],
+    /// Gain of the strikes per mode, so that they ring equally loud
+    /// whatever the decay time
+    strike_gains: [f32; MAX_MODES],
+    /// Modes below the limit of the sample rate
+    active: usize,
+    burst: Burst,
+}
+
+impl ModalResonator {
+    pub fn new() -> Self {
+        let mut resonator = Self {
+            params: ModalParams::default(),
+            sample_rate: 48000.0,
+            filters: core::array::from_fn(|_| Filter::new(FilterType::Bandpass)),
+            amplitudes: [0.0; MAX_MODES],
+            strike_gains: [0.0; MAX_MODES],
+            active: 0,
+            burst: Burst::new(0x5EED_0002),
+        };
+        resonator.update().expect("Those settings always work");
+        resonator
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) -> Result<(), PhysicalError> {
+        if self.sample_rate != sample_rate {
+            let previous = self.sample_rate;
+            self.sample_rate = sample_rate;
+            if let Err(error) = self.update() {
+                self.sample_rate = previous;
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn set_params(&mut self, params: ModalParams) -> Result<(), PhysicalError> {
+        if self.params != params {
+            let previous = self.params;
+            self.params = params;
+            if let Err(error) = self.update() {
+                self.params = previous;
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn params(&self) -> &ModalParams {
+        &self.params
+    }
+
+    /// Strikes the resonator with a short noise burst
+    pub fn strike(&mut self, velocity: f32) {
+        let samples = libm::roundf(STRIKE_MS * 0.001 * self.sample_rate);
+        self.burst.trigger(samples as u32, velocity);
+    }
+
+    /// Excites the modes with the input on top of the strikes, the input
+    /// passes the modes at their frequency with their amplitude
+    #[inline]
+    pub fn tick(&mut self, input: f32) -> f32 {
+        let burst = self.burst.tick();
+        let mut output = 0.0;
+        for ((filter, amplitude), strike_gain) in self.filters[..self.active]
+            .iter_mut()
+            .zip(self.amplitudes.iter())
+            .zip(self.strike_gains.iter())
+        {
+            output += filter.tick(input + burst * strike_gain) * amplitude;
+        }
+        output
+    }
+
+    /// Takes the excitation from the left channel, the resonator goes to
+    /// both outputs
+    pub fn process(&mut self, audio_buffer: &mut [(f32, f32)]) {
+        for (left, right) in audio_buffer.iter_mut() {
+            let output = self.tick(*left);
+            *left = output;
+            *right = output;
+        }
+    }
+
+    /// Silences the resonator
+    pub fn reset(&mut self) {
+        for filter in self.filters.iter_mut() {
+            filter.reset();
+        }
+        self.burst.reset();
+    }
+
+    fn update(&mut self) -> Result<(), PhysicalError> {
+        let params = self.params;
+        if params.count == 0 {
+            return Err(PhysicalError::NoModes);
+        }
+        if params.count > MAX_MODES {
+            return Err(PhysicalError::TooManyModes);
+        }
+        if params.frequency <= 0.0 {
+            return Err(PhysicalError::FrequencyTooLow);
+        }
+        if params.decay_ms.is_sign_negative()
+            || params.modes[..params.count]
+                .iter()
+                .any(|mode| mode.decay.is_sign_negative())
+        {
+            return Err(PhysicalError::DecayNegative);
+        }
+
+        self.burst.set_sample_rate(self.sample_rate)?;
+        // Modes that would fold over are left out
+        let limit = MAX_FREQUENCY * self.sample_rate;
+        self.active = 0;
+        for mode in &params.modes[..params.count] {
+            let frequency = params.frequency * mode.ratio;
+            if frequency <= 0.0 || frequency > limit {
+                continue;
+            }
+            // A resonance decays with exp(-pi * f * t / Q)
+            let decay = params.decay_ms * mode.decay * 0.001;
+            let quality = (core::f32::consts::PI * frequency * decay / LN_1000).max(0.5);
+            let filter = &mut self.filters[self.active];
+            filter.set_precise(true)?;
+            filter.set_sample_rate(self.sample_rate)?;
+            filter.set_params(FilterParams {
+                frequency,
+                quality,
+                gain: 0.0,
+            })?;
+            self.amplitudes[self.active] = mode.amplitude;
+            // The bandpass has unity gain at its peak, the energy that a
+            // burst puts into the mode shrinks with the bandwidth
+            self.strike_gains[self.active] = quality;
+            self.active += 1;
+        }
+        Ok(())
+    }
+}
+
+impl Default for ModalResonator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
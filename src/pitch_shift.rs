@@ -0,0 +1,179 @@
+// Pitch Shifter
+//
+// Time domain pitch shifter with two delay taps that sweep through a short
+// delay line at a rate given by the pitch ratio. The taps are half a window
+// apart and crossfaded with triangular windows that sum to one, so whenever
+// one tap jumps back to the other end of the window it is silent.
+use micromath::F32Ext;
+
+/// Length of the delay line, must be a power of two
+const BUFFER_LENGTH: usize = 4096;
+const BUFFER_MASK: usize = BUFFER_LENGTH - 1;
+
+pub const MAX_SEMITONES: f32 = 12.0;
+pub const MAX_CENTS: f32 = 100.0;
+
+#[derive(Debug)]
+pub enum PitchShiftError {
+    SemitonesOutOfRange,
+    CentsOutOfRange,
+    WindowTooLong,
+    WindowTooShort,
+    MixOutOfRange,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub struct PitchShiftParams {
+    /// Transposition in semitones (-12.0..=12.0)
+    pub semitones: f32,
+    /// Fine tuning in cents (-100.0..=100.0)
+    pub cents: f32,
+    /// Crossfade window length in milliseconds
+    pub window_ms: f32,
+    /// Dry/wet mix (0.0 = dry, 1.0 = wet)
+    pub mix: f32,
+}
+
+impl Default for PitchShiftParams {
+    fn default() -> Self {
+        Self {
+            semitones: 0.0,
+            cents: 0.0,
+            window_ms: 40.0,
+            mix: 1.0,
+        }
+    }
+}
+
+pub struct PitchShifter {
+    params: PitchShiftParams,
+    sample_rate: f32,
+    buffer: [f32; BUFFER_LENGTH],
+    write_pos: usize,
+    window: f32,
+    phase: f32,
+    phase_increment: f32,
+}
+
+impl PitchShifter {
+    pub fn new() -> Self {
+        let mut pitch_shifter = Self {
+            params: PitchShiftParams::default(),
+            sample_rate: 48000.0,
+            buffer: [0.0; BUFFER_LENGTH],
+            write_pos: 0,
+            window: 0.0,
+            phase: 0.0,
+            phase_increment: 0.0,
+        };
+        pitch_shifter
+            .update_increments()
+            .expect("Those settings always work");
+        pitch_shifter
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) -> Result<(), PitchShiftError> {
+        if self.sample_rate != sample_rate {
+            let previous = self.sample_rate;
+            self.sample_rate = sample_rate;
+            if let Err(err) = self.update_increments() {
+                self.sample_rate = previous;
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn set_params(&mut self, params: PitchShiftParams) -> Result<(), PitchShiftError> {
+        if params.semitones.abs() > MAX_SEMITONES {
+            return Err(PitchShiftError::SemitonesOutOfRange);
+        }
+        if params.cents.abs() > MAX_CENTS {
+            return Err(PitchShiftError::CentsOutOfRange);
+        }
+        if !(0.0..=1.0).contains(&params.mix) {
+            return Err(PitchShiftError::MixOutOfRange);
+        }
+        if self.params != params {
+            let previous = self.params;
+            self.params = params;
+            if let Err(err) = self.update_increments() {
+                self.params = previous;
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Pitch ratio resulting from the current transposition
+    pub fn ratio(&self) -> f32 {
+        let semitones = self.params.semitones + self.params.cents / 100.0;
+        2_f32.powf(semitones / 12.0)
+    }
+
+    #[inline]
+    pub fn tick(&mut self, input: f32) -> f32 {
+        self.buffer[self.write_pos] = input;
+
+        let other_phase = if self.phase >= 0.5 {
+            self.phase - 0.5
+        } else {
+            self.phase + 0.5
+        };
+        let tap1 = self.read(self.phase * self.window);
+        let tap2 = self.read(other_phase * self.window);
+        let wet = tap1 * triangle(self.phase) + tap2 * triangle(other_phase);
+
+        self.phase += self.phase_increment;
+        if self.phase >= 1.0 {
+            self.phase -= 1.0;
+        } else if self.phase < 0.0 {
+            self.phase += 1.0;
+        }
+        self.write_pos = (self.write_pos + 1) & BUFFER_MASK;
+
+        input + self.params.mix * (wet - input)
+    }
+
+    pub fn reset(&mut self) {
+        self.buffer = [0.0; BUFFER_LENGTH];
+        self.write_pos = 0;
+        self.phase = 0.0;
+    }
+
+    /// Reads the delay line `delay` samples behind the write position
+    #[inline]
+    fn read(&self, delay: f32) -> f32 {
+        let whole = delay as usize;
+        let fraction = delay - whole as f32;
+        let a = self.buffer[self.write_pos.wrapping_sub(whole) & BUFFER_MASK];
+        let b = self.buffer[self.write_pos.wrapping_sub(whole + 1) & BUFFER_MASK];
+        a + fraction * (b - a)
+    }
+
+    fn update_increments(&mut self) -> Result<(), PitchShiftError> {
+        let window = self.params.window_ms * 0.001 * self.sample_rate;
+        if window > (BUFFER_LENGTH - 2) as f32 {
+            return Err(PitchShiftError::WindowTooLong);
+        }
+        if window < 2.0 {
+            return Err(PitchShiftError::WindowTooShort);
+        }
+        self.window = window;
+        // The delay grows by (1 - ratio) samples per sample
+        self.phase_increment = (1.0 - self.ratio()) / window;
+        Ok(())
+    }
+}
+
+impl Default for PitchShifter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Triangular window over 0..1 peaking at 0.5
+#[inline]
+fn triangle(phase: f32) -> f32 {
+    1.0 - (2.0 * phase - 1.0).abs()
+}
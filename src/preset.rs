@@ -0,0 +1,147 @@
+// Preset
+//
+// The parameters of the processor in a versioned binary format for flash:
+// a magic, the format version and the length of the payload, the payload in
+// little endian and a CRC-32 over all of it. Each slot is a key of the
+// settings store. Reading a broken write or a preset from a newer firmware
+// is an error rather than garbage parameters.
+use crate::crc::crc32;
+use crate::filter::{FilterParams, FilterType};
+use crate::modulation::Range;
+use crate::spectral::SpectralMode;
+
+pub const MAX_PRESETS: usize = 16;
+/// Settings key of the first slot
+pub const PRESET_KEY: u16 = 0x0100;
+
+const MAGIC: [u8; 4] = *b"DKPR";
+const VERSION: u8 = 1;
+const HEADER_SIZE: usize = 6;
+const PAYLOAD_SIZE: usize = 14;
+const CRC_SIZE: usize = 4;
+
+#[derive(Debug, PartialEq)]
+pub enum PresetError {
+    SlotOutOfRange,
+    /// Erased flash, nothing was saved
+    Empty,
+    InvalidHeader,
+    /// Saved by a newer firmware
+    UnsupportedVersion(u8),
+    CrcMismatch,
+    InvalidValue,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct Preset {
+    pub filter_type: FilterType,
+    pub filter: FilterParams,
+    pub spectral_mode: SpectralMode,
+}
+
+impl Preset {
+    /// Bytes of a stored preset
+    pub const SIZE: usize = HEADER_SIZE + PAYLOAD_SIZE + CRC_SIZE;
+
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[..4].copy_from_slice(&MAGIC);
+        bytes[4] = VERSION;
+        bytes[5] = PAYLOAD_SIZE as u8;
+
+        let payload = &mut bytes[HEADER_SIZE..HEADER_SIZE + PAYLOAD_SIZE];
+        payload[0] = match self.filter_type {
+            FilterType::Lowpass => 0,
+            FilterType::Bell => 1,
+            FilterType::Bandpass => 2,
+        };
+        payload[1] = match self.spectral_mode {
+            SpectralMode::Off => 0,
+            SpectralMode::Freeze => 1,
+            SpectralMode::Denoise => 2,
+            SpectralMode::Robotize => 3,
+            SpectralMode::Whisperize => 4,
+        };
+        payload[2..6].copy_from_slice(&self.filter.frequency.to_le_bytes());
+        payload[6..10].copy_from_slice(&self.filter.quality.to_le_bytes());
+        payload[10..14].copy_from_slice(&self.filter.gain.to_le_bytes());
+
+        let crc = crc32(&bytes[..HEADER_SIZE + PAYLOAD_SIZE]);
+        bytes[HEADER_SIZE + PAYLOAD_SIZE..].copy_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+
+    /// Reads a preset from the start of `bytes`, anything after it is
+    /// ignored
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, PresetError> {
+        let header = bytes.get(..HEADER_SIZE).ok_or(PresetError::InvalidHeader)?;
+        if header.iter().all(|byte| *byte == 0xFF) {
+            return Err(PresetError::Empty);
+        }
+        if header[..4] != MAGIC {
+            return Err(PresetError::InvalidHeader);
+        }
+        if header[4] > VERSION {
+            return Err(PresetError::UnsupportedVersion(header[4]));
+        }
+        if header[4] != VERSION || usize::from(header[5]) != PAYLOAD_SIZE {
+            return Err(PresetError::InvalidHeader);
+        }
+        let bytes = bytes.get(..Self::SIZE).ok_or(PresetError::InvalidHeader)?;
+        let (data, crc) = bytes.split_at(HEADER_SIZE + PAYLOAD_SIZE);
+        if crc32(data).to_le_bytes() != crc {
+            return Err(PresetError::CrcMismatch);
+        }
+
+        let payload = &data[HEADER_SIZE..];
+        // Only values the controls can reach, which the filter always takes
+        let float = |offset: usize, range: Range| {
+            let value = f32::from_le_bytes([
+                payload[offset],
+                payload[offset + 1],
+                payload[offset + 2],
+                payload[offset + 3],
+            ]);
+            range.contains(value).then_some(value)
+        };
+        let filter_type = match payload[0] {
+            0 => FilterType::Lowpass,
+            1 => FilterType::Bell,
+            2 => FilterType::Bandpass,
+            _ => return Err(PresetError::InvalidValue),
+        };
+        let spectral_mode = match payload[1] {
+            0 => SpectralMode::Off,
+            1 => SpectralMode::Freeze,
+            2 => SpectralMode::Denoise,
+            3 => SpectralMode::Robotize,
+            4 => SpectralMode::Whisperize,
+            _ => return Err(PresetError::InvalidValue),
+        };
+        let (Some(frequency), Some(quality), Some(gain)) = (
+            float(2, Range::FILTER_FREQUENCY),
+            float(6, Range::FILTER_QUALITY),
+            float(10, Range::FILTER_GAIN),
+        ) else {
+            return Err(PresetError::InvalidValue);
+        };
+
+        Ok(Self {
+            filter_type,
+            filter: FilterParams {
+                frequency,
+                quality,
+                gain,
+            },
+            spectral_mode,
+        })
+    }
+}
+
+/// Settings key of a preset slot
+pub fn slot_key(slot: usize) -> Result<u16, PresetError> {
+    if slot >= MAX_PRESETS {
+        return Err(PresetError::SlotOutOfRange);
+    }
+    Ok(PRESET_KEY + slot as u16)
+}
@@ -1,12 +1,30 @@
 use daisy::audio::BLOCK_LENGTH;
 
-use crate::filter::{Filter, FilterType};
+use crate::filter::{Filter, FilterError, FilterType};
+use crate::octaver::{Octaver, OctaverParams};
+use crate::pitch_shift::{PitchShiftError, PitchShiftParams, PitchShifter};
+use crate::preset::Preset;
+use crate::spectral::{Spectral, SpectralMode};
 
 pub use crate::filter::FilterParams;
 
+/// FFT size of the spectral effects
+const SPECTRAL_SIZE: usize = 512;
+const SPECTRAL_BINS: usize = SPECTRAL_SIZE / 2 + 1;
+
 pub struct Processor {
     filter_left: Filter,
     filter_right: Filter,
+    octaver_left: Octaver,
+    octaver_right: Octaver,
+    octaver_params: OctaverParams,
+    pitch_shifter_left: PitchShifter,
+    pitch_shifter_right: PitchShifter,
+    /// The shifter delays the signal even without transposition, so it is
+    /// left out then
+    pitch_shift: bool,
+    spectral_left: Spectral<SPECTRAL_SIZE, SPECTRAL_BINS>,
+    spectral_right: Spectral<SPECTRAL_SIZE, SPECTRAL_BINS>,
 }
 
 impl Processor {
@@ -19,21 +37,121 @@ impl Processor {
         filter_right
             .set_sample_rate(daisy::audio::FS.to_Hz() as f32)
             .unwrap();
-        Self {
+        let mut processor = Self {
             filter_left,
             filter_right,
+            octaver_left: Octaver::new(),
+            octaver_right: Octaver::new(),
+            octaver_params: OctaverParams::default(),
+            pitch_shifter_left: PitchShifter::new(),
+            pitch_shifter_right: PitchShifter::new(),
+            pitch_shift: false,
+            spectral_left: Spectral::new().unwrap(),
+            spectral_right: Spectral::new().unwrap(),
+        };
+        for octaver in [&mut processor.octaver_left, &mut processor.octaver_right] {
+            octaver
+                .set_sample_rate(daisy::audio::FS.to_Hz() as f32)
+                .unwrap();
+        }
+        for pitch_shifter in [
+            &mut processor.pitch_shifter_left,
+            &mut processor.pitch_shifter_right,
+        ] {
+            pitch_shifter
+                .set_sample_rate(daisy::audio::FS.to_Hz() as f32)
+                .unwrap();
         }
+        // Both start without effect
+        processor.set_octaver(OctaverParams {
+            dry: 1.0,
+            down: 0.0,
+            up: 0.0,
+        });
+        processor
+    }
+
+    pub fn update(&mut self, params: FilterParams) -> Result<(), FilterError> {
+        self.filter_left.set_params(params)?;
+        self.filter_right.set_params(params)
+    }
+
+    pub fn set_filter_type(&mut self, filter_type: FilterType) -> Result<(), FilterError> {
+        self.filter_left.set_filter_type(filter_type)?;
+        self.filter_right.set_filter_type(filter_type)
+    }
+
+    /// Applies all parameters of the preset, unchanged ones cost nothing
+    pub fn load(&mut self, preset: Preset) -> Result<(), FilterError> {
+        self.set_filter_type(preset.filter_type)?;
+        self.update(preset.filter)?;
+        self.set_spectral_mode(preset.spectral_mode);
+        Ok(())
+    }
+
+    pub fn set_spectral_mode(&mut self, mode: SpectralMode) {
+        self.spectral_left.set_mode(mode);
+        self.spectral_right.set_mode(mode);
+    }
+
+    /// Levels of the octaves, without any the octaver is left out
+    pub fn set_octaver(&mut self, params: OctaverParams) {
+        self.octaver_left.set_params(params);
+        self.octaver_right.set_params(params);
+        self.octaver_params = params;
     }
 
-    pub fn update(&mut self, params: FilterParams) {
-        self.filter_left.set_params(params).unwrap();
-        self.filter_right.set_params(params).unwrap();
+    pub fn octaver_params(&self) -> OctaverParams {
+        self.octaver_params
+    }
+
+    /// Transposes the output by `semitones` (-12.0..=12.0)
+    pub fn set_pitch_shift(&mut self, semitones: f32) -> Result<(), PitchShiftError> {
+        let params = PitchShiftParams {
+            semitones,
+            ..PitchShiftParams::default()
+        };
+        self.pitch_shifter_left.set_params(params)?;
+        self.pitch_shifter_right.set_params(params)?;
+        self.pitch_shift = semitones != 0.0;
+        Ok(())
+    }
+
+    pub fn set_freeze(&mut self, frozen: bool) {
+        self.spectral_left.freeze.set_frozen(frozen);
+        self.spectral_right.freeze.set_frozen(frozen);
+    }
+
+    pub fn set_denoise_learning(&mut self, learning: bool) {
+        self.spectral_left.denoise.set_learning(learning);
+        self.spectral_right.denoise.set_learning(learning);
+    }
+
+    /// Delay between input and output in samples
+    pub fn latency(&self) -> usize {
+        self.spectral_left.latency()
     }
 
     pub fn process(&mut self, audio_buffer: &mut [(f32, f32); BLOCK_LENGTH]) {
+        let octaver = self.octaver_params.down != 0.0 || self.octaver_params.up != 0.0;
         for (left, right) in audio_buffer.iter_mut() {
-            *left = self.filter_left.tick(*left);
-            *right = self.filter_right.tick(*right);
+            let (mut l, mut r) = (self.filter_left.tick(*left), self.filter_right.tick(*right));
+            if octaver {
+                l = self.octaver_left.tick(l);
+                r = self.octaver_right.tick(r);
+            }
+            if self.pitch_shift {
+                l = self.pitch_shifter_left.tick(l);
+                r = self.pitch_shifter_right.tick(r);
+            }
+            *left = self.spectral_left.tick(l);
+            *right = self.spectral_right.tick(r);
         }
     }
 }
+
+impl Default for Processor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,131 @@
+// QSPI Flash
+//
+// Driver for the 8 MB IS25LP064 flash of the Daisy Seed on the QUADSPI
+// peripheral. It stays in single line SPI mode, slow but plenty for presets
+// and settings. Programming only clears bits, so a sector has to be erased
+// to 0xFF before it is written again. Erasing a sector takes up to 300 ms
+// and blocks, keep it away from the audio.
+use stm32h7xx_hal::pac::QUADSPI;
+use stm32h7xx_hal::xspi::{Qspi, XspiWord};
+
+use crate::flash::{Flash, FlashError, SECTOR_SIZE};
+
+pub const FLASH_SIZE: u32 = 8 * 1024 * 1024;
+/// Programming never crosses the end of a page
+pub const PAGE_SIZE: u32 = 256;
+/// Most data bytes of one transfer, the FIFO of the peripheral
+const MAX_TRANSFER: usize = 32;
+
+const WRITE_ENABLE: u8 = 0x06;
+const READ_STATUS: u8 = 0x05;
+const READ: u8 = 0x03;
+const PAGE_PROGRAM: u8 = 0x02;
+const SECTOR_ERASE: u8 = 0x20;
+const RESET_ENABLE: u8 = 0x66;
+const RESET: u8 = 0x99;
+/// Write in progress bit of the status register
+const STATUS_BUSY: u8 = 0x01;
+
+pub struct QspiFlash {
+    qspi: Qspi<QUADSPI>,
+}
+
+impl QspiFlash {
+    pub fn new(qspi: Qspi<QUADSPI>) -> Result<Self, FlashError> {
+        let mut flash = Self { qspi };
+        // The chip may still be busy with something from before a reset
+        flash.command(RESET_ENABLE)?;
+        flash.command(RESET)?;
+        flash.wait()?;
+        Ok(flash)
+    }
+
+    fn command(&mut self, instruction: u8) -> Result<(), FlashError> {
+        self.qspi
+            .write_extended(
+                XspiWord::U8(instruction),
+                XspiWord::None,
+                XspiWord::None,
+                &[],
+            )
+            .map_err(|_| FlashError::Bus)
+    }
+
+    /// Polls the status register until programming or erasing is done
+    fn wait(&mut self) -> Result<(), FlashError> {
+        let mut status = [STATUS_BUSY];
+        while status[0] & STATUS_BUSY != 0 {
+            self.qspi
+                .read_extended(
+                    XspiWord::U8(READ_STATUS),
+                    XspiWord::None,
+                    XspiWord::None,
+                    0,
+                    &mut status,
+                )
+                .map_err(|_| FlashError::Bus)?;
+        }
+        Ok(())
+    }
+}
+
+impl Flash for QspiFlash {
+    fn capacity(&self) -> u32 {
+        FLASH_SIZE
+    }
+
+    fn read(&mut self, address: u32, buffer: &mut [u8]) -> Result<(), FlashError> {
+        self.check_range(address, buffer.len())?;
+        for (index, chunk) in buffer.chunks_mut(MAX_TRANSFER).enumerate() {
+            let address = address + (index * MAX_TRANSFER) as u32;
+            self.qspi
+                .read_extended(
+                    XspiWord::U8(READ),
+                    XspiWord::U24(address),
+                    XspiWord::None,
+                    0,
+                    chunk,
+                )
+                .map_err(|_| FlashError::Bus)?;
+        }
+        Ok(())
+    }
+
+    fn erase_sector(&mut self, address: u32) -> Result<(), FlashError> {
+        self.check_range(address, SECTOR_SIZE as usize)?;
+        if !address.is_multiple_of(SECTOR_SIZE) {
+            return Err(FlashError::Unaligned);
+        }
+        self.command(WRITE_ENABLE)?;
+        self.qspi
+            .write_extended(
+                XspiWord::U8(SECTOR_ERASE),
+                XspiWord::U24(address),
+                XspiWord::None,
+                &[],
+            )
+            .map_err(|_| FlashError::Bus)?;
+        self.wait()
+    }
+
+    fn program(&mut self, mut address: u32, mut data: &[u8]) -> Result<(), FlashError> {
+        self.check_range(address, data.len())?;
+        while !data.is_empty() {
+            let page_left = (PAGE_SIZE - address % PAGE_SIZE) as usize;
+            let (chunk, rest) = data.split_at(data.len().min(page_left).min(MAX_TRANSFER));
+            self.command(WRITE_ENABLE)?;
+            self.qspi
+                .write_extended(
+                    XspiWord::U8(PAGE_PROGRAM),
+                    XspiWord::U24(address),
+                    XspiWord::None,
+                    chunk,
+                )
+                .map_err(|_| FlashError::Bus)?;
+            self.wait()?;
+            address += chunk.len() as u32;
+            data = rest;
+        }
+        Ok(())
+    }
+}
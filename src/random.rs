@@ -0,0 +1,36 @@
+// Random
+//
+// Xorshift pseudo random number generator. Not suited for cryptography, but
+// fast, tiny and deterministic, which is all audio needs.
+
+#[derive(Clone)]
+pub struct Random {
+    state: u32,
+}
+
+impl Random {
+    pub fn new(seed: u32) -> Self {
+        // Xorshift gets stuck at zero
+        Self { state: seed.max(1) }
+    }
+
+    #[inline]
+    pub fn next_u32(&mut self) -> u32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        self.state
+    }
+
+    /// Uniformly distributed in 0.0..1.0
+    #[inline]
+    pub fn unipolar(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1 << 24) as f32
+    }
+
+    /// Uniformly distributed in -1.0..1.0
+    #[inline]
+    pub fn bipolar(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1 << 23) as f32 - 1.0
+    }
+}
@@ -0,0 +1,574 @@
+// Sequencer
+//
+// Step sequencer and arpeggiator. Either plays the steps of a pattern or
+// arpeggiates the held notes, clocked by an internal tempo or by external
+// clock pulses, e.g. from a gate input or MIDI clock. Swing delays every
+// second step. The sequencer doesn't make any sound itself, it emits note
+// events with their offset in samples within the block for the voices.
+//
+// Everything runs on sample counts and a seeded random generator, so the
+// same calls always produce the same events.
+use heapless::Vec;
+
+use crate::random::Random;
+
+pub const MAX_STEPS: usize = 32;
+pub const MAX_PATTERNS: usize = 8;
+/// Most notes the arpeggiator holds at once
+pub const MAX_HELD_NOTES: usize = 16;
+/// Most events emitted by one call to `process`
+pub const MAX_EVENTS: usize = 16;
+pub const MAX_OCTAVES: u8 = 4;
+
+#[derive(Debug, PartialEq)]
+pub enum SequencerError {
+    TempoOutOfRange,
+    StepsPerBeatOutOfRange,
+    SwingOutOfRange,
+    PulsesOutOfRange,
+    OctavesOutOfRange,
+    GateOutOfRange,
+    ProbabilityOutOfRange,
+    LengthOutOfRange,
+    PatternOutOfRange,
+    StepOutOfRange,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Step {
+    pub note: u8,
+    pub velocity: u8,
+    /// Length of the note relative to the step (0.0..=1.0)
+    pub gate: f32,
+    /// Chance that the step plays (0.0..=1.0)
+    pub probability: f32,
+    /// Glides from the previous note, which is held until this one starts
+    pub slide: bool,
+    /// Disabled steps are rests
+    pub enabled: bool,
+}
+
+impl Step {
+    pub const REST: Step = Step {
+        note: 60,
+        velocity: 100,
+        gate: 0.5,
+        probability: 1.0,
+        slide: false,
+        enabled: false,
+    };
+
+    pub const fn new(note: u8, velocity: u8) -> Self {
+        Self {
+            note,
+            velocity,
+            enabled: true,
+            ..Self::REST
+        }
+    }
+
+    fn validate(&self) -> Result<(), SequencerError> {
+        if !(0.0..=1.0).contains(&self.gate) {
+            return Err(SequencerError::GateOutOfRange);
+        }
+        if !(0.0..=1.0).contains(&self.probability) {
+            return Err(SequencerError::ProbabilityOutOfRange);
+        }
+        Ok(())
+    }
+}
+
+impl Default for Step {
+    fn default() -> Self {
+        Self::REST
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub struct Pattern {
+    pub steps: [Step; MAX_STEPS],
+    /// Number of steps played (1..=MAX_STEPS)
+    pub length: usize,
+}
+
+impl Pattern {
+    pub fn new(steps: &[Step]) -> Result<Self, SequencerError> {
+        if steps.is_empty() || steps.len() > MAX_STEPS {
+            return Err(SequencerError::LengthOutOfRange);
+        }
+        let mut pattern = Self {
+            steps: [Step::REST; MAX_STEPS],
+            length: steps.len(),
+        };
+        pattern.steps[..steps.len()].copy_from_slice(steps);
+        pattern.validate()?;
+        Ok(pattern)
+    }
+
+    fn validate(&self) -> Result<(), SequencerError> {
+        if !(1..=MAX_STEPS).contains(&self.length) {
+            return Err(SequencerError::LengthOutOfRange);
+        }
+        self.steps.iter().try_for_each(Step::validate)
+    }
+}
+
+impl Default for Pattern {
+    /// Sixteen rests
+    fn default() -> Self {
+        Self {
+            steps: [Step::REST; MAX_STEPS],
+            length: 16,
+        }
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum ClockSource {
+    /// Runs at `SequencerParams::tempo`
+    #[default]
+    Internal,
+    /// Advances on `clock` pulses
+    External,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum PlayMode {
+    /// Plays the steps of the selected pattern
+    #[default]
+    Pattern,
+    /// Plays the held notes one after the other
+    Arpeggiator,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum ArpMode {
+    #[default]
+    Up,
+    Down,
+    /// Up and down again without repeating the highest and lowest note
+    UpDown,
+    Random,
+    /// In the order the notes were played
+    AsPlayed,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub struct SequencerParams {
+    /// Beats per minute of the internal clock
+    pub tempo: f32,
+    /// Steps per beat, 4 plays sixteenth notes
+    pub steps_per_beat: u8,
+    /// Share of a pair of steps taken by the first one (0.5..=0.75), 0.5
+    /// plays straight and 0.67 triplet swing
+    pub swing: f32,
+    pub clock: ClockSource,
+    /// External clock pulses per step, 6 for sixteenth notes from MIDI clock
+    pub pulses_per_step: u8,
+    pub play: PlayMode,
+    pub arp_mode: ArpMode,
+    /// Octaves the arpeggio spans (1..=MAX_OCTAVES)
+    pub octaves: u8,
+    /// Length of the arpeggiated notes relative to the step (0.0..=1.0)
+    pub arp_gate: f32,
+}
+
+impl Default for SequencerParams {
+    fn default() -> Self {
+        Self {
+            tempo: 120.0,
+            steps_per_beat: 4,
+            swing: 0.5,
+            clock: ClockSource::Internal,
+            pulses_per_step: 1,
+            play: PlayMode::Pattern,
+            arp_mode: ArpMode::Up,
+            octaves: 1,
+            arp_gate: 0.5,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SequencerEvent {
+    /// With `slide` the previous note is still held and the voice should
+    /// glide instead of retriggering
+    NoteOn {
+        note: u8,
+        velocity: u8,
+        slide: bool,
+    },
+    NoteOff {
+        note: u8,
+    },
+}
+
+/// Events with their offset in samples within the block
+pub type SequencerEvents = Vec<(usize, SequencerEvent), MAX_EVENTS>;
+
+pub struct Sequencer {
+    params: SequencerParams,
+    sample_rate: f32,
+    patterns: [Pattern; MAX_PATTERNS],
+    pattern: usize,
+    queued_pattern: Option<usize>,
+    running: bool,
+    /// Step that plays next
+    step: usize,
+    /// Steps played since the start, picks the swung ones
+    count: u32,
+    /// Samples until the next step, if one is due
+    until_step: Option<f32>,
+    /// Note that sounds and the samples until it ends, `None` while it is
+    /// held for a slide into the next step
+    sounding: Option<(u8, Option<f32>)>,
+    pulses: u8,
+    /// Samples since the last external clock pulse
+    since_pulse: f32,
+    /// Step length measured from the external clock
+    pulse_step_length: f32,
+    held: Vec<(u8, u8), MAX_HELD_NOTES>,
+    arp_index: usize,
+    random: Random,
+}
+
+impl Sequencer {
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            params: SequencerParams::default(),
+            sample_rate,
+            patterns: [Pattern::default(); MAX_PATTERNS],
+            pattern: 0,
+            queued_pattern: None,
+            running: false,
+            step: 0,
+            count: 0,
+            until_step: None,
+            sounding: None,
+            pulses: 0,
+            since_pulse: 0.0,
+            pulse_step_length: sample_rate / 8.0,
+            held: Vec::new(),
+            arp_index: 0,
+            random: Random::new(0x5E0_0001),
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+    }
+
+    pub fn set_params(&mut self, params: SequencerParams) -> Result<(), SequencerError> {
+        if !(20.0..=300.0).contains(&params.tempo) {
+            return Err(SequencerError::TempoOutOfRange);
+        }
+        if params.steps_per_beat == 0 {
+            return Err(SequencerError::StepsPerBeatOutOfRange);
+        }
+        if !(0.5..=0.75).contains(&params.swing) {
+            return Err(SequencerError::SwingOutOfRange);
+        }
+        if params.pulses_per_step == 0 {
+            return Err(SequencerError::PulsesOutOfRange);
+        }
+        if !(1..=MAX_OCTAVES).contains(&params.octaves) {
+            return Err(SequencerError::OctavesOutOfRange);
+        }
+        if !(0.0..=1.0).contains(&params.arp_gate) {
+            return Err(SequencerError::GateOutOfRange);
+        }
+        let clock_changed = self.params.clock != params.clock;
+        self.params = params;
+        if clock_changed && self.running {
+            self.until_step = match params.clock {
+                ClockSource::Internal => Some(0.0),
+                ClockSource::External => None,
+            };
+        }
+        Ok(())
+    }
+
+    pub fn params(&self) -> &SequencerParams {
+        &self.params
+    }
+
+    pub fn pattern(&self, index: usize) -> Option<&Pattern> {
+        self.patterns.get(index)
+    }
+
+    pub fn set_pattern(&mut self, index: usize, pattern: Pattern) -> Result<(), SequencerError> {
+        pattern.validate()?;
+        let slot = self
+            .patterns
+            .get_mut(index)
+            .ok_or(SequencerError::PatternOutOfRange)?;
+        *slot = pattern;
+        Ok(())
+    }
+
+    pub fn set_step(
+        &mut self,
+        pattern: usize,
+        index: usize,
+        step: Step,
+    ) -> Result<(), SequencerError> {
+        step.validate()?;
+        let pattern = self
+            .patterns
+            .get_mut(pattern)
+            .ok_or(SequencerError::PatternOutOfRange)?;
+        *pattern
+            .steps
+            .get_mut(index)
+            .ok_or(SequencerError::StepOutOfRange)? = step;
+        Ok(())
+    }
+
+    /// Switches to another pattern at the end of the current one, or right
+    /// away while stopped
+    pub fn select_pattern(&mut self, index: usize) -> Result<(), SequencerError> {
+        if index >= MAX_PATTERNS {
+            return Err(SequencerError::PatternOutOfRange);
+        }
+        if self.running {
+            self.queued_pattern = Some(index);
+        } else {
+            self.pattern = index;
+            self.step = 0;
+        }
+        Ok(())
+    }
+
+    pub fn selected_pattern(&self) -> usize {
+        self.pattern
+    }
+
+    /// Step that plays next
+    pub fn position(&self) -> usize {
+        self.step
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// Starts from the first step, the internal clock plays it right away
+    pub fn start(&mut self) {
+        self.running = true;
+        self.rewind();
+        self.until_step = match self.params.clock {
+            ClockSource::Internal => Some(0.0),
+            ClockSource::External => None,
+        };
+    }
+
+    /// Stops and ends the sounding note
+    pub fn stop(&mut self) -> SequencerEvents {
+        self.running = false;
+        self.until_step = None;
+        let mut events = Vec::new();
+        if let Some((note, _)) = self.sounding.take() {
+            let _ = events.push((0, SequencerEvent::NoteOff { note }));
+        }
+        events
+    }
+
+    /// Jumps back to the first step, e.g. on a reset input
+    pub fn rewind(&mut self) {
+        if let Some(pattern) = self.queued_pattern.take() {
+            self.pattern = pattern;
+        }
+        self.step = 0;
+        self.count = 0;
+        self.arp_index = 0;
+        self.pulses = 0;
+    }
+
+    /// External clock pulse, e.g. the rising edge of a gate input. Takes
+    /// effect at the start of the next block.
+    pub fn clock(&mut self) {
+        // The distance between the pulses gives the tempo for the gate
+        // lengths and the swing
+        if self.since_pulse > 0.0 {
+            self.pulse_step_length = self.since_pulse * self.params.pulses_per_step as f32;
+        }
+        self.since_pulse = 0.0;
+        if !self.running || self.params.clock != ClockSource::External {
+            return;
+        }
+        if self.pulses == 0 {
+            let delay = if !self.count.is_multiple_of(2) {
+                (2.0 * self.params.swing - 1.0) * self.pulse_step_length
+            } else {
+                0.0
+            };
+            self.until_step = Some(delay);
+        }
+        self.pulses = (self.pulses + 1) % self.params.pulses_per_step;
+    }
+
+    /// Adds a held note for the arpeggiator
+    pub fn note_on(&mut self, note: u8, velocity: u8) {
+        self.held.retain(|(held, _)| *held != note);
+        if self.held.is_full() {
+            self.held.remove(0);
+        }
+        let _ = self.held.push((note, velocity));
+    }
+
+    pub fn note_off(&mut self, note: u8) {
+        self.held.retain(|(held, _)| *held != note);
+    }
+
+    pub fn held_notes(&self) -> impl Iterator<Item = u8> + '_ {
+        self.held.iter().map(|(note, _)| *note)
+    }
+
+    /// Runs for a block of `samples` and returns the events within it
+    pub fn process(&mut self, samples: usize) -> SequencerEvents {
+        let mut events = Vec::new();
+        let block = samples as f32;
+        loop {
+            let note_off = self.sounding.and_then(|(_, until)| until);
+            let (time, is_step) = match (self.until_step, note_off) {
+                // Notes end before the next one starts at the same time
+                (Some(step), Some(off)) if off <= step => (off, false),
+                (Some(step), _) => (step, true),
+                (None, Some(off)) => (off, false),
+                (None, None) => break,
+            };
+            if time >= block {
+                break;
+            }
+            let offset = time.max(0.0) as usize;
+            if is_step {
+                self.play_step(time, offset, &mut events);
+            } else if let Some((note, _)) = self.sounding.take() {
+                let _ = events.push((offset, SequencerEvent::NoteOff { note }));
+            }
+        }
+
+        if let Some(until) = self.until_step.as_mut() {
+            *until -= block;
+        }
+        if let Some((_, Some(until))) = self.sounding.as_mut() {
+            *until -= block;
+        }
+        self.since_pulse += block;
+        events
+    }
+
+    /// Length of the step that plays next in samples, with swing
+    fn step_length(&self) -> f32 {
+        let length = match self.params.clock {
+            ClockSource::Internal => {
+                self.sample_rate * 60.0 / (self.params.tempo * self.params.steps_per_beat as f32)
+            }
+            ClockSource::External => self.pulse_step_length,
+        };
+        let swing = self.params.swing;
+        if self.count.is_multiple_of(2) {
+            2.0 * swing * length
+        } else {
+            2.0 * (1.0 - swing) * length
+        }
+    }
+
+    fn play_step(&mut self, time: f32, offset: usize, events: &mut SequencerEvents) {
+        let length = self.step_length();
+        self.until_step = match self.params.clock {
+            ClockSource::Internal => Some(time + length),
+            ClockSource::External => None,
+        };
+
+        let next = match self.params.play {
+            PlayMode::Pattern => self.next_pattern_note(),
+            PlayMode::Arpeggiator => self.next_arp_note(),
+        };
+        self.count = self.count.wrapping_add(1);
+
+        let Some((note, velocity, gate, slide)) = next else {
+            return;
+        };
+        // A held note slides into the new one, anything else ends first.
+        // Sliding into the same note ties them.
+        let previous = self.sounding.take().map(|(note, _)| note);
+        let slide = slide && previous.is_some();
+        if !(slide && previous == Some(note)) {
+            if let Some(previous) = previous.filter(|_| !slide) {
+                let _ = events.push((offset, SequencerEvent::NoteOff { note: previous }));
+            }
+            let _ = events.push((
+                offset,
+                SequencerEvent::NoteOn {
+                    note,
+                    velocity,
+                    slide,
+                },
+            ));
+            if let Some(previous) = previous.filter(|_| slide) {
+                let _ = events.push((offset, SequencerEvent::NoteOff { note: previous }));
+            }
+        }
+
+        // The note is held into a slide on the next step
+        let held = self.params.play == PlayMode::Pattern && self.next_step_slides();
+        let until = (!held).then_some(time + gate * length);
+        self.sounding = Some((note, until));
+    }
+
+    fn next_pattern_note(&mut self) -> Option<(u8, u8, f32, bool)> {
+        let pattern = &self.patterns[self.pattern];
+        let step = pattern.steps[self.step];
+        self.step += 1;
+        if self.step >= pattern.length {
+            self.step = 0;
+            if let Some(queued) = self.queued_pattern.take() {
+                self.pattern = queued;
+            }
+        }
+        let plays =
+            step.enabled && (step.probability >= 1.0 || self.random.unipolar() < step.probability);
+        plays.then_some((step.note, step.velocity, step.gate, step.slide))
+    }
+
+    fn next_step_slides(&self) -> bool {
+        let step = self.patterns[self.pattern].steps[self.step];
+        step.enabled && step.slide
+    }
+
+    fn next_arp_note(&mut self) -> Option<(u8, u8, f32, bool)> {
+        let notes = self.held.len();
+        if notes == 0 {
+            self.arp_index = 0;
+            return None;
+        }
+        let total = notes * self.params.octaves as usize;
+        let index = match self.params.arp_mode {
+            ArpMode::Up | ArpMode::AsPlayed => self.arp_index % total,
+            ArpMode::Down => total - 1 - self.arp_index % total,
+            ArpMode::UpDown => {
+                let period = (2 * total).saturating_sub(2).max(1);
+                let position = self.arp_index % period;
+                if position < total {
+                    position
+                } else {
+                    period - position
+                }
+            }
+            ArpMode::Random => (self.random.unipolar() * total as f32) as usize % total,
+        };
+        self.arp_index = self.arp_index.wrapping_add(1);
+
+        let (note, velocity) = if self.params.arp_mode == ArpMode::AsPlayed {
+            self.held[index % notes]
+        } else {
+            let mut sorted = self.held.clone();
+            sorted.sort_unstable_by_key(|(note, _)| *note);
+            sorted[index % notes]
+        };
+        let octave = (index / notes) as u8;
+        let note = note.saturating_add(12 * octave).min(127);
+        Some((note, velocity, self.params.arp_gate, false))
+    }
+}
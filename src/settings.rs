@@ -0,0 +1,367 @@
+// Settings
+//
+// Log structured key/value store on flash for presets, MIDI mappings and
+// calibration. Rewriting one spot on every change would soon wear out its
+// sector, so every change is appended as a new record and the sectors are
+// used in turn as a ring. A sector starts with a header holding its sequence
+// number, each record holds key, value and a CRC, and the last record of a
+// key wins. When the active sector is full the next free one takes over.
+// Once no free sector is left, whatever is still current in the oldest
+// sector gets copied over and the oldest one is erased.
+//
+// Losing power at any point loses at most the change being written: a torn
+// record or sector header fails its CRC and is skipped, and the oldest sector
+// is only erased once everything still needed from it has been copied.
+use heapless::Vec;
+
+use crate::crc::crc32;
+use crate::flash::{Flash, FlashError, SECTOR_SIZE};
+
+pub const MAX_KEYS: usize = 32;
+pub const MAX_VALUE: usize = 64;
+pub const MAX_SECTORS: usize = 16;
+/// Size of all current records together. Bounded, so that the records
+/// copied from the oldest sector always fit into the next one, even after
+/// the power failed while copying.
+pub const MAX_LIVE_BYTES: usize = 1536;
+
+const MAGIC: [u8; 4] = *b"DKKV";
+const SECTOR_HEADER: u32 = 12;
+const RECORD_HEADER: usize = 4;
+const CRC_SIZE: usize = 4;
+/// Largest record a torn length byte can claim
+const MAX_RECORD: usize = record_size(u8::MAX as usize);
+const FLAG_VALUE: u8 = 0x00;
+const FLAG_REMOVED: u8 = 0x01;
+
+#[derive(Debug, PartialEq)]
+pub enum SettingsError {
+    Flash(FlashError),
+    /// Needs 2..=MAX_SECTORS whole sectors within the flash
+    InvalidRegion,
+    /// 0xFFFF is what erased flash reads
+    InvalidKey,
+    ValueTooLong,
+    TooManyKeys,
+    Full,
+    BufferTooSmall,
+}
+
+impl From<FlashError> for SettingsError {
+    fn from(error: FlashError) -> Self {
+        SettingsError::Flash(error)
+    }
+}
+
+/// Records are padded to whole words
+const fn record_size(length: usize) -> usize {
+    (RECORD_HEADER + length + CRC_SIZE).next_multiple_of(4)
+}
+
+/// Where the current value of a key is
+#[derive(Copy, Clone)]
+struct Entry {
+    key: u16,
+    sector: usize,
+    offset: u32,
+    length: usize,
+}
+
+pub struct SettingsStore<F: Flash> {
+    flash: F,
+    address: u32,
+    sectors: usize,
+    /// Sequence numbers of the sectors in use, `None` for free ones
+    sequences: [Option<u32>; MAX_SECTORS],
+    active: usize,
+    /// Offset of the next record in the active sector
+    offset: u32,
+    entries: Vec<Entry, MAX_KEYS>,
+}
+
+impl<F: Flash> SettingsStore<F> {
+    /// Reads the store in the `sectors` sectors from `address` on, and
+    /// finishes whatever was interrupted by a power loss. Flash without a
+    /// store becomes an empty one.
+    pub fn mount(flash: F, address: u32, sectors: usize) -> Result<Self, SettingsError> {
+        if !(2..=MAX_SECTORS).contains(&sectors)
+            || !address.is_multiple_of(SECTOR_SIZE)
+            || flash
+                .check_range(address, sectors * SECTOR_SIZE as usize)
+                .is_err()
+        {
+            return Err(SettingsError::InvalidRegion);
+        }
+        let mut store = Self {
+            flash,
+            address,
+            sectors,
+            sequences: [None; MAX_SECTORS],
+            active: 0,
+            offset: 0,
+            entries: Vec::new(),
+        };
+        for sector in 0..sectors {
+            store.sequences[sector] = store.read_header(sector)?;
+        }
+
+        // Replay the sectors from the oldest on
+        let mut order: Vec<usize, MAX_SECTORS> = (0..sectors)
+            .filter(|sector| store.sequences[*sector].is_some())
+            .collect();
+        order.sort_unstable_by_key(|sector| store.sequences[*sector]);
+        for sector in &order {
+            store.active = *sector;
+            store.offset = store.replay(*sector)?;
+        }
+
+        if order.is_empty() {
+            store.start_sector(0)?;
+        } else if store.free_sector().is_none() {
+            // The power failed before the oldest sector was erased
+            store.reclaim(order[0])?;
+        }
+        Ok(store)
+    }
+
+    /// Copies the value of `key` into `buffer`, returns its length or `None`
+    /// if there is no such key
+    pub fn get(&mut self, key: u16, buffer: &mut [u8]) -> Result<Option<usize>, SettingsError> {
+        let Some(entry) = self.entry(key) else {
+            return Ok(None);
+        };
+        let value = buffer
+            .get_mut(..entry.length)
+            .ok_or(SettingsError::BufferTooSmall)?;
+        let address = self.sector_address(entry.sector) + entry.offset + RECORD_HEADER as u32;
+        self.flash.read(address, value)?;
+        Ok(Some(entry.length))
+    }
+
+    pub fn contains(&self, key: u16) -> bool {
+        self.entry(key).is_some()
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = u16> + '_ {
+        self.entries.iter().map(|entry| entry.key)
+    }
+
+    pub fn set(&mut self, key: u16, value: &[u8]) -> Result<(), SettingsError> {
+        if key == u16::MAX {
+            return Err(SettingsError::InvalidKey);
+        }
+        if value.len() > MAX_VALUE {
+            return Err(SettingsError::ValueTooLong);
+        }
+        let existing = self.entry(key);
+        if existing.is_none() && self.entries.is_full() {
+            return Err(SettingsError::TooManyKeys);
+        }
+        let live = self.live_bytes() + record_size(value.len())
+            - existing.map_or(0, |entry| record_size(entry.length));
+        if live > MAX_LIVE_BYTES {
+            return Err(SettingsError::Full);
+        }
+        self.append(key, FLAG_VALUE, value)
+    }
+
+    pub fn remove(&mut self, key: u16) -> Result<(), SettingsError> {
+        if !self.contains(key) {
+            return Ok(());
+        }
+        self.append(key, FLAG_REMOVED, &[])
+    }
+
+    pub fn into_flash(self) -> F {
+        self.flash
+    }
+
+    fn entry(&self, key: u16) -> Option<Entry> {
+        self.entries.iter().find(|entry| entry.key == key).copied()
+    }
+
+    fn live_bytes(&self) -> usize {
+        self.entries
+            .iter()
+            .map(|entry| record_size(entry.length))
+            .sum()
+    }
+
+    fn sector_address(&self, sector: usize) -> u32 {
+        self.address + sector as u32 * SECTOR_SIZE
+    }
+
+    /// Sequence number of a sector in use
+    fn read_header(&mut self, sector: usize) -> Result<Option<u32>, SettingsError> {
+        let mut header = [0; SECTOR_HEADER as usize];
+        self.flash.read(self.sector_address(sector), &mut header)?;
+        let sequence = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        let crc = u32::from_le_bytes([header[8], header[9], header[10], header[11]]);
+        Ok((header[..4] == MAGIC && crc32(&header[..8]) == crc).then_some(sequence))
+    }
+
+    /// Erases a sector and makes it the active one
+    fn start_sector(&mut self, sector: usize) -> Result<(), SettingsError> {
+        let sequence = self
+            .sequences
+            .iter()
+            .flatten()
+            .max()
+            .map_or(0, |last| last + 1);
+        let address = self.sector_address(sector);
+        self.flash.erase_sector(address)?;
+        let mut header = [0; SECTOR_HEADER as usize];
+        header[..4].copy_from_slice(&MAGIC);
+        header[4..8].copy_from_slice(&sequence.to_le_bytes());
+        let crc = crc32(&header[..8]);
+        header[8..].copy_from_slice(&crc.to_le_bytes());
+        self.flash.program(address, &header)?;
+        self.sequences[sector] = Some(sequence);
+        self.active = sector;
+        self.offset = SECTOR_HEADER;
+        Ok(())
+    }
+
+    /// The next free sector after the active one
+    fn free_sector(&self) -> Option<usize> {
+        (1..=self.sectors)
+            .map(|step| (self.active + step) % self.sectors)
+            .find(|sector| self.sequences[*sector].is_none())
+    }
+
+    /// Applies the records of a sector, returns where the next one goes
+    fn replay(&mut self, sector: usize) -> Result<u32, SettingsError> {
+        let address = self.sector_address(sector);
+        let mut record = [0; MAX_RECORD];
+        let mut offset = SECTOR_HEADER as usize;
+        while offset + RECORD_HEADER <= SECTOR_SIZE as usize {
+            self.flash
+                .read(address + offset as u32, &mut record[..RECORD_HEADER])?;
+            if record[..RECORD_HEADER].iter().all(|byte| *byte == 0xFF) {
+                // The end, unless a torn write left something behind
+                return Ok(if self.is_erased(address + offset as u32)? {
+                    offset as u32
+                } else {
+                    SECTOR_SIZE
+                });
+            }
+            // A torn length can only be larger, as unprogrammed bits are 1,
+            // so skipping it never lands within the torn record
+            let length = usize::from(record[2]);
+            let size = record_size(length);
+            if offset + size > SECTOR_SIZE as usize {
+                break;
+            }
+            self.flash.read(
+                address + (offset + RECORD_HEADER) as u32,
+                &mut record[RECORD_HEADER..size],
+            )?;
+            let (data, crc) = record.split_at(RECORD_HEADER + length);
+            if crc32(data).to_le_bytes() == crc[..CRC_SIZE] {
+                let key = u16::from_le_bytes([record[0], record[1]]);
+                self.apply(key, record[3], sector, offset as u32, length);
+            }
+            offset += size;
+        }
+        Ok(SECTOR_SIZE)
+    }
+
+    /// Whether the rest of the sector from `address` on is erased
+    fn is_erased(&mut self, mut address: u32) -> Result<bool, SettingsError> {
+        let mut chunk = [0; 64];
+        while !address.is_multiple_of(SECTOR_SIZE) {
+            let length = chunk
+                .len()
+                .min((SECTOR_SIZE - address % SECTOR_SIZE) as usize);
+            self.flash.read(address, &mut chunk[..length])?;
+            if chunk[..length].iter().any(|byte| *byte != 0xFF) {
+                return Ok(false);
+            }
+            address += length as u32;
+        }
+        Ok(true)
+    }
+
+    fn apply(&mut self, key: u16, flags: u8, sector: usize, offset: u32, length: usize) {
+        let index = self.entries.iter().position(|entry| entry.key == key);
+        let entry = Entry {
+            key,
+            sector,
+            offset,
+            length,
+        };
+        match (flags, index) {
+            (FLAG_REMOVED, Some(index)) => {
+                self.entries.swap_remove(index);
+            }
+            (FLAG_REMOVED, None) => {}
+            (_, Some(index)) => self.entries[index] = entry,
+            // `set` never writes more keys than fit
+            (_, None) => {
+                let _ = self.entries.push(entry);
+            }
+        }
+    }
+
+    fn append(&mut self, key: u16, flags: u8, value: &[u8]) -> Result<(), SettingsError> {
+        if self.offset as usize + record_size(value.len()) > SECTOR_SIZE as usize {
+            self.next_sector()?;
+        }
+        self.write_record(key, flags, value)
+    }
+
+    /// Moves on to the next free sector and frees the oldest one if that was
+    /// the last
+    fn next_sector(&mut self) -> Result<(), SettingsError> {
+        let sector = self.free_sector().ok_or(SettingsError::Full)?;
+        self.start_sector(sector)?;
+        if self.free_sector().is_none() {
+            let oldest = (0..self.sectors)
+                .filter(|sector| *sector != self.active)
+                .min_by_key(|sector| self.sequences[*sector])
+                .expect("There are at least two sectors");
+            self.reclaim(oldest)?;
+        }
+        Ok(())
+    }
+
+    /// Copies the current values of a sector to the active one and erases it
+    fn reclaim(&mut self, sector: usize) -> Result<(), SettingsError> {
+        let keys: Vec<u16, MAX_KEYS> = self
+            .entries
+            .iter()
+            .filter(|entry| entry.sector == sector)
+            .map(|entry| entry.key)
+            .collect();
+        let mut value = [0; MAX_VALUE];
+        for key in keys {
+            let length = self.get(key, &mut value)?.unwrap_or(0);
+            self.write_record(key, FLAG_VALUE, &value[..length])?;
+        }
+        self.flash.erase_sector(self.sector_address(sector))?;
+        self.sequences[sector] = None;
+        Ok(())
+    }
+
+    fn write_record(&mut self, key: u16, flags: u8, value: &[u8]) -> Result<(), SettingsError> {
+        let size = record_size(value.len());
+        if self.offset as usize + size > SECTOR_SIZE as usize {
+            return Err(SettingsError::Full);
+        }
+        let mut record = [0xFF; record_size(MAX_VALUE)];
+        record[..2].copy_from_slice(&key.to_le_bytes());
+        record[2] = value.len() as u8;
+        record[3] = flags;
+        record[RECORD_HEADER..RECORD_HEADER + value.len()].copy_from_slice(value);
+        let crc = crc32(&record[..RECORD_HEADER + value.len()]);
+        record[RECORD_HEADER + value.len()..][..CRC_SIZE].copy_from_slice(&crc.to_le_bytes());
+
+        // The space is gone even if the write fails halfway
+        let offset = self.offset;
+        self.offset += size as u32;
+        self.flash
+            .program(self.sector_address(self.active) + offset, &record[..size])?;
+        self.apply(key, flags, self.active, offset, value.len());
+        Ok(())
+    }
+}
@@ -0,0 +1,387 @@
+// Spectral
+//
+// Short-time Fourier transform with weighted overlap-add resynthesis and
+// effects working on its spectra. Analysis and synthesis use a square root
+// Hann window at a hop size of `N / 4`, which sums up to a constant, so an
+// effect that leaves the spectrum alone reconstructs the input perfectly,
+// delayed by `N` samples.
+//
+// Effects that keep state per bin are sized by their number of bins, `B`,
+// which has to be `N / 2 + 1` for a transform of size `N`.
+use micromath::F32Ext;
+
+use crate::fft::{Complex, RealFft};
+use crate::random::Random;
+use crate::spectrum::Window;
+
+/// Overlap of the analysis frames
+const OVERLAP: usize = 4;
+/// Sum of the overlapping Hann windows (analysis times synthesis)
+const WINDOW_SUM: f32 = OVERLAP as f32 / 2.0;
+/// Smoothing of the denoise gains over time, reduces "musical noise"
+const GAIN_SMOOTHING: f32 = 0.5;
+
+#[derive(Debug)]
+pub enum SpectralError {
+    SizeNotPowerOfTwo,
+}
+
+/// Modifies the packed spectrum (see `RealFft`) of one STFT frame
+pub trait SpectralEffect<const N: usize> {
+    fn process_spectrum(&mut self, spectrum: &mut [f32; N]);
+}
+
+/// Leaves the spectrum untouched
+pub struct Bypass;
+
+impl<const N: usize> SpectralEffect<N> for Bypass {
+    fn process_spectrum(&mut self, _spectrum: &mut [f32; N]) {}
+}
+
+pub struct Stft<const N: usize> {
+    fft: RealFft<N>,
+    window: [f32; N],
+    input: [f32; N],
+    write_pos: usize,
+    frame: [f32; N],
+    accumulator: [f32; N],
+    output: [f32; N],
+    hop_pos: usize,
+}
+
+impl<const N: usize> Stft<N> {
+    pub const HOP: usize = N / OVERLAP;
+    /// Delay between input and output in samples
+    pub const LATENCY: usize = N;
+
+    pub fn new() -> Result<Self, SpectralError> {
+        if N < 2 * OVERLAP {
+            return Err(SpectralError::SizeNotPowerOfTwo);
+        }
+        let fft = RealFft::new().map_err(|_| SpectralError::SizeNotPowerOfTwo)?;
+        let mut window = [0.0; N];
+        Window::Hann.fill(&mut window);
+        for value in window.iter_mut() {
+            *value = libm::sqrtf(*value);
+        }
+        Ok(Self {
+            fft,
+            window,
+            input: [0.0; N],
+            write_pos: 0,
+            frame: [0.0; N],
+            accumulator: [0.0; N],
+            output: [0.0; N],
+            hop_pos: 0,
+        })
+    }
+
+    #[inline]
+    pub fn tick(&mut self, input: f32, effect: &mut impl SpectralEffect<N>) -> f32 {
+        self.input[self.write_pos] = input;
+        self.write_pos = (self.write_pos + 1) % N;
+
+        let output = self.output[self.hop_pos];
+        self.hop_pos += 1;
+        if self.hop_pos == Self::HOP {
+            self.hop_pos = 0;
+            self.process_frame(effect);
+        }
+        output
+    }
+
+    pub fn reset(&mut self) {
+        self.input.fill(0.0);
+        self.accumulator.fill(0.0);
+        self.output.fill(0.0);
+        self.write_pos = 0;
+        self.hop_pos = 0;
+    }
+
+    fn process_frame(&mut self, effect: &mut impl SpectralEffect<N>) {
+        // Oldest sample first
+        for (i, value) in self.frame.iter_mut().enumerate() {
+            *value = self.input[(self.write_pos + i) % N] * self.window[i];
+        }
+        self.fft.forward(&mut self.frame);
+        effect.process_spectrum(&mut self.frame);
+        self.fft.inverse(&mut self.frame);
+
+        let scale = 1.0 / WINDOW_SUM;
+        for ((sum, value), weight) in self
+            .accumulator
+            .iter_mut()
+            .zip(&self.frame)
+            .zip(&self.window)
+        {
+            *sum += value * weight * scale;
+        }
+
+        // The first hop has received all its contributions
+        let hop = Self::HOP;
+        self.output[..hop].copy_from_slice(&self.accumulator[..hop]);
+        self.accumulator.copy_within(hop.., 0);
+        self.accumulator[N - hop..].fill(0.0);
+    }
+}
+
+/// Holds the current sound, resynthesizing it with the phase advance per
+/// frame that each bin had when the freeze started
+pub struct Freeze<const B: usize> {
+    frozen: bool,
+    magnitudes: [f32; B],
+    phases: [f32; B],
+    advances: [f32; B],
+}
+
+impl<const B: usize> Freeze<B> {
+    pub fn new() -> Self {
+        Self {
+            frozen: false,
+            magnitudes: [0.0; B],
+            phases: [0.0; B],
+            advances: [0.0; B],
+        }
+    }
+
+    pub fn set_frozen(&mut self, frozen: bool) {
+        self.frozen = frozen;
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.frozen
+    }
+}
+
+impl<const B: usize> Default for Freeze<B> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize, const B: usize> SpectralEffect<N> for Freeze<B> {
+    fn process_spectrum(&mut self, spectrum: &mut [f32; N]) {
+        const { assert!(B == N / 2 + 1, "One bin per frequency up to N / 2") };
+        for k in 0..B {
+            let phase = if self.frozen {
+                let phase = wrap_phase(self.phases[k] + self.advances[k]);
+                set_bin(spectrum, k, polar(self.magnitudes[k], phase));
+                phase
+            } else {
+                let bin = RealFft::bin(spectrum, k);
+                let phase = bin.im.atan2(bin.re);
+                self.magnitudes[k] = libm::sqrtf(bin.norm_sqr());
+                self.advances[k] = wrap_phase(phase - self.phases[k]);
+                phase
+            };
+            self.phases[k] = phase;
+        }
+    }
+}
+
+/// Spectral gate: attenuates bins that don't rise far enough above a
+/// learned noise profile
+pub struct Denoise<const B: usize> {
+    learning: bool,
+    frames: u32,
+    profile: [f32; B],
+    gains: [f32; B],
+    threshold: f32,
+    reduction: f32,
+}
+
+impl<const B: usize> Denoise<B> {
+    pub fn new() -> Self {
+        Self {
+            learning: false,
+            frames: 0,
+            profile: [0.0; B],
+            gains: [1.0; B],
+            threshold: 2.0,
+            reduction: 0.1,
+        }
+    }
+
+    /// While learning, the input is averaged into the noise profile and
+    /// passed through. Starting to learn discards the previous profile.
+    pub fn set_learning(&mut self, learning: bool) {
+        if learning && !self.learning {
+            self.profile.fill(0.0);
+            self.frames = 0;
+        }
+        self.learning = learning;
+    }
+
+    pub fn is_learning(&self) -> bool {
+        self.learning
+    }
+
+    /// Bins below `threshold` times the noise profile are attenuated to
+    /// `reduction` (0.0..=1.0, linear)
+    pub fn set_gate(&mut self, threshold: f32, reduction: f32) {
+        self.threshold = threshold.max(0.0);
+        self.reduction = reduction.clamp(0.0, 1.0);
+    }
+}
+
+impl<const B: usize> Default for Denoise<B> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize, const B: usize> SpectralEffect<N> for Denoise<B> {
+    fn process_spectrum(&mut self, spectrum: &mut [f32; N]) {
+        const { assert!(B == N / 2 + 1, "One bin per frequency up to N / 2") };
+        if self.learning {
+            // Running average of the magnitudes
+            self.frames += 1;
+            let weight = 1.0 / self.frames as f32;
+            for k in 0..B {
+                let magnitude = libm::sqrtf(RealFft::bin(spectrum, k).norm_sqr());
+                self.profile[k] += weight * (magnitude - self.profile[k]);
+            }
+            return;
+        }
+
+        for k in 0..B {
+            let bin = RealFft::bin(spectrum, k);
+            let magnitude = libm::sqrtf(bin.norm_sqr());
+            let target = if magnitude > self.threshold * self.profile[k] {
+                1.0
+            } else {
+                self.reduction
+            };
+            self.gains[k] = target + GAIN_SMOOTHING * (self.gains[k] - target);
+            set_bin(spectrum, k, bin * self.gains[k]);
+        }
+    }
+}
+
+/// Discards all phases, which makes the output periodic at the hop rate
+pub struct Robotize;
+
+impl<const N: usize> SpectralEffect<N> for Robotize {
+    fn process_spectrum(&mut self, spectrum: &mut [f32; N]) {
+        for k in 0..=N / 2 {
+            let magnitude = libm::sqrtf(RealFft::bin(spectrum, k).norm_sqr());
+            set_bin(spectrum, k, Complex::new(magnitude, 0.0));
+        }
+    }
+}
+
+/// Randomizes all phases, which removes any sense of pitch
+pub struct Whisperize {
+    random: Random,
+}
+
+impl Whisperize {
+    pub fn new() -> Self {
+        Self {
+            random: Random::new(0x1234_5678),
+        }
+    }
+}
+
+impl Default for Whisperize {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> SpectralEffect<N> for Whisperize {
+    fn process_spectrum(&mut self, spectrum: &mut [f32; N]) {
+        for k in 0..=N / 2 {
+            let magnitude = libm::sqrtf(RealFft::bin(spectrum, k).norm_sqr());
+            let phase = core::f32::consts::PI * self.random.bipolar();
+            set_bin(spectrum, k, polar(magnitude, phase));
+        }
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum SpectralMode {
+    /// The STFT is skipped, no latency
+    #[default]
+    Off,
+    Freeze,
+    Denoise,
+    Robotize,
+    Whisperize,
+}
+
+/// One channel of STFT processing with all spectral effects, `B` is
+/// `N / 2 + 1`
+pub struct Spectral<const N: usize, const B: usize> {
+    mode: SpectralMode,
+    stft: Stft<N>,
+    pub freeze: Freeze<B>,
+    pub denoise: Denoise<B>,
+    whisperize: Whisperize,
+}
+
+impl<const N: usize, const B: usize> Spectral<N, B> {
+    pub fn new() -> Result<Self, SpectralError> {
+        Ok(Self {
+            mode: SpectralMode::Off,
+            stft: Stft::new()?,
+            freeze: Freeze::new(),
+            denoise: Denoise::new(),
+            whisperize: Whisperize::new(),
+        })
+    }
+
+    pub fn set_mode(&mut self, mode: SpectralMode) {
+        if self.mode != mode {
+            self.mode = mode;
+            self.stft.reset();
+        }
+    }
+
+    pub fn mode(&self) -> SpectralMode {
+        self.mode
+    }
+
+    /// Delay between input and output in samples for the current mode
+    pub fn latency(&self) -> usize {
+        match self.mode {
+            SpectralMode::Off => 0,
+            _ => Stft::<N>::LATENCY,
+        }
+    }
+
+    #[inline]
+    pub fn tick(&mut self, input: f32) -> f32 {
+        match self.mode {
+            SpectralMode::Off => input,
+            SpectralMode::Freeze => self.stft.tick(input, &mut self.freeze),
+            SpectralMode::Denoise => self.stft.tick(input, &mut self.denoise),
+            SpectralMode::Robotize => self.stft.tick(input, &mut Robotize),
+            SpectralMode::Whisperize => self.stft.tick(input, &mut self.whisperize),
+        }
+    }
+}
+
+#[inline]
+fn set_bin<const N: usize>(spectrum: &mut [f32; N], k: usize, value: Complex) {
+    match k {
+        0 => spectrum[0] = value.re,
+        k if k == N / 2 => spectrum[1] = value.re,
+        k => {
+            spectrum[2 * k] = value.re;
+            spectrum[2 * k + 1] = value.im;
+        }
+    }
+}
+
+#[inline]
+fn polar(magnitude: f32, phase: f32) -> Complex {
+    Complex::new(magnitude * phase.cos(), magnitude * phase.sin())
+}
+
+/// Wraps a phase into -π..π
+#[inline]
+fn wrap_phase(phase: f32) -> f32 {
+    use core::f32::consts::{PI, TAU};
+    phase - TAU * ((phase + PI) / TAU).floor()
+}
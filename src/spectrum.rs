@@ -0,0 +1,204 @@
+// Spectrum
+//
+// Windowed spectral analysis on top of the real FFT: magnitude spectra, band
+// energies and transfer function measurements, e.g. to check the response
+// of a `Filter` from its measured output.
+use crate::fft::{Complex, RealFft};
+
+/// Magnitudes below this are treated as silence when dividing spectra
+const MIN_MAGNITUDE: f32 = 1.0e-9;
+
+#[derive(Debug)]
+pub enum SpectrumError {
+    SizeNotPowerOfTwo,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum Window {
+    Rectangular,
+    /// Good general purpose window
+    #[default]
+    Hann,
+    /// 4-term Blackman-Harris, sidelobes below -92 dB for a large dynamic range
+    BlackmanHarris,
+    /// Very flat main lobe for accurate amplitude readings
+    FlatTop,
+}
+
+impl Window {
+    /// Coefficients `a_k` of the cosine sum `Σ (-1)^k a_k cos(2πkn/N)`
+    fn coefficients(&self) -> &'static [f64] {
+        match self {
+            Window::Rectangular => &[1.0],
+            Window::Hann => &[0.5, 0.5],
+            Window::BlackmanHarris => &[0.35875, 0.48829, 0.14128, 0.01168],
+            Window::FlatTop => &[
+                0.21557895,
+                0.41663158,
+                0.277263158,
+                0.083578947,
+                0.006947368,
+            ],
+        }
+    }
+
+    /// Fills `table` with the periodic version of the window, as it is used
+    /// for spectral analysis
+    pub fn fill(&self, table: &mut [f32]) {
+        let length = table.len() as f64;
+        for (n, value) in table.iter_mut().enumerate() {
+            let mut sum = 0.0;
+            for (k, coefficient) in self.coefficients().iter().enumerate() {
+                let cos = libm::cos(2.0 * core::f64::consts::PI * (k * n) as f64 / length);
+                let sign = if k % 2 == 0 { 1.0 } else { -1.0 };
+                sum += sign * coefficient * cos;
+            }
+            *value = sum as f32;
+        }
+    }
+}
+
+/// Spectral analysis of blocks of `N` samples
+pub struct SpectrumAnalyzer<const N: usize> {
+    fft: RealFft<N>,
+    window: Window,
+    table: [f32; N],
+    coherent_gain: f32,
+    /// `N` times the energy of the window, scales the bins to power
+    power_gain: f32,
+    spectrum: [f32; N],
+}
+
+impl<const N: usize> SpectrumAnalyzer<N> {
+    /// Number of bins from DC to Nyquist
+    pub const BINS: usize = N / 2 + 1;
+
+    pub fn new(window: Window) -> Result<Self, SpectrumError> {
+        let fft = RealFft::new().map_err(|_| SpectrumError::SizeNotPowerOfTwo)?;
+        let mut analyzer = Self {
+            fft,
+            window,
+            table: [0.0; N],
+            coherent_gain: 1.0,
+            power_gain: 1.0,
+            spectrum: [0.0; N],
+        };
+        analyzer.update_window();
+        Ok(analyzer)
+    }
+
+    pub fn set_window(&mut self, window: Window) {
+        if self.window != window {
+            self.window = window;
+            self.update_window();
+        }
+    }
+
+    /// Windows and transforms a block of samples, the result is available
+    /// through the other methods until the next call
+    pub fn analyze(&mut self, input: &[f32; N]) {
+        for ((value, sample), weight) in self.spectrum.iter_mut().zip(input).zip(&self.table) {
+            *value = sample * weight;
+        }
+        self.fft.forward(&mut self.spectrum);
+    }
+
+    /// The last spectrum in the packed layout of `RealFft`
+    pub fn spectrum(&self) -> &[f32; N] {
+        &self.spectrum
+    }
+
+    pub fn bin(&self, k: usize) -> Complex {
+        RealFft::bin(&self.spectrum, k)
+    }
+
+    /// Center frequency of bin `k` in Hz
+    pub fn bin_frequency(k: usize, sample_rate: f32) -> f32 {
+        k as f32 * sample_rate / N as f32
+    }
+
+    /// Amplitude of a sinusoid centered on bin `k`, corrected for the window
+    pub fn magnitude(&self, k: usize) -> f32 {
+        let scale = if k == 0 || k == N / 2 { 1.0 } else { 2.0 };
+        scale * libm::sqrtf(self.bin(k).norm_sqr()) / self.coherent_gain
+    }
+
+    /// Writes the magnitude of the first `magnitudes.len()` bins
+    ///
+    /// # Panics
+    /// Panics if more than `Self::BINS` magnitudes are requested
+    pub fn magnitudes(&self, magnitudes: &mut [f32]) {
+        assert!(magnitudes.len() <= Self::BINS);
+        for (k, magnitude) in magnitudes.iter_mut().enumerate() {
+            *magnitude = self.magnitude(k);
+        }
+    }
+
+    /// Energy of the bins centered from `low` up to but not including
+    /// `high` Hz, the power of the signal in that band whatever the window.
+    /// A band reaching Nyquist includes the Nyquist bin, so adjacent bands
+    /// add up to the energy of the whole range.
+    pub fn band_energy(&self, low: f32, high: f32, sample_rate: f32) -> f32 {
+        let bin_width = sample_rate / N as f32;
+        let first = (libm::ceilf(low / bin_width).max(0.0) as usize).min(N / 2 + 1);
+        let end = if high >= sample_rate / 2.0 {
+            N / 2 + 1
+        } else {
+            (libm::ceilf(high / bin_width).max(0.0) as usize).min(N / 2 + 1)
+        };
+        let scale = 1.0 / self.power_gain;
+        (first..end.max(first))
+            .map(|k| {
+                let power = self.bin(k).norm_sqr() * scale;
+                if k == 0 || k == N / 2 {
+                    power
+                } else {
+                    2.0 * power
+                }
+            })
+            .sum()
+    }
+
+    /// Energy of the bands between consecutive `edges` in Hz
+    ///
+    /// # Panics
+    /// Panics unless there is one more edge than bands
+    pub fn band_energies(&self, edges: &[f32], sample_rate: f32, energies: &mut [f32]) {
+        assert_eq!(edges.len(), energies.len() + 1);
+        for (band, energy) in edges.windows(2).zip(energies.iter_mut()) {
+            *energy = self.band_energy(band[0], band[1], sample_rate);
+        }
+    }
+
+    /// Measures the magnitude response of a system, e.g. a `Filter`, from a
+    /// broadband `input` signal and the `output` it produced.
+    ///
+    /// Bins without input energy are set to 0.0. The analyzer holds the
+    /// spectrum of `output` afterwards.
+    ///
+    /// # Panics
+    /// Panics if more than `Self::BINS` bins are requested
+    pub fn response(&mut self, input: &[f32; N], output: &[f32; N], response: &mut [f32]) {
+        self.analyze(input);
+        self.magnitudes(response);
+        self.analyze(output);
+        for (k, gain) in response.iter_mut().enumerate() {
+            *gain = if *gain > MIN_MAGNITUDE {
+                self.magnitude(k) / *gain
+            } else {
+                0.0
+            };
+        }
+    }
+
+    fn update_window(&mut self) {
+        self.window.fill(&mut self.table);
+        self.coherent_gain = self.table.iter().sum();
+        self.power_gain = N as f32 * self.table.iter().map(|weight| weight * weight).sum::<f32>();
+    }
+}
+
+/// Converts a linear amplitude to decibels
+pub fn amplitude_to_db(amplitude: f32) -> f32 {
+    20.0 * libm::log10f(amplitude.max(MIN_MAGNITUDE))
+}
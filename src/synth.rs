@@ -0,0 +1,323 @@
+// Synth
+//
+// Polyphonic subtractive synthesizer on top of the voice allocator. Every
+// voice mixes two band-limited oscillators into its own lowpass filter,
+// whose cutoff follows a filter envelope, followed by an amplitude envelope.
+// The filter is updated once per block, everything else runs per sample.
+use crate::allocator::{AllocationMode, VoiceAllocator, VoiceEvent};
+use crate::envelope::{Envelope, EnvelopeError, EnvelopeParams};
+use crate::filter::{Filter, FilterParams, FilterType};
+use crate::oscillator::{Oscillator, OscillatorParams, Waveform};
+
+/// Highest filter cutoff relative to the sample rate, keeps clear of Nyquist
+const MAX_CUTOFF: f32 = 0.45;
+/// Voices below this level count as silent once released
+const SILENCE: f32 = 1.0e-4;
+
+#[derive(Debug)]
+pub enum SynthError {
+    CutoffOutOfRange,
+    ResonanceOutOfRange,
+    MixOutOfRange,
+    Envelope(EnvelopeError),
+}
+
+impl From<EnvelopeError> for SynthError {
+    fn from(error: EnvelopeError) -> Self {
+        SynthError::Envelope(error)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub struct SynthParams {
+    pub waveform_1: Waveform,
+    pub waveform_2: Waveform,
+    /// Detune of the second oscillator in cents
+    pub detune: f32,
+    /// Balance between the oscillators (0.0 = first, 1.0 = second)
+    pub mix: f32,
+    pub pulse_width: f32,
+    /// Filter cutoff in Hz without envelope
+    pub cutoff: f32,
+    pub resonance: f32,
+    /// Filter envelope depth in octaves (may be negative)
+    pub filter_envelope_depth: f32,
+    /// Cutoff follows the note, 1.0 tracks it fully
+    pub key_tracking: f32,
+    pub filter_envelope: EnvelopeParams,
+    pub amp_envelope: EnvelopeParams,
+    /// How much the velocity scales the level (0.0..=1.0)
+    pub velocity_sensitivity: f32,
+    /// Spread of the voices in unison mode in cents
+    pub unison_detune: f32,
+    pub volume: f32,
+}
+
+impl Default for SynthParams {
+    fn default() -> Self {
+        Self {
+            waveform_1: Waveform::Saw,
+            waveform_2: Waveform::Square,
+            detune: 7.0,
+            mix: 0.5,
+            pulse_width: 0.5,
+            cutoff: 800.0,
+            resonance: 1.0,
+            filter_envelope_depth: 3.0,
+            key_tracking: 0.5,
+            filter_envelope: EnvelopeParams::adsr(2.0, 300.0, 0.2, 300.0)
+                .expect("Those settings always work"),
+            amp_envelope: EnvelopeParams::adsr(5.0, 100.0, 0.8, 300.0)
+                .expect("Those settings always work"),
+            velocity_sensitivity: 0.7,
+            unison_detune: 15.0,
+            volume: 0.3,
+        }
+    }
+}
+
+/// Frequency of a MIDI note number in Hz, A4 (69) at 440 Hz
+pub fn note_to_frequency(note: f32) -> f32 {
+    440.0 * libm::exp2f((note - 69.0) / 12.0)
+}
+
+pub struct Voice {
+    sample_rate: f32,
+    oscillators: [Oscillator; 2],
+    filter: Filter,
+    filter_envelope: Envelope,
+    amp_envelope: Envelope,
+    note: u8,
+    gain: f32,
+    /// Detune of this voice in unison mode in cents
+    spread: f32,
+}
+
+impl Voice {
+    pub fn new(sample_rate: f32) -> Self {
+        let mut voice = Self {
+            sample_rate,
+            oscillators: [Oscillator::new(), Oscillator::new()],
+            filter: Filter::new(FilterType::Lowpass),
+            filter_envelope: Envelope::new(sample_rate),
+            amp_envelope: Envelope::new(sample_rate),
+            note: 69,
+            gain: 0.0,
+            spread: 0.0,
+        };
+        for oscillator in voice.oscillators.iter_mut() {
+            oscillator
+                .set_sample_rate(sample_rate)
+                .expect("Default frequency is below Nyquist");
+        }
+        voice
+            .filter
+            .set_sample_rate(sample_rate)
+            .expect("Default cutoff is below Nyquist");
+        voice
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.amp_envelope.is_active() && (self.amp_envelope.gate() || self.level() > SILENCE)
+    }
+
+    /// Current amplitude of the voice
+    pub fn level(&self) -> f32 {
+        self.amp_envelope.value() * self.gain
+    }
+
+    pub fn note_on(&mut self, params: &SynthParams, note: u8, velocity: u8, retrigger: bool) {
+        self.note = note;
+        if retrigger || !self.amp_envelope.gate() {
+            let velocity = velocity as f32 / 127.0;
+            self.gain = 1.0 - params.velocity_sensitivity * (1.0 - velocity);
+            if !self.amp_envelope.is_active() {
+                for oscillator in self.oscillators.iter_mut() {
+                    oscillator.reset();
+                }
+                self.filter.reset();
+            }
+            self.filter_envelope.gate_on(false);
+            self.amp_envelope.gate_on(false);
+        }
+        self.update_oscillators(params);
+    }
+
+    pub fn note_off(&mut self) {
+        self.filter_envelope.gate_off();
+        self.amp_envelope.gate_off();
+    }
+
+    pub fn set_params(&mut self, params: &SynthParams) -> Result<(), SynthError> {
+        self.filter_envelope.set_params(params.filter_envelope)?;
+        self.amp_envelope.set_params(params.amp_envelope)?;
+        self.update_oscillators(params);
+        Ok(())
+    }
+
+    /// Adds the voice to both channels of the buffer
+    pub fn process(&mut self, params: &SynthParams, audio_buffer: &mut [(f32, f32)]) {
+        if !self.amp_envelope.is_active() {
+            return;
+        }
+
+        // The filter follows the envelope once per block
+        let octaves = params.filter_envelope_depth * self.filter_envelope.value()
+            + params.key_tracking * (self.note as f32 - 60.0) / 12.0;
+        let cutoff =
+            (params.cutoff * libm::exp2f(octaves)).clamp(20.0, MAX_CUTOFF * self.sample_rate);
+        self.filter
+            .set_params(FilterParams {
+                frequency: cutoff,
+                quality: params.resonance,
+                gain: 0.0,
+            })
+            .expect("Cutoff is clamped below Nyquist");
+
+        let [oscillator_1, oscillator_2] = &mut self.oscillators;
+        for (left, right) in audio_buffer.iter_mut() {
+            let mix = oscillator_1.tick() * (1.0 - params.mix) + oscillator_2.tick() * params.mix;
+            self.filter_envelope.tick();
+            let sample = self.filter.tick(mix) * self.amp_envelope.tick() * self.gain;
+            *left += sample;
+            *right += sample;
+        }
+    }
+
+    fn update_oscillators(&mut self, params: &SynthParams) {
+        let frequency = note_to_frequency(self.note as f32 + self.spread / 100.0);
+        let detuned = frequency * libm::exp2f(params.detune / 1200.0);
+        let nyquist = self.sample_rate / 2.0;
+        for (oscillator, (waveform, frequency)) in self
+            .oscillators
+            .iter_mut()
+            .zip([(params.waveform_1, frequency), (params.waveform_2, detuned)])
+        {
+            oscillator
+                .set_params(OscillatorParams {
+                    waveform,
+                    frequency: frequency.min(nyquist),
+                    pulse_width: params.pulse_width,
+                    fm_index: 0.0,
+                })
+                .expect("Frequency is clamped to Nyquist");
+        }
+    }
+}
+
+/// Synthesizer with `N` voices
+pub struct Synth<const N: usize> {
+    params: SynthParams,
+    allocator: VoiceAllocator<N>,
+    voices: [Voice; N],
+}
+
+impl<const N: usize> Synth<N> {
+    pub fn new(sample_rate: f32) -> Self {
+        let mut synth = Self {
+            params: SynthParams::default(),
+            allocator: VoiceAllocator::new(AllocationMode::default()),
+            voices: core::array::from_fn(|_| Voice::new(sample_rate)),
+        };
+        synth
+            .set_params(SynthParams::default())
+            .expect("Those settings always work");
+        synth
+    }
+
+    pub fn set_params(&mut self, params: SynthParams) -> Result<(), SynthError> {
+        if !(0.0..=1.0).contains(&params.mix) {
+            return Err(SynthError::MixOutOfRange);
+        }
+        if params.cutoff <= 0.0 {
+            return Err(SynthError::CutoffOutOfRange);
+        }
+        if params.resonance <= 0.0 {
+            return Err(SynthError::ResonanceOutOfRange);
+        }
+        // All voices or none
+        params.filter_envelope.validate()?;
+        params.amp_envelope.validate()?;
+        self.params = params;
+        self.update_spread();
+        for voice in self.voices.iter_mut() {
+            voice
+                .set_params(&self.params)
+                .expect("The envelopes are valid");
+        }
+        Ok(())
+    }
+
+    pub fn params(&self) -> &SynthParams {
+        &self.params
+    }
+
+    pub fn set_mode(&mut self, mode: AllocationMode) {
+        let events = self.allocator.set_mode(mode);
+        self.apply(&events);
+        self.update_spread();
+        let params = self.params;
+        for voice in self.voices.iter_mut() {
+            voice.update_oscillators(&params);
+        }
+    }
+
+    pub fn note_on(&mut self, note: u8, velocity: u8) {
+        let events = self.allocator.note_on(note, velocity);
+        self.apply(&events);
+    }
+
+    pub fn note_off(&mut self, note: u8) {
+        let events = self.allocator.note_off(note);
+        self.apply(&events);
+    }
+
+    pub fn all_notes_off(&mut self) {
+        let events = self.allocator.all_notes_off();
+        self.apply(&events);
+    }
+
+    /// Adds the voices to the buffer
+    pub fn process(&mut self, audio_buffer: &mut [(f32, f32)]) {
+        let mut mix = [(0.0, 0.0); 64];
+        for block in audio_buffer.chunks_mut(mix.len()) {
+            let mix = &mut mix[..block.len()];
+            mix.fill((0.0, 0.0));
+            for (index, voice) in self.voices.iter_mut().enumerate() {
+                voice.process(&self.params, mix);
+                self.allocator
+                    .update_voice(index, voice.is_active(), voice.level());
+            }
+            for ((left, right), (mix_left, mix_right)) in block.iter_mut().zip(mix.iter()) {
+                *left += mix_left * self.params.volume;
+                *right += mix_right * self.params.volume;
+            }
+        }
+    }
+
+    fn apply(&mut self, events: &[VoiceEvent]) {
+        for event in events {
+            match *event {
+                VoiceEvent::NoteOn {
+                    voice,
+                    note,
+                    velocity,
+                    retrigger,
+                } => self.voices[voice].note_on(&self.params, note, velocity, retrigger),
+                VoiceEvent::NoteOff { voice } => self.voices[voice].note_off(),
+            }
+        }
+    }
+
+    /// Spreads the voices evenly across the unison detune
+    fn update_spread(&mut self) {
+        let unison = matches!(self.allocator.mode(), AllocationMode::Unison { .. });
+        for (index, voice) in self.voices.iter_mut().enumerate() {
+            voice.spread = if unison && N > 1 {
+                self.params.unison_detune * (2.0 * index as f32 / (N - 1) as f32 - 1.0)
+            } else {
+                0.0
+            };
+        }
+    }
+}
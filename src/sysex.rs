@@ -0,0 +1,186 @@
+// SysEx
+//
+// System exclusive messages to back up and share presets. A request asks for
+// the current parameters or a stored preset, the answer is a dump that can
+// be sent back later to load it again. Any SysEx librarian can record and
+// send them, and `tools/render` turns them into text and back.
+//
+//     F0 7D 01 01 <slot> F7                        request
+//     F0 7D 01 02 <slot> <preset> <checksum> F7    dump
+//
+// 0x7D is the manufacturer ID set aside for non-commercial use, a
+// placeholder until there is one of our own, followed by a device ID. The
+// slot is 0..MAX_PRESETS or 0x7F for the current parameters. A dump holds
+// the stored preset format with its own CRC, packed into 7-bit bytes: every
+// group of up to 7 bytes is led by a byte with their high bits. The
+// checksum makes the slot and the packed bytes sum up to a multiple of 128.
+use heapless::Vec;
+
+use crate::preset::{MAX_PRESETS, Preset, PresetError};
+
+pub const MANUFACTURER_ID: u8 = 0x7D;
+/// Tells these messages apart from others with the same manufacturer ID
+pub const DEVICE_ID: u8 = 0x01;
+/// Longest message, with the 0xF0 and 0xF7 bytes
+pub const MAX_MESSAGE: usize = 6 + packed_length(Preset::SIZE) + 1;
+
+const REQUEST: u8 = 0x01;
+const DUMP: u8 = 0x02;
+const CURRENT: u8 = 0x7F;
+const START: u8 = 0xF0;
+const END: u8 = 0xF7;
+
+#[derive(Debug, PartialEq)]
+pub enum SysExError {
+    /// For another manufacturer or device, to be ignored
+    NotForUs,
+    UnknownCommand(u8),
+    InvalidLength,
+    /// Data byte with the high bit set
+    InvalidByte,
+    SlotOutOfRange,
+    ChecksumMismatch,
+    Preset(PresetError),
+}
+
+impl From<PresetError> for SysExError {
+    fn from(error: PresetError) -> Self {
+        SysExError::Preset(error)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Slot {
+    /// The parameters in use, whether they are stored or not
+    Current,
+    Stored(usize),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SysExMessage {
+    Request(Slot),
+    Dump(Slot, Preset),
+}
+
+/// Bytes of `length` bytes packed into 7 bits
+pub const fn packed_length(length: usize) -> usize {
+    length + length.div_ceil(7)
+}
+
+/// Packs 8-bit data into 7-bit bytes, handing them to `push`
+pub fn pack(data: &[u8], mut push: impl FnMut(u8)) {
+    for group in data.chunks(7) {
+        let high_bits = group
+            .iter()
+            .enumerate()
+            .fold(0, |bits, (index, byte)| bits | (byte >> 7) << index);
+        push(high_bits);
+        for byte in group {
+            push(byte & 0x7F);
+        }
+    }
+}
+
+/// Unpacks what `pack` made, handing the bytes to `push`
+pub fn unpack(data: &[u8], mut push: impl FnMut(u8)) -> Result<(), SysExError> {
+    if data.iter().any(|byte| *byte > 0x7F) {
+        return Err(SysExError::InvalidByte);
+    }
+    for group in data.chunks(8) {
+        let (high_bits, bytes) = group.split_first().expect("Chunks are never empty");
+        if bytes.is_empty() {
+            return Err(SysExError::InvalidLength);
+        }
+        for (index, byte) in bytes.iter().enumerate() {
+            push(byte | (high_bits >> index & 1) << 7);
+        }
+    }
+    Ok(())
+}
+
+/// Makes the bytes sum up to a multiple of 128
+fn checksum(data: &[u8]) -> u8 {
+    let sum = data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    0u8.wrapping_sub(sum) & 0x7F
+}
+
+impl Slot {
+    fn to_byte(self) -> u8 {
+        match self {
+            Slot::Current => CURRENT,
+            Slot::Stored(slot) => slot as u8,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Result<Self, SysExError> {
+        match byte {
+            CURRENT => Ok(Slot::Current),
+            _ if usize::from(byte) < MAX_PRESETS => Ok(Slot::Stored(usize::from(byte))),
+            _ => Err(SysExError::SlotOutOfRange),
+        }
+    }
+}
+
+impl SysExMessage {
+    /// Bytes of the message, from 0xF0 to 0xF7
+    pub fn encode(&self) -> Result<Vec<u8, MAX_MESSAGE>, SysExError> {
+        let (command, slot) = match self {
+            SysExMessage::Request(slot) => (REQUEST, slot),
+            SysExMessage::Dump(slot, _) => (DUMP, slot),
+        };
+        if let Slot::Stored(index) = slot
+            && *index >= MAX_PRESETS
+        {
+            return Err(SysExError::SlotOutOfRange);
+        }
+
+        // Everything fits into `MAX_MESSAGE`
+        let mut bytes = Vec::new();
+        let mut push = |byte| bytes.push(byte).unwrap();
+        push(START);
+        push(MANUFACTURER_ID);
+        push(DEVICE_ID);
+        push(command);
+        push(slot.to_byte());
+        if let SysExMessage::Dump(_, preset) = self {
+            pack(&preset.to_bytes(), &mut push);
+            let checksum = checksum(&bytes[4..]);
+            bytes.push(checksum).unwrap();
+        }
+        bytes.push(END).unwrap();
+        Ok(bytes)
+    }
+
+    /// Reads a message with or without the 0xF0 and 0xF7 bytes, as from
+    /// `MidiParser::sysex` or from a file
+    pub fn parse(data: &[u8]) -> Result<Self, SysExError> {
+        let data = data.strip_prefix(&[START]).unwrap_or(data);
+        let data = data.strip_suffix(&[END]).unwrap_or(data);
+        let [MANUFACTURER_ID, DEVICE_ID, command, body @ ..] = data else {
+            return Err(SysExError::NotForUs);
+        };
+        if body.iter().any(|byte| *byte > 0x7F) {
+            return Err(SysExError::InvalidByte);
+        }
+
+        match (*command, body) {
+            (REQUEST, [slot]) => Ok(SysExMessage::Request(Slot::from_byte(*slot)?)),
+            (REQUEST, _) => Err(SysExError::InvalidLength),
+            (DUMP, [slot, packed @ .., _]) if packed.len() == packed_length(Preset::SIZE) => {
+                if checksum(body) != 0 {
+                    return Err(SysExError::ChecksumMismatch);
+                }
+                let slot = Slot::from_byte(*slot)?;
+                let mut bytes = [0; Preset::SIZE];
+                let mut index = 0;
+                unpack(packed, |byte| {
+                    bytes[index] = byte;
+                    index += 1;
+                })?;
+                Ok(SysExMessage::Dump(slot, Preset::from_bytes(&bytes)?))
+            }
+            (DUMP, _) => Err(SysExError::InvalidLength),
+            (command, _) => Err(SysExError::UnknownCommand(command)),
+        }
+    }
+}
@@ -0,0 +1,225 @@
+// Tuner
+//
+// Monophonic pitch detection based on the YIN algorithm
+// (de Cheveigné & Kawahara, 2002). Samples are collected cheaply from the
+// audio interrupt and the actual analysis is meant to run in a low priority
+// context, because one detection costs roughly `WINDOW * WINDOW` operations.
+use micromath::F32Ext;
+
+use crate::filter::{Filter, FilterParams, FilterType};
+
+/// Number of samples compared per lag (after decimation)
+pub const WINDOW: usize = 1024;
+/// Input samples are decimated by this factor before analysis
+pub const DECIMATION: usize = 2;
+
+const BUFFER: usize = 2 * WINDOW;
+const THRESHOLD: f32 = 0.15;
+const MIN_FREQUENCY: f32 = 30.0;
+const MAX_FREQUENCY: f32 = 1_500.0;
+const MIN_RMS: f32 = 1.0e-3;
+const A4_FREQUENCY: f32 = 440.0;
+const A4_MIDI_NOTE: i32 = 69;
+
+pub const NOTE_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+#[derive(Debug)]
+pub enum TunerError {
+    SampleRateTooLow,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Pitch {
+    /// Detected fundamental in Hz
+    pub frequency: f32,
+    /// Nearest equal tempered note as MIDI note number
+    pub note: u8,
+    /// Offset to the nearest note in cents (-50.0..50.0)
+    pub cents: f32,
+    /// Periodicity of the signal, 1.0 for a perfectly periodic signal
+    pub confidence: f32,
+}
+
+impl Pitch {
+    pub fn from_frequency(frequency: f32, confidence: f32) -> Self {
+        // micromath's `log2` is inaccurate below 1.0, so only feed it ratios >= 1
+        let ratio = frequency / A4_FREQUENCY;
+        let octaves = if ratio < 1.0 {
+            -(1.0 / ratio).log2()
+        } else {
+            ratio.log2()
+        };
+        let semitones = 12.0 * octaves;
+        let nearest = semitones.round();
+        let note = (A4_MIDI_NOTE + nearest as i32).clamp(0, 127) as u8;
+        Self {
+            frequency,
+            note,
+            cents: 100.0 * (semitones - nearest),
+            confidence,
+        }
+    }
+
+    pub fn note_name(&self) -> &'static str {
+        NOTE_NAMES[self.note as usize % 12]
+    }
+
+    pub fn octave(&self) -> i8 {
+        (self.note / 12) as i8 - 1
+    }
+}
+
+pub struct Tuner {
+    sample_rate: f32,
+    anti_alias: Filter,
+    decimation_phase: usize,
+    ring: [f32; BUFFER],
+    write_pos: usize,
+    filled: usize,
+    frame: [f32; BUFFER],
+    difference: [f32; WINDOW],
+}
+
+impl Tuner {
+    pub fn new(sample_rate: f32) -> Result<Self, TunerError> {
+        let analysis_rate = sample_rate / DECIMATION as f32;
+        if analysis_rate < 2.0 * MAX_FREQUENCY {
+            return Err(TunerError::SampleRateTooLow);
+        }
+
+        let mut anti_alias = Filter::new(FilterType::Lowpass);
+        anti_alias.set_sample_rate(sample_rate).unwrap();
+        anti_alias
+            .set_params(FilterParams {
+                frequency: 0.4 * analysis_rate,
+                quality: 0.71,
+                gain: 0.0,
+            })
+            .expect("Cutoff is always below nyquist");
+
+        Ok(Self {
+            sample_rate,
+            anti_alias,
+            decimation_phase: 0,
+            ring: [0.0; BUFFER],
+            write_pos: 0,
+            filled: 0,
+            frame: [0.0; BUFFER],
+            difference: [0.0; WINDOW],
+        })
+    }
+
+    pub fn push_samples(&mut self, samples: &[f32]) {
+        for sample in samples {
+            self.push(*sample);
+        }
+    }
+
+    #[inline]
+    pub fn push(&mut self, sample: f32) {
+        let filtered = self.anti_alias.tick(sample);
+        self.decimation_phase += 1;
+        if self.decimation_phase < DECIMATION {
+            return;
+        }
+        self.decimation_phase = 0;
+
+        self.ring[self.write_pos] = filtered;
+        self.write_pos = (self.write_pos + 1) % BUFFER;
+        self.filled = (self.filled + 1).min(BUFFER);
+    }
+
+    pub fn reset(&mut self) {
+        self.anti_alias.reset();
+        self.decimation_phase = 0;
+        self.ring = [0.0; BUFFER];
+        self.write_pos = 0;
+        self.filled = 0;
+    }
+
+    /// Runs the analysis on the most recent samples.
+    ///
+    /// Returns `None` while the buffer is still filling up, if the signal is
+    /// too quiet or if no period could be found.
+    pub fn detect(&mut self) -> Option<Pitch> {
+        if self.filled < BUFFER {
+            return None;
+        }
+
+        // Unroll the ring buffer, oldest sample first
+        let (newer, older) = self.ring.split_at(self.write_pos);
+        self.frame[..older.len()].copy_from_slice(older);
+        self.frame[older.len()..].copy_from_slice(newer);
+
+        let energy: f32 = self.frame[..WINDOW].iter().map(|x| x * x).sum();
+        if (energy / WINDOW as f32).sqrt() < MIN_RMS {
+            return None;
+        }
+
+        let analysis_rate = self.sample_rate / DECIMATION as f32;
+        let min_tau = ((analysis_rate / MAX_FREQUENCY) as usize).max(2);
+        let max_tau = ((analysis_rate / MIN_FREQUENCY) as usize).min(WINDOW - 1);
+
+        self.cumulative_mean_normalized_difference(max_tau);
+
+        let tau = self.absolute_threshold(min_tau, max_tau)?;
+        let period = self.parabolic_interpolation(tau, max_tau);
+        let confidence = (1.0 - self.difference[tau]).clamp(0.0, 1.0);
+
+        Some(Pitch::from_frequency(analysis_rate / period, confidence))
+    }
+
+    fn cumulative_mean_normalized_difference(&mut self, max_tau: usize) {
+        self.difference[0] = 1.0;
+        let mut running_sum = 0.0;
+        for tau in 1..=max_tau {
+            let mut sum = 0.0;
+            for j in 0..WINDOW {
+                let delta = self.frame[j] - self.frame[j + tau];
+                sum += delta * delta;
+            }
+            running_sum += sum;
+            self.difference[tau] = if running_sum > 0.0 {
+                sum * tau as f32 / running_sum
+            } else {
+                1.0
+            };
+        }
+    }
+
+    fn absolute_threshold(&self, min_tau: usize, max_tau: usize) -> Option<usize> {
+        let mut tau = min_tau;
+        while tau < max_tau {
+            if self.difference[tau] < THRESHOLD {
+                // Walk down to the bottom of the dip
+                while tau + 1 < max_tau && self.difference[tau + 1] < self.difference[tau] {
+                    tau += 1;
+                }
+                return Some(tau);
+            }
+            tau += 1;
+        }
+
+        // No dip below the threshold, fall back to the global minimum
+        let (tau, value) = (min_tau..max_tau)
+            .map(|tau| (tau, self.difference[tau]))
+            .min_by(|a, b| a.1.total_cmp(&b.1))?;
+        (value < 2.0 * THRESHOLD).then_some(tau)
+    }
+
+    fn parabolic_interpolation(&self, tau: usize, max_tau: usize) -> f32 {
+        if tau < 1 || tau + 1 > max_tau {
+            return tau as f32;
+        }
+        let s0 = self.difference[tau - 1];
+        let s1 = self.difference[tau];
+        let s2 = self.difference[tau + 1];
+        let denominator = 2.0 * (2.0 * s1 - s2 - s0);
+        if denominator.abs() < f32::EPSILON {
+            return tau as f32;
+        }
+        tau as f32 + (s2 - s0) / denominator
+    }
+}
@@ -0,0 +1,130 @@
+// USB MIDI
+//
+// Framing of MIDI messages into the 4 byte event packets of the USB MIDI
+// class. The first byte holds the virtual cable in the high nibble and the
+// code index number (CIN) in the low one, which tells how many of the other
+// three bytes belong to the message. System exclusive messages are split
+// into packets of three bytes, the one with 0xF7 ends them.
+//
+// Incoming packets are unpacked back into bytes for a `MidiParser`, so USB
+// delivers the same messages as the serial input.
+use crate::midi::{MidiEncoder, MidiError, MidiMessage, MidiParser};
+
+/// Event packet of the USB MIDI class
+pub type UsbMidiPacket = [u8; 4];
+
+/// Code index numbers (CIN)
+const CIN_TWO_BYTES: u8 = 0x2;
+const CIN_THREE_BYTES: u8 = 0x3;
+const CIN_SYSEX: u8 = 0x4;
+const CIN_SYSEX_END_1: u8 = 0x5;
+const CIN_SYSEX_END_2: u8 = 0x6;
+const CIN_SYSEX_END_3: u8 = 0x7;
+const CIN_SINGLE_BYTE: u8 = 0xF;
+
+/// Number of MIDI bytes in a packet of the code index number
+fn packet_length(cin: u8) -> usize {
+    match cin {
+        CIN_SYSEX_END_1 | CIN_SINGLE_BYTE => 1,
+        CIN_TWO_BYTES | CIN_SYSEX_END_2 | 0xC | 0xD => 2,
+        CIN_THREE_BYTES | CIN_SYSEX | CIN_SYSEX_END_3 | 0x8..=0xE => 3,
+        // Reserved for future extensions
+        _ => 0,
+    }
+}
+
+/// Packs a message for `cable`. System exclusive messages have their own
+/// function, as their data isn't part of the message.
+pub fn encode(cable: u8, message: MidiMessage) -> Option<UsbMidiPacket> {
+    // Every packet carries its own status byte
+    let bytes = MidiEncoder::new(false).encode(message);
+    let status = *bytes.first()?;
+    let cin = match status {
+        0x80..=0xEF => status >> 4,
+        0xF1 | 0xF3 => CIN_TWO_BYTES,
+        0xF2 => CIN_THREE_BYTES,
+        0xF6 => CIN_SYSEX_END_1,
+        _ => CIN_SINGLE_BYTE,
+    };
+    let mut packet = [(cable & 0x0F) << 4 | cin, 0, 0, 0];
+    packet[1..1 + bytes.len()].copy_from_slice(&bytes);
+    Some(packet)
+}
+
+/// Packs a system exclusive message, `data` without 0xF0 and 0xF7, and
+/// hands the packets to `write`
+pub fn encode_sysex(cable: u8, data: &[u8], mut write: impl FnMut(UsbMidiPacket)) {
+    let cable = (cable & 0x0F) << 4;
+    let bytes = core::iter::once(0xF0)
+        .chain(data.iter().map(|byte| byte & 0x7F))
+        .chain(core::iter::once(0xF7));
+    let mut packet = [0; 4];
+    let mut length = 0;
+    for byte in bytes {
+        length += 1;
+        packet[length] = byte;
+        if byte == 0xF7 {
+            packet[0] = cable
+                | match length {
+                    1 => CIN_SYSEX_END_1,
+                    2 => CIN_SYSEX_END_2,
+                    _ => CIN_SYSEX_END_3,
+                };
+            write(packet);
+        } else if length == 3 {
+            packet[0] = cable | CIN_SYSEX;
+            write(packet);
+            packet = [0; 4];
+            length = 0;
+        }
+    }
+}
+
+/// Unpacks the packets of one virtual cable and parses the MIDI bytes
+pub struct UsbMidiParser {
+    cable: u8,
+    parser: MidiParser,
+}
+
+impl UsbMidiParser {
+    pub fn new(cable: u8) -> Self {
+        Self {
+            cable: cable & 0x0F,
+            parser: MidiParser::new(),
+        }
+    }
+
+    /// Data of the last complete system exclusive message
+    pub fn sysex(&self) -> &[u8] {
+        self.parser.sysex()
+    }
+
+    /// Parses a packet, handing the messages and errors to `handle`.
+    /// Packets of other cables and of reserved code index numbers are
+    /// ignored.
+    pub fn parse(
+        &mut self,
+        packet: UsbMidiPacket,
+        handle: impl FnMut(Result<MidiMessage, MidiError>),
+    ) {
+        if packet[0] >> 4 != self.cable {
+            return;
+        }
+        let length = packet_length(packet[0] & 0x0F);
+        self.parser.parse_slice(&packet[1..1 + length], handle);
+    }
+
+    /// Parses all complete packets of a USB transfer
+    pub fn parse_transfer(
+        &mut self,
+        data: &[u8],
+        mut handle: impl FnMut(Result<MidiMessage, MidiError>),
+    ) {
+        for packet in data.chunks_exact(4) {
+            // Empty packets pad some transfers
+            if packet != [0; 4] {
+                self.parse([packet[0], packet[1], packet[2], packet[3]], &mut handle);
+            }
+        }
+    }
+}
@@ -0,0 +1,142 @@
+// USB MIDI Class
+//
+// Class compliant USB MIDI device for usb-device: an audio control interface
+// without any units, as the class requires, and a MIDI streaming interface
+// with one embedded jack each way, so hosts show a single port without
+// installing a driver. The event packets are framed by `usb_midi`.
+use usb_device::class_prelude::*;
+
+use crate::usb_midi::UsbMidiPacket;
+
+/// Bulk endpoint size of a full speed device
+pub const MAX_PACKET_SIZE: u16 = 64;
+
+const AUDIO: u8 = 0x01;
+const AUDIO_CONTROL: u8 = 0x01;
+const MIDI_STREAMING: u8 = 0x03;
+
+const CS_INTERFACE: u8 = 0x24;
+const CS_ENDPOINT: u8 = 0x25;
+const HEADER: u8 = 0x01;
+const MS_HEADER: u8 = 0x01;
+const MIDI_IN_JACK: u8 = 0x02;
+const MIDI_OUT_JACK: u8 = 0x03;
+const MS_GENERAL: u8 = 0x01;
+const EMBEDDED: u8 = 0x01;
+const EXTERNAL: u8 = 0x02;
+
+/// Jacks: the host sends into the embedded IN jack and receives from the
+/// embedded OUT jack, the external ones stand for the synth
+const EMBEDDED_IN: u8 = 1;
+const EXTERNAL_IN: u8 = 2;
+const EMBEDDED_OUT: u8 = 3;
+const EXTERNAL_OUT: u8 = 4;
+
+/// Length of the class specific MIDI streaming descriptors: the header,
+/// two IN jacks, two OUT jacks and two audio endpoints with their class
+/// specific part
+const MS_TOTAL_LENGTH: u16 = 7 + 2 * 6 + 2 * 9 + 2 * (9 + 5);
+
+pub struct UsbMidiClass<'a, B: UsbBus> {
+    audio_control: InterfaceNumber,
+    midi_streaming: InterfaceNumber,
+    out_endpoint: EndpointOut<'a, B>,
+    in_endpoint: EndpointIn<'a, B>,
+}
+
+impl<B: UsbBus> UsbMidiClass<'_, B> {
+    pub fn new(alloc: &UsbBusAllocator<B>) -> UsbMidiClass<'_, B> {
+        UsbMidiClass {
+            audio_control: alloc.interface(),
+            midi_streaming: alloc.interface(),
+            out_endpoint: alloc.bulk(MAX_PACKET_SIZE),
+            in_endpoint: alloc.bulk(MAX_PACKET_SIZE),
+        }
+    }
+
+    /// Reads the event packets received from the host, returns the number of
+    /// bytes. `WouldBlock` if there are none.
+    pub fn read(&mut self, data: &mut [u8]) -> usb_device::Result<usize> {
+        self.out_endpoint.read(data)
+    }
+
+    /// Sends event packets to the host, at most `MAX_PACKET_SIZE` bytes at
+    /// once. `WouldBlock` while the last ones are still pending.
+    pub fn write(&mut self, packets: &[UsbMidiPacket]) -> usb_device::Result<usize> {
+        let mut data = [0; MAX_PACKET_SIZE as usize];
+        let length = (packets.len() * 4).min(data.len());
+        for (chunk, packet) in data[..length].chunks_exact_mut(4).zip(packets) {
+            chunk.copy_from_slice(packet);
+        }
+        self.in_endpoint.write(&data[..length])
+    }
+}
+
+impl<B: UsbBus> UsbClass<B> for UsbMidiClass<'_, B> {
+    fn get_configuration_descriptors(
+        &self,
+        writer: &mut DescriptorWriter,
+    ) -> usb_device::Result<()> {
+        writer.interface(self.audio_control, AUDIO, AUDIO_CONTROL, 0x00)?;
+        // Audio 1.0, the header is the only descriptor
+        writer.write(
+            CS_INTERFACE,
+            &[
+                HEADER,
+                0x00,
+                0x01,
+                0x09,
+                0x00,
+                0x01,
+                u8::from(self.midi_streaming),
+            ],
+        )?;
+
+        writer.interface(self.midi_streaming, AUDIO, MIDI_STREAMING, 0x00)?;
+        let [length_low, length_high] = MS_TOTAL_LENGTH.to_le_bytes();
+        writer.write(
+            CS_INTERFACE,
+            &[MS_HEADER, 0x00, 0x01, length_low, length_high],
+        )?;
+        writer.write(CS_INTERFACE, &[MIDI_IN_JACK, EMBEDDED, EMBEDDED_IN, 0x00])?;
+        writer.write(CS_INTERFACE, &[MIDI_IN_JACK, EXTERNAL, EXTERNAL_IN, 0x00])?;
+        // Each OUT jack has one source pin
+        writer.write(
+            CS_INTERFACE,
+            &[
+                MIDI_OUT_JACK,
+                EMBEDDED,
+                EMBEDDED_OUT,
+                0x01,
+                EXTERNAL_IN,
+                0x01,
+                0x00,
+            ],
+        )?;
+        writer.write(
+            CS_INTERFACE,
+            &[
+                MIDI_OUT_JACK,
+                EXTERNAL,
+                EXTERNAL_OUT,
+                0x01,
+                EMBEDDED_IN,
+                0x01,
+                0x00,
+            ],
+        )?;
+
+        // Audio endpoints have two more bytes, refresh and synch address
+        writer.endpoint_ex(&self.out_endpoint, |data| {
+            data[..2].fill(0);
+            Ok(2)
+        })?;
+        writer.write(CS_ENDPOINT, &[MS_GENERAL, 0x01, EMBEDDED_IN])?;
+        writer.endpoint_ex(&self.in_endpoint, |data| {
+            data[..2].fill(0);
+            Ok(2)
+        })?;
+        writer.write(CS_ENDPOINT, &[MS_GENERAL, 0x01, EMBEDDED_OUT])?;
+        Ok(())
+    }
+}
@@ -0,0 +1,228 @@
+// Vocoder
+//
+// Channel vocoder: the modulator (left input) is split into bands by a bank
+// of bandpass filters, the envelope of every band then controls the level of
+// the same band of the carrier (right input or the internal oscillator).
+use micromath::F32Ext;
+
+use crate::filter::{Filter, FilterError, FilterParams, FilterType, one_pole_coefficient};
+
+pub const MIN_BANDS: usize = 8;
+pub const MAX_BANDS: usize = 32;
+
+#[derive(Debug)]
+pub enum VocoderError {
+    BandsOutOfRange,
+    FrequencyRangeInvalid,
+    CarrierFrequencyOutOfRange,
+    TimeNegative,
+    Filter(FilterError),
+}
+
+impl From<FilterError> for VocoderError {
+    fn from(error: FilterError) -> Self {
+        VocoderError::Filter(error)
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum Carrier {
+    /// Right input channel
+    #[default]
+    External,
+    /// Internal sawtooth oscillator at `VocoderParams::carrier_frequency`
+    Oscillator,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub struct VocoderParams {
+    /// Number of bands (`MIN_BANDS..=MAX_BANDS`)
+    pub bands: usize,
+    /// Center frequency of the lowest band in Hz
+    pub min_frequency: f32,
+    /// Center frequency of the highest band in Hz
+    pub max_frequency: f32,
+    pub attack_ms: f32,
+    pub release_ms: f32,
+    pub carrier: Carrier,
+    pub carrier_frequency: f32,
+}
+
+impl Default for VocoderParams {
+    fn default() -> Self {
+        Self {
+            bands: 16,
+            min_frequency: 100.0,
+            max_frequency: 8_000.0,
+            attack_ms: 2.0,
+            release_ms: 30.0,
+            carrier: Carrier::External,
+            carrier_frequency: 110.0,
+        }
+    }
+}
+
+pub struct Vocoder {
+    params: VocoderParams,
+    sample_rate: f32,
+    analysis: [Filter; MAX_BANDS],
+    synthesis: [Filter; MAX_BANDS],
+    envelopes: [f32; MAX_BANDS],
+    attack: f32,
+    release: f32,
+    gain: f32,
+    phase: f32,
+    phase_increment: f32,
+}
+
+impl Vocoder {
+    pub fn new() -> Self {
+        let mut vocoder = Self {
+            params: VocoderParams::default(),
+            sample_rate: 48000.0,
+            analysis: core::array::from_fn(|_| Filter::new(FilterType::Bandpass)),
+            synthesis: core::array::from_fn(|_| Filter::new(FilterType::Bandpass)),
+            envelopes: [0.0; MAX_BANDS],
+            attack: 0.0,
+            release: 0.0,
+            gain: 1.0,
+            phase: 0.0,
+            phase_increment: 0.0,
+        };
+        vocoder
+            .update_bands(VocoderParams::default(), 48000.0)
+            .expect("Those settings always work");
+        vocoder
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) -> Result<(), VocoderError> {
+        if self.sample_rate != sample_rate {
+            self.update_bands(self.params, sample_rate)?;
+        }
+        Ok(())
+    }
+
+    pub fn set_params(&mut self, params: VocoderParams) -> Result<(), VocoderError> {
+        if self.params != params {
+            self.update_bands(params, self.sample_rate)?;
+        }
+        Ok(())
+    }
+
+    /// Takes the modulator from the left and the carrier from the right
+    /// channel, the vocoded signal goes to both outputs
+    #[inline]
+    pub fn tick(&mut self, input: (f32, f32)) -> (f32, f32) {
+        let (modulator, external) = input;
+        let carrier = match self.params.carrier {
+            Carrier::External => external,
+            Carrier::Oscillator => {
+                self.phase += self.phase_increment;
+                if self.phase >= 1.0 {
+                    self.phase -= 1.0;
+                }
+                2.0 * self.phase - 1.0
+            }
+        };
+
+        let bands = self.params.bands;
+        let mut output = 0.0;
+        for ((analysis, synthesis), envelope) in self.analysis[..bands]
+            .iter_mut()
+            .zip(self.synthesis[..bands].iter_mut())
+            .zip(self.envelopes[..bands].iter_mut())
+        {
+            let level = analysis.tick(modulator).abs();
+            let coefficient = if level > *envelope {
+                self.attack
+            } else {
+                self.release
+            };
+            *envelope += coefficient * (level - *envelope);
+            output += synthesis.tick(carrier) * *envelope;
+        }
+
+        output *= self.gain;
+        (output, output)
+    }
+
+    pub fn process(&mut self, audio_buffer: &mut [(f32, f32)]) {
+        for frame in audio_buffer.iter_mut() {
+            *frame = self.tick(*frame);
+        }
+    }
+
+    pub fn reset(&mut self) {
+        for filter in self.analysis.iter_mut().chain(self.synthesis.iter_mut()) {
+            filter.reset();
+        }
+        self.envelopes = [0.0; MAX_BANDS];
+        self.phase = 0.0;
+    }
+
+    /// Takes over the parameters and sample rate only if all bands work
+    /// with them
+    fn update_bands(
+        &mut self,
+        params: VocoderParams,
+        sample_rate: f32,
+    ) -> Result<(), VocoderError> {
+        if !(MIN_BANDS..=MAX_BANDS).contains(&params.bands) {
+            return Err(VocoderError::BandsOutOfRange);
+        }
+        if params.min_frequency <= 0.0
+            || params.max_frequency <= params.min_frequency
+            || params.max_frequency >= sample_rate / 2.0
+        {
+            return Err(VocoderError::FrequencyRangeInvalid);
+        }
+        // The oscillator only wraps upwards
+        if !(0.0..sample_rate / 2.0).contains(&params.carrier_frequency) {
+            return Err(VocoderError::CarrierFrequencyOutOfRange);
+        }
+        if params.attack_ms.is_sign_negative() || params.release_ms.is_sign_negative() {
+            return Err(VocoderError::TimeNegative);
+        }
+        let bands = params.bands;
+
+        // Bands are spaced evenly on a logarithmic scale, each one as wide
+        // as the distance to its neighbours
+        let ratio = (params.max_frequency / params.min_frequency).powf(1.0 / (bands - 1) as f32);
+        let quality = libm::sqrtf(ratio) / (ratio - 1.0);
+        let mut analysis_filters = self.analysis.clone();
+        let mut synthesis_filters = self.synthesis.clone();
+        let mut frequency = params.min_frequency;
+        for (analysis, synthesis) in analysis_filters[..bands]
+            .iter_mut()
+            .zip(synthesis_filters[..bands].iter_mut())
+        {
+            let filter_params = FilterParams {
+                frequency,
+                quality,
+                gain: 0.0,
+            };
+            for filter in [analysis, synthesis] {
+                filter.set_sample_rate(sample_rate)?;
+                filter.set_params(filter_params)?;
+            }
+            frequency *= ratio;
+        }
+
+        self.params = params;
+        self.sample_rate = sample_rate;
+        self.analysis = analysis_filters;
+        self.synthesis = synthesis_filters;
+        self.attack = one_pole_coefficient(params.attack_ms, sample_rate);
+        self.release = one_pole_coefficient(params.release_ms, sample_rate);
+        // Narrow bands pass less energy, compensate roughly for the bandwidth
+        self.gain = 2.0 * libm::sqrtf(quality);
+        self.phase_increment = params.carrier_frequency / sample_rate;
+        Ok(())
+    }
+}
+
+impl Default for Vocoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
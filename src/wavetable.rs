@@ -0,0 +1,340 @@
+// Wavetable
+//
+// Wavetable oscillator with band-limited mip levels and morphing between
+// frames. A table consists of up to `MAX_FRAMES` single cycle frames of
+// `FRAME_LENGTH` samples each. Loading a frame builds its mip levels: every
+// level keeps half the harmonics of the previous one, starting with a
+// quarter of the frame length. The levels are stored with eight samples per
+// harmonic, which keeps the images of the linear interpolation low, so they
+// get shorter with the harmonics down to a minimum. Only the first level
+// keeps the frame length with four samples per harmonic, it plays the lowest
+// octave (below about 47 Hz at 48 kHz).
+//
+// Like the looper, the table works on externally provided memory, on the
+// Daisy this is the SDRAM (`.sdram_bss` section): a full table of
+// `MAX_FRAMES` takes `TABLE_STORAGE` floats, 6.4 MB of the 64 MB. The source
+// data is 16 bit PCM, 1 MB for a full table. The build script bakes it into
+// the internal flash (see `baked::WAVETABLE`), which only has room for 8
+// frames next to the firmware. Larger tables are loaded at runtime, e.g.
+// read from the QSPI flash into SDRAM first.
+use crate::fft::RealFft;
+
+/// Samples per single cycle frame
+pub const FRAME_LENGTH: usize = 2048;
+pub const MAX_FRAMES: usize = 256;
+/// Number of mip levels, the last one holds only the fundamental
+pub const LEVELS: usize = 10;
+/// Shortest mip level
+const MIN_LEVEL_LENGTH: usize = 64;
+/// Memory needed per frame for all its mip levels
+pub const FRAME_STORAGE: usize = level_offset(LEVELS);
+/// Memory needed for a table of `MAX_FRAMES`
+pub const TABLE_STORAGE: usize = MAX_FRAMES * FRAME_STORAGE;
+
+const LEVEL_LENGTHS: [usize; LEVELS] = {
+    let mut lengths = [0; LEVELS];
+    let mut level = 0;
+    while level < LEVELS {
+        lengths[level] = level_length(level);
+        level += 1;
+    }
+    lengths
+};
+const LEVEL_OFFSETS: [usize; LEVELS] = {
+    let mut offsets = [0; LEVELS];
+    let mut level = 0;
+    while level < LEVELS {
+        offsets[level] = level_offset(level);
+        level += 1;
+    }
+    offsets
+};
+
+#[derive(Debug, PartialEq)]
+pub enum WavetableError {
+    BufferTooShort,
+    DataSizeInvalid,
+    TooManyFrames,
+    Empty,
+    FrequencyOverNyquist,
+    FrequencyNegative,
+    MorphOutOfRange,
+}
+
+/// Highest harmonic kept in mip level `level`
+const fn level_harmonics(level: usize) -> usize {
+    (FRAME_LENGTH / 4) >> level
+}
+
+const fn level_length(level: usize) -> usize {
+    let length = 8 * level_harmonics(level);
+    if length > FRAME_LENGTH {
+        FRAME_LENGTH
+    } else if length < MIN_LEVEL_LENGTH {
+        MIN_LEVEL_LENGTH
+    } else {
+        length
+    }
+}
+
+/// Start of mip level `level` within a frame
+const fn level_offset(level: usize) -> usize {
+    let mut offset = 0;
+    let mut i = 0;
+    while i < level {
+        offset += level_length(i);
+        i += 1;
+    }
+    offset
+}
+
+pub struct Wavetable<'a> {
+    storage: &'a mut [f32],
+    frames: usize,
+}
+
+impl<'a> Wavetable<'a> {
+    /// Takes `FRAME_STORAGE` floats per frame, the table starts empty
+    pub fn new(storage: &'a mut [f32]) -> Result<Self, WavetableError> {
+        if storage.len() < FRAME_STORAGE {
+            return Err(WavetableError::BufferTooShort);
+        }
+        Ok(Self { storage, frames: 0 })
+    }
+
+    /// Maximum number of frames the memory can hold
+    pub fn capacity(&self) -> usize {
+        (self.storage.len() / FRAME_STORAGE).min(MAX_FRAMES)
+    }
+
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// Replaces the table with 16 bit little endian PCM frames
+    ///
+    /// This transforms every frame a couple of times, so don't call it from
+    /// the audio interrupt.
+    pub fn load_pcm16(&mut self, data: &[u8]) -> Result<(), WavetableError> {
+        let frames = self.check_size(data.len(), 2 * FRAME_LENGTH)?;
+        let fft = RealFft::new().expect("FRAME_LENGTH is a power of two");
+        for (index, frame) in data.chunks_exact(2 * FRAME_LENGTH).enumerate() {
+            let samples = frame
+                .chunks_exact(2)
+                .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / 32768.0);
+            self.build_frame(&fft, index, samples);
+        }
+        self.frames = frames;
+        Ok(())
+    }
+
+    /// Replaces the table with frames of floating point samples
+    pub fn load(&mut self, data: &[f32]) -> Result<(), WavetableError> {
+        let frames = self.check_size(data.len(), FRAME_LENGTH)?;
+        let fft = RealFft::new().expect("FRAME_LENGTH is a power of two");
+        for (index, frame) in data.chunks_exact(FRAME_LENGTH).enumerate() {
+            self.build_frame(&fft, index, frame.iter().copied());
+        }
+        self.frames = frames;
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.frames = 0;
+    }
+
+    fn check_size(&self, length: usize, frame_size: usize) -> Result<usize, WavetableError> {
+        if length == 0 {
+            return Err(WavetableError::Empty);
+        }
+        if !length.is_multiple_of(frame_size) {
+            return Err(WavetableError::DataSizeInvalid);
+        }
+        let frames = length / frame_size;
+        if frames > self.capacity() {
+            return Err(WavetableError::TooManyFrames);
+        }
+        Ok(frames)
+    }
+
+    fn build_frame(
+        &mut self,
+        fft: &RealFft<FRAME_LENGTH>,
+        index: usize,
+        samples: impl Iterator<Item = f32>,
+    ) {
+        let mut spectrum = [0.0; FRAME_LENGTH];
+        for (value, sample) in spectrum.iter_mut().zip(samples) {
+            *value = sample;
+        }
+        fft.forward(&mut spectrum);
+        // No DC and no Nyquist, neither of them is a harmonic
+        spectrum[0] = 0.0;
+        spectrum[1] = 0.0;
+
+        let frame = &mut self.storage[index * FRAME_STORAGE..(index + 1) * FRAME_STORAGE];
+        let mut level_samples = [0.0; FRAME_LENGTH];
+        for level in 0..LEVELS {
+            level_samples.copy_from_slice(&spectrum);
+            level_samples[2 * (level_harmonics(level) + 1)..].fill(0.0);
+            fft.inverse(&mut level_samples);
+
+            // The level is band-limited far below its own Nyquist frequency,
+            // so picking every n-th sample is enough to shorten it
+            let step = FRAME_LENGTH / level_length(level);
+            let destination = &mut frame[level_offset(level)..level_offset(level + 1)];
+            for (i, value) in destination.iter_mut().enumerate() {
+                *value = level_samples[i * step];
+            }
+        }
+    }
+
+    /// Linear interpolation within one mip level of one frame
+    #[inline]
+    fn read(&self, frame: usize, level: usize, phase: f32) -> f32 {
+        let length = LEVEL_LENGTHS[level];
+        let samples = &self.storage[frame * FRAME_STORAGE + LEVEL_OFFSETS[level]..][..length];
+        let position = phase * length as f32;
+        let index = (position as usize).min(length - 1);
+        let fraction = position - index as f32;
+        let next = if index + 1 == length { 0 } else { index + 1 };
+        samples[index] + fraction * (samples[next] - samples[index])
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub struct WavetableParams {
+    pub frequency: f32,
+    /// Position in the table, from the first (0.0) to the last frame (1.0)
+    pub morph: f32,
+}
+
+impl Default for WavetableParams {
+    fn default() -> Self {
+        Self {
+            frequency: 440.0,
+            morph: 0.0,
+        }
+    }
+}
+
+/// Plays a `Wavetable`, which can be shared by many oscillators
+pub struct WavetableOscillator {
+    params: WavetableParams,
+    sample_rate: f32,
+    phase: f32,
+    increment: f32,
+    level: usize,
+    level_fade: f32,
+}
+
+impl WavetableOscillator {
+    pub fn new() -> Self {
+        let mut oscillator = Self {
+            params: WavetableParams::default(),
+            sample_rate: 48000.0,
+            phase: 0.0,
+            increment: 0.0,
+            level: 0,
+            level_fade: 0.0,
+        };
+        oscillator.update().expect("Those settings always work");
+        oscillator
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) -> Result<(), WavetableError> {
+        if self.sample_rate != sample_rate {
+            self.sample_rate = sample_rate;
+            self.update()?;
+        }
+        Ok(())
+    }
+
+    pub fn set_params(&mut self, params: WavetableParams) -> Result<(), WavetableError> {
+        if !(0.0..=1.0).contains(&params.morph) {
+            return Err(WavetableError::MorphOutOfRange);
+        }
+        if self.params != params {
+            let previous = self.params;
+            self.params = params;
+            if let Err(error) = self.update() {
+                self.params = previous;
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn reset(&mut self) {
+        self.phase = 0.0;
+    }
+
+    #[inline]
+    pub fn tick(&mut self, table: &Wavetable) -> f32 {
+        if table.frames == 0 {
+            return 0.0;
+        }
+
+        let position = self.params.morph * (table.frames - 1) as f32;
+        let frame = (position as usize).min(table.frames - 1);
+        let frame_fade = position - frame as f32;
+        let next_frame = (frame + 1).min(table.frames - 1);
+        let next_level = (self.level + 1).min(LEVELS - 1);
+
+        let read = |frame| {
+            let lower = table.read(frame, self.level, self.phase);
+            let upper = table.read(frame, next_level, self.phase);
+            lower + self.level_fade * (upper - lower)
+        };
+        let current = read(frame);
+        let output = if frame_fade > 0.0 {
+            current + frame_fade * (read(next_frame) - current)
+        } else {
+            current
+        };
+
+        self.phase += self.increment;
+        if self.phase >= 1.0 {
+            self.phase -= 1.0;
+        }
+        output
+    }
+
+    /// Writes the oscillator to both channels of the buffer
+    pub fn process(&mut self, table: &Wavetable, audio_buffer: &mut [(f32, f32)]) {
+        for frame in audio_buffer.iter_mut() {
+            let sample = self.tick(table);
+            *frame = (sample, sample);
+        }
+    }
+
+    fn update(&mut self) -> Result<(), WavetableError> {
+        let frequency = self.params.frequency;
+        if frequency.is_sign_negative() {
+            return Err(WavetableError::FrequencyNegative);
+        }
+        if frequency > self.sample_rate / 2.0 {
+            return Err(WavetableError::FrequencyOverNyquist);
+        }
+        self.increment = frequency / self.sample_rate;
+
+        // Level `l` holds harmonics up to `FRAME_LENGTH / 4 / 2^l`, which is
+        // free of aliasing up to `log2(FRAME_LENGTH * f / fs) = l + 1`.
+        // Fading towards the next level over that octave keeps it that way
+        // without jumps in brightness.
+        let position = libm::log2f(FRAME_LENGTH as f32 * self.increment).max(0.0);
+        self.level = (position as usize).min(LEVELS - 1);
+        self.level_fade = if self.level == LEVELS - 1 {
+            0.0
+        } else {
+            position - self.level as f32
+        };
+        Ok(())
+    }
+}
+
+impl Default for WavetableOscillator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,183 @@
+// SysEx
+//
+// Turns preset dumps recorded from the Daisy into text and back, to keep
+// backups and share sounds:
+//
+//     cargo run --release --bin sysex -- decode dump.syx > presets.txt
+//     cargo run --release --bin sysex -- encode presets.txt dump.syx
+//     cargo run --release --bin sysex -- request 3 request.syx
+//
+// Every preset in the text starts with its slot, 0-15 or `current`, the
+// parameters left out are the defaults:
+//
+//     slot 3
+//     filter bell
+//     frequency 1234.5
+//     quality 2.5
+//     gain -6
+//     spectral robotize
+//
+// `request` writes a message that asks for a dump of a slot, for SysEx
+// librarians that send a file and record the answer.
+use std::fs;
+use std::process::ExitCode;
+
+use render::filter::FilterType;
+use render::preset::Preset;
+use render::spectral::SpectralMode;
+use render::sysex::{Slot, SysExError, SysExMessage};
+
+const FILTER_TYPES: [(FilterType, &str); 3] = [
+    (FilterType::Lowpass, "lowpass"),
+    (FilterType::Bell, "bell"),
+    (FilterType::Bandpass, "bandpass"),
+];
+const SPECTRAL_MODES: [(SpectralMode, &str); 5] = [
+    (SpectralMode::Off, "off"),
+    (SpectralMode::Freeze, "freeze"),
+    (SpectralMode::Denoise, "denoise"),
+    (SpectralMode::Robotize, "robotize"),
+    (SpectralMode::Whisperize, "whisperize"),
+];
+
+fn main() -> ExitCode {
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+    let arguments: Vec<&str> = arguments.iter().map(String::as_str).collect();
+    let result = match arguments[..] {
+        ["decode", input] => decode(input),
+        ["encode", input, output] => encode(input, output),
+        ["request", slot, output] => request(slot, output),
+        _ => Err(
+            "Usage: sysex decode <dump.syx> | encode <presets.txt> <dump.syx> | \
+             request <slot|current> <request.syx>"
+                .to_string(),
+        ),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("{error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn decode(input: &str) -> Result<(), String> {
+    let bytes = fs::read(input).map_err(|error| format!("{input}: {error}"))?;
+    let mut presets = Vec::new();
+    for message in messages(&bytes) {
+        match SysExMessage::parse(message) {
+            Ok(SysExMessage::Dump(slot, preset)) => presets.push(to_text(slot, &preset)),
+            // Other messages recorded along with the dumps
+            Ok(SysExMessage::Request(_)) | Err(SysExError::NotForUs) => {}
+            Err(error) => return Err(format!("{input}: {error:?}")),
+        }
+    }
+    if presets.is_empty() {
+        return Err(format!("{input}: no preset dumps"));
+    }
+    print!("{}", presets.join("\n"));
+    Ok(())
+}
+
+fn encode(input: &str, output: &str) -> Result<(), String> {
+    let text = fs::read_to_string(input).map_err(|error| format!("{input}: {error}"))?;
+    let mut bytes = Vec::new();
+    for (index, preset) in text
+        .split("\n\n")
+        .filter(|preset| !preset.trim().is_empty())
+        .enumerate()
+    {
+        let (slot, preset) =
+            from_text(preset).map_err(|error| format!("{input}: preset {}: {error}", index + 1))?;
+        let message = SysExMessage::Dump(slot, preset)
+            .encode()
+            .map_err(|error| format!("{input}: preset {}: {error:?}", index + 1))?;
+        bytes.extend_from_slice(&message);
+    }
+    fs::write(output, bytes).map_err(|error| format!("{output}: {error}"))
+}
+
+fn request(slot: &str, output: &str) -> Result<(), String> {
+    let message = SysExMessage::Request(parse_slot(slot)?)
+        .encode()
+        .map_err(|error| format!("{error:?}"))?;
+    fs::write(output, message).map_err(|error| format!("{output}: {error}"))
+}
+
+/// The system exclusive messages in a file, from 0xF0 to 0xF7
+fn messages(bytes: &[u8]) -> impl Iterator<Item = &[u8]> {
+    bytes
+        .split_inclusive(|byte| *byte == 0xF7)
+        .filter_map(|message| {
+            let start = message.iter().position(|byte| *byte == 0xF0)?;
+            Some(&message[start..])
+        })
+}
+
+fn to_text(slot: Slot, preset: &Preset) -> String {
+    let slot = match slot {
+        Slot::Current => "current".to_string(),
+        Slot::Stored(slot) => slot.to_string(),
+    };
+    let filter_type = name(&FILTER_TYPES, preset.filter_type);
+    let spectral_mode = name(&SPECTRAL_MODES, preset.spectral_mode);
+    format!(
+        "slot {slot}\nfilter {filter_type}\nfrequency {}\nquality {}\ngain {}\nspectral {spectral_mode}\n",
+        preset.filter.frequency, preset.filter.quality, preset.filter.gain
+    )
+}
+
+fn from_text(text: &str) -> Result<(Slot, Preset), String> {
+    let mut slot = None;
+    let mut preset = Preset::default();
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        let (key, value) = line
+            .split_once(char::is_whitespace)
+            .ok_or_else(|| format!("no value in `{line}`"))?;
+        let value = value.trim();
+        let number = || {
+            value
+                .parse::<f32>()
+                .map_err(|_| format!("`{value}` is not a number"))
+        };
+        match key {
+            "slot" => slot = Some(parse_slot(value)?),
+            "filter" => preset.filter_type = by_name(&FILTER_TYPES, value)?,
+            "frequency" => preset.filter.frequency = number()?,
+            "quality" => preset.filter.quality = number()?,
+            "gain" => preset.filter.gain = number()?,
+            "spectral" => preset.spectral_mode = by_name(&SPECTRAL_MODES, value)?,
+            _ => return Err(format!("unknown parameter `{key}`")),
+        }
+    }
+    // The firmware would refuse anything that doesn't come back the same
+    Preset::from_bytes(&preset.to_bytes()).map_err(|error| format!("{error:?}"))?;
+    Ok((slot.ok_or("no slot")?, preset))
+}
+
+fn parse_slot(text: &str) -> Result<Slot, String> {
+    match text {
+        "current" => Ok(Slot::Current),
+        _ => text
+            .parse()
+            .map(Slot::Stored)
+            .map_err(|_| format!("`{text}` is not a slot")),
+    }
+}
+
+fn name<T: PartialEq + Copy>(names: &[(T, &'static str)], value: T) -> &'static str {
+    names
+        .iter()
+        .find(|(candidate, _)| *candidate == value)
+        .map(|(_, name)| *name)
+        .expect("Every value has a name")
+}
+
+fn by_name<T: Copy>(names: &[(T, &str)], text: &str) -> Result<T, String> {
+    names
+        .iter()
+        .find(|(_, name)| *name == text)
+        .map(|(value, _)| *value)
+        .ok_or_else(|| format!("unknown value `{text}`"))
+}
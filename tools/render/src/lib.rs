@@ -0,0 +1,105 @@
+// Render
+//
+// The DSP modules of the firmware built for the host, for the render tool and
+// the tests. The firmware crate only builds for the Daisy, so the modules are
+// included by path. micromath is only needed without std, on the host the
+// methods of std take precedence.
+#[allow(unused_imports)]
+#[path = "../../../src/allocator.rs"]
+pub mod allocator;
+#[allow(unused_imports)]
+#[path = "../../../src/convolution.rs"]
+pub mod convolution;
+#[allow(unused_imports)]
+#[path = "../../../src/crc.rs"]
+pub mod crc;
+#[allow(unused_imports)]
+#[path = "../../../src/drums.rs"]
+pub mod drums;
+#[allow(unused_imports)]
+#[path = "../../../src/envelope.rs"]
+pub mod envelope;
+#[allow(unused_imports)]
+#[path = "../../../src/fft.rs"]
+pub mod fft;
+#[allow(unused_imports)]
+#[path = "../../../src/filter.rs"]
+pub mod filter;
+#[allow(unused_imports)]
+#[path = "../../../src/flash.rs"]
+pub mod flash;
+#[allow(unused_imports)]
+#[path = "../../../src/granular.rs"]
+pub mod granular;
+#[allow(unused_imports)]
+#[path = "../../../src/lfo.rs"]
+pub mod lfo;
+#[allow(unused_imports)]
+#[path = "../../../src/looper.rs"]
+pub mod looper;
+#[allow(unused_imports)]
+#[path = "../../../src/midi.rs"]
+pub mod midi;
+#[allow(unused_imports)]
+#[path = "../../../src/midi_clock.rs"]
+pub mod midi_clock;
+#[allow(unused_imports)]
+#[path = "../../../src/midi_map.rs"]
+pub mod midi_map;
+#[allow(unused_imports)]
+#[path = "../../../src/modulation.rs"]
+pub mod modulation;
+#[allow(unused_imports)]
+#[path = "../../../src/morph.rs"]
+pub mod morph;
+#[allow(unused_imports)]
+#[path = "../../../src/noise.rs"]
+pub mod noise;
+#[allow(unused_imports)]
+#[path = "../../../src/octaver.rs"]
+pub mod octaver;
+#[allow(unused_imports)]
+#[path = "../../../src/oscillator.rs"]
+pub mod oscillator;
+#[allow(unused_imports)]
+#[path = "../../../src/physical.rs"]
+pub mod physical;
+#[allow(unused_imports)]
+#[path = "../../../src/pitch_shift.rs"]
+pub mod pitch_shift;
+#[allow(unused_imports)]
+#[path = "../../../src/preset.rs"]
+pub mod preset;
+#[allow(unused_imports)]
+#[path = "../../../src/random.rs"]
+pub mod random;
+#[allow(unused_imports)]
+#[path = "../../../src/sequencer.rs"]
+pub mod sequencer;
+#[allow(unused_imports)]
+#[path = "../../../src/settings.rs"]
+pub mod settings;
+#[allow(unused_imports)]
+#[path = "../../../src/spectral.rs"]
+pub mod spectral;
+#[allow(unused_imports)]
+#[path = "../../../src/spectrum.rs"]
+pub mod spectrum;
+#[allow(unused_imports)]
+#[path = "../../../src/synth.rs"]
+pub mod synth;
+#[allow(unused_imports)]
+#[path = "../../../src/sysex.rs"]
+pub mod sysex;
+#[allow(unused_imports)]
+#[path = "../../../src/tuner.rs"]
+pub mod tuner;
+#[allow(unused_imports)]
+#[path = "../../../src/usb_midi.rs"]
+pub mod usb_midi;
+#[allow(unused_imports)]
+#[path = "../../../src/vocoder.rs"]
+pub mod vocoder;
+#[allow(unused_imports)]
+#[path = "../../../src/wavetable.rs"]
+pub mod wavetable;
@@ -0,0 +1,134 @@
+// Render
+//
+// Renders the drum voices to a WAV file on the host, for sound checks without
+// flashing the board:
+//
+//     cargo run --release -- pattern drums.wav
+//
+// `kick`, `snare`, `hihat`, `open-hihat` and `clap` render four hits of a
+// single voice, every second one accented, `pattern` plays two bars of a
+// beat on the whole kit.
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::process::ExitCode;
+
+use render::drums::{Drum, DrumKit, HiHatParams};
+
+const SAMPLE_RATE: u32 = 48_000;
+const BPM: f32 = 120.0;
+/// Rendered in blocks like on the board
+const BLOCK_LENGTH: usize = 32;
+
+/// Sixteenth steps of the beat: the drum, the steps it plays on and the
+/// steps with an accent
+const PATTERN: [(Drum, &str, &str); 4] = [
+    (Drum::Kick, "x-----x-x-------", "x-------x-------"),
+    (Drum::Snare, "----x-------x---", "------------x---"),
+    (Drum::HiHat, "x-x-x-x-x-x-x-xx", "x---x---x---x---"),
+    (Drum::Clap, "------------x---", "----------------"),
+];
+
+fn main() -> ExitCode {
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+    let (Some(what), Some(path)) = (arguments.first(), arguments.get(1)) else {
+        eprintln!("Usage: render <kick|snare|hihat|open-hihat|clap|pattern> <output.wav>");
+        return ExitCode::FAILURE;
+    };
+
+    let mut kit = DrumKit::new(SAMPLE_RATE as f32);
+    let step = (SAMPLE_RATE as f32 * 60.0 / BPM / 4.0) as usize;
+    let mut triggers = Vec::new();
+    match what.as_str() {
+        "pattern" => {
+            for bar in 0..2 {
+                for (drum, steps, accents) in PATTERN {
+                    for (index, (hit, accent)) in steps.bytes().zip(accents.bytes()).enumerate() {
+                        if hit == b'x' {
+                            triggers.push(((bar * 16 + index) * step, drum, accent == b'x'));
+                        }
+                    }
+                }
+            }
+        }
+        voice => {
+            let drum = match voice {
+                "kick" => Drum::Kick,
+                "snare" => Drum::Snare,
+                "hihat" => Drum::HiHat,
+                "open-hihat" => {
+                    kit.hihat
+                        .set_params(HiHatParams {
+                            decay_ms: 400.0,
+                            ..Default::default()
+                        })
+                        .expect("Valid hi-hat settings");
+                    Drum::HiHat
+                }
+                "clap" => Drum::Clap,
+                _ => {
+                    eprintln!("Unknown voice {voice}");
+                    return ExitCode::FAILURE;
+                }
+            };
+            for hit in 0..4 {
+                triggers.push((hit * 4 * step, drum, hit % 2 == 1));
+            }
+        }
+    }
+    triggers.sort_by_key(|(time, _, _)| *time);
+
+    // Runs on a little past the last hit for the tails
+    let length = triggers.last().map_or(0, |(time, _, _)| *time) + SAMPLE_RATE as usize;
+    let mut samples = Vec::with_capacity(length);
+    let mut pending = triggers.iter().peekable();
+    let mut block = [(0.0, 0.0); BLOCK_LENGTH];
+    for start in (0..length).step_by(BLOCK_LENGTH) {
+        // Triggers take effect at the start of the block, like with the
+        // inputs of the board
+        while let Some((_, drum, accent)) =
+            pending.next_if(|(time, _, _)| *time < start + BLOCK_LENGTH)
+        {
+            kit.trigger(*drum, *accent);
+        }
+        block.fill((0.0, 0.0));
+        kit.process(&mut block);
+        samples.extend(block.iter().map(|(left, _)| *left));
+    }
+
+    let peak = samples
+        .iter()
+        .fold(0.0_f32, |peak, sample| peak.max(sample.abs()));
+    if let Err(error) = write_wav(path, &samples) {
+        eprintln!("Failed to write {path}: {error}");
+        return ExitCode::FAILURE;
+    }
+    println!(
+        "Wrote {path}: {:.2} s, peak {:.1} dBFS",
+        samples.len() as f32 / SAMPLE_RATE as f32,
+        20.0 * peak.log10()
+    );
+    ExitCode::SUCCESS
+}
+
+/// Writes mono 16 bit PCM, clipping at full scale
+fn write_wav(path: &str, samples: &[f32]) -> std::io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    let data_length = 2 * samples.len() as u32;
+    file.write_all(b"RIFF")?;
+    file.write_all(&(36 + data_length).to_le_bytes())?;
+    file.write_all(b"WAVEfmt ")?;
+    file.write_all(&16_u32.to_le_bytes())?;
+    file.write_all(&1_u16.to_le_bytes())?; // PCM
+    file.write_all(&1_u16.to_le_bytes())?; // Mono
+    file.write_all(&SAMPLE_RATE.to_le_bytes())?;
+    file.write_all(&(2 * SAMPLE_RATE).to_le_bytes())?;
+    file.write_all(&2_u16.to_le_bytes())?; // Bytes per frame
+    file.write_all(&16_u16.to_le_bytes())?;
+    file.write_all(b"data")?;
+    file.write_all(&data_length.to_le_bytes())?;
+    for sample in samples {
+        let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        file.write_all(&value.to_le_bytes())?;
+    }
+    file.flush()
+}
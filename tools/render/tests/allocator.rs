@@ -0,0 +1,215 @@
+// Allocator
+//
+// Streams of notes through every allocation mode and note priority, checked
+// against the voice events they produce.
+use render::allocator::{AllocationMode, NotePriority, VoiceAllocator, VoiceEvent};
+
+fn on(voice: usize, note: u8, retrigger: bool) -> VoiceEvent {
+    VoiceEvent::NoteOn {
+        voice,
+        note,
+        velocity: 100,
+        retrigger,
+    }
+}
+
+fn off(voice: usize) -> VoiceEvent {
+    VoiceEvent::NoteOff { voice }
+}
+
+/// Voices that play the notes, one after the other
+fn play<const N: usize>(allocator: &mut VoiceAllocator<N>, notes: &[u8]) -> Vec<usize> {
+    notes
+        .iter()
+        .map(|note| match allocator.note_on(*note, 100)[..] {
+            [VoiceEvent::NoteOn { voice, .. }] => voice,
+            ref events => panic!("{note}: {events:?}"),
+        })
+        .collect()
+}
+
+/// Reports every voice as sounding, at the given levels
+fn sounding<const N: usize>(allocator: &mut VoiceAllocator<N>, levels: [f32; N]) {
+    for (voice, level) in levels.into_iter().enumerate() {
+        allocator.update_voice(voice, true, level);
+    }
+}
+
+#[test]
+fn oldest_steals_released_voices_first() {
+    let mut allocator = VoiceAllocator::<4>::new(AllocationMode::Oldest);
+    assert_eq!(play(&mut allocator, &[60, 62, 64, 65]), [0, 1, 2, 3]);
+    sounding(&mut allocator, [0.5; 4]);
+
+    // All held: the first note goes
+    assert_eq!(allocator.note_on(67, 100)[..], [on(0, 67, true)]);
+    // A released voice goes before older held ones
+    assert_eq!(allocator.note_off(64)[..], [off(2)]);
+    assert_eq!(play(&mut allocator, &[69]), [2]);
+    // An idle voice goes before anything else
+    allocator.note_off(62);
+    allocator.update_voice(1, false, 0.0);
+    assert_eq!(play(&mut allocator, &[71]), [1]);
+    assert_eq!(play(&mut allocator, &[72]), [3]);
+
+    // A repeated note takes its own voice, even in its release
+    allocator.note_off(69);
+    assert_eq!(play(&mut allocator, &[69]), [2]);
+    assert_eq!(allocator.voice_note(2), Some(69));
+}
+
+#[test]
+fn quietest_steals_the_lowest_level() {
+    let mut allocator = VoiceAllocator::<3>::new(AllocationMode::Quietest);
+    assert_eq!(play(&mut allocator, &[60, 62, 64]), [0, 1, 2]);
+    sounding(&mut allocator, [0.5, 0.1, 0.9]);
+    assert_eq!(play(&mut allocator, &[65]), [1]);
+
+    // Released voices first, even when they are louder
+    sounding(&mut allocator, [0.5, 0.1, 0.9]);
+    allocator.note_off(64);
+    assert_eq!(play(&mut allocator, &[67]), [2]);
+}
+
+#[test]
+fn round_robin_cycles_through_the_voices() {
+    let mut allocator = VoiceAllocator::<3>::new(AllocationMode::RoundRobin);
+    for note in [60, 62, 64, 65, 67] {
+        allocator.note_on(note, 100);
+        allocator.note_off(note);
+    }
+    // Every voice is idle, still the next one in turn
+    assert_eq!(play(&mut allocator, &[69, 71, 72]), [2, 0, 1]);
+
+    // Skips the voices that are held
+    allocator.note_off(69);
+    allocator.update_voice(2, false, 0.0);
+    assert_eq!(play(&mut allocator, &[74]), [2]);
+    sounding(&mut allocator, [0.5; 3]);
+    assert_eq!(play(&mut allocator, &[76]), [0]);
+}
+
+#[test]
+fn unison_plays_one_note_on_all_voices() {
+    let mut allocator = VoiceAllocator::<3>::new(AllocationMode::Unison {
+        priority: NotePriority::Last,
+        legato: true,
+    });
+    assert_eq!(
+        allocator.note_on(60, 100)[..],
+        [on(0, 60, true), on(1, 60, true), on(2, 60, true)]
+    );
+    // Legato changes the pitch only
+    assert_eq!(
+        allocator.note_on(64, 100)[..],
+        [on(0, 64, false), on(1, 64, false), on(2, 64, false)]
+    );
+    assert_eq!(
+        allocator.note_off(64)[..],
+        [on(0, 60, false), on(1, 60, false), on(2, 60, false)]
+    );
+    assert_eq!(allocator.note_off(60)[..], [off(0), off(1), off(2)]);
+    assert!(allocator.note_off(60).is_empty());
+}
+
+/// Notes picked by mono mode while holding and then releasing `notes`, with
+/// their retrigger flag
+fn mono(priority: NotePriority, legato: bool, notes: &[u8]) -> Vec<Option<(u8, bool)>> {
+    let mut allocator = VoiceAllocator::<2>::new(AllocationMode::Mono { priority, legato });
+    let picked = |events: &[VoiceEvent]| match events {
+        [] => None,
+        [
+            VoiceEvent::NoteOn {
+                voice: 0,
+                note,
+                retrigger,
+                ..
+            },
+        ] => Some((*note, *retrigger)),
+        [VoiceEvent::NoteOff { voice: 0 }] => Some((0, false)),
+        events => panic!("{events:?}"),
+    };
+    let mut result = Vec::new();
+    for note in notes {
+        result.push(picked(&allocator.note_on(*note, 100)));
+    }
+    for note in notes {
+        result.push(picked(&allocator.note_off(*note)));
+    }
+    result
+}
+
+#[test]
+fn mono_follows_the_note_priority() {
+    let notes = [60, 67, 64];
+    let released = Some((0, false));
+    assert_eq!(
+        mono(NotePriority::Last, false, &notes),
+        [
+            Some((60, true)),
+            Some((67, true)),
+            Some((64, true)),
+            // Releasing 60 and 67 leaves 64 playing
+            None,
+            None,
+            released,
+        ]
+    );
+    assert_eq!(
+        mono(NotePriority::Low, false, &notes),
+        [
+            Some((60, true)),
+            None,
+            None,
+            Some((64, true)),
+            None,
+            released,
+        ]
+    );
+    assert_eq!(
+        mono(NotePriority::High, false, &notes),
+        [
+            Some((60, true)),
+            Some((67, true)),
+            None,
+            None,
+            Some((64, true)),
+            released,
+        ]
+    );
+}
+
+#[test]
+fn mono_retriggers_unless_legato() {
+    let notes = [60, 64];
+    assert_eq!(
+        mono(NotePriority::Last, false, &notes),
+        [Some((60, true)), Some((64, true)), None, Some((0, false))]
+    );
+    // The first note always starts the envelopes
+    assert_eq!(
+        mono(NotePriority::Last, true, &notes),
+        [Some((60, true)), Some((64, false)), None, Some((0, false))]
+    );
+
+    // Returning to a held note is legato as well
+    let mut allocator = VoiceAllocator::<1>::new(AllocationMode::Mono {
+        priority: NotePriority::Last,
+        legato: true,
+    });
+    allocator.note_on(60, 100);
+    allocator.note_on(64, 100);
+    assert_eq!(allocator.note_off(64)[..], [on(0, 60, false)]);
+}
+
+#[test]
+fn switching_modes_releases_everything() {
+    let mut allocator = VoiceAllocator::<2>::new(AllocationMode::Oldest);
+    play(&mut allocator, &[60, 62]);
+    assert_eq!(
+        allocator.set_mode(AllocationMode::RoundRobin)[..],
+        [off(0), off(1)]
+    );
+    assert_eq!(allocator.voice_note(0), None);
+    assert!(allocator.all_notes_off().is_empty());
+}
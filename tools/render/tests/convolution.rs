@@ -0,0 +1,111 @@
+// Convolution
+//
+// The partitioned convolution against the plain sum of products, for short
+// impulse responses in the time domain, one partition and several.
+use render::convolution::{ConvolutionError, Convolver, DIRECT_FORM_MAX_TAPS};
+use render::random::Random;
+
+fn noise(length: usize, seed: u32) -> Vec<f32> {
+    let mut random = Random::new(seed);
+    (0..length).map(|_| random.bipolar()).collect()
+}
+
+fn naive(input: &[f32], impulse_response: &[f32]) -> Vec<f32> {
+    (0..input.len())
+        .map(|n| {
+            impulse_response
+                .iter()
+                .enumerate()
+                .take(n + 1)
+                .map(|(k, tap)| tap * input[n - k])
+                .sum()
+        })
+        .collect()
+}
+
+/// Largest difference to the naive convolution over a few blocks more than
+/// the impulse response is long, relative to the peak of the output
+fn max_error<const N: usize, const P: usize>(taps: usize) -> f32 {
+    let mut convolver = Convolver::<N, P>::new().unwrap();
+    let impulse_response = noise(taps, taps as u32);
+    convolver.set_impulse_response(&impulse_response).unwrap();
+
+    let block_length = Convolver::<N, P>::BLOCK_LENGTH;
+    let input = noise(taps + 8 * block_length, 1);
+    let input = &input[..input.len() / block_length * block_length];
+    let mut output = input.to_vec();
+    for block in output.chunks_exact_mut(block_length) {
+        convolver.process(block);
+    }
+
+    let expected = naive(input, &impulse_response);
+    let peak = expected.iter().map(|sample| sample.abs()).fold(0.0, f32::max);
+    let error = expected
+        .iter()
+        .zip(&output)
+        .map(|(expected, actual)| (expected - actual).abs())
+        .fold(0.0, f32::max);
+    error / peak
+}
+
+#[test]
+fn short_impulse_responses_are_convolved_directly() {
+    for taps in [1, 5, DIRECT_FORM_MAX_TAPS] {
+        let error = max_error::<128, 8>(taps);
+        assert!(error < 1e-5, "{taps} taps: {error}");
+    }
+}
+
+#[test]
+fn one_partition_matches() {
+    for taps in [DIRECT_FORM_MAX_TAPS + 1, 40, 64] {
+        let error = max_error::<128, 8>(taps);
+        assert!(error < 1e-5, "{taps} taps: {error}");
+    }
+}
+
+#[test]
+fn several_partitions_match() {
+    for taps in [65, 100, 512] {
+        let error = max_error::<128, 8>(taps);
+        assert!(error < 1e-5, "{taps} taps: {error}");
+    }
+    let error = max_error::<256, 8>(700);
+    assert!(error < 1e-5, "700 taps: {error}");
+}
+
+#[test]
+fn reset_forgets_the_input() {
+    let mut convolver = Convolver::<128, 4>::new().unwrap();
+    convolver.set_impulse_response(&noise(100, 2)).unwrap();
+    let mut block = noise(64, 3);
+    convolver.process(&mut block);
+
+    convolver.reset();
+    let mut silence = [0.0; 64];
+    convolver.process(&mut silence);
+    assert!(silence.iter().all(|sample| *sample == 0.0));
+}
+
+#[test]
+fn invalid_impulse_responses_are_refused() {
+    let mut convolver = Convolver::<128, 4>::new().unwrap();
+    assert!(matches!(
+        convolver.set_impulse_response(&[]),
+        Err(ConvolutionError::ImpulseResponseEmpty)
+    ));
+    assert!(matches!(
+        convolver.set_impulse_response(&[0.0; 257]),
+        Err(ConvolutionError::ImpulseResponseTooLong)
+    ));
+    assert!(matches!(
+        Convolver::<100, 4>::new(),
+        Err(ConvolutionError::SizeNotPowerOfTwo)
+    ));
+
+    // Silent until there is an impulse response
+    let mut convolver = Convolver::<128, 4>::new().unwrap();
+    let mut block = noise(64, 4);
+    convolver.process(&mut block);
+    assert!(block.iter().all(|sample| *sample == 0.0));
+}
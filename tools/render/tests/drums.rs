@@ -0,0 +1,146 @@
+// Drums
+//
+// The pitch sweep of the kick, the voices going idle after their decay and
+// the level of accented hits.
+use render::drums::{Clap, ClapParams, HiHat, HiHatParams, Kick, KickParams, Snare, SnareParams};
+
+const SAMPLE_RATE: f32 = 48_000.0;
+
+fn samples(ms: f32) -> usize {
+    (ms * 0.001 * SAMPLE_RATE) as usize
+}
+
+/// What the tests need of every voice
+trait Voice {
+    fn trigger(&mut self, accent: bool);
+    fn tick(&mut self) -> f32;
+    fn is_active(&self) -> bool;
+}
+
+macro_rules! voice {
+    ($($drum:ty),*) => {
+        $(impl Voice for $drum {
+            fn trigger(&mut self, accent: bool) {
+                <$drum>::trigger(self, accent)
+            }
+            fn tick(&mut self) -> f32 {
+                <$drum>::tick(self)
+            }
+            fn is_active(&self) -> bool {
+                <$drum>::is_active(self)
+            }
+        })*
+    };
+}
+
+voice!(Kick, Snare, HiHat, Clap);
+
+fn run(voice: &mut impl Voice, ms: f32) -> Vec<f32> {
+    (0..samples(ms)).map(|_| voice.tick()).collect()
+}
+
+/// Times of the rising zero crossings in samples, interpolated between the
+/// samples
+fn rising_crossings(output: &[f32]) -> Vec<f32> {
+    output
+        .windows(2)
+        .enumerate()
+        .filter(|(_, pair)| pair[0] < 0.0 && pair[1] >= 0.0)
+        .map(|(n, pair)| n as f32 + pair[0] / (pair[0] - pair[1]))
+        .collect()
+}
+
+#[test]
+fn kick_sweeps_down_to_its_frequency() {
+    let mut kick = Kick::new(SAMPLE_RATE);
+    kick.set_params(KickParams {
+        frequency: 50.0,
+        pitch_depth: 2.0,
+        pitch_decay_ms: 40.0,
+        decay_ms: 2000.0,
+        drive: 0.0,
+        ..KickParams::default()
+    })
+    .unwrap();
+    kick.trigger(false);
+    let crossings = rising_crossings(&run(&mut kick, 1000.0));
+
+    // The sine starts rising, so the first crossing ends the first cycle.
+    // The sweep starts two octaves up and falls fast, on average it still
+    // runs more than an octave up.
+    let first = SAMPLE_RATE / crossings[0];
+    assert!(first > 100.0, "starts at {first} Hz");
+
+    // Once the pitch envelope is done, the kick stays at its frequency
+    let settled: Vec<f32> = crossings
+        .into_iter()
+        .filter(|time| *time > samples(100.0) as f32)
+        .collect();
+    let periods = (settled.len() - 1) as f32;
+    let frequency = SAMPLE_RATE * periods / (settled[settled.len() - 1] - settled[0]);
+    assert!((frequency - 50.0).abs() < 0.1, "ends at {frequency} Hz");
+}
+
+fn assert_goes_idle(mut voice: impl Voice, decay_ms: f32) {
+    voice.trigger(false);
+    run(&mut voice, decay_ms - 1.0);
+    assert!(voice.is_active(), "idle before {decay_ms} ms");
+    let output = run(&mut voice, 2.0);
+    assert!(!voice.is_active(), "still active after {decay_ms} ms");
+    assert_eq!(output.last(), Some(&0.0));
+}
+
+#[test]
+fn voices_go_idle_after_their_decay() {
+    assert_goes_idle(Kick::new(SAMPLE_RATE), KickParams::default().decay_ms);
+    let snare = SnareParams::default();
+    assert_goes_idle(
+        Snare::new(SAMPLE_RATE),
+        snare.tone_decay_ms.max(snare.noise_decay_ms),
+    );
+    assert_goes_idle(HiHat::new(SAMPLE_RATE), HiHatParams::default().decay_ms);
+    // The tail of the clap starts after its bursts
+    let clap = ClapParams::default();
+    assert_goes_idle(
+        Clap::new(SAMPLE_RATE),
+        clap.bursts as f32 * clap.spacing_ms + clap.decay_ms,
+    );
+}
+
+/// Plays the same hit of two fresh voices with and without accent
+fn assert_accent<V: Voice>(new: impl Fn() -> V, accent: f32) {
+    let (mut plain, mut accented) = (new(), new());
+    plain.trigger(false);
+    accented.trigger(true);
+    for (plain, accented) in run(&mut plain, 100.0)
+        .into_iter()
+        .zip(run(&mut accented, 100.0))
+    {
+        assert!(
+            (accented - plain * (1.0 + accent)).abs() < 1.0e-6,
+            "{accented} instead of {plain} * {}",
+            1.0 + accent
+        );
+    }
+}
+
+#[test]
+fn accent_raises_the_level() {
+    for accent in [0.5, 1.0] {
+        assert_accent(
+            || {
+                let mut kick = Kick::new(SAMPLE_RATE);
+                kick.set_params(KickParams {
+                    accent,
+                    ..KickParams::default()
+                })
+                .unwrap();
+                kick
+            },
+            accent,
+        );
+    }
+    assert_accent(|| Snare::new(SAMPLE_RATE), SnareParams::default().accent);
+    assert_accent(|| HiHat::new(SAMPLE_RATE), HiHatParams::default().accent);
+    assert_accent(|| Clap::new(SAMPLE_RATE), ClapParams::default().accent);
+}
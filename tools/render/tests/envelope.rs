@@ -0,0 +1,118 @@
+// Envelope
+//
+// Lengths and levels of the segments of the presets at the usual sample
+// rates.
+use render::envelope::{Envelope, EnvelopeError, EnvelopeParams, EnvelopeStage};
+
+const SAMPLE_RATES: [f32; 3] = [44_100.0, 48_000.0, 96_000.0];
+
+fn samples(time_ms: f32, sample_rate: f32) -> usize {
+    (time_ms * 0.001 * sample_rate).round() as usize
+}
+
+/// Ticks until the envelope leaves `stage` and returns the number of samples
+/// it took, with the level reached
+fn run(envelope: &mut Envelope, stage: EnvelopeStage) -> (usize, f32) {
+    assert_eq!(envelope.stage(), stage);
+    let mut count = 0;
+    while envelope.stage() == stage {
+        envelope.tick();
+        count += 1;
+        assert!(count < 1_000_000, "{stage:?} never ends");
+    }
+    (count, envelope.value())
+}
+
+#[test]
+fn adsr_segments_last_their_time() {
+    for sample_rate in SAMPLE_RATES {
+        let mut envelope = Envelope::new(sample_rate);
+        envelope
+            .set_params(EnvelopeParams::adsr(10.0, 50.0, 0.5, 120.0).unwrap())
+            .unwrap();
+
+        envelope.gate_on(false);
+        let (attack, peak) = run(&mut envelope, EnvelopeStage::Segment(0));
+        assert_eq!(attack, samples(10.0, sample_rate), "{sample_rate}");
+        assert_eq!(peak, 1.0);
+        let (decay, sustain) = run(&mut envelope, EnvelopeStage::Segment(1));
+        assert_eq!(decay, samples(50.0, sample_rate), "{sample_rate}");
+        assert_eq!(sustain, 0.5);
+
+        // Holds as long as the gate is on
+        assert_eq!(envelope.advance(10_000), 0.5);
+        assert_eq!(envelope.stage(), EnvelopeStage::Sustain);
+
+        envelope.gate_off();
+        let (release, end) = run(&mut envelope, EnvelopeStage::Segment(2));
+        assert_eq!(release, samples(120.0, sample_rate), "{sample_rate}");
+        assert_eq!(end, 0.0);
+        assert!(!envelope.is_active());
+    }
+}
+
+#[test]
+fn ar_and_ahdsr_segments_last_their_time() {
+    for sample_rate in SAMPLE_RATES {
+        let mut envelope = Envelope::new(sample_rate);
+        envelope
+            .set_params(EnvelopeParams::ar(3.0, 250.0).unwrap())
+            .unwrap();
+        envelope.gate_on(false);
+        let (attack, _) = run(&mut envelope, EnvelopeStage::Segment(0));
+        assert_eq!(attack, samples(3.0, sample_rate), "{sample_rate}");
+        envelope.gate_off();
+        let (release, _) = run(&mut envelope, EnvelopeStage::Segment(1));
+        assert_eq!(release, samples(250.0, sample_rate), "{sample_rate}");
+
+        envelope
+            .set_params(EnvelopeParams::ahdsr(1.0, 20.0, 30.0, 0.25, 40.0).unwrap())
+            .unwrap();
+        envelope.gate_on(false);
+        let lengths = [1.0, 20.0, 30.0];
+        for (index, time_ms) in lengths.into_iter().enumerate() {
+            let (length, _) = run(&mut envelope, EnvelopeStage::Segment(index));
+            assert_eq!(length, samples(time_ms, sample_rate), "{sample_rate}");
+        }
+        assert_eq!(envelope.value(), 0.25);
+        envelope.gate_off();
+        let (release, end) = run(&mut envelope, EnvelopeStage::Segment(3));
+        assert_eq!(release, samples(40.0, sample_rate), "{sample_rate}");
+        assert_eq!(end, 0.0);
+    }
+}
+
+#[test]
+fn releasing_early_starts_from_the_current_level() {
+    for sample_rate in SAMPLE_RATES {
+        let mut envelope = Envelope::new(sample_rate);
+        envelope
+            .set_params(EnvelopeParams::adsr(100.0, 50.0, 0.5, 80.0).unwrap())
+            .unwrap();
+        envelope.gate_on(false);
+        let level = envelope.advance(samples(50.0, sample_rate));
+        assert!((level - 0.5).abs() < 0.01, "{sample_rate}: {level}");
+
+        envelope.gate_off();
+        let (release, end) = run(&mut envelope, EnvelopeStage::Segment(2));
+        assert_eq!(release, samples(80.0, sample_rate), "{sample_rate}");
+        assert_eq!(end, 0.0);
+    }
+}
+
+#[test]
+fn presets_are_validated() {
+    assert!(EnvelopeParams::adsr(0.0, 0.0, 1.0, 0.0).is_ok());
+    assert_eq!(
+        EnvelopeParams::adsr(-1.0, 10.0, 0.5, 10.0).err(),
+        Some(EnvelopeError::TimeNegative)
+    );
+    assert_eq!(
+        EnvelopeParams::ar(5.0, -0.0).err(),
+        Some(EnvelopeError::TimeNegative)
+    );
+    assert_eq!(
+        EnvelopeParams::ahdsr(1.0, -5.0, 1.0, 0.5, 1.0).err(),
+        Some(EnvelopeError::TimeNegative)
+    );
+}
@@ -0,0 +1,131 @@
+// FFT
+//
+// Compares the complex and the real FFT with a naive DFT in double precision
+// for every supported power of two, and checks that the inverse transforms
+// undo the forward ones.
+use render::fft::{Complex, Fft, FftError, RealFft};
+use render::random::Random;
+
+/// Naive DFT of `input`
+fn dft(input: &[Complex]) -> Vec<(f64, f64)> {
+    let n = input.len();
+    (0..n)
+        .map(|k| {
+            input
+                .iter()
+                .enumerate()
+                .fold((0.0, 0.0), |(re, im), (j, x)| {
+                    // Reduced before the conversion to keep the angle exact
+                    let angle = -core::f64::consts::TAU * ((j * k) % n) as f64 / n as f64;
+                    let (sin, cos) = angle.sin_cos();
+                    let (x_re, x_im) = (x.re as f64, x.im as f64);
+                    (re + x_re * cos - x_im * sin, im + x_re * sin + x_im * cos)
+                })
+        })
+        .collect()
+}
+
+/// Largest distance between the bins, relative to the RMS of the signal
+/// times `sqrt(N)`, which is the RMS of its bins
+fn error(result: &[Complex], expected: &[(f64, f64)], input: &[Complex]) -> f64 {
+    let n = input.len() as f64;
+    let rms = (input.iter().map(|x| x.norm_sqr() as f64).sum::<f64>() / n).sqrt();
+    let distance = result
+        .iter()
+        .zip(expected)
+        .map(|(x, (re, im))| (x.re as f64 - re).hypot(x.im as f64 - im))
+        .fold(0.0, f64::max);
+    distance / (rms * n.sqrt())
+}
+
+fn noise<const N: usize>(seed: u32) -> [Complex; N] {
+    let mut random = Random::new(seed);
+    core::array::from_fn(|_| Complex::new(random.bipolar(), random.bipolar()))
+}
+
+fn check_complex<const N: usize>() {
+    let fft = Fft::<N>::new().unwrap();
+    let input = noise::<N>(0x0F0F_1234);
+    let expected = dft(&input);
+
+    let mut data = input;
+    fft.forward(&mut data);
+    let forward_error = error(&data, &expected, &input);
+    assert!(forward_error < 1.0e-6, "N = {N}: {forward_error}");
+
+    fft.inverse(&mut data);
+    let inverse_error = input
+        .iter()
+        .zip(data.iter())
+        .map(|(x, y)| (*x - *y).norm_sqr().sqrt())
+        .fold(0.0, f32::max);
+    assert!(inverse_error < 1.0e-5, "N = {N}: {inverse_error}");
+}
+
+fn check_real<const N: usize>() {
+    let fft = RealFft::<N>::new().unwrap();
+    let mut random = Random::new(0x5EED_4321);
+    let input: [f32; N] = core::array::from_fn(|_| random.bipolar());
+    let complex_input: Vec<Complex> = input.iter().map(|x| Complex::new(*x, 0.0)).collect();
+    let expected = dft(&complex_input);
+
+    let mut data = input;
+    fft.forward(&mut data);
+    let bins: Vec<Complex> = (0..=N / 2).map(|k| RealFft::bin(&data, k)).collect();
+    let forward_error = error(&bins, &expected[..=N / 2], &complex_input);
+    assert!(forward_error < 1.0e-6, "N = {N}: {forward_error}");
+
+    fft.inverse(&mut data);
+    let inverse_error = input
+        .iter()
+        .zip(data.iter())
+        .map(|(x, y)| (x - y).abs())
+        .fold(0.0, f32::max);
+    assert!(inverse_error < 1.0e-5, "N = {N}: {inverse_error}");
+}
+
+#[test]
+fn complex_fft_matches_dft() {
+    check_complex::<2>();
+    check_complex::<4>();
+    check_complex::<8>();
+    check_complex::<16>();
+    check_complex::<32>();
+    check_complex::<64>();
+    check_complex::<128>();
+    check_complex::<256>();
+    check_complex::<512>();
+    check_complex::<1024>();
+    check_complex::<2048>();
+    check_complex::<4096>();
+}
+
+#[test]
+fn real_fft_matches_dft() {
+    check_real::<4>();
+    check_real::<8>();
+    check_real::<16>();
+    check_real::<32>();
+    check_real::<64>();
+    check_real::<128>();
+    check_real::<256>();
+    check_real::<512>();
+    check_real::<1024>();
+    check_real::<2048>();
+    check_real::<4096>();
+    check_real::<8192>();
+}
+
+#[test]
+fn sizes_that_are_no_power_of_two_are_refused() {
+    assert!(matches!(Fft::<1>::new(), Err(FftError::SizeNotPowerOfTwo)));
+    assert!(matches!(Fft::<12>::new(), Err(FftError::SizeNotPowerOfTwo)));
+    assert!(matches!(
+        RealFft::<2>::new(),
+        Err(FftError::SizeNotPowerOfTwo)
+    ));
+    assert!(matches!(
+        RealFft::<24>::new(),
+        Err(FftError::SizeNotPowerOfTwo)
+    ));
+}
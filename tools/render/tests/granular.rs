@@ -0,0 +1,94 @@
+// Granular
+//
+// Spreads outside of their range are refused, and grains never read audio
+// that was written after they started. The largest grains that are accepted
+// also fit into the buffer when they are spawned.
+use render::granular::{Granular, GranularError, GranularParams};
+
+#[test]
+fn size_spread_out_of_range_is_refused() {
+    let mut buffer = vec![0.0; 48_000];
+    let mut granular = Granular::new(&mut buffer, 48_000.0).unwrap();
+    for size_spread in [-0.1, 1.5] {
+        let result = granular.set_params(GranularParams {
+            size_spread,
+            ..GranularParams::default()
+        });
+        assert!(matches!(result, Err(GranularError::SizeOutOfRange)));
+    }
+}
+
+#[test]
+fn pan_spread_out_of_range_is_refused() {
+    let mut buffer = vec![0.0; 48_000];
+    let mut granular = Granular::new(&mut buffer, 48_000.0).unwrap();
+    for pan_spread in [-0.1, 1.5] {
+        let result = granular.set_params(GranularParams {
+            pan_spread,
+            ..GranularParams::default()
+        });
+        assert!(matches!(result, Err(GranularError::PanOutOfRange)));
+    }
+    granular
+        .set_params(GranularParams {
+            size_spread: 1.0,
+            pan_spread: 1.0,
+            ..GranularParams::default()
+        })
+        .unwrap();
+}
+
+#[test]
+fn grains_pitched_down_from_the_oldest_audio_stay_in_the_buffer() {
+    let mut buffer = vec![0.0; 48_000];
+    let mut granular = Granular::new(&mut buffer, 48_000.0).unwrap();
+    granular
+        .set_params(GranularParams {
+            position: 1.0,
+            position_spread: 0.0,
+            size_ms: 200.0,
+            size_spread: 0.0,
+            pitch: -24.0,
+            pan_spread: 0.0,
+            mix: 1.0,
+            ..GranularParams::default()
+        })
+        .unwrap();
+    // A full buffer of silence, then a loud input that only the grains
+    // reading audio written after they started could pick up
+    for _ in 0..48_000 {
+        granular.tick((0.0, 0.0));
+    }
+    for _ in 0..9_600 {
+        let (left, right) = granular.tick((1.0, 1.0));
+        assert_eq!((left, right), (0.0, 0.0));
+    }
+}
+
+/// 100 ms grains two octaves up at the limit of the buffer, 4800 samples each
+fn largest_grains(buffer_length: usize) -> Result<(), GranularError> {
+    let mut buffer = vec![0.0; buffer_length];
+    let mut granular = Granular::new(&mut buffer, 48_000.0).unwrap();
+    granular.set_params(GranularParams {
+        size_ms: 100.0,
+        size_spread: 0.0,
+        pitch: 24.0,
+        pitch_spread: 0.0,
+        density: 200.0,
+        ..GranularParams::default()
+    })?;
+    let mut audio = [(0.5, -0.5); 32];
+    for _ in 0..3_000 {
+        granular.process(&mut audio);
+    }
+    Ok(())
+}
+
+#[test]
+fn grains_fill_the_buffer_exactly() {
+    largest_grains(4800 * 4 + 4).unwrap();
+    assert!(matches!(
+        largest_grains(4800 * 4 + 3),
+        Err(GranularError::SizeOutOfRange)
+    ));
+}
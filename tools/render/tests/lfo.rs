@@ -0,0 +1,133 @@
+// LFO
+//
+// Values of the waveforms over a cycle, the rate and the random waveforms
+// changing once per cycle.
+use render::lfo::{Lfo, LfoError, LfoParams, LfoWaveform};
+
+const SAMPLE_RATE: f32 = 1000.0;
+
+fn lfo(waveform: LfoWaveform, frequency: f32) -> Lfo {
+    let mut lfo = Lfo::new(SAMPLE_RATE, 0x1234_5678);
+    lfo.set_params(LfoParams {
+        waveform,
+        frequency,
+        phase: 0.0,
+    })
+    .unwrap();
+    lfo.reset();
+    lfo
+}
+
+/// Values at the start of every quarter of a 1 Hz cycle
+fn quarters(waveform: LfoWaveform) -> [f32; 4] {
+    let mut lfo = lfo(waveform, 1.0);
+    let start = lfo.value();
+    [start, lfo.advance(250), lfo.advance(250), lfo.advance(250)]
+}
+
+fn assert_close(values: [f32; 4], expected: [f32; 4]) {
+    for (value, expected) in values.iter().zip(expected) {
+        assert!(
+            (value - expected).abs() < 1.0e-3,
+            "{values:?} != {expected:?}"
+        );
+    }
+}
+
+#[test]
+fn waveforms_have_their_shape() {
+    assert_close(quarters(LfoWaveform::Sine), [0.0, 1.0, 0.0, -1.0]);
+    assert_close(quarters(LfoWaveform::Triangle), [0.0, 1.0, 0.0, -1.0]);
+    assert_close(quarters(LfoWaveform::Saw), [-1.0, -0.5, 0.0, 0.5]);
+    assert_close(quarters(LfoWaveform::Square), [1.0, 1.0, -1.0, -1.0]);
+}
+
+#[test]
+fn rate_is_the_frequency() {
+    for frequency in [0.1, 1.0, 7.5, 20.0] {
+        let mut lfo = lfo(LfoWaveform::Saw, frequency);
+        // The saw falls once per cycle
+        let mut cycles = 0;
+        let mut last = lfo.value();
+        for _ in 0..20_000 {
+            let value = lfo.tick();
+            if value < last {
+                cycles += 1;
+            }
+            last = value;
+        }
+        let expected = 20.0 * frequency;
+        assert!(
+            (cycles as f32 - expected).abs() <= 1.0,
+            "{frequency} Hz: {cycles} cycles"
+        );
+    }
+}
+
+#[test]
+fn random_waveforms_change_once_per_cycle() {
+    let mut sample_and_hold = lfo(LfoWaveform::SampleAndHold, 10.0);
+    let mut smooth = lfo(LfoWaveform::SmoothRandom, 10.0);
+    let mut steps = 0;
+    let mut last = sample_and_hold.value();
+    let mut last_smooth = smooth.value();
+    // A bit more than 10 cycles, the phase rounds either way at their end
+    for _ in 0..1050 {
+        let value = sample_and_hold.tick();
+        assert!((-1.0..=1.0).contains(&value));
+        if value != last {
+            steps += 1;
+        }
+        last = value;
+
+        // Ramps from one random value to the next, without jumps
+        let value = smooth.tick();
+        assert!((-1.0..=1.0).contains(&value));
+        assert!((value - last_smooth).abs() <= 2.0 * 10.0 / SAMPLE_RATE + 1.0e-6);
+        last_smooth = value;
+    }
+    assert_eq!(steps, 10);
+}
+
+#[test]
+fn trigger_picks_a_new_value_and_restarts_the_cycle() {
+    let mut lfo = lfo(LfoWaveform::SampleAndHold, 1.0);
+    lfo.advance(300);
+    let held = lfo.value();
+    lfo.trigger();
+    assert_ne!(lfo.value(), held);
+    // The next value comes a whole cycle after the trigger
+    let triggered = lfo.value();
+    assert_eq!(lfo.advance(999), triggered);
+    assert_ne!(lfo.advance(1), triggered);
+}
+
+#[test]
+fn reset_starts_at_the_phase() {
+    let mut lfo = lfo(LfoWaveform::Saw, 1.0);
+    lfo.set_params(LfoParams {
+        waveform: LfoWaveform::Saw,
+        frequency: 1.0,
+        phase: 0.75,
+    })
+    .unwrap();
+    lfo.advance(100);
+    lfo.reset();
+    assert!((lfo.value() - 0.5).abs() < 1.0e-6);
+}
+
+#[test]
+fn frequencies_out_of_range_are_refused() {
+    let mut lfo = Lfo::new(SAMPLE_RATE, 1);
+    let result = lfo.set_params(LfoParams {
+        frequency: -1.0,
+        ..LfoParams::default()
+    });
+    assert!(matches!(result, Err(LfoError::FrequencyNegative)));
+    let result = lfo.set_params(LfoParams {
+        frequency: 600.0,
+        ..LfoParams::default()
+    });
+    assert!(matches!(result, Err(LfoError::FrequencyOverNyquist)));
+    assert_eq!(lfo.params().frequency, 1.0);
+}
@@ -0,0 +1,205 @@
+// Looper
+//
+// Recording, overdubs, undo and feedback through `process`, as the firmware
+// drives the looper block by block.
+use render::looper::{Looper, LooperError, LooperState};
+
+/// Makes the fades 10 frames long
+const SAMPLE_RATE: f32 = 1000.0;
+const FADE: usize = 10;
+const LOOP: usize = 200;
+const BLOCK_LENGTH: usize = 32;
+
+/// A different value for every frame of the loop
+fn signal(n: usize) -> (f32, f32) {
+    let value = (n % LOOP) as f32 / LOOP as f32 + 0.1;
+    (value, -value)
+}
+
+/// Processes `count` frames of `input`, numbered from `start`, and returns
+/// what the loop added to them
+fn run(
+    looper: &mut Looper,
+    start: usize,
+    count: usize,
+    input: impl Fn(usize) -> (f32, f32),
+) -> Vec<(f32, f32)> {
+    let mut added = Vec::new();
+    for block_start in (start..start + count).step_by(BLOCK_LENGTH) {
+        let block_end = (block_start + BLOCK_LENGTH).min(start + count);
+        let mut block: Vec<(f32, f32)> = (block_start..block_end).map(&input).collect();
+        looper.process(&mut block);
+        for (n, output) in (block_start..block_end).zip(block) {
+            let input = input(n);
+            added.push((output.0 - input.0, output.1 - input.1));
+        }
+    }
+    added
+}
+
+fn silence(_: usize) -> (f32, f32) {
+    (0.0, 0.0)
+}
+
+fn assert_close(actual: (f32, f32), expected: (f32, f32), what: &str) {
+    assert!(
+        (actual.0 - expected.0).abs() < 1e-5 && (actual.1 - expected.1).abs() < 1e-5,
+        "{what}: {actual:?} != {expected:?}"
+    );
+}
+
+/// A looper that has recorded `signal` into a loop of `LOOP` frames and
+/// played it twice, so the playhead is back at the start
+fn recorded() -> Looper<'static> {
+    // Leaked to keep the tests short, they are tiny
+    let buffer = Vec::leak(vec![(0.0, 0.0); 1000]);
+    let undo = Vec::leak(vec![(0.0, 0.0); 1000]);
+    let mut looper = Looper::new(buffer, undo, SAMPLE_RATE).unwrap();
+    looper.record().unwrap();
+    let through = run(&mut looper, 0, LOOP, signal);
+    assert!(through.iter().all(|added| *added == (0.0, 0.0)));
+    looper.play().unwrap();
+    // The input goes on like the loop, so the crossfade at the loop start
+    // doesn't change it
+    run(&mut looper, LOOP, 2 * LOOP, signal);
+    assert_eq!(looper.position(), 0);
+    looper
+}
+
+#[test]
+fn recording_plays_back() {
+    let mut looper = recorded();
+    assert_eq!(looper.state(), LooperState::Playing);
+    assert_eq!(looper.length(), LOOP);
+
+    let played = run(&mut looper, 0, 2 * LOOP, silence);
+    for (n, added) in played.into_iter().enumerate() {
+        assert_close(added, signal(n), &format!("frame {n}"));
+    }
+
+    // Stopping fades out, playing again starts from the loop start
+    looper.stop();
+    let stopped = run(&mut looper, 0, 100, silence);
+    assert!(stopped[FADE..].iter().all(|added| *added == (0.0, 0.0)));
+    looper.play().unwrap();
+    let played = run(&mut looper, 0, LOOP, silence);
+    for (n, added) in played.into_iter().enumerate().skip(FADE) {
+        assert_close(added, signal(n), &format!("frame {n} after stopping"));
+    }
+}
+
+#[test]
+fn overdubs_layer_and_undo_restores() {
+    let mut looper = recorded();
+    let layer = |_| (0.25, 0.5);
+
+    looper.overdub().unwrap();
+    assert_eq!(looper.state(), LooperState::Overdubbing);
+    run(&mut looper, 0, LOOP, layer);
+    looper.play().unwrap();
+    assert!(looper.can_undo());
+
+    // The punch in ramps over the first frames, the punch out records
+    // silence
+    let played = run(&mut looper, 0, LOOP, silence);
+    for (n, added) in played.into_iter().enumerate().skip(FADE) {
+        let expected = (signal(n).0 + 0.25, signal(n).1 + 0.5);
+        assert_close(added, expected, &format!("frame {n} with the overdub"));
+    }
+
+    // Restored by the next `process`, while playing
+    looper.undo().unwrap();
+    assert!(!looper.can_undo());
+    let played = run(&mut looper, 0, LOOP, silence);
+    for (n, added) in played.into_iter().enumerate() {
+        assert_close(added, signal(n), &format!("frame {n} after undo"));
+    }
+    assert_eq!(looper.undo(), Err(LooperError::UndoUnavailable));
+}
+
+#[test]
+fn undo_while_overdubbing_restores_the_loop() {
+    let mut looper = recorded();
+    // Over frames 50..100 only
+    run(&mut looper, 0, 50, silence);
+    looper.overdub().unwrap();
+    run(&mut looper, 50, 50, |_| (1.0, 1.0));
+
+    // Stops recording the layer and removes it
+    looper.undo().unwrap();
+    assert_eq!(looper.state(), LooperState::Playing);
+    assert_eq!(looper.overdub(), Err(LooperError::UndoInProgress));
+    let played = run(&mut looper, 100, LOOP, silence);
+    for (n, added) in (100..).zip(played) {
+        assert_close(added, signal(n), &format!("frame {n} after undo"));
+    }
+}
+
+#[test]
+fn feedback_fades_the_loop_while_overdubbing() {
+    let mut looper = recorded();
+    assert_eq!(
+        looper.set_feedback(1.5),
+        Err(LooperError::FeedbackOutOfRange)
+    );
+    looper.set_feedback(0.5).unwrap();
+
+    // Two passes of silence halve the loop twice
+    looper.overdub().unwrap();
+    run(&mut looper, 0, 2 * LOOP, silence);
+    looper.play().unwrap();
+    let played = run(&mut looper, 0, LOOP, silence);
+    for (n, added) in played.into_iter().enumerate().skip(FADE) {
+        let expected = (0.25 * signal(n).0, 0.25 * signal(n).1);
+        assert_close(added, expected, &format!("frame {n}"));
+    }
+
+    // Undo brings back the loop from before the layer
+    looper.undo().unwrap();
+    let played = run(&mut looper, 0, LOOP, silence);
+    for (n, added) in played.into_iter().enumerate() {
+        assert_close(added, signal(n), &format!("frame {n} after undo"));
+    }
+}
+
+#[test]
+fn full_buffer_closes_the_loop() {
+    const CAPACITY: usize = 100;
+    let mut buffer = [(0.0, 0.0); CAPACITY];
+    let mut undo = [(0.0, 0.0); CAPACITY];
+    let mut looper = Looper::new(&mut buffer, &mut undo, SAMPLE_RATE).unwrap();
+    assert_eq!(looper.capacity(), CAPACITY);
+    looper.record().unwrap();
+
+    let ramp = |n: usize| ((n % CAPACITY) as f32, 0.0);
+    let added = run(&mut looper, 0, CAPACITY + 50, ramp);
+    assert_eq!(looper.state(), LooperState::Playing);
+    assert_eq!(looper.length(), CAPACITY);
+    assert!(added[..CAPACITY].iter().all(|added| *added == (0.0, 0.0)));
+    // Playback starts right away, the continued input crossfades into the
+    // loop start unchanged
+    for (n, added) in added.into_iter().enumerate().skip(CAPACITY) {
+        assert_close(added, ramp(n), &format!("frame {n}"));
+    }
+
+    assert_eq!(looper.record(), Err(LooperError::AlreadyRecorded));
+    looper.clear();
+    assert_eq!(looper.state(), LooperState::Empty);
+    assert_eq!(looper.play(), Err(LooperError::NothingRecorded));
+}
+
+#[test]
+fn buffers_are_checked() {
+    let mut buffer = [(0.0, 0.0); 100];
+    let mut undo = [(0.0, 0.0); 99];
+    assert!(matches!(
+        Looper::new(&mut buffer, &mut undo, SAMPLE_RATE),
+        Err(LooperError::BufferSizeMismatch)
+    ));
+    let mut buffer = [(0.0, 0.0); 4 * FADE - 1];
+    let mut undo = [(0.0, 0.0); 4 * FADE - 1];
+    assert!(matches!(
+        Looper::new(&mut buffer, &mut undo, SAMPLE_RATE),
+        Err(LooperError::BufferTooShort)
+    ));
+}
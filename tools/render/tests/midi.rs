@@ -0,0 +1,377 @@
+// MIDI
+//
+// Feeds recorded byte streams into the parser, including running status,
+// interleaved realtime bytes and malformed input.
+use render::midi::{KnobCc, MAX_SYSEX, MidiEncoder, MidiError, MidiMessage, MidiParser};
+
+fn parse(bytes: &[u8]) -> Vec<Result<MidiMessage, MidiError>> {
+    let mut parser = MidiParser::new();
+    let mut results = Vec::new();
+    parser.parse_slice(bytes, |result| results.push(result));
+    results
+}
+
+#[test]
+fn channel_messages() {
+    let results = parse(&[
+        0x90, 60, 100, // Note on
+        0x81, 60, 64, // Note off on channel 2
+        0xA2, 61, 10, // Poly pressure
+        0xB3, 7, 127, // Control change
+        0xC4, 5, // Program change
+        0xD5, 80, // Channel pressure
+        0xE6, 0x00, 0x40, // Pitch bend center
+        0xEF, 0x7F, 0x7F, // Pitch bend up
+        0xE0, 0x00, 0x00, // Pitch bend down
+    ]);
+    assert_eq!(
+        results,
+        [
+            Ok(MidiMessage::NoteOn {
+                channel: 0,
+                note: 60,
+                velocity: 100
+            }),
+            Ok(MidiMessage::NoteOff {
+                channel: 1,
+                note: 60,
+                velocity: 64
+            }),
+            Ok(MidiMessage::PolyPressure {
+                channel: 2,
+                note: 61,
+                pressure: 10
+            }),
+            Ok(MidiMessage::ControlChange {
+                channel: 3,
+                controller: 7,
+                value: 127
+            }),
+            Ok(MidiMessage::ProgramChange {
+                channel: 4,
+                program: 5
+            }),
+            Ok(MidiMessage::ChannelPressure {
+                channel: 5,
+                pressure: 80
+            }),
+            Ok(MidiMessage::PitchBend {
+                channel: 6,
+                value: 0
+            }),
+            Ok(MidiMessage::PitchBend {
+                channel: 15,
+                value: 8191
+            }),
+            Ok(MidiMessage::PitchBend {
+                channel: 0,
+                value: -8192
+            }),
+        ]
+    );
+}
+
+#[test]
+fn running_status_and_note_on_with_velocity_zero() {
+    // A chord played and released the way most keyboards send it
+    let results = parse(&[0x92, 60, 90, 64, 80, 67, 70, 60, 0, 64, 0, 67, 0]);
+    assert_eq!(
+        results,
+        [
+            Ok(MidiMessage::NoteOn {
+                channel: 2,
+                note: 60,
+                velocity: 90
+            }),
+            Ok(MidiMessage::NoteOn {
+                channel: 2,
+                note: 64,
+                velocity: 80
+            }),
+            Ok(MidiMessage::NoteOn {
+                channel: 2,
+                note: 67,
+                velocity: 70
+            }),
+            Ok(MidiMessage::NoteOff {
+                channel: 2,
+                note: 60,
+                velocity: 0
+            }),
+            Ok(MidiMessage::NoteOff {
+                channel: 2,
+                note: 64,
+                velocity: 0
+            }),
+            Ok(MidiMessage::NoteOff {
+                channel: 2,
+                note: 67,
+                velocity: 0
+            }),
+        ]
+    );
+}
+
+#[test]
+fn realtime_bytes_in_the_middle_of_messages() {
+    let results = parse(&[
+        0xFA, // Start
+        0xB0, 0xF8, 74, 0xF8, 20, // Clock within a control change
+        30, 0xFE, 40,   // Running status around active sensing
+        0xFC, // Stop
+    ]);
+    assert_eq!(
+        results,
+        [
+            Ok(MidiMessage::Start),
+            Ok(MidiMessage::Clock),
+            Ok(MidiMessage::Clock),
+            Ok(MidiMessage::ControlChange {
+                channel: 0,
+                controller: 74,
+                value: 20
+            }),
+            Ok(MidiMessage::ActiveSensing),
+            Ok(MidiMessage::ControlChange {
+                channel: 0,
+                controller: 30,
+                value: 40
+            }),
+            Ok(MidiMessage::Stop),
+        ]
+    );
+}
+
+#[test]
+fn system_common_messages_cancel_running_status() {
+    let results = parse(&[
+        0x90, 60, 100, // Note on
+        0xF2, 0x10, 0x02, // Song position 0x110
+        0xF3, 3, // Song select
+        0xF1, 0x25, // Time code quarter frame
+        0xF6, // Tune request
+        61, 100, // Data without status
+    ]);
+    assert_eq!(
+        results,
+        [
+            Ok(MidiMessage::NoteOn {
+                channel: 0,
+                note: 60,
+                velocity: 100
+            }),
+            Ok(MidiMessage::SongPosition(0x110)),
+            Ok(MidiMessage::SongSelect(3)),
+            Ok(MidiMessage::TimeCode(0x25)),
+            Ok(MidiMessage::TuneRequest),
+            Err(MidiError::UnexpectedByte(61)),
+            Err(MidiError::UnexpectedByte(100)),
+        ]
+    );
+}
+
+#[test]
+fn sysex() {
+    let mut parser = MidiParser::new();
+    let mut results = Vec::new();
+    // Identity reply with a clock in between
+    parser.parse_slice(
+        &[0xF0, 0x7E, 0x10, 0x06, 0xF8, 0x02, 0x41, 0xF7],
+        |result| results.push(result),
+    );
+    assert_eq!(
+        results,
+        [Ok(MidiMessage::Clock), Ok(MidiMessage::SysEx { length: 5 })]
+    );
+    assert_eq!(parser.sysex(), [0x7E, 0x10, 0x06, 0x02, 0x41]);
+
+    // Too long messages are dropped, the parser goes on afterwards
+    let mut long = vec![0xF0];
+    long.extend(std::iter::repeat_n(0x01, MAX_SYSEX + 1));
+    long.extend([0xF7, 0x90, 60, 100]);
+    results.clear();
+    parser.parse_slice(&long, |result| results.push(result));
+    assert_eq!(
+        results,
+        [
+            Err(MidiError::SysExOverflow),
+            Ok(MidiMessage::NoteOn {
+                channel: 0,
+                note: 60,
+                velocity: 100
+            }),
+        ]
+    );
+    assert!(parser.sysex().is_empty());
+}
+
+#[test]
+fn malformed_input() {
+    let results = parse(&[
+        0x40, // Data before any status
+        0x90, 60, // Note on cut short
+        0xB0, 1, 2, // by a control change
+        0xF0, 1, 2, // Sysex ended
+        0x80, 60, 0,    // by a note off instead of 0xF7
+        0xF7, // End of sysex without a start
+        0xF4, 0xF5, 0xF9, 0xFD, // Undefined
+        0xC0, 9, // Still in sync
+    ]);
+    assert_eq!(
+        results,
+        [
+            Err(MidiError::UnexpectedByte(0x40)),
+            Err(MidiError::IncompleteMessage),
+            Ok(MidiMessage::ControlChange {
+                channel: 0,
+                controller: 1,
+                value: 2
+            }),
+            Err(MidiError::UnterminatedSysEx),
+            Ok(MidiMessage::NoteOff {
+                channel: 0,
+                note: 60,
+                velocity: 0
+            }),
+            Err(MidiError::UnexpectedByte(0xF7)),
+            Err(MidiError::UndefinedStatus(0xF4)),
+            Err(MidiError::UndefinedStatus(0xF5)),
+            Err(MidiError::UndefinedStatus(0xF9)),
+            Err(MidiError::UndefinedStatus(0xFD)),
+            Ok(MidiMessage::ProgramChange {
+                channel: 0,
+                program: 9
+            }),
+        ]
+    );
+}
+
+#[test]
+fn reset_clears_running_status() {
+    let results = parse(&[0x90, 60, 100, 0xFF, 61, 100]);
+    assert_eq!(
+        results,
+        [
+            Ok(MidiMessage::NoteOn {
+                channel: 0,
+                note: 60,
+                velocity: 100
+            }),
+            Ok(MidiMessage::Reset),
+            Err(MidiError::UnexpectedByte(61)),
+            Err(MidiError::UnexpectedByte(100)),
+        ]
+    );
+}
+
+#[test]
+fn encoded_messages_parse_back() {
+    let messages = [
+        MidiMessage::NoteOn {
+            channel: 3,
+            note: 60,
+            velocity: 100,
+        },
+        MidiMessage::NoteOn {
+            channel: 3,
+            note: 64,
+            velocity: 90,
+        },
+        MidiMessage::Clock,
+        MidiMessage::NoteOff {
+            channel: 3,
+            note: 60,
+            velocity: 0,
+        },
+        MidiMessage::ControlChange {
+            channel: 15,
+            controller: 74,
+            value: 127,
+        },
+        MidiMessage::ProgramChange {
+            channel: 0,
+            program: 12,
+        },
+        MidiMessage::ChannelPressure {
+            channel: 1,
+            pressure: 30,
+        },
+        MidiMessage::PolyPressure {
+            channel: 2,
+            note: 61,
+            pressure: 5,
+        },
+        MidiMessage::PitchBend {
+            channel: 4,
+            value: -8192,
+        },
+        MidiMessage::PitchBend {
+            channel: 4,
+            value: 8191,
+        },
+        MidiMessage::SongPosition(0x3FFF),
+        MidiMessage::SongSelect(2),
+        MidiMessage::TimeCode(0x31),
+        MidiMessage::TuneRequest,
+        MidiMessage::Start,
+        MidiMessage::Continue,
+        MidiMessage::Stop,
+        MidiMessage::ActiveSensing,
+    ];
+    let mut encoder = MidiEncoder::new(true);
+    let bytes: Vec<u8> = messages
+        .iter()
+        .flat_map(|message| encoder.encode(*message))
+        .collect();
+    let results = parse(&bytes);
+    let expected: Vec<_> = messages.iter().map(|message| Ok(*message)).collect();
+    assert_eq!(results, expected);
+}
+
+#[test]
+fn running_status_leaves_out_repeated_status_bytes() {
+    let mut encoder = MidiEncoder::new(true);
+    let note = |note| MidiMessage::NoteOn {
+        channel: 0,
+        note,
+        velocity: 100,
+    };
+    assert_eq!(encoder.encode(note(60)), [0x90, 60, 100]);
+    assert_eq!(encoder.encode(note(64)), [64, 100]);
+    // Realtime bytes don't interrupt it, other statuses do
+    assert_eq!(encoder.encode(MidiMessage::Clock), [0xF8]);
+    assert_eq!(encoder.encode(note(67)), [67, 100]);
+    assert_eq!(encoder.encode(MidiMessage::TuneRequest), [0xF6]);
+    assert_eq!(encoder.encode(note(72)), [0x90, 72, 100]);
+
+    let mut encoder = MidiEncoder::new(false);
+    assert_eq!(encoder.encode(note(60)), [0x90, 60, 100]);
+    assert_eq!(encoder.encode(note(64)), [0x90, 64, 100]);
+    // Out of range values don't spill into status bytes
+    let wild = MidiMessage::ControlChange {
+        channel: 17,
+        controller: 200,
+        value: 128,
+    };
+    assert_eq!(encoder.encode(wild), [0xB1, 72, 0]);
+}
+
+#[test]
+fn knobs_send_control_changes_when_they_move() {
+    let mut knob = KnobCc::new(0, 74);
+    let cc = |value| {
+        Some(MidiMessage::ControlChange {
+            channel: 0,
+            controller: 74,
+            value,
+        })
+    };
+    assert_eq!(knob.update(0.5), cc(64));
+    // Noise around a step boundary stays quiet
+    for value in [0.503, 0.499, 0.505, 0.4985] {
+        assert_eq!(knob.update(value), None);
+    }
+    assert_eq!(knob.update(0.51), cc(65));
+    assert_eq!(knob.update(1.0), cc(127));
+    assert_eq!(knob.update(0.0), cc(0));
+}
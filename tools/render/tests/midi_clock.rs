@@ -0,0 +1,101 @@
+// MIDI Clock
+//
+// Runs the clock in audio blocks and checks that the pulses land on the
+// exact sample and don't drift.
+use render::midi::MidiMessage;
+use render::midi_clock::{MidiClock, MidiClockError};
+
+const SAMPLE_RATE: f32 = 48_000.0;
+const BLOCK_LENGTH: usize = 32;
+
+/// Messages of `blocks` with their time since `start`
+fn run(clock: &mut MidiClock, start: usize, blocks: usize) -> Vec<(usize, MidiMessage)> {
+    (0..blocks)
+        .flat_map(|block| {
+            clock
+                .process(BLOCK_LENGTH)
+                .into_iter()
+                .map(move |(offset, message)| (start + block * BLOCK_LENGTH + offset, message))
+        })
+        .collect()
+}
+
+fn pulses(messages: &[(usize, MidiMessage)]) -> Vec<usize> {
+    messages
+        .iter()
+        .filter(|(_, message)| *message == MidiMessage::Clock)
+        .map(|(time, _)| *time)
+        .collect()
+}
+
+#[test]
+fn pulses_follow_the_tempo_without_drift() {
+    let mut clock = MidiClock::new(SAMPLE_RATE);
+    // 125 BPM is 960 samples per pulse exactly
+    clock.set_tempo(125.0).unwrap();
+    assert!(run(&mut clock, 0, 100).is_empty());
+    clock.start();
+
+    let messages = run(&mut clock, 0, 30 * 1500);
+    assert_eq!(messages[0], (0, MidiMessage::Start));
+    let times = pulses(&messages);
+    assert_eq!(times.len(), 1500);
+    assert!(
+        times
+            .iter()
+            .enumerate()
+            .all(|(pulse, time)| *time == 960 * pulse)
+    );
+
+    // 123 BPM has 975.6 samples per pulse, after ten minutes the pulses are
+    // still within half a sample of where they belong
+    let mut clock = MidiClock::new(SAMPLE_RATE);
+    clock.set_tempo(123.0).unwrap();
+    clock.start();
+    let ten_minutes = 600 * SAMPLE_RATE as usize / BLOCK_LENGTH;
+    let times = pulses(&run(&mut clock, 0, ten_minutes));
+    assert_eq!(times.len(), 123 * 10 * 24);
+    let spacing = SAMPLE_RATE as f64 * 60.0 / (123.0 * 24.0);
+    for (pulse, time) in times.iter().enumerate() {
+        assert!((*time as f64 - pulse as f64 * spacing).abs() <= 0.5);
+    }
+}
+
+#[test]
+fn start_stop_and_continue() {
+    let mut clock = MidiClock::new(SAMPLE_RATE);
+    clock.set_tempo(125.0).unwrap();
+    clock.start();
+    let messages = run(&mut clock, 0, 60);
+    assert_eq!(pulses(&messages), [0, 960]);
+
+    clock.stop();
+    let messages = run(&mut clock, 1920, 60);
+    assert_eq!(messages, [(1920, MidiMessage::Stop)]);
+    assert!(!clock.is_running());
+
+    // Continues with a pulse right away
+    clock.resume();
+    let messages = run(&mut clock, 3840, 30);
+    assert_eq!(
+        messages,
+        [(3840, MidiMessage::Continue), (3840, MidiMessage::Clock)]
+    );
+}
+
+#[test]
+fn tempo_changes_take_effect_with_the_next_pulse() {
+    let mut clock = MidiClock::new(SAMPLE_RATE);
+    clock.set_tempo(125.0).unwrap();
+    clock.start();
+    // The pulse at 960 is scheduled, at 250 BPM they are 480 apart
+    let first = pulses(&run(&mut clock, 0, 10));
+    assert_eq!(first, [0]);
+    clock.set_tempo(250.0).unwrap();
+    assert_eq!(clock.tempo(), 250.0);
+    let times = pulses(&run(&mut clock, 320, 50));
+    assert_eq!(times, [480, 960, 1440]);
+
+    assert_eq!(clock.set_tempo(301.0), Err(MidiClockError::TempoOutOfRange));
+    assert_eq!(clock.set_tempo(19.0), Err(MidiClockError::TempoOutOfRange));
+}
@@ -0,0 +1,187 @@
+// MIDI Map
+//
+// Controller mappings, MIDI learn, storing the mappings and soft takeover.
+use render::midi::MidiMessage;
+use render::midi_map::{Controller, Mapping, MidiMap, MidiMapError, SoftTakeover};
+use render::modulation::Destination;
+
+fn cc(channel: u8, controller: u8, value: u8) -> MidiMessage {
+    MidiMessage::ControlChange {
+        channel,
+        controller,
+        value,
+    }
+}
+
+#[test]
+fn control_changes_move_their_parameters() {
+    let mut map = MidiMap::new();
+    map.set_mapping(Mapping {
+        channel: 0,
+        controller: Controller::Cc(74),
+        destination: Destination::FilterFrequency,
+    })
+    .unwrap();
+
+    assert_eq!(
+        map.handle(cc(0, 74, 127)),
+        Some((Destination::FilterFrequency, 1.0))
+    );
+    assert_eq!(
+        map.handle(cc(0, 74, 0)),
+        Some((Destination::FilterFrequency, 0.0))
+    );
+    // Other channels, controllers and messages
+    assert_eq!(map.handle(cc(1, 74, 64)), None);
+    assert_eq!(map.handle(cc(0, 71, 64)), None);
+    let note = MidiMessage::NoteOn {
+        channel: 0,
+        note: 74,
+        velocity: 100,
+    };
+    assert_eq!(map.handle(note), None);
+}
+
+#[test]
+fn nrpns_have_14_bits() {
+    let mut map = MidiMap::new();
+    map.set_mapping(Mapping {
+        channel: 2,
+        controller: Controller::Nrpn(0x0123),
+        destination: Destination::FilterQuality,
+    })
+    .unwrap();
+
+    // Select 0x0123 (MSB 2, LSB 0x23), then coarse and fine data entry
+    assert_eq!(map.handle(cc(2, 99, 2)), None);
+    assert_eq!(map.handle(cc(2, 98, 0x23)), None);
+    let (destination, coarse) = map.handle(cc(2, 6, 64)).unwrap();
+    assert_eq!(destination, Destination::FilterQuality);
+    assert_eq!(coarse, 8192.0 / 16_383.0);
+    let (_, fine) = map.handle(cc(2, 38, 1)).unwrap();
+    assert_eq!(fine, 8193.0 / 16_383.0);
+    assert_eq!(map.handle(cc(2, 6, 127)).unwrap().1, 16_256.0 / 16_383.0);
+
+    // Data entry for an RPN, e.g. the pitch bend range, isn't ours
+    map.handle(cc(2, 101, 0));
+    map.handle(cc(2, 100, 0));
+    assert_eq!(map.handle(cc(2, 6, 12)), None);
+}
+
+#[test]
+fn learn_maps_the_next_controller() {
+    let mut map = MidiMap::new();
+    map.set_mapping(Mapping {
+        channel: 0,
+        controller: Controller::Cc(1),
+        destination: Destination::FilterFrequency,
+    })
+    .unwrap();
+
+    map.learn(Destination::FilterFrequency).unwrap();
+    // Neither notes nor the NRPN selection count
+    map.handle(MidiMessage::NoteOn {
+        channel: 0,
+        note: 60,
+        velocity: 100,
+    });
+    map.handle(cc(3, 99, 0));
+    assert_eq!(map.learning(), Some(Destination::FilterFrequency));
+
+    assert_eq!(
+        map.handle(cc(3, 20, 32)),
+        Some((Destination::FilterFrequency, 32.0 / 127.0))
+    );
+    assert_eq!(map.learning(), None);
+    assert_eq!(
+        map.mappings(),
+        [Mapping {
+            channel: 3,
+            controller: Controller::Cc(20),
+            destination: Destination::FilterFrequency,
+        }]
+    );
+
+    // NRPNs are learned on their data entry
+    map.learn(Destination::FilterGain).unwrap();
+    map.handle(cc(3, 98, 5));
+    assert_eq!(
+        map.handle(cc(3, 6, 0)),
+        Some((Destination::FilterGain, 0.0))
+    );
+    assert_eq!(
+        map.mappings()[1].controller,
+        Controller::Nrpn(5),
+        "{:?}",
+        map.mappings()
+    );
+}
+
+#[test]
+fn mappings_are_stored_and_restored() {
+    let mut map = MidiMap::new();
+    let mappings = [
+        Mapping {
+            channel: 15,
+            controller: Controller::Cc(119),
+            destination: Destination::FilterGain,
+        },
+        Mapping {
+            channel: 0,
+            controller: Controller::Nrpn(16_383),
+            destination: Destination::Custom(7),
+        },
+    ];
+    for mapping in mappings {
+        map.set_mapping(mapping).unwrap();
+    }
+    let stored: Vec<[u8; Mapping::SIZE]> = map.mappings().iter().map(Mapping::to_bytes).collect();
+
+    let mut restored = MidiMap::new();
+    let loaded: Vec<Mapping> = stored
+        .iter()
+        .map(|bytes| Mapping::from_bytes(*bytes).unwrap())
+        .collect();
+    restored.restore(&loaded).unwrap();
+    assert_eq!(restored.mappings(), mappings);
+
+    // Invalid mappings are refused and leave the old ones alone
+    assert_eq!(
+        Mapping::from_bytes([0x20, 0, 1, 0]),
+        Err(MidiMapError::InvalidBytes)
+    );
+    assert_eq!(
+        Mapping::from_bytes([0x00, 0, 99, 0]),
+        Err(MidiMapError::ControllerOutOfRange)
+    );
+    let invalid = Mapping {
+        channel: 16,
+        ..mappings[0]
+    };
+    assert_eq!(
+        restored.restore(&[mappings[1], invalid]),
+        Err(MidiMapError::ChannelOutOfRange)
+    );
+    assert_eq!(restored.mappings(), mappings);
+}
+
+#[test]
+fn soft_takeover_waits_for_the_knob() {
+    let mut takeover = SoftTakeover::new();
+    assert_eq!(takeover.update(0.2), Some(0.2));
+
+    // Set over MIDI, the knob has to get there first
+    takeover.set_value(0.7);
+    assert!(!takeover.is_engaged());
+    assert_eq!(takeover.update(0.3), None);
+    assert_eq!(takeover.update(0.6), None);
+    assert_eq!(takeover.value(), 0.7);
+    // Passing the value takes over, even in one jump
+    assert_eq!(takeover.update(0.75), Some(0.75));
+    assert_eq!(takeover.update(0.5), Some(0.5));
+
+    // From above, coming close is enough
+    takeover.set_value(0.1);
+    assert_eq!(takeover.update(0.4), None);
+    assert_eq!(takeover.update(0.11), Some(0.11));
+}
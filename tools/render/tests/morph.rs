@@ -0,0 +1,119 @@
+// Morph
+//
+// Interpolating presets along the curves of their parameters.
+use render::filter::{FilterParams, FilterType};
+use render::modulation::Range;
+use render::morph::{Morph, MorphControl, MorphError, interpolate, step};
+use render::preset::Preset;
+use render::spectral::SpectralMode;
+
+fn a() -> Preset {
+    Preset {
+        filter_type: FilterType::Lowpass,
+        filter: FilterParams {
+            frequency: 100.0,
+            quality: 0.5,
+            gain: -12.0,
+        },
+        spectral_mode: SpectralMode::Off,
+    }
+}
+
+fn b() -> Preset {
+    Preset {
+        filter_type: FilterType::Bell,
+        filter: FilterParams {
+            frequency: 400.0,
+            quality: 4.5,
+            gain: 12.0,
+        },
+        spectral_mode: SpectralMode::Freeze,
+    }
+}
+
+fn assert_close(actual: f32, expected: f32) {
+    assert!(
+        (actual - expected).abs() <= expected.abs() * 1e-5,
+        "{actual} != {expected}"
+    );
+}
+
+#[test]
+fn parameters_follow_their_curves() {
+    // Frequencies in equal ratios, everything else in equal steps
+    let half = a().morph(&b(), 0.5, 0.5);
+    assert_close(half.filter.frequency, 200.0);
+    assert_close(half.filter.quality, 2.5);
+    assert_close(half.filter.gain, 0.0);
+    let quarter = a().morph(&b(), 0.25, 0.5);
+    assert_close(quarter.filter.frequency, 100.0 * 2.0f32.sqrt());
+    assert_close(quarter.filter.quality, 1.5);
+
+    // The ends are the presets themselves, amounts beyond them are clamped
+    assert_eq!(a().morph(&b(), 0.0, 0.5), a());
+    assert_eq!(a().morph(&b(), -1.0, 0.5), a());
+    let end = a().morph(&b(), 2.0, 0.5);
+    assert_close(end.filter.frequency, 400.0);
+    assert_eq!(end.filter_type, FilterType::Bell);
+
+    // Values outside of the range are left alone
+    assert_close(
+        interpolate(&Range::FILTER_FREQUENCY, 10.0, 40_000.0, 0.0),
+        10.0,
+    );
+    assert_close(interpolate(&Range::FILTER_GAIN, -30.0, 30.0, 1.0), 30.0);
+}
+
+#[test]
+fn stepped_parameters_switch_at_the_threshold() {
+    assert_eq!(step(1, 2, 0.49, 0.5), 1);
+    assert_eq!(step(1, 2, 0.5, 0.5), 2);
+
+    let before = a().morph(&b(), 0.2, 0.3);
+    assert_eq!(before.filter_type, FilterType::Lowpass);
+    assert_eq!(before.spectral_mode, SpectralMode::Off);
+    let after = a().morph(&b(), 0.3, 0.3);
+    assert_eq!(after.filter_type, FilterType::Bell);
+    assert_eq!(after.spectral_mode, SpectralMode::Freeze);
+}
+
+#[test]
+fn control_switches_with_hysteresis() {
+    let mut control = MorphControl::new();
+    assert_eq!(control.apply(&a(), &b()), a());
+
+    // A knob wobbling around the threshold switches once
+    for amount in [0.49, 0.51, 0.49, 0.51] {
+        control.set_amount(amount);
+        assert!(!control.is_switched(), "{amount}");
+    }
+    control.set_amount(0.53);
+    assert!(control.is_switched());
+    for amount in [0.49, 0.51, 0.49] {
+        control.set_amount(amount);
+        assert!(control.is_switched(), "{amount}");
+        assert_eq!(control.apply(&a(), &b()).filter_type, FilterType::Bell);
+    }
+    control.set_amount(0.47);
+    assert!(!control.is_switched());
+
+    // Continuous parameters follow the amount either way
+    assert_close(control.apply(&a(), &b()).filter.gain, 12.0 * -0.06);
+
+    // The ends always switch, even with the threshold right at them
+    control.set_threshold(1.0).unwrap();
+    control.set_amount(1.0);
+    assert_eq!(control.apply(&a(), &b()).filter_type, FilterType::Bell);
+    control.set_threshold(0.0).unwrap();
+    control.set_amount(0.0);
+    assert_eq!(control.apply(&a(), &b()), a().morph(&b(), 0.0, 0.0));
+
+    assert_eq!(
+        control.set_threshold(1.5),
+        Err(MorphError::ThresholdOutOfRange)
+    );
+    assert_eq!(
+        control.set_threshold(f32::NAN),
+        Err(MorphError::ThresholdOutOfRange)
+    );
+}
@@ -0,0 +1,138 @@
+// Noise
+//
+// Checks the spectral slope of the noise generators by averaging spectra
+// over several seconds and fitting a line to the power density per octave.
+use render::noise::{BrownNoise, PinkNoise, SampleAndHold, VelvetNoise, WhiteNoise};
+use render::spectrum::{SpectrumAnalyzer, Window};
+
+const SAMPLE_RATE: f32 = 48_000.0;
+const SIZE: usize = 4096;
+const FRAMES: usize = 128;
+
+/// Slope of the power density in dB per octave between `low` and `high`
+fn slope(mut generator: impl FnMut() -> f32, low: f32, high: f32) -> f32 {
+    let mut analyzer = SpectrumAnalyzer::<SIZE>::new(Window::Hann).unwrap();
+    let mut bands = Vec::new();
+    let mut frequency = low;
+    while frequency * 2.0 <= high {
+        bands.push((frequency, 0.0));
+        frequency *= 2.0;
+    }
+
+    let mut block = [0.0; SIZE];
+    for _ in 0..FRAMES {
+        block.iter_mut().for_each(|sample| *sample = generator());
+        analyzer.analyze(&block);
+        for (low, energy) in bands.iter_mut() {
+            *energy += analyzer.band_energy(*low, 2.0 * *low, SAMPLE_RATE);
+        }
+    }
+
+    // Least squares fit of the density in dB over the octave number
+    let points: Vec<(f32, f32)> = bands
+        .iter()
+        .enumerate()
+        .map(|(octave, (low, energy))| (octave as f32, 10.0 * (energy / low).log10()))
+        .collect();
+    let count = points.len() as f32;
+    let mean_x = points.iter().map(|(x, _)| x).sum::<f32>() / count;
+    let mean_y = points.iter().map(|(_, y)| y).sum::<f32>() / count;
+    let covariance: f32 = points
+        .iter()
+        .map(|(x, y)| (x - mean_x) * (y - mean_y))
+        .sum();
+    let variance: f32 = points.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+    covariance / variance
+}
+
+fn assert_slope(name: &str, measured: f32, expected: f32, tolerance: f32) {
+    assert!(
+        (measured - expected).abs() <= tolerance,
+        "{name} falls by {measured:.2} dB per octave instead of {expected:.2}"
+    );
+}
+
+#[test]
+fn white_noise_is_flat() {
+    let mut noise = WhiteNoise::new(1);
+    assert_slope(
+        "White noise",
+        slope(|| noise.tick(), 100.0, 20_000.0),
+        0.0,
+        0.3,
+    );
+}
+
+#[test]
+fn pink_noise_falls_by_3_db_per_octave() {
+    let mut noise = PinkNoise::new(2);
+    assert_slope(
+        "Pink noise",
+        slope(|| noise.tick(), 50.0, 20_000.0),
+        -3.0,
+        0.5,
+    );
+}
+
+#[test]
+fn brown_noise_falls_by_6_db_per_octave() {
+    // Above the corner of the leaky integrator
+    let mut noise = BrownNoise::new(3);
+    assert_slope(
+        "Brown noise",
+        slope(|| noise.tick(), 200.0, 12_800.0),
+        -6.0,
+        0.5,
+    );
+}
+
+#[test]
+fn velvet_noise_is_flat_with_the_right_density() {
+    let mut noise = VelvetNoise::new(SAMPLE_RATE, 4);
+    assert_slope(
+        "Velvet noise",
+        slope(|| noise.tick(), 100.0, 20_000.0),
+        0.0,
+        0.5,
+    );
+
+    noise.set_density(1500.0).unwrap();
+    let impulses = (0..SAMPLE_RATE as usize)
+        .filter(|_| noise.tick() != 0.0)
+        .count();
+    assert!((1499..=1501).contains(&impulses), "{impulses} impulses");
+}
+
+#[test]
+fn white_noise_stays_in_range() {
+    let mut noise = WhiteNoise::new(5);
+    for _ in 0..100_000 {
+        assert!((-1.0..1.0).contains(&noise.tick()));
+        assert!((-1.0..1.0).contains(&noise.triangular()));
+    }
+}
+
+#[test]
+fn sample_and_hold_steps_at_its_rate_and_glides() {
+    let mut random = SampleAndHold::new(SAMPLE_RATE, 6);
+    random.set_rate(10.0).unwrap();
+    let mut steps = 0;
+    let mut previous = random.value();
+    for _ in 0..SAMPLE_RATE as usize {
+        let value = random.tick();
+        assert!((-1.0..=1.0).contains(&value));
+        if value != previous {
+            steps += 1;
+        }
+        previous = value;
+    }
+    assert!((9..=11).contains(&steps), "{steps} steps");
+
+    // A glide moves on in every sample, also in blocks
+    random.set_slew(50.0);
+    random.trigger();
+    let start = random.value();
+    let halfway = random.advance(32);
+    let end = random.advance(SAMPLE_RATE as usize / 2);
+    assert!((halfway - start).abs() > 0.0 && (halfway - start).abs() < (end - start).abs());
+}
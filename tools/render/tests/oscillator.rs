@@ -0,0 +1,107 @@
+// Oscillator
+//
+// The fundamental of every waveform and the aliasing of the sawtooth and the
+// square, measured from their spectra.
+use render::oscillator::{Oscillator, OscillatorParams, Waveform};
+use render::spectrum::{SpectrumAnalyzer, Window};
+
+const SAMPLE_RATE: f32 = 48_000.0;
+const N: usize = 8192;
+const BIN_WIDTH: f32 = SAMPLE_RATE / N as f32;
+
+fn oscillator(waveform: Waveform, frequency: f32) -> Oscillator {
+    let mut oscillator = Oscillator::new();
+    oscillator.set_sample_rate(SAMPLE_RATE).unwrap();
+    oscillator
+        .set_params(OscillatorParams {
+            waveform,
+            frequency,
+            ..Default::default()
+        })
+        .unwrap();
+    oscillator
+}
+
+fn analyze(window: Window, mut tick: impl FnMut() -> f32) -> SpectrumAnalyzer<N> {
+    let mut samples = [0.0; N];
+    samples.iter_mut().for_each(|sample| *sample = tick());
+    let mut analyzer = SpectrumAnalyzer::new(window).unwrap();
+    analyzer.analyze(&samples);
+    analyzer
+}
+
+/// Energy of the bins that are not harmonics of `frequency`, relative to the
+/// fundamental, in dB
+fn alias_db(analyzer: &SpectrumAnalyzer<N>, frequency: f32) -> f32 {
+    let power = |k: usize| analyzer.magnitude(k).powi(2);
+    let fundamental = (frequency / BIN_WIDTH).round() as usize;
+    let harmonic = |k: usize| {
+        let distance = (k as f32 * BIN_WIDTH / frequency).fract();
+        distance.min(1.0 - distance) * frequency < 4.0 * BIN_WIDTH
+    };
+    let aliases: f32 = (1..N / 2).filter(|k| !harmonic(*k)).map(power).sum();
+    let fundamental: f32 = (fundamental - 3..=fundamental + 3).map(power).sum();
+    10.0 * (aliases / fundamental).log10()
+}
+
+#[test]
+fn fundamentals_are_in_tune_and_level() {
+    use core::f32::consts::PI;
+    let waveforms = [
+        (Waveform::Sine, 1.0),
+        (Waveform::Saw, 2.0 / PI),
+        (Waveform::Square, 4.0 / PI),
+        (Waveform::Triangle, 8.0 / (PI * PI)),
+    ];
+    for (waveform, amplitude) in waveforms {
+        for frequency in [110.0, 440.0, 1234.5, 3000.0] {
+            let mut oscillator = oscillator(waveform, frequency);
+            let analyzer = analyze(Window::BlackmanHarris, || oscillator.tick());
+            let peak = (1..N / 2)
+                .max_by(|a, b| analyzer.magnitude(*a).total_cmp(&analyzer.magnitude(*b)))
+                .unwrap();
+            let peak_frequency = SpectrumAnalyzer::<N>::bin_frequency(peak, SAMPLE_RATE);
+            assert!(
+                (peak_frequency - frequency).abs() <= BIN_WIDTH / 2.0,
+                "{waveform:?} at {frequency} Hz peaks at {peak_frequency} Hz"
+            );
+
+            let mut oscillator = self::oscillator(waveform, frequency);
+            let analyzer = analyze(Window::FlatTop, || oscillator.tick());
+            let level = analyzer.magnitude(peak);
+            assert!(
+                (level / amplitude - 1.0).abs() < 0.02,
+                "{waveform:?} at {frequency} Hz: {level} instead of {amplitude}"
+            );
+        }
+    }
+}
+
+#[test]
+fn aliasing_stays_low_near_nyquist() {
+    for waveform in [Waveform::Saw, Waveform::Square] {
+        for frequency in [2_500.0, 5_100.0, 9_700.0, 15_300.0] {
+            let mut oscillator = oscillator(waveform, frequency);
+            let analyzer = analyze(Window::BlackmanHarris, || oscillator.tick());
+            let alias = alias_db(&analyzer, frequency);
+
+            // The same waveform without the corrections
+            let mut phase = 0.0f32;
+            let naive = analyze(Window::BlackmanHarris, || {
+                phase = (phase + frequency / SAMPLE_RATE).fract();
+                match waveform {
+                    Waveform::Saw => 2.0 * phase - 1.0,
+                    _ if phase < 0.5 => 1.0,
+                    _ => -1.0,
+                }
+            });
+            let naive = alias_db(&naive, frequency);
+            // PolyBLEP takes away the bulk of it, less so the closer the
+            // fundamental gets to Nyquist
+            assert!(
+                alias < -15.0 && alias < naive - 12.0,
+                "{waveform:?} at {frequency} Hz: {alias} dB, naive {naive} dB"
+            );
+        }
+    }
+}
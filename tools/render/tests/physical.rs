@@ -0,0 +1,163 @@
+// Physical
+//
+// The plucked string rings at its frequency up to high notes and decays in
+// its decay time, the modes of the resonator sit at their ratios.
+use render::physical::{KarplusStrong, ModalParams, ModalResonator, PhysicalError, StringParams};
+use render::spectrum::{SpectrumAnalyzer, Window};
+
+const SAMPLE_RATE: f32 = 48_000.0;
+const N: usize = 8192;
+/// Distance between the two measurements of the fundamental, 0.1 s
+const SPACING: usize = 4800;
+
+/// Hann windowed DFT of `samples` at `frequency`
+fn fundamental(samples: &[f32], frequency: f32) -> (f64, f64) {
+    let length = samples.len() as f64;
+    let (mut real, mut imaginary) = (0.0, 0.0);
+    for (n, sample) in samples.iter().enumerate() {
+        let window = 0.5 - 0.5 * (core::f64::consts::TAU * n as f64 / length).cos();
+        let phase = core::f64::consts::TAU * frequency as f64 * n as f64 / SAMPLE_RATE as f64;
+        real += *sample as f64 * window * phase.cos();
+        imaginary -= *sample as f64 * window * phase.sin();
+    }
+    (real, imaginary)
+}
+
+/// Frequency from the phase that the fundamental advances between two
+/// windows, far finer than a bin, and its decay in dB per second
+fn measure(output: &[f32], nominal: f32) -> (f32, f32) {
+    let window = 2048;
+    let first = fundamental(&output[..window], nominal);
+    let second = fundamental(&output[SPACING..SPACING + window], nominal);
+    let advance = second.1.atan2(second.0) - first.1.atan2(first.0);
+    let expected = core::f64::consts::TAU * nominal as f64 * SPACING as f64 / SAMPLE_RATE as f64;
+    let error = (advance - expected).rem_euclid(core::f64::consts::TAU);
+    let error = if error > core::f64::consts::PI {
+        error - core::f64::consts::TAU
+    } else {
+        error
+    };
+    let seconds = SPACING as f64 / SAMPLE_RATE as f64;
+    let frequency = nominal as f64 + error / (core::f64::consts::TAU * seconds);
+    let level =
+        |(real, imaginary): (f64, f64)| 20.0 * (real * real + imaginary * imaginary).sqrt().log10();
+    let decay = (level(first) - level(second)) / seconds;
+    (frequency as f32, decay as f32)
+}
+
+fn pluck(params: StringParams) -> Vec<f32> {
+    let mut string = KarplusStrong::new();
+    string.set_params(params).unwrap();
+    string.pluck(1.0);
+    // The burst and the higher harmonics die down first
+    for _ in 0..4800 {
+        string.tick(0.0);
+    }
+    (0..SPACING + 2048).map(|_| string.tick(0.0)).collect()
+}
+
+#[test]
+fn high_notes_stay_in_tune() {
+    for frequency in [110.0, 440.0, 1000.0, 2000.0, 3520.0] {
+        let output = pluck(StringParams {
+            frequency,
+            decay_ms: 3000.0,
+            damping: 0.5,
+        });
+        let (measured, _) = measure(&output, frequency);
+        let cents = 1200.0 * (measured / frequency).log2();
+        assert!(cents.abs() < 2.0, "{frequency} Hz plays {cents} cents off");
+    }
+}
+
+#[test]
+fn fundamental_decays_in_the_decay_time() {
+    for (frequency, decay_ms) in [(110.0, 2000.0), (440.0, 1000.0), (880.0, 500.0)] {
+        let output = pluck(StringParams {
+            frequency,
+            decay_ms,
+            damping: 0.5,
+        });
+        let (_, decay) = measure(&output, frequency);
+        let t60 = 60.0 / decay * 1000.0;
+        assert!(
+            (t60 / decay_ms - 1.0).abs() < 0.1,
+            "{frequency} Hz decays in {t60} ms instead of {decay_ms} ms"
+        );
+    }
+}
+
+#[test]
+fn modes_sit_at_their_ratios() {
+    for params in [
+        ModalParams::bar(220.0, 1000.0),
+        ModalParams::membrane(110.0, 1000.0),
+        ModalParams::bell(220.0, 2000.0),
+    ] {
+        let mut resonator = ModalResonator::new();
+        resonator.set_params(params).unwrap();
+        resonator.strike(1.0);
+        let mut block = [0.0; N];
+        for sample in block.iter_mut() {
+            *sample = resonator.tick(0.0);
+        }
+        let mut analyzer = SpectrumAnalyzer::<N>::new(Window::Hann).unwrap();
+        analyzer.analyze(&block);
+        let bin = SAMPLE_RATE / N as f32;
+
+        for mode in &params.modes[..params.count] {
+            let expected = params.frequency * mode.ratio;
+            // The strongest bin within a quarter tone of the mode
+            let low = (expected * 0.97 / bin) as usize;
+            let high = (expected * 1.03 / bin) as usize + 1;
+            let peak = (low..=high)
+                .max_by(|a, b| analyzer.magnitude(*a).total_cmp(&analyzer.magnitude(*b)))
+                .unwrap();
+            let found = SpectrumAnalyzer::<N>::bin_frequency(peak, SAMPLE_RATE);
+            assert!(
+                (found - expected).abs() <= bin,
+                "mode {} peaks at {found} Hz instead of {expected} Hz",
+                mode.ratio
+            );
+        }
+    }
+}
+
+#[test]
+fn strings_out_of_range_are_refused() {
+    let mut string = KarplusStrong::new();
+    for (params, refused) in [
+        (
+            StringParams {
+                frequency: 10.0,
+                ..StringParams::default()
+            },
+            PhysicalError::FrequencyTooLow,
+        ),
+        (
+            StringParams {
+                frequency: 30_000.0,
+                ..StringParams::default()
+            },
+            PhysicalError::FrequencyTooHigh,
+        ),
+        (
+            StringParams {
+                damping: 1.5,
+                ..StringParams::default()
+            },
+            PhysicalError::DampingOutOfRange,
+        ),
+    ] {
+        let error = string.set_params(params).unwrap_err();
+        assert_eq!(
+            std::mem::discriminant(&error),
+            std::mem::discriminant(&refused)
+        );
+    }
+    assert!(string.params() == &StringParams::default());
+    assert!(matches!(
+        ModalParams::new(440.0, 1000.0, &[]),
+        Err(PhysicalError::NoModes)
+    ));
+}
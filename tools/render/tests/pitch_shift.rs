@@ -0,0 +1,102 @@
+// Pitch Shift
+//
+// Sines shifted by the pitch shifter and split by the octaver land at the
+// expected frequencies.
+use render::octaver::{Octaver, OctaverParams};
+use render::pitch_shift::{PitchShiftError, PitchShiftParams, PitchShifter};
+use render::spectrum::{SpectrumAnalyzer, Window};
+
+const SAMPLE_RATE: f32 = 48_000.0;
+const N: usize = 8192;
+
+/// Share of the energy of the output within a semitone of `expected`, for
+/// a sine at `frequency` after the effect has settled. The crossfades of
+/// the pitch shifter spread the energy over lines a few Hz apart, so the
+/// strongest bin alone may sit next to the expected frequency.
+fn share(frequency: f32, expected: f32, mut effect: impl FnMut(f32) -> f32) -> f32 {
+    let mut sine = (0..).map(|n| {
+        (core::f64::consts::TAU * frequency as f64 * n as f64 / SAMPLE_RATE as f64).sin() as f32
+    });
+    for _ in 0..4800 {
+        effect(sine.next().unwrap());
+    }
+    let mut block = [0.0; N];
+    for sample in block.iter_mut() {
+        *sample = effect(sine.next().unwrap());
+    }
+    let mut analyzer = SpectrumAnalyzer::<N>::new(Window::Hann).unwrap();
+    analyzer.analyze(&block);
+    let semitone = 2_f32.powf(1.0 / 12.0);
+    let near = analyzer.band_energy(expected / semitone, expected * semitone, SAMPLE_RATE);
+    near / analyzer.band_energy(0.0, SAMPLE_RATE / 2.0, SAMPLE_RATE)
+}
+
+fn assert_lands(share: f32, expected: f32, minimum: f32) {
+    assert!(share > minimum, "{share} of the energy near {expected} Hz");
+}
+
+#[test]
+fn shifted_sine_lands_at_the_ratio() {
+    for (semitones, cents) in [(7.0, 0.0), (12.0, 0.0), (-12.0, 0.0), (-5.0, 30.0)] {
+        let mut shifter = PitchShifter::new();
+        shifter
+            .set_params(PitchShiftParams {
+                semitones,
+                cents,
+                ..PitchShiftParams::default()
+            })
+            .unwrap();
+        let expected = 440.0 * shifter.ratio();
+        assert_lands(
+            share(440.0, expected, |input| shifter.tick(input)),
+            expected,
+            0.9,
+        );
+    }
+}
+
+#[test]
+fn transpositions_out_of_range_are_refused() {
+    let mut shifter = PitchShifter::new();
+    let result = shifter.set_params(PitchShiftParams {
+        semitones: 13.0,
+        ..PitchShiftParams::default()
+    });
+    assert!(matches!(result, Err(PitchShiftError::SemitonesOutOfRange)));
+    let result = shifter.set_params(PitchShiftParams {
+        cents: -101.0,
+        ..PitchShiftParams::default()
+    });
+    assert!(matches!(result, Err(PitchShiftError::CentsOutOfRange)));
+    let result = shifter.set_params(PitchShiftParams {
+        window_ms: 0.01,
+        ..PitchShiftParams::default()
+    });
+    assert!(matches!(result, Err(PitchShiftError::WindowTooShort)));
+    assert_eq!(shifter.ratio(), 1.0);
+}
+
+#[test]
+fn octaver_produces_the_octaves() {
+    let mut octaver = Octaver::new();
+    octaver.set_params(OctaverParams {
+        dry: 0.0,
+        down: 1.0,
+        up: 0.0,
+    });
+    // The octave below is a square wave, which has 8/pi^2 of its energy in
+    // the fundamental
+    assert_lands(
+        share(220.0, 110.0, |input| octaver.tick(input)),
+        110.0,
+        0.75,
+    );
+
+    let mut octaver = Octaver::new();
+    octaver.set_params(OctaverParams {
+        dry: 0.0,
+        down: 0.0,
+        up: 1.0,
+    });
+    assert_lands(share(220.0, 440.0, |input| octaver.tick(input)), 440.0, 0.9);
+}
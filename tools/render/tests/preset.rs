@@ -0,0 +1,147 @@
+// Preset
+//
+// Storing presets and refusing anything that isn't one.
+use render::crc::crc32;
+use render::filter::{FilterParams, FilterType};
+use render::preset::{MAX_PRESETS, PRESET_KEY, Preset, PresetError, slot_key};
+use render::spectral::SpectralMode;
+
+fn preset() -> Preset {
+    Preset {
+        filter_type: FilterType::Bell,
+        filter: FilterParams {
+            frequency: 1234.5,
+            quality: 2.5,
+            gain: -6.0,
+        },
+        spectral_mode: SpectralMode::Robotize,
+    }
+}
+
+#[test]
+fn crc_matches_the_standard() {
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    assert_eq!(crc32(&[]), 0);
+}
+
+#[test]
+fn presets_survive_the_round_trip() {
+    for preset in [Preset::default(), preset()] {
+        assert_eq!(Preset::from_bytes(&preset.to_bytes()), Ok(preset));
+    }
+
+    // Whatever follows doesn't matter
+    let mut value = vec![0xFF; 64];
+    value[..Preset::SIZE].copy_from_slice(&preset().to_bytes());
+    assert_eq!(Preset::from_bytes(&value), Ok(preset()));
+}
+
+#[test]
+fn erased_flash_is_empty() {
+    assert_eq!(
+        Preset::from_bytes(&[0xFF; Preset::SIZE]),
+        Err(PresetError::Empty)
+    );
+}
+
+#[test]
+fn corrupt_presets_are_refused() {
+    let bytes = preset().to_bytes();
+
+    // Every flipped bit is noticed
+    for index in 0..bytes.len() {
+        for bit in 0..8 {
+            let mut corrupt = bytes;
+            corrupt[index] ^= 1 << bit;
+            assert!(
+                Preset::from_bytes(&corrupt).is_err(),
+                "byte {index} bit {bit}"
+            );
+        }
+    }
+
+    // Cut short
+    assert_eq!(
+        Preset::from_bytes(&bytes[..Preset::SIZE - 1]),
+        Err(PresetError::InvalidHeader)
+    );
+    assert_eq!(Preset::from_bytes(&[]), Err(PresetError::InvalidHeader));
+
+    let mut corrupt = bytes;
+    corrupt[Preset::SIZE - 1] ^= 0x80;
+    assert_eq!(Preset::from_bytes(&corrupt), Err(PresetError::CrcMismatch));
+    let mut corrupt = bytes;
+    corrupt[0] = b'X';
+    assert_eq!(
+        Preset::from_bytes(&corrupt),
+        Err(PresetError::InvalidHeader)
+    );
+}
+
+/// Replaces part of the payload and fixes up the CRC
+fn patched(offset: usize, data: &[u8]) -> [u8; Preset::SIZE] {
+    let mut bytes = preset().to_bytes();
+    bytes[offset..offset + data.len()].copy_from_slice(data);
+    let crc = crc32(&bytes[..Preset::SIZE - 4]);
+    bytes[Preset::SIZE - 4..].copy_from_slice(&crc.to_le_bytes());
+    bytes
+}
+
+#[test]
+fn invalid_values_are_refused() {
+    // Version from a newer firmware
+    assert_eq!(
+        Preset::from_bytes(&patched(4, &[2])),
+        Err(PresetError::UnsupportedVersion(2))
+    );
+    // Unknown filter type and spectral mode
+    assert_eq!(
+        Preset::from_bytes(&patched(6, &[3])),
+        Err(PresetError::InvalidValue)
+    );
+    assert_eq!(
+        Preset::from_bytes(&patched(7, &[5])),
+        Err(PresetError::InvalidValue)
+    );
+    // NaN and negative frequencies
+    assert_eq!(
+        Preset::from_bytes(&patched(8, &f32::NAN.to_le_bytes())),
+        Err(PresetError::InvalidValue)
+    );
+    assert_eq!(
+        Preset::from_bytes(&patched(8, &(-100.0f32).to_le_bytes())),
+        Err(PresetError::InvalidValue)
+    );
+    assert_eq!(
+        Preset::from_bytes(&patched(16, &f32::INFINITY.to_le_bytes())),
+        Err(PresetError::InvalidValue)
+    );
+    // Beyond the ranges of the controls, the frequency above Nyquist
+    for (offset, value) in [
+        (8, 30_000.0f32),
+        (12, 0.05),
+        (12, 10.0),
+        (16, -30.0),
+        (16, 30.0),
+    ] {
+        assert_eq!(
+            Preset::from_bytes(&patched(offset, &value.to_le_bytes())),
+            Err(PresetError::InvalidValue),
+            "{value} at {offset}"
+        );
+    }
+    // The ends of the ranges are fine
+    for (offset, value) in [(8, 20_000.0f32), (12, 0.1), (16, -24.0)] {
+        assert!(Preset::from_bytes(&patched(offset, &value.to_le_bytes())).is_ok());
+    }
+}
+
+#[test]
+fn slots_have_their_own_keys() {
+    assert_eq!(slot_key(0), Ok(PRESET_KEY));
+    assert_eq!(
+        slot_key(MAX_PRESETS - 1),
+        Ok(PRESET_KEY + MAX_PRESETS as u16 - 1)
+    );
+    assert_eq!(slot_key(MAX_PRESETS), Err(PresetError::SlotOutOfRange));
+}
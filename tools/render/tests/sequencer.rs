@@ -0,0 +1,330 @@
+// Sequencer
+//
+// Runs the sequencer in blocks like on the board and checks when the notes
+// start and end.
+use render::sequencer::{
+    ArpMode, ClockSource, Pattern, PlayMode, Sequencer, SequencerEvent, SequencerParams, Step,
+};
+
+const SAMPLE_RATE: f32 = 48_000.0;
+const BLOCK_LENGTH: usize = 32;
+/// Sixteenth notes at 120 BPM
+const STEP: usize = 6000;
+
+/// Events of `samples` with their time since the start
+fn run(sequencer: &mut Sequencer, samples: usize) -> Vec<(usize, SequencerEvent)> {
+    (0..samples)
+        .step_by(BLOCK_LENGTH)
+        .flat_map(|start| {
+            sequencer
+                .process(BLOCK_LENGTH.min(samples - start))
+                .into_iter()
+                .map(move |(offset, event)| (start + offset, event))
+        })
+        .collect()
+}
+
+fn note_ons(events: &[(usize, SequencerEvent)]) -> Vec<(usize, u8)> {
+    events
+        .iter()
+        .filter_map(|(time, event)| match event {
+            SequencerEvent::NoteOn { note, .. } => Some((*time, *note)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn arpeggio(mode: ArpMode, octaves: u8, notes: &[u8], steps: usize) -> Vec<u8> {
+    let mut sequencer = Sequencer::new(SAMPLE_RATE);
+    sequencer
+        .set_params(SequencerParams {
+            play: PlayMode::Arpeggiator,
+            arp_mode: mode,
+            octaves,
+            ..Default::default()
+        })
+        .unwrap();
+    for note in notes {
+        sequencer.note_on(*note, 100);
+    }
+    sequencer.start();
+    note_ons(&run(&mut sequencer, steps * STEP - 1))
+        .into_iter()
+        .map(|(_, note)| note)
+        .collect()
+}
+
+#[test]
+fn steps_play_on_time_with_their_gate() {
+    let mut sequencer = Sequencer::new(SAMPLE_RATE);
+    let mut step = Step::new(60, 100);
+    step.gate = 0.25;
+    sequencer
+        .set_pattern(
+            0,
+            Pattern::new(&[step, Step::REST, Step::new(64, 90)]).unwrap(),
+        )
+        .unwrap();
+    sequencer.start();
+
+    let events = run(&mut sequencer, 4 * STEP);
+    let expected = [
+        (
+            0,
+            SequencerEvent::NoteOn {
+                note: 60,
+                velocity: 100,
+                slide: false,
+            },
+        ),
+        (STEP / 4, SequencerEvent::NoteOff { note: 60 }),
+        (
+            2 * STEP,
+            SequencerEvent::NoteOn {
+                note: 64,
+                velocity: 90,
+                slide: false,
+            },
+        ),
+        (2 * STEP + STEP / 2, SequencerEvent::NoteOff { note: 64 }),
+        (
+            3 * STEP,
+            SequencerEvent::NoteOn {
+                note: 60,
+                velocity: 100,
+                slide: false,
+            },
+        ),
+        (3 * STEP + STEP / 4, SequencerEvent::NoteOff { note: 60 }),
+    ];
+    assert_eq!(events, expected);
+}
+
+#[test]
+fn swing_delays_every_second_step() {
+    let mut sequencer = Sequencer::new(SAMPLE_RATE);
+    sequencer
+        .set_pattern(0, Pattern::new(&[Step::new(60, 100); 4]).unwrap())
+        .unwrap();
+    sequencer
+        .set_params(SequencerParams {
+            swing: 0.625,
+            ..Default::default()
+        })
+        .unwrap();
+    sequencer.start();
+
+    let times: Vec<usize> = note_ons(&run(&mut sequencer, 4 * STEP))
+        .into_iter()
+        .map(|(time, _)| time)
+        .collect();
+    assert_eq!(times, [0, 7500, 12_000, 19_500]);
+}
+
+#[test]
+fn slides_overlap_the_notes() {
+    let mut sequencer = Sequencer::new(SAMPLE_RATE);
+    let mut slide = Step::new(67, 100);
+    slide.slide = true;
+    sequencer
+        .set_pattern(0, Pattern::new(&[Step::new(60, 100), slide]).unwrap())
+        .unwrap();
+    sequencer.start();
+
+    let events = run(&mut sequencer, 2 * STEP);
+    let expected = [
+        (
+            0,
+            SequencerEvent::NoteOn {
+                note: 60,
+                velocity: 100,
+                slide: false,
+            },
+        ),
+        (
+            STEP,
+            SequencerEvent::NoteOn {
+                note: 67,
+                velocity: 100,
+                slide: true,
+            },
+        ),
+        (STEP, SequencerEvent::NoteOff { note: 60 }),
+        (STEP + STEP / 2, SequencerEvent::NoteOff { note: 67 }),
+    ];
+    assert_eq!(events, expected);
+}
+
+#[test]
+fn probability_is_deterministic() {
+    let play = || {
+        let mut sequencer = Sequencer::new(SAMPLE_RATE);
+        let mut step = Step::new(60, 100);
+        step.probability = 0.5;
+        sequencer
+            .set_pattern(0, Pattern::new(&[step; 16]).unwrap())
+            .unwrap();
+        sequencer.start();
+        note_ons(&run(&mut sequencer, 64 * STEP))
+    };
+    let first = play();
+    assert_eq!(first, play());
+    assert!(
+        (20..=44).contains(&first.len()),
+        "{} of 64 steps",
+        first.len()
+    );
+}
+
+#[test]
+fn queued_pattern_starts_after_the_current_one() {
+    let mut sequencer = Sequencer::new(SAMPLE_RATE);
+    sequencer
+        .set_pattern(0, Pattern::new(&[Step::new(60, 100); 2]).unwrap())
+        .unwrap();
+    sequencer
+        .set_pattern(1, Pattern::new(&[Step::new(72, 100)]).unwrap())
+        .unwrap();
+    sequencer.start();
+    run(&mut sequencer, STEP);
+    sequencer.select_pattern(1).unwrap();
+
+    let notes: Vec<u8> = note_ons(&run(&mut sequencer, 3 * STEP))
+        .into_iter()
+        .map(|(_, note)| note)
+        .collect();
+    assert_eq!(notes, [60, 72, 72]);
+    assert_eq!(sequencer.selected_pattern(), 1);
+}
+
+#[test]
+fn external_clock_advances_the_steps() {
+    let mut sequencer = Sequencer::new(SAMPLE_RATE);
+    sequencer
+        .set_pattern(
+            0,
+            Pattern::new(&[Step::new(60, 100), Step::new(62, 100)]).unwrap(),
+        )
+        .unwrap();
+    sequencer
+        .set_params(SequencerParams {
+            clock: ClockSource::External,
+            pulses_per_step: 6,
+            swing: 0.75,
+            ..Default::default()
+        })
+        .unwrap();
+    sequencer.start();
+
+    // MIDI clock at 120 BPM, 24 pulses per quarter note every 1000 samples
+    let mut events = Vec::new();
+    for start in (0..4 * STEP).step_by(BLOCK_LENGTH) {
+        if start % 1000 < BLOCK_LENGTH {
+            sequencer.clock();
+        }
+        events.extend(
+            sequencer
+                .process(BLOCK_LENGTH)
+                .into_iter()
+                .map(|(offset, event)| (start + offset, event)),
+        );
+    }
+    // The pulses land within a block of their time, the swung steps half a
+    // step later
+    let ons = note_ons(&events);
+    let times: Vec<usize> = ons.iter().map(|(time, _)| *time).collect();
+    let notes: Vec<u8> = ons.iter().map(|(_, note)| *note).collect();
+    assert_eq!(notes, [60, 62, 60, 62]);
+    for (time, expected) in times.iter().zip([0, 9000, 12_000, 21_000]) {
+        assert!(
+            time.abs_diff(expected) < 2 * BLOCK_LENGTH,
+            "{time} instead of {expected}"
+        );
+    }
+
+    // Without pulses it waits
+    assert!(note_ons(&run(&mut sequencer, 4 * STEP)).is_empty());
+}
+
+#[test]
+fn arpeggiator_modes() {
+    let chord = [64, 60, 67];
+    assert_eq!(arpeggio(ArpMode::Up, 1, &chord, 4), [60, 64, 67, 60]);
+    assert_eq!(arpeggio(ArpMode::Down, 1, &chord, 4), [67, 64, 60, 67]);
+    assert_eq!(
+        arpeggio(ArpMode::UpDown, 1, &chord, 6),
+        [60, 64, 67, 64, 60, 64]
+    );
+    assert_eq!(arpeggio(ArpMode::AsPlayed, 1, &chord, 4), [64, 60, 67, 64]);
+    assert_eq!(
+        arpeggio(ArpMode::Up, 2, &chord, 7),
+        [60, 64, 67, 72, 76, 79, 60]
+    );
+    assert_eq!(arpeggio(ArpMode::Down, 2, &[60, 64], 4), [76, 72, 64, 60]);
+
+    let random = arpeggio(ArpMode::Random, 2, &chord, 32);
+    assert_eq!(random, arpeggio(ArpMode::Random, 2, &chord, 32));
+    assert!(
+        random
+            .iter()
+            .all(|note| [60, 64, 67, 72, 76, 79].contains(note))
+    );
+    assert!(random.windows(2).any(|pair| pair[0] > pair[1]));
+}
+
+#[test]
+fn arpeggiator_follows_the_held_notes() {
+    let mut sequencer = Sequencer::new(SAMPLE_RATE);
+    sequencer
+        .set_params(SequencerParams {
+            play: PlayMode::Arpeggiator,
+            ..Default::default()
+        })
+        .unwrap();
+    sequencer.start();
+    // Nothing held, nothing plays
+    assert!(run(&mut sequencer, 2 * STEP).is_empty());
+
+    sequencer.note_on(60, 100);
+    sequencer.note_on(65, 100);
+    let notes: Vec<u8> = note_ons(&run(&mut sequencer, 2 * STEP))
+        .into_iter()
+        .map(|(_, note)| note)
+        .collect();
+    assert_eq!(notes, [60, 65]);
+
+    sequencer.note_off(60);
+    let notes: Vec<u8> = note_ons(&run(&mut sequencer, 2 * STEP))
+        .into_iter()
+        .map(|(_, note)| note)
+        .collect();
+    assert_eq!(notes, [65, 65]);
+
+    let events = sequencer.stop();
+    assert!(events.is_empty() || events[0].1 == SequencerEvent::NoteOff { note: 65 });
+}
+
+#[test]
+fn notes_held_before_the_start_are_arpeggiated() {
+    // The firmware hands every note to the sequencer, also while it stands
+    let mut sequencer = Sequencer::new(SAMPLE_RATE);
+    sequencer
+        .set_params(SequencerParams {
+            play: PlayMode::Arpeggiator,
+            ..Default::default()
+        })
+        .unwrap();
+    sequencer.note_on(64, 100);
+    sequencer.note_on(60, 100);
+    assert!(run(&mut sequencer, 2 * STEP).is_empty());
+
+    for _ in 0..2 {
+        sequencer.start();
+        let notes: Vec<u8> = note_ons(&run(&mut sequencer, 2 * STEP - 1))
+            .into_iter()
+            .map(|(_, note)| note)
+            .collect();
+        assert_eq!(notes, [60, 64]);
+        sequencer.stop();
+    }
+}
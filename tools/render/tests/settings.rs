@@ -0,0 +1,250 @@
+// Settings
+//
+// The settings store on RAM flash: keeping values across mounts, spreading
+// the writes over all sectors and surviving a power loss at every step.
+use std::collections::HashMap;
+
+use render::flash::{FlashError, RamFlash, SECTOR_SIZE};
+use render::settings::{MAX_KEYS, MAX_VALUE, SettingsError, SettingsStore};
+
+const SECTORS: usize = 3;
+const SIZE: usize = SECTORS * SECTOR_SIZE as usize;
+
+type Store<'a> = SettingsStore<&'a mut RamFlash<SIZE>>;
+
+fn mount(flash: &mut RamFlash<SIZE>) -> Store<'_> {
+    SettingsStore::mount(flash, 0, SECTORS).unwrap()
+}
+
+fn get(store: &mut Store, key: u16) -> Option<Vec<u8>> {
+    let mut buffer = [0; MAX_VALUE];
+    let length = store.get(key, &mut buffer).unwrap()?;
+    Some(buffer[..length].to_vec())
+}
+
+/// A different value for every key and generation
+fn value(key: u16, generation: usize) -> Vec<u8> {
+    (0..MAX_VALUE)
+        .map(|index| (key as usize * 31 + generation * 7 + index) as u8)
+        .collect()
+}
+
+/// Sequence numbers in the sector headers, `None` for free sectors
+fn sequences(flash: &RamFlash<SIZE>) -> Vec<Option<u32>> {
+    flash
+        .memory()
+        .chunks(SECTOR_SIZE as usize)
+        .map(|sector| {
+            (sector[..4] == *b"DKKV")
+                .then(|| u32::from_le_bytes([sector[4], sector[5], sector[6], sector[7]]))
+        })
+        .collect()
+}
+
+#[test]
+fn values_survive_remounting() {
+    let mut flash = RamFlash::new();
+    let mut store = mount(&mut flash);
+    assert_eq!(get(&mut store, 1), None);
+
+    store.set(1, b"one").unwrap();
+    store.set(2, b"two").unwrap();
+    store.set(3, &[]).unwrap();
+    store.set(1, b"uno").unwrap();
+    store.remove(2).unwrap();
+    // Removing a missing key is fine
+    store.remove(4).unwrap();
+
+    let mut store = mount(&mut flash);
+    assert_eq!(get(&mut store, 1), Some(b"uno".to_vec()));
+    assert_eq!(get(&mut store, 2), None);
+    assert_eq!(get(&mut store, 3), Some(Vec::new()));
+    assert!(store.contains(3));
+    assert!(!store.contains(2));
+    let mut keys: Vec<u16> = store.keys().collect();
+    keys.sort();
+    assert_eq!(keys, [1, 3]);
+}
+
+#[test]
+fn invalid_use_is_refused() {
+    let mut flash = RamFlash::new();
+    for (address, sectors) in [(0, 1), (100, 2), (SECTOR_SIZE, SECTORS)] {
+        assert!(matches!(
+            SettingsStore::mount(&mut flash, address, sectors),
+            Err(SettingsError::InvalidRegion)
+        ));
+    }
+
+    let mut store = mount(&mut flash);
+    assert_eq!(store.set(0xFFFF, b"x"), Err(SettingsError::InvalidKey));
+    assert_eq!(
+        store.set(1, &[0; MAX_VALUE + 1]),
+        Err(SettingsError::ValueTooLong)
+    );
+    store.set(1, b"four").unwrap();
+    assert_eq!(
+        store.get(1, &mut [0; 3]),
+        Err(SettingsError::BufferTooSmall)
+    );
+
+    // The store is bounded in keys and bytes
+    for key in 2..=MAX_KEYS as u16 {
+        store.set(key, &[]).unwrap();
+    }
+    assert_eq!(store.set(100, &[]), Err(SettingsError::TooManyKeys));
+    let full = (1..=MAX_KEYS as u16).find_map(|key| store.set(key, &value(key, 0)).err());
+    assert_eq!(full, Some(SettingsError::Full));
+    // Overwriting with a value of the same size still works
+    store.set(1, &value(1, 1)).unwrap();
+    assert_eq!(get(&mut store, 1), Some(value(1, 1)));
+}
+
+#[test]
+fn writes_go_round_all_sectors() {
+    let mut flash = RamFlash::new();
+    let mut history = Vec::new();
+    for generation in 0..2000 {
+        let key = (generation % 4) as u16;
+        mount(&mut flash).set(key, &value(key, generation)).unwrap();
+        history.push(sequences(&flash));
+    }
+
+    // Every sector was started over and over, in turn
+    let mut started = [0; SECTORS];
+    for (before, after) in history.iter().zip(&history[1..]) {
+        for sector in 0..SECTORS {
+            if after[sector].is_some() && after[sector] != before[sector] {
+                started[sector] += 1;
+            }
+        }
+    }
+    let (min, max) = (started.iter().min(), started.iter().max());
+    assert!(*min.unwrap() >= 10, "{started:?}");
+    assert!(max.unwrap() - min.unwrap() <= 1, "{started:?}");
+
+    let mut store = mount(&mut flash);
+    for key in 0..4 {
+        assert_eq!(get(&mut store, key), Some(value(key, 1996 + key as usize)));
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Op {
+    Set(u16, usize),
+    Remove(u16),
+}
+
+impl Op {
+    fn key(self) -> u16 {
+        match self {
+            Op::Set(key, _) | Op::Remove(key) => key,
+        }
+    }
+
+    fn run(self, store: &mut Store) -> Result<(), SettingsError> {
+        match self {
+            Op::Set(key, generation) => store.set(key, &value(key, generation)),
+            Op::Remove(key) => store.remove(key),
+        }
+    }
+
+    fn apply(self, values: &mut HashMap<u16, Vec<u8>>) {
+        match self {
+            Op::Set(key, generation) => {
+                values.insert(key, value(key, generation));
+            }
+            Op::Remove(key) => {
+                values.remove(&key);
+            }
+        }
+    }
+}
+
+/// Two full sectors, so that the next write starts the last free sector
+/// and copies what is still current out of the oldest one
+fn prefilled() -> (RamFlash<SIZE>, HashMap<u16, Vec<u8>>) {
+    let mut flash = RamFlash::new();
+    let mut store = mount(&mut flash);
+    let mut values = HashMap::new();
+    for generation in 0..112 {
+        let op = Op::Set((generation as u16 + 1).min(9), generation);
+        op.run(&mut store).unwrap();
+        op.apply(&mut values);
+    }
+    assert_eq!(sequences(&flash), [Some(0), Some(1), None]);
+    (flash, values)
+}
+
+/// Every key holds its value from before, only `op` may have happened
+fn check(store: &mut Store, before: &HashMap<u16, Vec<u8>>, op: Option<Op>) {
+    let mut after = before.clone();
+    if let Some(op) = op {
+        op.apply(&mut after);
+    }
+    for key in 1..=12 {
+        let found = get(store, key);
+        assert!(
+            found.as_ref() == before.get(&key) || found.as_ref() == after.get(&key),
+            "key {key} after {op:?}"
+        );
+        if op.is_none_or(|op| op.key() != key) {
+            assert_eq!(found.as_ref(), before.get(&key), "key {key}");
+        }
+    }
+}
+
+#[test]
+fn power_loss_loses_at_most_the_last_change() {
+    let (prefilled, values) = prefilled();
+    let ops = [
+        Op::Set(9, 200),
+        Op::Set(3, 201),
+        Op::Remove(5),
+        Op::Set(10, 202),
+    ];
+
+    for step in 0.. {
+        let mut flash = prefilled.clone();
+        flash.set_power(Some(step));
+        let mut store = mount(&mut flash);
+        let mut values = values.clone();
+        let mut failed = None;
+        for op in ops {
+            match op.run(&mut store) {
+                Ok(()) => op.apply(&mut values),
+                Err(error) => {
+                    assert_eq!(error, SettingsError::Flash(FlashError::PowerLoss));
+                    failed = Some(op);
+                    break;
+                }
+            }
+        }
+        if failed.is_none() {
+            flash.set_power(None);
+            check(&mut mount(&mut flash), &values, None);
+            break;
+        }
+
+        // The power may fail again while mounting finishes the copying
+        flash.set_power(Some(step % 97));
+        match SettingsStore::mount(&mut flash, 0, SECTORS) {
+            Ok(_) | Err(SettingsError::Flash(FlashError::PowerLoss)) => {}
+            Err(error) => panic!("step {step}: {error:?}"),
+        }
+        flash.set_power(None);
+        let mut store = mount(&mut flash);
+        check(&mut store, &values, failed);
+
+        // And it keeps working
+        let mut values: HashMap<u16, Vec<u8>> = (1..=12)
+            .filter_map(|key| Some((key, get(&mut store, key)?)))
+            .collect();
+        for generation in 0..60 {
+            let op = Op::Set(11, generation);
+            op.run(&mut store).unwrap();
+            op.apply(&mut values);
+        }
+        check(&mut mount(&mut flash), &values, None);
+    }
+}
@@ -0,0 +1,46 @@
+// Spectral
+//
+// Perfect reconstruction through the STFT and the bin-sized effect state.
+use render::random::Random;
+use render::spectral::{Bypass, Denoise, Freeze, Spectral, SpectralMode, Stft};
+
+const N: usize = 512;
+const BINS: usize = N / 2 + 1;
+
+#[test]
+fn bypass_reconstructs_the_input_delayed() {
+    let mut stft = Stft::<N>::new().unwrap();
+    let mut random = Random::new(11);
+    let input: Vec<f32> = (0..8 * N).map(|_| 0.5 * random.bipolar()).collect();
+    let output: Vec<f32> = input
+        .iter()
+        .map(|sample| stft.tick(*sample, &mut Bypass))
+        .collect();
+
+    assert_eq!(Stft::<N>::LATENCY, N);
+    for n in Stft::<N>::LATENCY..input.len() {
+        let expected = input[n - Stft::<N>::LATENCY];
+        assert!(
+            (output[n] - expected).abs() < 1e-6,
+            "{n}: {} != {expected}",
+            output[n]
+        );
+    }
+}
+
+#[test]
+fn effects_keep_one_value_per_bin() {
+    assert_eq!(size_of::<Freeze<BINS>>(), 3 * BINS * 4 + 4);
+    assert_eq!(size_of::<Denoise<BINS>>(), 2 * BINS * 4 + 4 * 4);
+
+    // Frozen noise keeps its level instead of dying out
+    let mut spectral = Spectral::<N, BINS>::new().unwrap();
+    spectral.set_mode(SpectralMode::Freeze);
+    let mut random = Random::new(5);
+    for _ in 0..4 * N {
+        spectral.tick(0.5 * random.bipolar());
+    }
+    spectral.freeze.set_frozen(true);
+    let energy: f32 = (0..8 * N).map(|_| spectral.tick(0.0).powi(2)).sum();
+    assert!(energy > 0.01 * N as f32, "{energy}");
+}
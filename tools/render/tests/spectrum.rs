@@ -0,0 +1,125 @@
+// Spectrum
+//
+// Gains of the windows, magnitudes of sinusoids and band energies against
+// the power of the signal.
+use render::spectrum::{SpectrumAnalyzer, Window};
+
+const SAMPLE_RATE: f32 = 48_000.0;
+const N: usize = 4096;
+const WINDOWS: [Window; 4] = [
+    Window::Rectangular,
+    Window::Hann,
+    Window::BlackmanHarris,
+    Window::FlatTop,
+];
+
+fn sine(amplitude: f32, bin: f32) -> [f32; N] {
+    core::array::from_fn(|n| {
+        let phase = core::f64::consts::TAU * bin as f64 * n as f64 / N as f64 + 0.3;
+        amplitude * phase.sin() as f32
+    })
+}
+
+#[test]
+fn windows_have_their_coherent_gain() {
+    // The first coefficient of the cosine sum is the mean of the window
+    for (window, mean) in WINDOWS.into_iter().zip([1.0, 0.5, 0.35875, 0.21557895]) {
+        let mut table = [0.0; N];
+        window.fill(&mut table);
+        let sum: f32 = table.iter().sum();
+        assert!((sum / N as f32 - mean).abs() < 1.0e-6, "{window:?}");
+        // Periodic, so symmetric around the middle rather than the ends
+        for n in 1..N / 2 {
+            assert!((table[n] - table[N - n]).abs() < 1.0e-6, "{window:?}");
+        }
+    }
+}
+
+#[test]
+fn magnitude_of_a_centered_sinusoid_is_its_amplitude() {
+    for window in WINDOWS {
+        let mut analyzer = SpectrumAnalyzer::<N>::new(window).unwrap();
+        analyzer.analyze(&sine(0.7, 100.0));
+        let magnitude = analyzer.magnitude(100);
+        assert!((magnitude - 0.7).abs() < 1.0e-4, "{window:?}: {magnitude}");
+    }
+}
+
+#[test]
+fn flat_top_reads_amplitudes_between_bins() {
+    let mut analyzer = SpectrumAnalyzer::<N>::new(Window::FlatTop).unwrap();
+    analyzer.analyze(&sine(0.7, 100.5));
+    let magnitude = analyzer.magnitude(100);
+    assert!((magnitude / 0.7 - 1.0).abs() < 0.002, "{magnitude}");
+}
+
+#[test]
+fn band_energy_is_the_power_of_the_signal() {
+    // Between bins the sinusoid spreads over more bins, the band takes them
+    // all in. Except with the rectangular window, which leaks too far.
+    let center = SpectrumAnalyzer::<N>::bin_frequency(100, SAMPLE_RATE);
+    let power = 0.5 * 0.7 * 0.7;
+    for window in WINDOWS {
+        let mut analyzer = SpectrumAnalyzer::<N>::new(window).unwrap();
+        let bins: &[f32] = match window {
+            Window::Rectangular => &[100.0],
+            _ => &[100.0, 100.5],
+        };
+        for &bin in bins {
+            analyzer.analyze(&sine(0.7, bin));
+            let energy = analyzer.band_energy(center - 1_000.0, center + 1_000.0, SAMPLE_RATE);
+            assert!(
+                (energy / power - 1.0).abs() < 0.01,
+                "{window:?} at bin {bin}: {energy}"
+            );
+        }
+    }
+}
+
+#[test]
+fn band_energy_of_white_noise_grows_with_the_bandwidth() {
+    let mut random = render::random::Random::new(0x1234_5678);
+    let noise: [f32; N] = core::array::from_fn(|_| random.bipolar());
+    // Uniform noise has a power of 1/3, spread evenly up to Nyquist
+    let nyquist = SAMPLE_RATE / 2.0;
+    let mut analyzer = SpectrumAnalyzer::<N>::new(Window::Hann).unwrap();
+    analyzer.analyze(&noise);
+    let total = analyzer.band_energy(0.0, nyquist, SAMPLE_RATE);
+    assert!((total * 3.0 - 1.0).abs() < 0.1, "{total}");
+
+    let edges = [0.0, nyquist / 4.0, nyquist / 2.0, nyquist];
+    let mut energies = [0.0; 3];
+    analyzer.band_energies(&edges, SAMPLE_RATE, &mut energies);
+    let ratio = energies[2] / (energies[0] + energies[1]);
+    assert!((ratio - 1.0).abs() < 0.1, "{energies:?}");
+}
+
+#[test]
+fn band_energies_of_a_partition_add_up_to_the_total() {
+    let mut random = render::random::Random::new(0x8765_4321);
+    let noise: [f32; N] = core::array::from_fn(|_| random.bipolar());
+    let nyquist = SAMPLE_RATE / 2.0;
+    let mut analyzer = SpectrumAnalyzer::<N>::new(Window::Hann).unwrap();
+    analyzer.analyze(&noise);
+    let total = analyzer.band_energy(0.0, nyquist, SAMPLE_RATE);
+
+    // Edges on bin centers as well as between them
+    let bin = SpectrumAnalyzer::<N>::bin_frequency(1, SAMPLE_RATE);
+    let edges = [
+        0.0,
+        bin,
+        100.0 * bin,
+        1234.5,
+        5000.0,
+        500.0 * bin,
+        12_000.0,
+        nyquist,
+    ];
+    let mut energies = [0.0; 7];
+    analyzer.band_energies(&edges, SAMPLE_RATE, &mut energies);
+    let sum: f32 = energies.iter().sum();
+    assert!(
+        (sum / total - 1.0).abs() < 1.0e-5,
+        "{sum} instead of {total}"
+    );
+}
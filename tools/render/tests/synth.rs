@@ -0,0 +1,113 @@
+// Synth
+//
+// Unison spread measured from the spectrum, and parameters that are taken
+// for all voices or not at all.
+use render::allocator::{AllocationMode, NotePriority};
+use render::envelope::{EnvelopeError, EnvelopeParams};
+use render::oscillator::Waveform;
+use render::spectrum::{SpectrumAnalyzer, Window};
+use render::synth::{Synth, SynthError, SynthParams, note_to_frequency};
+
+const SAMPLE_RATE: f32 = 48_000.0;
+const N: usize = 8192;
+
+/// Plain sines without filter movement, so the spectrum shows the pitches
+fn sines() -> SynthParams {
+    SynthParams {
+        waveform_1: Waveform::Sine,
+        waveform_2: Waveform::Sine,
+        detune: 0.0,
+        mix: 0.0,
+        cutoff: 8000.0,
+        filter_envelope_depth: 0.0,
+        key_tracking: 0.0,
+        amp_envelope: EnvelopeParams::adsr(1.0, 1.0, 1.0, 1.0).unwrap(),
+        unison_detune: 50.0,
+        ..SynthParams::default()
+    }
+}
+
+/// Frequencies of the peaks in the spectrum of the note, lowest first
+fn peaks<const V: usize>(synth: &mut Synth<V>, note: u8) -> Vec<f32> {
+    synth.note_on(note, 127);
+    // Past the attack
+    synth.process(&mut [(0.0, 0.0); 480]);
+    let mut buffer = [(0.0, 0.0); N];
+    synth.process(&mut buffer);
+    let mut samples = [0.0; N];
+    for (sample, (left, _)) in samples.iter_mut().zip(buffer) {
+        *sample = left;
+    }
+
+    let mut analyzer = SpectrumAnalyzer::<N>::new(Window::BlackmanHarris).unwrap();
+    analyzer.analyze(&samples);
+    let loudest = (0..N / 2)
+        .map(|k| analyzer.magnitude(k))
+        .fold(0.0, f32::max);
+    (1..N / 2 - 1)
+        .filter(|&k| {
+            let magnitude = analyzer.magnitude(k);
+            magnitude > 0.1 * loudest
+                && magnitude > analyzer.magnitude(k - 1)
+                && magnitude >= analyzer.magnitude(k + 1)
+        })
+        .map(|k| SpectrumAnalyzer::<N>::bin_frequency(k, SAMPLE_RATE))
+        .collect()
+}
+
+fn assert_within_bin(actual: f32, expected: f32) {
+    let bin = SAMPLE_RATE / N as f32;
+    assert!(
+        (actual - expected).abs() <= bin,
+        "{actual} Hz instead of {expected} Hz"
+    );
+}
+
+#[test]
+fn unison_spreads_the_voices_across_the_detune() {
+    let mut synth = Synth::<3>::new(SAMPLE_RATE);
+    synth.set_params(sines()).unwrap();
+    synth.set_mode(AllocationMode::Unison {
+        priority: NotePriority::Last,
+        legato: false,
+    });
+
+    let peaks = peaks(&mut synth, 69);
+    assert_eq!(peaks.len(), 3, "{peaks:?}");
+    for (peak, cents) in peaks.into_iter().zip([-50.0, 0.0, 50.0]) {
+        assert_within_bin(peak, note_to_frequency(69.0 + cents / 100.0));
+    }
+
+    // Polyphonic voices play the note as it is
+    synth.set_mode(AllocationMode::Oldest);
+    synth.process(&mut [(0.0, 0.0); 480]);
+    let peaks = self::peaks(&mut synth, 69);
+    assert_eq!(peaks.len(), 1, "{peaks:?}");
+    assert_within_bin(peaks[0], 440.0);
+}
+
+#[test]
+fn invalid_envelopes_change_no_voice() {
+    let mut synth = Synth::<2>::new(SAMPLE_RATE);
+    let before = *synth.params();
+
+    // A valid filter envelope with an invalid amplitude envelope
+    let mut params = sines();
+    params.amp_envelope.segments[0].time_ms = -1.0;
+    assert!(matches!(
+        synth.set_params(params),
+        Err(SynthError::Envelope(EnvelopeError::TimeNegative))
+    ));
+    assert!(*synth.params() == before);
+
+    let mut params = sines();
+    params.filter_envelope.sustain = Some(7);
+    assert!(matches!(
+        synth.set_params(params),
+        Err(SynthError::Envelope(EnvelopeError::SustainOutOfRange))
+    ));
+    assert!(*synth.params() == before);
+
+    assert!(synth.set_params(sines()).is_ok());
+    assert!(*synth.params() == sines());
+}
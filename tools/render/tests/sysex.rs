@@ -0,0 +1,173 @@
+// SysEx
+//
+// Preset requests and dumps, byte for byte and through the MIDI parser.
+use render::filter::{FilterParams, FilterType};
+use render::midi::{MidiMessage, MidiParser};
+use render::preset::{MAX_PRESETS, Preset, PresetError};
+use render::spectral::SpectralMode;
+use render::sysex::{
+    DEVICE_ID, MANUFACTURER_ID, MAX_MESSAGE, Slot, SysExError, SysExMessage, pack, packed_length,
+    unpack,
+};
+
+fn preset() -> Preset {
+    Preset {
+        filter_type: FilterType::Bandpass,
+        filter: FilterParams {
+            frequency: 880.0,
+            quality: 4.0,
+            gain: 3.5,
+        },
+        spectral_mode: SpectralMode::Whisperize,
+    }
+}
+
+#[test]
+fn packing_keeps_every_bit() {
+    let data: Vec<u8> = (0..=255).rev().collect();
+    for length in 0..30 {
+        let mut packed = Vec::new();
+        pack(&data[..length], |byte| packed.push(byte));
+        assert_eq!(packed.len(), packed_length(length));
+        assert!(packed.iter().all(|byte| *byte < 0x80));
+
+        let mut unpacked = Vec::new();
+        unpack(&packed, |byte| unpacked.push(byte)).unwrap();
+        assert_eq!(unpacked, data[..length]);
+    }
+
+    assert_eq!(unpack(&[0, 0x80], |_| {}), Err(SysExError::InvalidByte));
+    // A group of only high bits
+    assert_eq!(
+        unpack(&[0, 1, 2, 3, 4, 5, 6, 7, 0], |_| {}),
+        Err(SysExError::InvalidLength)
+    );
+}
+
+#[test]
+fn requests_are_short() {
+    let request = SysExMessage::Request(Slot::Stored(3));
+    let bytes = request.encode().unwrap();
+    assert_eq!(bytes, [0xF0, MANUFACTURER_ID, DEVICE_ID, 0x01, 3, 0xF7]);
+    assert_eq!(SysExMessage::parse(&bytes), Ok(request));
+
+    let current = SysExMessage::Request(Slot::Current);
+    assert_eq!(current.encode().unwrap()[4], 0x7F);
+    // Without 0xF0 and 0xF7, as from the MIDI parser
+    assert_eq!(
+        SysExMessage::parse(&[MANUFACTURER_ID, DEVICE_ID, 0x01, 0x7F]),
+        Ok(current)
+    );
+}
+
+#[test]
+fn dumps_survive_the_round_trip() {
+    for slot in [
+        Slot::Current,
+        Slot::Stored(0),
+        Slot::Stored(MAX_PRESETS - 1),
+    ] {
+        for preset in [Preset::default(), preset()] {
+            let dump = SysExMessage::Dump(slot, preset);
+            let bytes = dump.encode().unwrap();
+            assert_eq!(bytes.len(), MAX_MESSAGE);
+            assert!(bytes[1..bytes.len() - 1].iter().all(|byte| *byte < 0x80));
+            assert_eq!(SysExMessage::parse(&bytes), Ok(dump));
+        }
+    }
+}
+
+#[test]
+fn dumps_come_through_the_midi_parser() {
+    let dump = SysExMessage::Dump(Slot::Stored(5), preset());
+    let mut parser = MidiParser::new();
+    let mut messages = Vec::new();
+    // With a clock in the middle, as it may come from an output
+    let bytes = dump.encode().unwrap();
+    let (first, second) = bytes.split_at(10);
+    parser.parse_slice(first, |result| messages.push(result.unwrap()));
+    parser.parse_slice(&[0xF8], |result| messages.push(result.unwrap()));
+    parser.parse_slice(second, |result| messages.push(result.unwrap()));
+
+    assert_eq!(
+        messages,
+        [
+            MidiMessage::Clock,
+            MidiMessage::SysEx {
+                length: MAX_MESSAGE - 2
+            }
+        ]
+    );
+    assert_eq!(SysExMessage::parse(parser.sysex()), Ok(dump));
+}
+
+#[test]
+fn broken_messages_are_refused() {
+    let bytes = SysExMessage::Dump(Slot::Stored(1), preset())
+        .encode()
+        .unwrap();
+
+    // Every changed byte is noticed, mostly by the checksum
+    for index in 4..bytes.len() - 1 {
+        for change in [1, 0x40] {
+            let mut broken = bytes.clone();
+            broken[index] ^= change;
+            assert!(
+                SysExMessage::parse(&broken).is_err(),
+                "byte {index} ^ {change}"
+            );
+        }
+    }
+    let mut broken = bytes.clone();
+    broken[6] ^= 1;
+    assert_eq!(
+        SysExMessage::parse(&broken),
+        Err(SysExError::ChecksumMismatch)
+    );
+
+    // A byte missing
+    let mut short = bytes.clone();
+    short.remove(10);
+    assert_eq!(SysExMessage::parse(&short), Err(SysExError::InvalidLength));
+
+    // Someone else's messages
+    let mut other = bytes.clone();
+    other[1] = 0x41;
+    assert_eq!(SysExMessage::parse(&other), Err(SysExError::NotForUs));
+    let mut other = bytes.clone();
+    other[2] = DEVICE_ID + 1;
+    assert_eq!(SysExMessage::parse(&other), Err(SysExError::NotForUs));
+    assert_eq!(SysExMessage::parse(&[]), Err(SysExError::NotForUs));
+
+    assert_eq!(
+        SysExMessage::parse(&[MANUFACTURER_ID, DEVICE_ID, 0x03, 0]),
+        Err(SysExError::UnknownCommand(0x03))
+    );
+    assert_eq!(
+        SysExMessage::parse(&[MANUFACTURER_ID, DEVICE_ID, 0x01, MAX_PRESETS as u8]),
+        Err(SysExError::SlotOutOfRange)
+    );
+    assert_eq!(
+        SysExMessage::Request(Slot::Stored(MAX_PRESETS)).encode(),
+        Err(SysExError::SlotOutOfRange)
+    );
+}
+
+#[test]
+fn invalid_presets_are_refused() {
+    // A valid checksum around a preset that fails its CRC
+    let mut bytes = SysExMessage::Dump(Slot::Current, preset())
+        .encode()
+        .unwrap();
+    let last = bytes.len() - 2;
+    bytes[20] ^= 1;
+    let sum = bytes[4..last]
+        .iter()
+        .map(|byte| u32::from(*byte))
+        .sum::<u32>();
+    bytes[last] = (128 - sum % 128) as u8 & 0x7F;
+    assert_eq!(
+        SysExMessage::parse(&bytes),
+        Err(SysExError::Preset(PresetError::CrcMismatch))
+    );
+}
@@ -0,0 +1,94 @@
+// Tuner
+//
+// Pitch detection on a sine, a sawtooth and a plucked string with noise,
+// within a few cents of the true frequency.
+use std::f32::consts::TAU;
+
+use render::random::Random;
+use render::tuner::{Pitch, Tuner};
+
+const SAMPLE_RATE: f32 = 48_000.0;
+/// Enough to fill the analysis buffer
+const LENGTH: usize = 8192;
+
+fn detect(signal: impl Fn(usize) -> f32) -> Option<Pitch> {
+    let mut tuner = Tuner::new(SAMPLE_RATE).unwrap();
+    let samples: Vec<f32> = (0..LENGTH).map(signal).collect();
+    tuner.push_samples(&samples);
+    tuner.detect()
+}
+
+fn cents(actual: f32, expected: f32) -> f32 {
+    1200.0 * (actual / expected).log2()
+}
+
+#[test]
+fn sines_are_detected() {
+    for frequency in [55.0, 110.0, 261.63, 440.0, 1000.0] {
+        let pitch = detect(|n| 0.5 * (TAU * frequency * n as f32 / SAMPLE_RATE).sin()).unwrap();
+        assert!(
+            cents(pitch.frequency, frequency).abs() < 2.0,
+            "{frequency} Hz: {pitch:?}"
+        );
+        assert!(pitch.confidence > 0.95, "{frequency} Hz: {pitch:?}");
+    }
+
+    let pitch = detect(|n| (TAU * 440.0 * n as f32 / SAMPLE_RATE).sin()).unwrap();
+    assert_eq!(pitch.note, 69);
+    assert_eq!((pitch.note_name(), pitch.octave()), ("A", 4));
+}
+
+#[test]
+fn sawtooths_are_detected() {
+    for frequency in [82.41, 146.83, 329.63, 659.26] {
+        let pitch = detect(|n| {
+            let phase = (frequency * n as f32 / SAMPLE_RATE).fract();
+            0.5 * (2.0 * phase - 1.0)
+        })
+        .unwrap();
+        assert!(
+            cents(pitch.frequency, frequency).abs() < 3.0,
+            "{frequency} Hz: {pitch:?}"
+        );
+        assert!(pitch.confidence > 0.9, "{frequency} Hz: {pitch:?}");
+    }
+}
+
+#[test]
+fn noisy_plucked_strings_are_detected() {
+    // The open strings of a guitar, a few cents off
+    for frequency in [82.0, 110.5, 147.5, 195.0, 247.9, 330.0] {
+        let mut random = Random::new(7);
+        let noise: Vec<f32> = (0..LENGTH).map(|_| random.bipolar()).collect();
+        let pitch = detect(|n| {
+            let time = n as f32 / SAMPLE_RATE;
+            // Decaying harmonics, the higher ones faster, with a bit of hiss
+            let string: f32 = (1..=8)
+                .map(|harmonic| {
+                    let harmonic = harmonic as f32;
+                    let decay = (-time * 2.0 * harmonic).exp();
+                    decay / harmonic * (TAU * frequency * harmonic * time).sin()
+                })
+                .sum();
+            0.3 * string + 0.02 * noise[n]
+        })
+        .unwrap();
+        assert!(
+            cents(pitch.frequency, frequency).abs() < 5.0,
+            "{frequency} Hz: {pitch:?}"
+        );
+        assert!(pitch.confidence > 0.8, "{frequency} Hz: {pitch:?}");
+    }
+}
+
+#[test]
+fn silence_and_noise_are_not_notes() {
+    assert_eq!(detect(|_| 0.0), None);
+    assert_eq!(detect(|_| 1.0e-5), None);
+
+    let mut random = Random::new(3);
+    let noise: Vec<f32> = (0..LENGTH).map(|_| random.bipolar()).collect();
+    if let Some(pitch) = detect(|n| 0.5 * noise[n]) {
+        assert!(pitch.confidence < 0.8, "{pitch:?}");
+    }
+}
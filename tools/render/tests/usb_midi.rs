@@ -0,0 +1,180 @@
+// USB MIDI
+//
+// Framing of messages into USB MIDI event packets and back.
+use render::midi::{MidiError, MidiMessage};
+use render::usb_midi::{UsbMidiPacket, UsbMidiParser, encode, encode_sysex};
+
+fn parse(parser: &mut UsbMidiParser, packets: &[UsbMidiPacket]) -> Vec<MidiMessage> {
+    let mut messages = Vec::new();
+    for packet in packets {
+        parser.parse(*packet, |result| messages.push(result.unwrap()));
+    }
+    messages
+}
+
+fn sysex_packets(data: &[u8]) -> Vec<UsbMidiPacket> {
+    let mut packets = Vec::new();
+    encode_sysex(0, data, |packet| packets.push(packet));
+    packets
+}
+
+#[test]
+fn channel_messages_take_one_packet() {
+    let note_on = MidiMessage::NoteOn {
+        channel: 3,
+        note: 60,
+        velocity: 100,
+    };
+    assert_eq!(encode(0, note_on), Some([0x09, 0x93, 60, 100]));
+    let program = MidiMessage::ProgramChange {
+        channel: 0,
+        program: 5,
+    };
+    assert_eq!(encode(2, program), Some([0x2C, 0xC0, 5, 0]));
+    let bend = MidiMessage::PitchBend {
+        channel: 15,
+        value: -8192,
+    };
+    assert_eq!(encode(0, bend), Some([0x0E, 0xEF, 0, 0]));
+}
+
+#[test]
+fn system_messages_have_their_own_code_index() {
+    assert_eq!(encode(0, MidiMessage::Clock), Some([0x0F, 0xF8, 0, 0]));
+    assert_eq!(
+        encode(0, MidiMessage::TuneRequest),
+        Some([0x05, 0xF6, 0, 0])
+    );
+    assert_eq!(
+        encode(0, MidiMessage::SongSelect(7)),
+        Some([0x02, 0xF3, 7, 0])
+    );
+    assert_eq!(
+        encode(0, MidiMessage::SongPosition(0x81)),
+        Some([0x03, 0xF2, 0x01, 0x01])
+    );
+    // The data isn't part of the message
+    assert_eq!(encode(0, MidiMessage::SysEx { length: 3 }), None);
+}
+
+#[test]
+fn messages_survive_the_round_trip() {
+    let messages = [
+        MidiMessage::NoteOn {
+            channel: 0,
+            note: 64,
+            velocity: 1,
+        },
+        MidiMessage::NoteOff {
+            channel: 9,
+            note: 36,
+            velocity: 64,
+        },
+        MidiMessage::ControlChange {
+            channel: 1,
+            controller: 74,
+            value: 127,
+        },
+        MidiMessage::ChannelPressure {
+            channel: 2,
+            pressure: 50,
+        },
+        MidiMessage::PitchBend {
+            channel: 4,
+            value: 8191,
+        },
+        MidiMessage::Start,
+        MidiMessage::SongPosition(1000),
+        MidiMessage::Stop,
+    ];
+    let packets: Vec<UsbMidiPacket> = messages
+        .iter()
+        .map(|message| encode(0, *message).unwrap())
+        .collect();
+
+    let mut parser = UsbMidiParser::new(0);
+    assert_eq!(parse(&mut parser, &packets), messages);
+}
+
+#[test]
+fn sysex_is_split_into_packets() {
+    assert_eq!(
+        sysex_packets(&[0x7D, 1, 2, 3, 4]),
+        [[0x04, 0xF0, 0x7D, 1], [0x04, 2, 3, 4], [0x05, 0xF7, 0, 0]]
+    );
+    assert_eq!(
+        sysex_packets(&[0x7D, 1, 2, 3]),
+        [[0x04, 0xF0, 0x7D, 1], [0x07, 2, 3, 0xF7]]
+    );
+    assert_eq!(
+        sysex_packets(&[0x7D, 1, 2]),
+        [[0x04, 0xF0, 0x7D, 1], [0x06, 2, 0xF7, 0]]
+    );
+    assert_eq!(
+        sysex_packets(&[0x7D, 1]),
+        [[0x04, 0xF0, 0x7D, 1], [0x05, 0xF7, 0, 0]]
+    );
+    assert_eq!(sysex_packets(&[0x7D]), [[0x07, 0xF0, 0x7D, 0xF7]]);
+    assert_eq!(sysex_packets(&[]), [[0x06, 0xF0, 0xF7, 0]]);
+
+    let data: Vec<u8> = (0..100).collect();
+    let mut parser = UsbMidiParser::new(0);
+    assert_eq!(
+        parse(&mut parser, &sysex_packets(&data)),
+        [MidiMessage::SysEx { length: 100 }]
+    );
+    assert_eq!(parser.sysex(), data);
+}
+
+#[test]
+fn realtime_goes_between_sysex_packets() {
+    let mut packets = sysex_packets(&[0x7D, 1, 2, 3, 4]);
+    packets.insert(1, encode(0, MidiMessage::Clock).unwrap());
+    let mut parser = UsbMidiParser::new(0);
+    assert_eq!(
+        parse(&mut parser, &packets),
+        [MidiMessage::Clock, MidiMessage::SysEx { length: 5 }]
+    );
+}
+
+#[test]
+fn transfers_are_filtered_by_cable() {
+    let data = [
+        0x09, 0x90, 60, 100, // cable 0
+        0x19, 0x90, 61, 100, // cable 1
+        0x00, 0x00, 0x00, 0x00, // padding
+        0x01, 0x12, 0x34, 0x56, // reserved code index
+        0x08, 0x80, 60, 0, // cable 0
+        0x09, 0x90, // incomplete packet
+    ];
+    let mut messages = Vec::new();
+    UsbMidiParser::new(0).parse_transfer(&data, |result| messages.push(result.unwrap()));
+    assert_eq!(
+        messages,
+        [
+            MidiMessage::NoteOn {
+                channel: 0,
+                note: 60,
+                velocity: 100,
+            },
+            MidiMessage::NoteOff {
+                channel: 0,
+                note: 60,
+                velocity: 0,
+            },
+        ]
+    );
+
+    let mut messages = Vec::new();
+    UsbMidiParser::new(1).parse_transfer(&data, |result| messages.push(result.unwrap()));
+    assert_eq!(messages.len(), 1);
+}
+
+#[test]
+fn broken_packets_are_errors() {
+    // A data byte without status
+    let mut results = Vec::new();
+    UsbMidiParser::new(0).parse([0x09, 0x40, 0x40, 0x40], |result| results.push(result));
+    assert!(results.iter().all(Result::is_err), "{results:?}");
+    assert_eq!(results.first(), Some(&Err(MidiError::UnexpectedByte(0x40))));
+}
@@ -0,0 +1,124 @@
+// Vocoder
+//
+// The band levels of the modulator shape the carrier. Parameters that
+// don't fit the sample rate are refused and leave the bands as they were.
+use render::random::Random;
+use render::spectrum::{SpectrumAnalyzer, Window};
+use render::vocoder::{Carrier, Vocoder, VocoderError, VocoderParams};
+
+const SAMPLE_RATE: f32 = 48_000.0;
+const N: usize = 4096;
+
+/// Runs a chirp through both channels and returns the left output
+fn render(vocoder: &mut Vocoder) -> Vec<f32> {
+    (0..4800)
+        .map(|n| {
+            let t = n as f32 / 48_000.0;
+            let input = (core::f32::consts::TAU * (200.0 + 20_000.0 * t) * t).sin();
+            vocoder.tick((input, input)).0
+        })
+        .collect()
+}
+
+#[test]
+fn max_frequency_over_nyquist_is_refused() {
+    let mut vocoder = Vocoder::new();
+    let result = vocoder.set_params(VocoderParams {
+        bands: 24,
+        max_frequency: 24_000.0,
+        ..VocoderParams::default()
+    });
+    assert!(matches!(result, Err(VocoderError::FrequencyRangeInvalid)));
+    assert_eq!(render(&mut vocoder), render(&mut Vocoder::new()));
+}
+
+#[test]
+fn sample_rate_below_the_bands_is_refused() {
+    let mut vocoder = Vocoder::new();
+    let result = vocoder.set_sample_rate(16_000.0);
+    assert!(matches!(result, Err(VocoderError::FrequencyRangeInvalid)));
+    assert_eq!(render(&mut vocoder), render(&mut Vocoder::new()));
+}
+
+#[test]
+fn valid_params_are_taken_over() {
+    let mut vocoder = Vocoder::new();
+    let params = VocoderParams {
+        bands: 24,
+        max_frequency: 12_000.0,
+        ..VocoderParams::default()
+    };
+    vocoder.set_params(params).unwrap();
+    assert_ne!(render(&mut vocoder), render(&mut Vocoder::new()));
+}
+
+#[test]
+fn carrier_and_times_out_of_range_are_refused() {
+    let mut vocoder = Vocoder::new();
+    for carrier_frequency in [-1.0, 24_000.0, 48_000.0] {
+        let result = vocoder.set_params(VocoderParams {
+            carrier: Carrier::Oscillator,
+            carrier_frequency,
+            ..VocoderParams::default()
+        });
+        assert!(matches!(
+            result,
+            Err(VocoderError::CarrierFrequencyOutOfRange)
+        ));
+    }
+    for (attack_ms, release_ms) in [(-1.0, 30.0), (2.0, -1.0)] {
+        let result = vocoder.set_params(VocoderParams {
+            attack_ms,
+            release_ms,
+            ..VocoderParams::default()
+        });
+        assert!(matches!(result, Err(VocoderError::TimeNegative)));
+    }
+    assert_eq!(render(&mut vocoder), render(&mut Vocoder::new()));
+}
+
+/// Energy of the output around `low` and `high` Hz, with a sine at `low`
+/// and then at `high` as modulator and white noise as carrier
+fn shaped_energies(modulator: f32) -> (f32, f32) {
+    let mut vocoder = Vocoder::new();
+    let mut random = Random::new(0x0C0D_E500);
+    let mut output = [0.0; N];
+    // Let the envelopes settle first
+    for n in 0..N + 4800 {
+        let t = n as f32 / SAMPLE_RATE;
+        let input = (core::f32::consts::TAU * modulator * t).sin();
+        let (left, _) = vocoder.tick((input, random.bipolar()));
+        if n >= 4800 {
+            output[n - 4800] = left;
+        }
+    }
+    let mut analyzer = SpectrumAnalyzer::<N>::new(Window::Hann).unwrap();
+    analyzer.analyze(&output);
+    let energy =
+        |frequency: f32| analyzer.band_energy(frequency / 1.2, frequency * 1.2, SAMPLE_RATE);
+    (energy(500.0), energy(4_000.0))
+}
+
+#[test]
+fn modulator_bands_shape_the_carrier() {
+    // The noise comes through the bands the sine excites, much louder than
+    // through the others
+    let (low_at_low, high_at_low) = shaped_energies(500.0);
+    let (low_at_high, high_at_high) = shaped_energies(4_000.0);
+    assert!(
+        low_at_low > 20.0 * low_at_high,
+        "{low_at_low} {low_at_high}"
+    );
+    assert!(
+        high_at_high > 20.0 * high_at_low,
+        "{high_at_high} {high_at_low}"
+    );
+
+    // Without a modulator nothing comes through
+    let mut vocoder = Vocoder::new();
+    let mut random = Random::new(1);
+    let silence = (0..4800)
+        .map(|_| vocoder.tick((0.0, random.bipolar())).0.abs())
+        .fold(0.0, f32::max);
+    assert_eq!(silence, 0.0);
+}
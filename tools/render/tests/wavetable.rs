@@ -0,0 +1,109 @@
+// Wavetable
+//
+// The mip levels keep a sawtooth table free of aliasing over the whole range
+// without losing the harmonics below an eighth of the sample rate, and full
+// tables fit into the SDRAM.
+use render::spectrum::{SpectrumAnalyzer, Window};
+use render::wavetable::{
+    FRAME_LENGTH, FRAME_STORAGE, MAX_FRAMES, TABLE_STORAGE, Wavetable, WavetableError,
+    WavetableOscillator, WavetableParams,
+};
+
+const SAMPLE_RATE: f32 = 48_000.0;
+const N: usize = 8192;
+const BIN_WIDTH: f32 = SAMPLE_RATE / N as f32;
+/// Size of the SDRAM of the Daisy Seed
+const SDRAM: usize = 64 * 1024 * 1024;
+
+fn saw_frame() -> Vec<f32> {
+    (0..FRAME_LENGTH)
+        .map(|i| 2.0 * (i as f32 / FRAME_LENGTH as f32 + 0.5).fract() - 1.0)
+        .collect()
+}
+
+fn analyze(table: &Wavetable, frequency: f32) -> SpectrumAnalyzer<N> {
+    let mut oscillator = WavetableOscillator::new();
+    oscillator.set_sample_rate(SAMPLE_RATE).unwrap();
+    oscillator
+        .set_params(WavetableParams {
+            frequency,
+            morph: 0.0,
+        })
+        .unwrap();
+    let mut samples = [0.0; N];
+    samples
+        .iter_mut()
+        .for_each(|sample| *sample = oscillator.tick(table));
+    let mut analyzer = SpectrumAnalyzer::new(Window::BlackmanHarris).unwrap();
+    analyzer.analyze(&samples);
+    analyzer
+}
+
+/// Power of the harmonic `harmonic` of `frequency`
+fn harmonic_power(analyzer: &SpectrumAnalyzer<N>, frequency: f32, harmonic: usize) -> f32 {
+    let center = (harmonic as f32 * frequency / BIN_WIDTH).round() as usize;
+    (center - 3..=center + 3)
+        .map(|k| analyzer.magnitude(k).powi(2))
+        .sum()
+}
+
+#[test]
+fn mip_levels_are_band_limited() {
+    let mut storage = vec![0.0; FRAME_STORAGE];
+    let mut table = Wavetable::new(&mut storage).unwrap();
+    table.load(&saw_frame()).unwrap();
+
+    // From the first level up to the last, including the fades between
+    for frequency in [30.0, 110.0, 440.0, 1234.5, 3000.0, 5100.0, 9700.0, 15300.0] {
+        let analyzer = analyze(&table, frequency);
+        let fundamental = harmonic_power(&analyzer, frequency, 1);
+
+        let harmonic = |k: usize| {
+            let distance = (k as f32 * BIN_WIDTH / frequency).fract();
+            distance.min(1.0 - distance) * frequency < 4.0 * BIN_WIDTH
+        };
+        let aliases: f32 = (1..N / 2)
+            .filter(|k| !harmonic(*k))
+            .map(|k| analyzer.magnitude(k).powi(2))
+            .sum();
+        let alias = 10.0 * (aliases / fundamental).log10();
+        assert!(alias < -40.0, "{frequency} Hz aliases at {alias} dB");
+
+        // The harmonics of a saw fall with 1 / h. A level keeps them up to a
+        // quarter of the sample rate at the bottom of its octave and fades
+        // into the next one, which keeps them up to an eighth.
+        let brightest = SAMPLE_RATE / 8.0 - 4.0 * BIN_WIDTH;
+        for h in (2..).take_while(|h| *h as f32 * frequency < brightest) {
+            let level = 10.0 * (harmonic_power(&analyzer, frequency, h) / fundamental).log10();
+            let expected = -20.0 * (h as f32).log10();
+            assert!(
+                (level - expected).abs() < 1.0,
+                "harmonic {h} of {frequency} Hz at {level} dB instead of {expected} dB"
+            );
+        }
+    }
+}
+
+#[test]
+fn full_tables_fit_into_the_sdram() {
+    assert!(TABLE_STORAGE * size_of::<f32>() < SDRAM / 8);
+
+    let frames: Vec<f32> = (0..MAX_FRAMES).flat_map(|_| saw_frame()).collect();
+    let mut storage = vec![0.0; TABLE_STORAGE];
+    let mut table = Wavetable::new(&mut storage).unwrap();
+    assert_eq!(table.capacity(), MAX_FRAMES);
+    table.load(&frames).unwrap();
+    assert_eq!(table.frames(), MAX_FRAMES);
+
+    let mut storage = vec![0.0; 2 * FRAME_STORAGE];
+    let mut table = Wavetable::new(&mut storage).unwrap();
+    assert_eq!(
+        table.load(&frames[..3 * FRAME_LENGTH]),
+        Err(WavetableError::TooManyFrames)
+    );
+    assert_eq!(
+        table.load_pcm16(&[0; 2 * FRAME_LENGTH + 2]),
+        Err(WavetableError::DataSizeInvalid)
+    );
+    assert_eq!(table.frames(), 0);
+}